@@ -0,0 +1,425 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Identifies a window for as long as it exists. On Win32 this is the value of its `HWND`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct WindowId(pub usize);
+
+/// What happens when the last window of a thread is closed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExitPolicy {
+    /// Quit the thread's event loop.
+    OnLastWindowClosed,
+    /// Keep running until the application quits explicitly.
+    Explicit,
+}
+
+impl Default for ExitPolicy {
+    #[inline]
+    fn default() -> Self {
+        ExitPolicy::OnLastWindowClosed
+    }
+}
+
+/// Creates the OS objects behind composition.
+///
+/// The queue and compositor are created at most once per `AppModel` and shared by all of its
+/// windows, while each window gets a target of its own.
+pub trait CompositionBackend {
+    type Queue;
+    type Compositor;
+    type Target;
+    type Error;
+
+    fn create_queue(&mut self) -> Result<Self::Queue, Self::Error>;
+    fn create_compositor(&mut self) -> Result<Self::Compositor, Self::Error>;
+    fn create_target(
+        &mut self,
+        window: WindowId,
+        compositor: &Self::Compositor,
+    ) -> Result<Self::Target, Self::Error>;
+    /// Asks the event loop to stop.
+    fn quit(&mut self);
+}
+
+/// An error produced while attaching composition to a window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AppError<E> {
+    /// The window isn't open.
+    UnknownWindow(WindowId),
+    /// The window already has a composition target.
+    AlreadyAttached(WindowId),
+    /// The backend failed to create an object.
+    Backend(E),
+}
+
+impl<E: fmt::Debug> fmt::Display for AppError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AppError::UnknownWindow(id) => write!(f, "window {:?} isn't open", id),
+            AppError::AlreadyAttached(id) => {
+                write!(f, "window {:?} already has a composition target", id)
+            }
+            AppError::Backend(ref err) => write!(f, "failed to set up composition: {:?}", err),
+        }
+    }
+}
+
+/// The windows of one thread and the composition objects they share.
+///
+/// Ownership follows the order composition requires: targets are released before the compositor,
+/// and the compositor before the dispatcher queue. The shared objects are created lazily by the
+/// first window that attaches composition and live until the model is dropped, so windows opened
+/// later reuse them. The queue can also be created on its own, to run work without composition.
+pub struct AppModel<B: CompositionBackend> {
+    windows: BTreeMap<WindowId, Option<B::Target>>,
+    compositor: Option<B::Compositor>,
+    queue: Option<B::Queue>,
+    backend: B,
+    exit_policy: ExitPolicy,
+}
+
+impl<B: CompositionBackend> AppModel<B> {
+    pub fn new(backend: B) -> Self {
+        AppModel {
+            windows: BTreeMap::new(),
+            compositor: None,
+            queue: None,
+            backend,
+            exit_policy: ExitPolicy::default(),
+        }
+    }
+
+    #[inline]
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    #[inline]
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    #[inline]
+    pub fn exit_policy(&self) -> ExitPolicy {
+        self.exit_policy
+    }
+
+    #[inline]
+    pub fn set_exit_policy(&mut self, policy: ExitPolicy) {
+        self.exit_policy = policy;
+    }
+
+    /// Starts tracking a window. Returns `false` if it was already open.
+    pub fn window_opened(&mut self, id: WindowId) -> bool {
+        if self.windows.contains_key(&id) {
+            return false;
+        }
+        self.windows.insert(id, None);
+        true
+    }
+
+    /// Stops tracking a window and releases its target.
+    ///
+    /// Returns `true` if this closed the last window and the backend was asked to quit.
+    pub fn window_closed(&mut self, id: WindowId) -> bool {
+        if self.windows.remove(&id).is_none() {
+            return false;
+        }
+        if self.windows.is_empty() && self.exit_policy == ExitPolicy::OnLastWindowClosed {
+            self.backend.quit();
+            return true;
+        }
+        false
+    }
+
+    /// Creates a composition target for an open window, creating the shared queue and compositor
+    /// first if this is the first window to use composition.
+    pub fn attach_composition(&mut self, id: WindowId) -> Result<&B::Target, AppError<B::Error>> {
+        match self.windows.get(&id) {
+            None => return Err(AppError::UnknownWindow(id)),
+            Some(&Some(_)) => return Err(AppError::AlreadyAttached(id)),
+            Some(&None) => (),
+        }
+
+        if self.compositor.is_none() {
+            // The queue has to exist before the compositor is created on this thread.
+            self.queue().map_err(AppError::Backend)?;
+            let compositor = self
+                .backend
+                .create_compositor()
+                .map_err(AppError::Backend)?;
+            self.compositor = Some(compositor);
+        }
+
+        let target = {
+            let compositor = self.compositor.as_ref().unwrap();
+            self.backend
+                .create_target(id, compositor)
+                .map_err(AppError::Backend)?
+        };
+        let slot = self.windows.get_mut(&id).unwrap();
+        *slot = Some(target);
+        Ok(slot.as_ref().unwrap())
+    }
+
+    /// The dispatcher queue of the thread, creating it on first use.
+    pub fn queue(&mut self) -> Result<&B::Queue, B::Error> {
+        if self.queue.is_none() {
+            self.queue = Some(self.backend.create_queue()?);
+        }
+        Ok(self.queue.as_ref().unwrap())
+    }
+
+    /// The compositor shared by the windows, once one of them has attached composition.
+    #[inline]
+    pub fn compositor(&self) -> Option<&B::Compositor> {
+        self.compositor.as_ref()
+    }
+
+    /// The composition target of a window, if it has one.
+    #[inline]
+    pub fn target(&self, id: WindowId) -> Option<&B::Target> {
+        self.windows.get(&id).and_then(|target| target.as_ref())
+    }
+
+    #[inline]
+    pub fn is_open(&self, id: WindowId) -> bool {
+        self.windows.contains_key(&id)
+    }
+
+    #[inline]
+    pub fn window_count(&self) -> usize {
+        self.windows.len()
+    }
+
+    /// The ids of the open windows, in ascending order.
+    pub fn window_ids<'a>(&'a self) -> impl Iterator<Item = WindowId> + 'a {
+        self.windows.keys().cloned()
+    }
+}
+
+impl<B: CompositionBackend> Drop for AppModel<B> {
+    fn drop(&mut self) {
+        // Field order would drop them in this order anyway; this makes the requirement explicit.
+        self.windows.clear();
+        self.compositor.take();
+        self.queue.take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    type Log = Rc<RefCell<Vec<String>>>;
+
+    /// An object created by the fake backend; logs when it is released.
+    struct Object {
+        name: String,
+        log: Log,
+    }
+
+    impl Drop for Object {
+        fn drop(&mut self) {
+            self.log.borrow_mut().push(format!("drop {}", self.name));
+        }
+    }
+
+    #[derive(Default)]
+    struct FakeBackend {
+        log: Log,
+        fail: Option<&'static str>,
+    }
+
+    impl FakeBackend {
+        fn create(&self, name: String) -> Result<Object, String> {
+            if self.fail == Some(name.split(' ').next().unwrap()) {
+                return Err(format!("can't create {}", name));
+            }
+            self.log.borrow_mut().push(format!("create {}", name));
+            Ok(Object {
+                name,
+                log: self.log.clone(),
+            })
+        }
+    }
+
+    impl CompositionBackend for FakeBackend {
+        type Queue = Object;
+        type Compositor = Object;
+        type Target = Object;
+        type Error = String;
+
+        fn create_queue(&mut self) -> Result<Object, String> {
+            self.create("queue".to_owned())
+        }
+
+        fn create_compositor(&mut self) -> Result<Object, String> {
+            self.create("compositor".to_owned())
+        }
+
+        fn create_target(
+            &mut self,
+            window: WindowId,
+            compositor: &Object,
+        ) -> Result<Object, String> {
+            assert_eq!(compositor.name, "compositor");
+            self.create(format!("target {}", window.0))
+        }
+
+        fn quit(&mut self) {
+            self.log.borrow_mut().push("quit".to_owned());
+        }
+    }
+
+    fn model() -> (AppModel<FakeBackend>, Log) {
+        let backend = FakeBackend::default();
+        let log = backend.log.clone();
+        (AppModel::new(backend), log)
+    }
+
+    fn take(log: &Log) -> Vec<String> {
+        log.borrow_mut().drain(..).collect()
+    }
+
+    #[test]
+    fn shares_queue_and_compositor_between_windows() {
+        let (mut app, log) = model();
+        assert!(app.window_opened(WindowId(1)));
+        assert!(app.window_opened(WindowId(2)));
+        assert!(!app.window_opened(WindowId(1)));
+        assert_eq!(
+            app.window_ids().collect::<Vec<_>>(),
+            vec![WindowId(1), WindowId(2)]
+        );
+
+        assert_eq!(
+            app.attach_composition(WindowId(2)).unwrap().name,
+            "target 2"
+        );
+        assert_eq!(
+            app.attach_composition(WindowId(1)).unwrap().name,
+            "target 1"
+        );
+        assert_eq!(
+            take(&log),
+            vec![
+                "create queue",
+                "create compositor",
+                "create target 2",
+                "create target 1"
+            ]
+        );
+        assert_eq!(app.target(WindowId(1)).unwrap().name, "target 1");
+        assert_eq!(app.compositor().unwrap().name, "compositor");
+    }
+
+    #[test]
+    fn rejects_unknown_and_attached_windows() {
+        let (mut app, log) = model();
+        assert_eq!(
+            app.attach_composition(WindowId(1)).err(),
+            Some(AppError::UnknownWindow(WindowId(1)))
+        );
+        // Nothing is created for a window that can't use it.
+        assert!(take(&log).is_empty());
+
+        app.window_opened(WindowId(1));
+        app.attach_composition(WindowId(1)).unwrap();
+        assert_eq!(
+            app.attach_composition(WindowId(1)).err(),
+            Some(AppError::AlreadyAttached(WindowId(1)))
+        );
+        assert_eq!(take(&log).len(), 3);
+    }
+
+    #[test]
+    fn closing_a_window_releases_its_target() {
+        let (mut app, log) = model();
+        app.window_opened(WindowId(1));
+        app.window_opened(WindowId(2));
+        app.attach_composition(WindowId(1)).unwrap();
+        take(&log);
+
+        assert!(!app.window_closed(WindowId(1)));
+        assert_eq!(take(&log), vec!["drop target 1"]);
+        assert!(!app.is_open(WindowId(1)));
+        assert!(app.target(WindowId(1)).is_none());
+        // The shared objects outlive the windows that used them.
+        assert!(app.compositor().is_some());
+        assert!(!app.window_closed(WindowId(1)));
+
+        assert!(app.window_closed(WindowId(2)));
+        assert_eq!(take(&log), vec!["quit"]);
+        assert_eq!(app.window_count(), 0);
+    }
+
+    #[test]
+    fn explicit_exit_policy_keeps_running() {
+        let (mut app, log) = model();
+        app.set_exit_policy(ExitPolicy::Explicit);
+        app.window_opened(WindowId(1));
+        assert!(!app.window_closed(WindowId(1)));
+        assert!(take(&log).is_empty());
+    }
+
+    #[test]
+    fn releases_in_dependency_order() {
+        let (mut app, log) = model();
+        app.window_opened(WindowId(1));
+        app.window_opened(WindowId(2));
+        app.attach_composition(WindowId(1)).unwrap();
+        app.attach_composition(WindowId(2)).unwrap();
+        take(&log);
+
+        drop(app);
+        assert_eq!(
+            take(&log),
+            vec![
+                "drop target 1",
+                "drop target 2",
+                "drop compositor",
+                "drop queue"
+            ]
+        );
+    }
+
+    #[test]
+    fn backend_failures_can_be_retried() {
+        let (mut app, log) = model();
+        app.window_opened(WindowId(1));
+
+        app.backend_mut().fail = Some("compositor");
+        assert_eq!(
+            app.attach_composition(WindowId(1)).err(),
+            Some(AppError::Backend("can't create compositor".to_owned()))
+        );
+        assert!(app.compositor().is_none());
+        assert!(app.target(WindowId(1)).is_none());
+
+        app.backend_mut().fail = Some("target");
+        assert_eq!(
+            app.attach_composition(WindowId(1)).err(),
+            Some(AppError::Backend("can't create target 1".to_owned()))
+        );
+        // The queue and compositor are kept and not created again.
+        app.backend_mut().fail = None;
+        app.attach_composition(WindowId(1)).unwrap();
+        assert_eq!(
+            take(&log),
+            vec!["create queue", "create compositor", "create target 1"]
+        );
+    }
+
+    #[test]
+    fn queue_can_be_created_alone() {
+        let (mut app, log) = model();
+        app.queue().unwrap();
+        app.queue().unwrap();
+        assert_eq!(take(&log), vec!["create queue"]);
+        assert!(app.compositor().is_none());
+    }
+}
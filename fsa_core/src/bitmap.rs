@@ -0,0 +1,164 @@
+/// Size in bytes of one BGRA pixel.
+pub const BYTES_PER_PIXEL: usize = 4;
+
+/// A straight (non-premultiplied) RGBA color.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rgba {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Rgba {
+    #[inline]
+    pub fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Rgba { r, g, b, a }
+    }
+
+    /// Returns the color as premultiplied BGRA bytes.
+    #[inline]
+    pub fn to_premultiplied_bgra(self) -> [u8; 4] {
+        [
+            premultiply(self.b, self.a),
+            premultiply(self.g, self.a),
+            premultiply(self.r, self.a),
+            self.a,
+        ]
+    }
+}
+
+/// Multiplies a color channel by an alpha value, rounding to the nearest integer.
+#[inline]
+pub fn premultiply(channel: u8, alpha: u8) -> u8 {
+    let v = channel as u32 * alpha as u32 + 128;
+    ((v + (v >> 8)) >> 8) as u8
+}
+
+/// A tightly packed buffer of premultiplied BGRA pixels.
+///
+/// This is the layout composition drawing surfaces use for
+/// `DirectXPixelFormat::B8G8R8A8UIntNormalized` with `DirectXAlphaMode::Premultiplied`, so a
+/// `Bitmap` can be uploaded as-is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bitmap {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl Bitmap {
+    /// Creates a fully transparent bitmap.
+    pub fn new(width: u32, height: u32) -> Self {
+        Bitmap {
+            width,
+            height,
+            data: vec![0; width as usize * height as usize * BYTES_PER_PIXEL],
+        }
+    }
+
+    /// Creates a bitmap from straight RGBA bytes, premultiplying and swizzling them to BGRA.
+    ///
+    /// Returns `None` if `rgba` doesn't hold exactly `width * height` pixels.
+    pub fn from_rgba(rgba: &[u8], width: u32, height: u32) -> Option<Self> {
+        if rgba.len() != width as usize * height as usize * BYTES_PER_PIXEL {
+            return None;
+        }
+        let mut data = Vec::with_capacity(rgba.len());
+        for px in rgba.chunks(BYTES_PER_PIXEL) {
+            data.extend_from_slice(&Rgba::new(px[0], px[1], px[2], px[3]).to_premultiplied_bgra());
+        }
+        Some(Bitmap {
+            width,
+            height,
+            data,
+        })
+    }
+
+    /// Number of bytes in one row of pixels.
+    #[inline]
+    pub fn stride(&self) -> usize {
+        self.width as usize * BYTES_PER_PIXEL
+    }
+
+    /// Returns the premultiplied BGRA bytes of the pixel at `(x, y)`.
+    #[inline]
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = self.offset(x, y);
+        [
+            self.data[i],
+            self.data[i + 1],
+            self.data[i + 2],
+            self.data[i + 3],
+        ]
+    }
+
+    /// Composites a premultiplied BGRA pixel over the pixel at `(x, y)`.
+    ///
+    /// Coordinates outside of the bitmap are ignored.
+    pub fn blend_pixel(&mut self, x: i32, y: i32, bgra: [u8; 4]) {
+        if x < 0 || y < 0 || x as u32 >= self.width || y as u32 >= self.height {
+            return;
+        }
+        let i = self.offset(x as u32, y as u32);
+        let inv = 255 - bgra[3];
+        for (c, &value) in bgra.iter().enumerate() {
+            self.data[i + c] = value.saturating_add(premultiply(self.data[i + c], inv));
+        }
+    }
+
+    #[inline]
+    fn offset(&self, x: u32, y: u32) -> usize {
+        y as usize * self.stride() + x as usize * BYTES_PER_PIXEL
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn premultiply_rounds_to_nearest() {
+        for channel in 0..=255u32 {
+            for alpha in 0..=255u32 {
+                assert_eq!(
+                    premultiply(channel as u8, alpha as u8) as u32,
+                    (channel * alpha + 127) / 255,
+                    "{} * {}",
+                    channel,
+                    alpha
+                );
+            }
+        }
+        assert_eq!(premultiply(254, 127), 127);
+    }
+
+    #[test]
+    fn converts_rgba_to_premultiplied_bgra() {
+        assert_eq!(
+            Rgba::new(255, 128, 0, 128).to_premultiplied_bgra(),
+            [0, 64, 128, 128]
+        );
+        let bitmap = Bitmap::from_rgba(&[10, 20, 30, 255, 200, 100, 50, 0], 2, 1).unwrap();
+        assert_eq!(bitmap.stride(), 8);
+        assert_eq!(bitmap.pixel(0, 0), [30, 20, 10, 255]);
+        assert_eq!(bitmap.pixel(1, 0), [0, 0, 0, 0]);
+        assert_eq!(Bitmap::from_rgba(&[0; 12], 2, 2), None);
+    }
+
+    #[test]
+    fn blends_over_pixels() {
+        let mut bitmap = Bitmap::new(2, 2);
+        assert_eq!(bitmap.data.len(), 16);
+        bitmap.blend_pixel(1, 1, [0, 0, 255, 255]);
+        assert_eq!(bitmap.pixel(1, 1), [0, 0, 255, 255]);
+        // Half-transparent blue over opaque red.
+        bitmap.blend_pixel(1, 1, [128, 0, 0, 128]);
+        assert_eq!(bitmap.pixel(1, 1), [128, 0, 127, 255]);
+        // Outside of the bitmap.
+        bitmap.blend_pixel(-1, 0, [255; 4]);
+        bitmap.blend_pixel(0, 2, [255; 4]);
+        assert_eq!(bitmap.pixel(0, 0), [0; 4]);
+        assert_eq!(bitmap.pixel(0, 1), [0; 4]);
+    }
+}
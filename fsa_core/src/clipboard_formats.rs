@@ -0,0 +1,643 @@
+use image;
+use std::{fmt, str};
+
+use le_bytes::{read_u16, read_u32, write_u16, write_u32};
+
+/// Size in bytes of a `BITMAPINFOHEADER`.
+const BITMAPINFOHEADER_SIZE: usize = 40;
+/// Size in bytes of a `BITMAPV2INFOHEADER`, the first header to include the color masks.
+const BITMAPV2INFOHEADER_SIZE: usize = 52;
+/// Size in bytes of a `BITMAPV5HEADER`.
+const BITMAPV5HEADER_SIZE: usize = 124;
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+/// `LCS_sRGB`: the pixels are in the sRGB color space.
+const LCS_SRGB: u32 = 0x7352_4742;
+/// `LCS_GM_IMAGES`: perceptual rendering intent.
+const LCS_GM_IMAGES: u32 = 4;
+const PIXEL_SIZE: usize = 4;
+
+const HTML_PREFIX: &str = "<html>\r\n<body>\r\n<!--StartFragment-->";
+const HTML_SUFFIX: &str = "<!--EndFragment-->\r\n</body>\r\n</html>";
+
+/// An image on the clipboard, as straight RGBA rows from top to bottom.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClipboardImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+/// The contents of an `HTML Format` clipboard entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HtmlFragment {
+    /// The whole document, including the context around the fragment.
+    pub html: String,
+    /// The part of the document that was copied.
+    pub fragment: String,
+    /// The document the fragment was copied from.
+    pub source_url: Option<String>,
+}
+
+/// An error produced while decoding an `HTML Format` clipboard entry.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HtmlFormatError {
+    /// A header field that is required is missing or isn't a number.
+    MissingField(&'static str),
+    /// An offset from the header points outside of the data, or splits a character.
+    OffsetOutOfBounds,
+}
+
+impl fmt::Display for HtmlFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HtmlFormatError::MissingField(field) => {
+                write!(f, "the `HTML Format` header has no valid `{}` field", field)
+            }
+            HtmlFormatError::OffsetOutOfBounds => {
+                write!(f, "an `HTML Format` offset lies outside of the data")
+            }
+        }
+    }
+}
+
+fn html_header(
+    start_html: usize,
+    end_html: usize,
+    start_fragment: usize,
+    end_fragment: usize,
+    source_url: Option<&str>,
+) -> String {
+    // The offsets are zero-padded so the header has the same length whatever their values.
+    let mut header = format!(
+        "Version:0.9\r\nStartHTML:{:010}\r\nEndHTML:{:010}\r\nStartFragment:{:010}\r\n\
+         EndFragment:{:010}\r\n",
+        start_html, end_html, start_fragment, end_fragment
+    );
+    if let Some(url) = source_url {
+        header.push_str("SourceURL:");
+        header.push_str(url);
+        header.push_str("\r\n");
+    }
+    header
+}
+
+/// Wraps an HTML fragment in the `HTML Format` clipboard format: a header of byte offsets,
+/// followed by a document that marks the fragment with `StartFragment`/`EndFragment` comments.
+///
+/// The result is UTF-8 and null-terminated.
+pub fn encode_html(fragment: &str, source_url: Option<&str>) -> Vec<u8> {
+    let start_html = html_header(0, 0, 0, 0, source_url).len();
+    let start_fragment = start_html + HTML_PREFIX.len();
+    let end_fragment = start_fragment + fragment.len();
+    let end_html = end_fragment + HTML_SUFFIX.len();
+    let header = html_header(
+        start_html,
+        end_html,
+        start_fragment,
+        end_fragment,
+        source_url,
+    );
+
+    let mut data = Vec::with_capacity(end_html + 1);
+    data.extend_from_slice(header.as_bytes());
+    data.extend_from_slice(HTML_PREFIX.as_bytes());
+    data.extend_from_slice(fragment.as_bytes());
+    data.extend_from_slice(HTML_SUFFIX.as_bytes());
+    data.push(0);
+    data
+}
+
+/// Reads an offset from the header. Negative values mean the field isn't used.
+fn html_offset(value: &str) -> Option<usize> {
+    match value.trim().parse::<i64>() {
+        Ok(value) if value >= 0 => Some(value as usize),
+        _ => None,
+    }
+}
+
+fn html_slice(data: &[u8], start: usize, end: usize) -> Result<String, HtmlFormatError> {
+    if start > end || end > data.len() {
+        return Err(HtmlFormatError::OffsetOutOfBounds);
+    }
+    str::from_utf8(&data[start..end])
+        .map(|text| text.to_owned())
+        .map_err(|_| HtmlFormatError::OffsetOutOfBounds)
+}
+
+/// Decodes an `HTML Format` clipboard entry.
+///
+/// `StartHTML` and `EndHTML` are optional; without them the document is taken to run from the end
+/// of the header to the end of the data.
+pub fn decode_html(data: &[u8]) -> Result<HtmlFragment, HtmlFormatError> {
+    let data = match data.iter().position(|&byte| byte == 0) {
+        Some(end) => &data[..end],
+        None => data,
+    };
+
+    let (mut start_html, mut end_html) = (None, None);
+    let (mut start_fragment, mut end_fragment) = (None, None);
+    let mut source_url = None;
+    let mut header_end = 0;
+    for line in data.split(|&byte| byte == b'\n') {
+        let text = match str::from_utf8(line) {
+            Ok(text) => text.trim_end_matches('\r'),
+            Err(_) => break,
+        };
+        let colon = match text.find(':') {
+            Some(colon) if !text.starts_with('<') => colon,
+            _ => break,
+        };
+        let (key, value) = (&text[..colon], &text[colon + 1..]);
+        match key {
+            "StartHTML" => start_html = html_offset(value),
+            "EndHTML" => end_html = html_offset(value),
+            "StartFragment" => start_fragment = html_offset(value),
+            "EndFragment" => end_fragment = html_offset(value),
+            "SourceURL" => source_url = Some(value.to_owned()),
+            _ => (),
+        }
+        header_end += line.len() + 1;
+        if start_html.map_or(false, |start| header_end >= start) {
+            break;
+        }
+    }
+
+    let start_fragment = start_fragment.ok_or(HtmlFormatError::MissingField("StartFragment"))?;
+    let end_fragment = end_fragment.ok_or(HtmlFormatError::MissingField("EndFragment"))?;
+    let fragment = html_slice(data, start_fragment, end_fragment)?;
+    let html = html_slice(
+        data,
+        start_html.unwrap_or(header_end.min(data.len())),
+        end_html.unwrap_or(data.len()),
+    )?;
+    Ok(HtmlFragment {
+        html,
+        fragment,
+        source_url,
+    })
+}
+
+/// An error produced while encoding or decoding a DIB for the clipboard.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DibError {
+    /// The data ends before the header or the pixels do.
+    Truncated,
+    /// The header is of an unknown kind, e.g. a `BITMAPCOREHEADER`.
+    UnsupportedHeader { size: u32 },
+    /// The pixel format can't be decoded.
+    UnsupportedFormat { bit_count: u16, compression: u32 },
+    /// The bitmap has no pixels, or is too large to address.
+    InvalidDimensions { width: i32, height: i32 },
+    /// The image to encode doesn't hold exactly `width * height` RGBA pixels.
+    PixelCountMismatch { width: u32, height: u32, len: usize },
+}
+
+impl fmt::Display for DibError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DibError::Truncated => write!(f, "the bitmap data is truncated"),
+            DibError::UnsupportedHeader { size } => {
+                write!(f, "unsupported bitmap header of {} bytes", size)
+            }
+            DibError::UnsupportedFormat {
+                bit_count,
+                compression,
+            } => write!(
+                f,
+                "unsupported bitmap format: {} bpp with compression {}",
+                bit_count, compression
+            ),
+            DibError::InvalidDimensions { width, height } => {
+                write!(f, "invalid bitmap dimensions ({}x{})", width, height)
+            }
+            DibError::PixelCountMismatch { width, height, len } => write!(
+                f,
+                "{} bytes of RGBA don't make a {}x{} image",
+                len, width, height
+            ),
+        }
+    }
+}
+
+/// Encodes an image as a `CF_DIBV5` bitmap: a `BITMAPV5HEADER` for a 32 bpp sRGB image with an
+/// alpha mask, followed by bottom-up BGRA rows.
+///
+/// Fails if the image is empty, too large for the header's fields, or its pixels don't match its
+/// dimensions.
+pub fn encode_dibv5(image: &ClipboardImage) -> Result<Vec<u8>, DibError> {
+    let invalid = DibError::InvalidDimensions {
+        width: image.width as i32,
+        height: image.height as i32,
+    };
+    // The header stores the dimensions as `LONG`s and the image size as a `DWORD`.
+    let max = i32::max_value() as u32;
+    if image.width == 0 || image.height == 0 || image.width > max || image.height > max {
+        return Err(invalid);
+    }
+    let (width, height) = (image.width as usize, image.height as usize);
+    let stride = width * PIXEL_SIZE;
+    let size = match stride.checked_mul(height) {
+        Some(size) if size as u64 <= u32::max_value() as u64 => size,
+        _ => return Err(invalid),
+    };
+    if image.rgba.len() != size {
+        return Err(DibError::PixelCountMismatch {
+            width: image.width,
+            height: image.height,
+            len: image.rgba.len(),
+        });
+    }
+    let mut data = vec![0u8; BITMAPV5HEADER_SIZE + size];
+
+    write_u32(&mut data, 0, BITMAPV5HEADER_SIZE as u32);
+    write_u32(&mut data, 4, image.width);
+    // A positive height makes the rows bottom-up, which more applications understand.
+    write_u32(&mut data, 8, image.height);
+    write_u16(&mut data, 12, 1);
+    write_u16(&mut data, 14, 32);
+    write_u32(&mut data, 16, BI_BITFIELDS);
+    write_u32(&mut data, 20, size as u32);
+    write_u32(&mut data, 40, 0x00FF_0000);
+    write_u32(&mut data, 44, 0x0000_FF00);
+    write_u32(&mut data, 48, 0x0000_00FF);
+    write_u32(&mut data, 52, 0xFF00_0000);
+    write_u32(&mut data, 56, LCS_SRGB);
+    write_u32(&mut data, 108, LCS_GM_IMAGES);
+
+    for (y, row) in image.rgba.chunks(stride).enumerate() {
+        let start = BITMAPV5HEADER_SIZE + (height - 1 - y) * stride;
+        let out = &mut data[start..start + stride];
+        for (src, dst) in row.chunks(PIXEL_SIZE).zip(out.chunks_mut(PIXEL_SIZE)) {
+            dst.copy_from_slice(&[src[2], src[1], src[0], src[3]]);
+        }
+    }
+    Ok(data)
+}
+
+/// Extracts a channel from a pixel with a bit mask, scaled to 8 bits.
+fn masked_channel(pixel: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    let shift = mask.trailing_zeros();
+    let bits = (mask >> shift).count_ones();
+    let value = (pixel & mask) >> shift;
+    if bits >= 8 {
+        (value >> (bits - 8)) as u8
+    } else {
+        let max = (1u32 << bits) - 1;
+        ((value * 255 + max / 2) / max) as u8
+    }
+}
+
+/// Decodes a `CF_DIB` or `CF_DIBV5` bitmap of 24 or 32 bpp.
+///
+/// 32 bpp `BI_RGB` bitmaps often leave the fourth byte zeroed, so they are only treated as
+/// having alpha if some pixel has a nonzero alpha byte.
+pub fn decode_dib(data: &[u8]) -> Result<ClipboardImage, DibError> {
+    let u32_at = |offset| read_u32(data, offset).ok_or(DibError::Truncated);
+    let header_size = u32_at(0)?;
+    if (header_size as usize) < BITMAPINFOHEADER_SIZE {
+        return Err(DibError::UnsupportedHeader { size: header_size });
+    }
+    if data.len() < header_size as usize {
+        return Err(DibError::Truncated);
+    }
+    let width = u32_at(4)? as i32;
+    let height = u32_at(8)? as i32;
+    let bit_count = read_u16(data, 14).ok_or(DibError::Truncated)?;
+    let compression = u32_at(16)?;
+    let colors_used = u32_at(32)? as usize;
+
+    if width <= 0 || height == 0 || height == i32::min_value() {
+        return Err(DibError::InvalidDimensions { width, height });
+    }
+    let top_down = height < 0;
+    let (width, height) = (width as usize, height.abs() as usize);
+
+    let mut offset = header_size as usize;
+    let masks = match (bit_count, compression) {
+        (24, BI_RGB) | (32, BI_RGB) => None,
+        (32, BI_BITFIELDS) => {
+            if header_size as usize == BITMAPINFOHEADER_SIZE {
+                // A plain `BITMAPINFOHEADER` is followed by the red, green and blue masks.
+                let masks = (u32_at(offset)?, u32_at(offset + 4)?, u32_at(offset + 8)?, 0);
+                offset += 12;
+                Some(masks)
+            } else if (header_size as usize) < BITMAPV2INFOHEADER_SIZE {
+                // Too short for the masks that every header after `BITMAPINFOHEADER` carries.
+                return Err(DibError::UnsupportedHeader { size: header_size });
+            } else {
+                let alpha = if header_size >= 56 { u32_at(52)? } else { 0 };
+                Some((u32_at(40)?, u32_at(44)?, u32_at(48)?, alpha))
+            }
+        }
+        _ => {
+            return Err(DibError::UnsupportedFormat {
+                bit_count,
+                compression,
+            })
+        }
+    };
+    // True color bitmaps can still carry a palette for display on palette devices.
+    offset = match colors_used.checked_mul(4).and_then(|size| size.checked_add(offset)) {
+        Some(end) if end <= data.len() => end,
+        _ => return Err(DibError::Truncated),
+    };
+
+    let bytes_per_pixel = bit_count as usize / 8;
+    let stride = match width.checked_mul(bit_count as usize) {
+        Some(bits) => (bits + 31) / 32 * 4,
+        None => {
+            return Err(DibError::InvalidDimensions {
+                width: width as i32,
+                height: height as i32,
+            })
+        }
+    };
+    match stride.checked_mul(height).and_then(|size| size.checked_add(offset)) {
+        Some(end) if end <= data.len() => (),
+        Some(_) => return Err(DibError::Truncated),
+        None => {
+            return Err(DibError::InvalidDimensions {
+                width: width as i32,
+                height: height as i32,
+            })
+        }
+    }
+
+    let mut rgba = Vec::with_capacity(width * height * PIXEL_SIZE);
+    for y in 0..height {
+        let row = if top_down { y } else { height - 1 - y };
+        let row = &data[offset + row * stride..];
+        for x in 0..width {
+            let px = &row[x * bytes_per_pixel..];
+            match masks {
+                Some((red, green, blue, alpha)) => {
+                    let pixel = read_u32(px, 0).ok_or(DibError::Truncated)?;
+                    rgba.extend_from_slice(&[
+                        masked_channel(pixel, red),
+                        masked_channel(pixel, green),
+                        masked_channel(pixel, blue),
+                        if alpha == 0 { 0xFF } else { masked_channel(pixel, alpha) },
+                    ]);
+                }
+                None => {
+                    let alpha = if bytes_per_pixel == 4 { px[3] } else { 0xFF };
+                    rgba.extend_from_slice(&[px[2], px[1], px[0], alpha]);
+                }
+            }
+        }
+    }
+
+    if masks.is_none() && bytes_per_pixel == 4 && rgba.chunks(PIXEL_SIZE).all(|px| px[3] == 0) {
+        for px in rgba.chunks_mut(PIXEL_SIZE) {
+            px[3] = 0xFF;
+        }
+    }
+
+    Ok(ClipboardImage {
+        width: width as u32,
+        height: height as u32,
+        rgba,
+    })
+}
+
+/// Encodes an image for the registered `PNG` clipboard format.
+pub fn encode_png(image: &ClipboardImage) -> image::ImageResult<Vec<u8>> {
+    let mut data = Vec::new();
+    image::png::PNGEncoder::new(&mut data).encode(
+        &image.rgba,
+        image.width,
+        image.height,
+        image::ColorType::RGBA(8),
+    )?;
+    Ok(data)
+}
+
+/// Decodes the registered `PNG` clipboard format.
+pub fn decode_png(data: &[u8]) -> image::ImageResult<ClipboardImage> {
+    let rgba = image::load_from_memory_with_format(data, image::ImageFormat::PNG)?.to_rgba();
+    let (width, height) = rgba.dimensions();
+    Ok(ClipboardImage {
+        width,
+        height,
+        rgba: rgba.into_raw(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads an offset field of an `HTML Format` header.
+    fn header_field(data: &[u8], key: &str) -> usize {
+        let text = str::from_utf8(data).unwrap();
+        let line = text
+            .lines()
+            .find(|line| line.starts_with(key) && line[key.len()..].starts_with(':'))
+            .unwrap();
+        line[key.len() + 1..].trim().parse().unwrap()
+    }
+
+    /// A 4x3 image whose pixels all differ, with varying alpha.
+    fn gradient(width: u32, height: u32) -> ClipboardImage {
+        let mut rgba = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                rgba.extend_from_slice(&[x as u8 * 40, y as u8 * 70, 200, 255 - x as u8 * 10]);
+            }
+        }
+        ClipboardImage {
+            width,
+            height,
+            rgba,
+        }
+    }
+
+    #[test]
+    fn html_header_offsets() {
+        for &url in &[None, Some("https://example.com/page")] {
+            let fragment = "<b>caf\u{e9}</b>";
+            let data = encode_html(fragment, url);
+            assert_eq!(data.last(), Some(&0));
+            let data = &data[..data.len() - 1];
+
+            let start_html = header_field(data, "StartHTML");
+            let end_html = header_field(data, "EndHTML");
+            let start_fragment = header_field(data, "StartFragment");
+            let end_fragment = header_field(data, "EndFragment");
+            assert!(str::from_utf8(&data[..start_html])
+                .unwrap()
+                .starts_with("Version:0.9\r\n"));
+            assert_eq!(end_html, data.len());
+            assert_eq!(&data[start_html..start_fragment], HTML_PREFIX.as_bytes());
+            assert_eq!(&data[start_fragment..end_fragment], fragment.as_bytes());
+            assert_eq!(&data[end_fragment..end_html], HTML_SUFFIX.as_bytes());
+
+            let decoded = decode_html(data).unwrap();
+            assert_eq!(decoded.fragment, fragment);
+            assert_eq!(decoded.html, [HTML_PREFIX, fragment, HTML_SUFFIX].concat());
+            assert_eq!(decoded.source_url.as_ref().map(|url| &url[..]), url);
+        }
+    }
+
+    #[test]
+    fn decodes_html_without_document_offsets() {
+        let data = [
+            "Version:0.9\r\nStartFragment:0000000066\r\nEndFragment:0000000071\r\n",
+            "<p>hello</p>",
+        ]
+        .concat();
+        let decoded = decode_html(data.as_bytes()).unwrap();
+        assert_eq!(decoded.fragment, "hello");
+        assert_eq!(decoded.html, "<p>hello</p>");
+        assert_eq!(decoded.source_url, None);
+    }
+
+    #[test]
+    fn rejects_malformed_html() {
+        assert_eq!(
+            decode_html(b"Version:0.9\r\nEndFragment:10\r\n"),
+            Err(HtmlFormatError::MissingField("StartFragment"))
+        );
+        assert_eq!(
+            decode_html(b"Version:0.9\r\nStartFragment:-1\r\nEndFragment:10\r\n"),
+            Err(HtmlFormatError::MissingField("StartFragment"))
+        );
+        assert_eq!(
+            decode_html(b"Version:0.9\r\nStartFragment:20\r\nEndFragment:9999\r\n"),
+            Err(HtmlFormatError::OffsetOutOfBounds)
+        );
+        assert_eq!(
+            decode_html(b"Version:0.9\r\nStartFragment:20\r\nEndFragment:10\r\n"),
+            Err(HtmlFormatError::OffsetOutOfBounds)
+        );
+    }
+
+    #[test]
+    fn dibv5_round_trip() {
+        let image = gradient(4, 3);
+        let data = encode_dibv5(&image).unwrap();
+        assert_eq!(data.len(), BITMAPV5HEADER_SIZE + 4 * 3 * PIXEL_SIZE);
+        assert_eq!(read_u32(&data, 0), Some(BITMAPV5HEADER_SIZE as u32));
+        // Bottom-up: the first row of the data is the last row of the image, in BGRA.
+        assert_eq!(
+            &data[BITMAPV5HEADER_SIZE..BITMAPV5HEADER_SIZE + 4],
+            &[200, 140, 0, 255]
+        );
+        assert_eq!(decode_dib(&data).unwrap(), image);
+    }
+
+    #[test]
+    fn rejects_invalid_images_to_encode() {
+        let mut image = gradient(4, 3);
+        image.width = 0;
+        image.rgba.clear();
+        assert_eq!(
+            encode_dibv5(&image),
+            Err(DibError::InvalidDimensions {
+                width: 0,
+                height: 3
+            })
+        );
+
+        let mut image = gradient(4, 3);
+        image.rgba.pop();
+        assert_eq!(
+            encode_dibv5(&image),
+            Err(DibError::PixelCountMismatch {
+                width: 4,
+                height: 3,
+                len: 47
+            })
+        );
+
+        let image = ClipboardImage {
+            width: 0x8000_0000,
+            height: 1,
+            rgba: Vec::new(),
+        };
+        assert!(encode_dibv5(&image).is_err());
+        let image = ClipboardImage {
+            width: 0x1_0000,
+            height: 0x1_0000,
+            rgba: Vec::new(),
+        };
+        assert!(encode_dibv5(&image).is_err());
+    }
+
+    /// A `BITMAPINFOHEADER` for an uncompressed bitmap.
+    fn info_header(width: i32, height: i32, bit_count: u16, colors_used: u32) -> Vec<u8> {
+        let mut data = vec![0u8; BITMAPINFOHEADER_SIZE];
+        write_u32(&mut data, 0, BITMAPINFOHEADER_SIZE as u32);
+        write_u32(&mut data, 4, width as u32);
+        write_u32(&mut data, 8, height as u32);
+        write_u16(&mut data, 12, 1);
+        write_u16(&mut data, 14, bit_count);
+        write_u32(&mut data, 16, BI_RGB);
+        write_u32(&mut data, 32, colors_used);
+        data
+    }
+
+    #[test]
+    fn decodes_top_down_24_bit() {
+        let mut data = info_header(2, -2, 24, 1);
+        // The palette entry, then rows padded to 8 bytes.
+        data.extend_from_slice(&[9, 9, 9, 0]);
+        data.extend_from_slice(&[1, 2, 3, 4, 5, 6, 0, 0]);
+        data.extend_from_slice(&[7, 8, 9, 10, 11, 12, 0, 0]);
+        let image = decode_dib(&data).unwrap();
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(
+            image.rgba,
+            vec![3, 2, 1, 255, 6, 5, 4, 255, 9, 8, 7, 255, 12, 11, 10, 255]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_dibs() {
+        assert_eq!(decode_dib(&[40, 0]), Err(DibError::Truncated));
+        assert_eq!(
+            decode_dib(&info_header(2, 2, 32, 0)[..20]),
+            Err(DibError::Truncated)
+        );
+        assert_eq!(
+            decode_dib(&info_header(2, 2, 32, 0)),
+            Err(DibError::Truncated)
+        );
+        assert_eq!(
+            decode_dib(&info_header(0, 2, 32, 0)),
+            Err(DibError::InvalidDimensions {
+                width: 0,
+                height: 2
+            })
+        );
+        assert_eq!(
+            decode_dib(&info_header(1, 1, 8, 0)),
+            Err(DibError::UnsupportedFormat {
+                bit_count: 8,
+                compression: BI_RGB
+            })
+        );
+
+        // A palette size that overflows, or runs past the data.
+        let mut data = info_header(1, 1, 32, u32::max_value());
+        data.extend_from_slice(&[0; 4]);
+        assert_eq!(decode_dib(&data), Err(DibError::Truncated));
+        let mut data = info_header(1, 1, 32, 2);
+        data.extend_from_slice(&[0; 8]);
+        assert_eq!(decode_dib(&data), Err(DibError::Truncated));
+
+        // Bitfields in a header too short to hold them.
+        let mut data = info_header(1, 1, 32, 0);
+        write_u32(&mut data, 0, 44);
+        write_u32(&mut data, 16, BI_BITFIELDS);
+        data.extend_from_slice(&[0; 8]);
+        assert_eq!(
+            decode_dib(&data),
+            Err(DibError::UnsupportedHeader { size: 44 })
+        );
+    }
+}
@@ -0,0 +1,405 @@
+use icon::{validate_rgba, BadIcon, IconImage};
+use std::fmt;
+
+/// The hit-test code of the client area (`HTCLIENT`).
+const HT_CLIENT: i16 = 1;
+
+/// Describes the appearance of the mouse cursor.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CursorIcon {
+    /// The platform-dependent default cursor.
+    Default,
+    /// A simple crosshair.
+    Crosshair,
+    /// A hand (often used to indicate links in web browsers).
+    Hand,
+    /// Self explanatory.
+    Arrow,
+    /// Indicates something is to be moved.
+    Move,
+    /// Indicates text that may be selected or edited.
+    Text,
+    /// Program busy indicator.
+    Wait,
+    /// Help indicator (often rendered as a "?")
+    Help,
+    /// Progress indicator. Shows that processing is being done. But in contrast
+    /// with "Wait" the user may still interact with the program. Often rendered
+    /// as a spinning beach ball, or an arrow with a watch or hourglass.
+    Progress,
+
+    /// Cursor showing that something cannot be done.
+    NotAllowed,
+    ContextMenu,
+    Cell,
+    VerticalText,
+    Alias,
+    Copy,
+    NoDrop,
+    Grab,
+    Grabbing,
+    AllScroll,
+    ZoomIn,
+    ZoomOut,
+
+    /// Indicate that some edge is to be moved. For example, the 'SeResize' cursor
+    /// is used when the movement starts from the south-east corner of the box.
+    EResize,
+    NResize,
+    NeResize,
+    NwResize,
+    SResize,
+    SeResize,
+    SwResize,
+    WResize,
+    EwResize,
+    NsResize,
+    NeswResize,
+    NwseResize,
+    ColResize,
+    RowResize,
+}
+
+impl Default for CursorIcon {
+    #[inline]
+    fn default() -> Self {
+        CursorIcon::Default
+    }
+}
+
+/// The predefined cursors of Windows, identified by their `IDC_*` resource.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SystemCursor {
+    Arrow,
+    IBeam,
+    Wait,
+    Cross,
+    UpArrow,
+    SizeNwse,
+    SizeNesw,
+    SizeWe,
+    SizeNs,
+    SizeAll,
+    No,
+    Hand,
+    AppStarting,
+    Help,
+}
+
+impl SystemCursor {
+    /// The resource id to pass to `LoadCursorW` through `MAKEINTRESOURCEW`.
+    pub fn resource_id(self) -> u16 {
+        match self {
+            SystemCursor::Arrow => 32512,
+            SystemCursor::IBeam => 32513,
+            SystemCursor::Wait => 32514,
+            SystemCursor::Cross => 32515,
+            SystemCursor::UpArrow => 32516,
+            SystemCursor::SizeNwse => 32642,
+            SystemCursor::SizeNesw => 32643,
+            SystemCursor::SizeWe => 32644,
+            SystemCursor::SizeNs => 32645,
+            SystemCursor::SizeAll => 32646,
+            SystemCursor::No => 32648,
+            SystemCursor::Hand => 32649,
+            SystemCursor::AppStarting => 32650,
+            SystemCursor::Help => 32651,
+        }
+    }
+}
+
+impl CursorIcon {
+    /// The system cursor that looks closest to the icon. Windows has fewer cursors than there are
+    /// icons, so several icons share a cursor.
+    pub fn system_cursor(self) -> SystemCursor {
+        match self {
+            CursorIcon::Default | CursorIcon::Arrow | CursorIcon::ContextMenu => {
+                SystemCursor::Arrow
+            }
+            CursorIcon::Hand | CursorIcon::Alias | CursorIcon::Copy => SystemCursor::Hand,
+            CursorIcon::Grab | CursorIcon::Grabbing => SystemCursor::SizeAll,
+            CursorIcon::Crosshair | CursorIcon::Cell => SystemCursor::Cross,
+            CursorIcon::Text | CursorIcon::VerticalText => SystemCursor::IBeam,
+            CursorIcon::NotAllowed | CursorIcon::NoDrop => SystemCursor::No,
+            CursorIcon::Move | CursorIcon::AllScroll | CursorIcon::ZoomIn | CursorIcon::ZoomOut => {
+                SystemCursor::SizeAll
+            }
+            CursorIcon::EResize
+            | CursorIcon::WResize
+            | CursorIcon::EwResize
+            | CursorIcon::ColResize => SystemCursor::SizeWe,
+            CursorIcon::NResize
+            | CursorIcon::SResize
+            | CursorIcon::NsResize
+            | CursorIcon::RowResize => SystemCursor::SizeNs,
+            CursorIcon::NeResize | CursorIcon::SwResize | CursorIcon::NeswResize => {
+                SystemCursor::SizeNesw
+            }
+            CursorIcon::NwResize | CursorIcon::SeResize | CursorIcon::NwseResize => {
+                SystemCursor::SizeNwse
+            }
+            CursorIcon::Wait => SystemCursor::Wait,
+            CursorIcon::Progress => SystemCursor::AppStarting,
+            CursorIcon::Help => SystemCursor::Help,
+        }
+    }
+}
+
+/// Cursor settings applied while the cursor is over the window's client area.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CursorState {
+    pub visible: bool,
+    pub grabbed: bool,
+    pub icon: CursorIcon,
+    /// Whether a custom cursor replaces `icon`.
+    pub custom: bool,
+}
+
+impl Default for CursorState {
+    #[inline]
+    fn default() -> Self {
+        CursorState {
+            visible: true,
+            grabbed: false,
+            icon: CursorIcon::Default,
+            custom: false,
+        }
+    }
+}
+
+/// How a window should answer `WM_SETCURSOR`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CursorAction {
+    /// Let `DefWindowProcW` pick the cursor, e.g. a resize arrow over the border.
+    Default,
+    /// Hide the cursor.
+    Hide,
+    /// Show a system cursor.
+    System(SystemCursor),
+    /// Show the window's custom cursor.
+    Custom,
+}
+
+/// Decides how to answer `WM_SETCURSOR`, given the hit-test code in the low word of `lParam`
+/// and whether the cursor is over this window rather than one of its children.
+///
+/// The cursor state only applies to the client area; the frame keeps the cursors Windows gives
+/// it.
+pub fn cursor_action(hit_test: i16, over_window: bool, state: CursorState) -> CursorAction {
+    if !over_window || hit_test != HT_CLIENT {
+        CursorAction::Default
+    } else if !state.visible {
+        CursorAction::Hide
+    } else if state.custom {
+        CursorAction::Custom
+    } else {
+        CursorAction::System(state.icon.system_cursor())
+    }
+}
+
+/// A cursor made from an RGBA image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomCursor {
+    pub(crate) image: IconImage,
+    pub(crate) hotspot_x: u32,
+    pub(crate) hotspot_y: u32,
+}
+
+impl CustomCursor {
+    /// Creates a cursor from straight RGBA pixels. The hotspot is the pixel that points at the
+    /// cursor position.
+    pub fn from_rgba(
+        rgba: Vec<u8>,
+        width: u32,
+        height: u32,
+        hotspot_x: u32,
+        hotspot_y: u32,
+    ) -> Result<Self, BadCursor> {
+        validate_rgba(&rgba, width, height).map_err(BadCursor::Image)?;
+        if hotspot_x >= width || hotspot_y >= height {
+            return Err(BadCursor::HotspotOutOfBounds {
+                hotspot_x,
+                hotspot_y,
+                width,
+                height,
+            });
+        }
+        Ok(CustomCursor {
+            image: IconImage {
+                rgba,
+                width,
+                height,
+            },
+            hotspot_x,
+            hotspot_y,
+        })
+    }
+
+    #[inline]
+    pub fn image(&self) -> &IconImage {
+        &self.image
+    }
+
+    #[inline]
+    pub fn hotspot(&self) -> (u32, u32) {
+        (self.hotspot_x, self.hotspot_y)
+    }
+}
+
+/// An error produced while creating a `CustomCursor`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BadCursor {
+    /// The pixels aren't a valid image.
+    Image(BadIcon),
+    /// The hotspot lies outside of the image.
+    HotspotOutOfBounds {
+        hotspot_x: u32,
+        hotspot_y: u32,
+        width: u32,
+        height: u32,
+    },
+}
+
+impl fmt::Display for BadCursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BadCursor::Image(ref err) => err.fmt(f),
+            BadCursor::HotspotOutOfBounds {
+                hotspot_x,
+                hotspot_y,
+                width,
+                height,
+            } => write!(
+                f,
+                "the cursor hotspot ({}, {}) lies outside of the {}x{} image",
+                hotspot_x, hotspot_y, width, height
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HT_CAPTION: i16 = 2;
+    const HT_BOTTOMRIGHT: i16 = 17;
+    const HT_NOWHERE: i16 = 0;
+
+    #[test]
+    fn resource_ids_match_winuser() {
+        // The `IDC_*` values from winuser.h.
+        let ids = [
+            (SystemCursor::Arrow, 32512),       // IDC_ARROW
+            (SystemCursor::IBeam, 32513),       // IDC_IBEAM
+            (SystemCursor::Wait, 32514),        // IDC_WAIT
+            (SystemCursor::Cross, 32515),       // IDC_CROSS
+            (SystemCursor::UpArrow, 32516),     // IDC_UPARROW
+            (SystemCursor::SizeNwse, 32642),    // IDC_SIZENWSE
+            (SystemCursor::SizeNesw, 32643),    // IDC_SIZENESW
+            (SystemCursor::SizeWe, 32644),      // IDC_SIZEWE
+            (SystemCursor::SizeNs, 32645),      // IDC_SIZENS
+            (SystemCursor::SizeAll, 32646),     // IDC_SIZEALL
+            (SystemCursor::No, 32648),          // IDC_NO
+            (SystemCursor::Hand, 32649),        // IDC_HAND
+            (SystemCursor::AppStarting, 32650), // IDC_APPSTARTING
+            (SystemCursor::Help, 32651),        // IDC_HELP
+        ];
+        for &(cursor, id) in ids.iter() {
+            assert_eq!(cursor.resource_id(), id, "{:?}", cursor);
+        }
+    }
+
+    #[test]
+    fn maps_icons_to_system_cursors() {
+        let cases = [
+            (CursorIcon::Default, SystemCursor::Arrow),
+            (CursorIcon::ContextMenu, SystemCursor::Arrow),
+            (CursorIcon::Hand, SystemCursor::Hand),
+            (CursorIcon::Text, SystemCursor::IBeam),
+            (CursorIcon::VerticalText, SystemCursor::IBeam),
+            (CursorIcon::Crosshair, SystemCursor::Cross),
+            (CursorIcon::NotAllowed, SystemCursor::No),
+            (CursorIcon::Grabbing, SystemCursor::SizeAll),
+            (CursorIcon::EwResize, SystemCursor::SizeWe),
+            (CursorIcon::RowResize, SystemCursor::SizeNs),
+            (CursorIcon::NeResize, SystemCursor::SizeNesw),
+            (CursorIcon::SeResize, SystemCursor::SizeNwse),
+            (CursorIcon::Wait, SystemCursor::Wait),
+            (CursorIcon::Progress, SystemCursor::AppStarting),
+            (CursorIcon::Help, SystemCursor::Help),
+        ];
+        for &(icon, cursor) in cases.iter() {
+            assert_eq!(icon.system_cursor(), cursor, "{:?}", icon);
+        }
+    }
+
+    #[test]
+    fn applies_the_cursor_state_to_the_client_area_only() {
+        let state = CursorState {
+            icon: CursorIcon::Text,
+            ..CursorState::default()
+        };
+        assert_eq!(
+            cursor_action(HT_CLIENT, true, state),
+            CursorAction::System(SystemCursor::IBeam)
+        );
+        assert_eq!(
+            cursor_action(HT_CLIENT, true, CursorState::default()),
+            CursorAction::System(SystemCursor::Arrow)
+        );
+        for &hit_test in &[HT_NOWHERE, HT_CAPTION, HT_BOTTOMRIGHT, -2] {
+            assert_eq!(cursor_action(hit_test, true, state), CursorAction::Default);
+        }
+        // Over a child window, the child picks its own cursor.
+        assert_eq!(
+            cursor_action(HT_CLIENT, false, state),
+            CursorAction::Default
+        );
+    }
+
+    #[test]
+    fn hidden_beats_custom_beats_icon() {
+        let custom = CursorState {
+            custom: true,
+            ..CursorState::default()
+        };
+        assert_eq!(cursor_action(HT_CLIENT, true, custom), CursorAction::Custom);
+        let hidden = CursorState {
+            visible: false,
+            ..custom
+        };
+        assert_eq!(cursor_action(HT_CLIENT, true, hidden), CursorAction::Hide);
+        // The frame still shows its cursors while the cursor is hidden over the client area.
+        assert_eq!(
+            cursor_action(HT_CAPTION, true, hidden),
+            CursorAction::Default
+        );
+    }
+
+    #[test]
+    fn validates_custom_cursors() {
+        let cursor = CustomCursor::from_rgba(vec![0; 2 * 3 * 4], 2, 3, 1, 2).unwrap();
+        assert_eq!(cursor.hotspot(), (1, 2));
+        assert_eq!((cursor.image().width(), cursor.image().height()), (2, 3));
+
+        assert_eq!(
+            CustomCursor::from_rgba(vec![0; 2 * 3 * 4], 2, 3, 2, 0),
+            Err(BadCursor::HotspotOutOfBounds {
+                hotspot_x: 2,
+                hotspot_y: 0,
+                width: 2,
+                height: 3,
+            })
+        );
+        assert_eq!(
+            CustomCursor::from_rgba(vec![0; 8], 2, 3, 0, 0),
+            Err(BadCursor::Image(BadIcon::DimensionsVsPixelCount {
+                width: 2,
+                height: 3,
+                width_x_height: 6,
+                pixel_count: 2,
+            }))
+        );
+    }
+}
@@ -0,0 +1,247 @@
+use std::fmt;
+
+use le_bytes::read_u32;
+
+/// Size in bytes of the `DROPFILES` header that starts a `CF_HDROP` block.
+pub const DROPFILES_SIZE: usize = 20;
+
+/// The file names of a `CF_HDROP` block, in the encoding the source used.
+///
+/// ANSI names are in the code page of the system and need converting before use. Names don't
+/// include their terminating null.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileNames {
+    Ansi(Vec<Vec<u8>>),
+    Wide(Vec<Vec<u16>>),
+}
+
+impl FileNames {
+    pub fn len(&self) -> usize {
+        match *self {
+            FileNames::Ansi(ref names) => names.len(),
+            FileNames::Wide(ref names) => names.len(),
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The contents of a `CF_HDROP` block: a `DROPFILES` header followed by a list of
+/// null-terminated file names, which ends with an empty name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DropFiles {
+    /// Where the files were dropped, in client coordinates of the target window unless
+    /// `non_client` is set, in which case they are screen coordinates.
+    pub point: (i32, i32),
+    pub non_client: bool,
+    pub names: FileNames,
+}
+
+/// An error produced while parsing a `CF_HDROP` block.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DropFilesError {
+    /// The block is too small to hold the `DROPFILES` header.
+    TooShort { len: usize },
+    /// The offset of the file list points into the header or past the end of the block.
+    OffsetOutOfBounds { offset: usize, len: usize },
+    /// A file name runs into the end of the block without a terminating null.
+    Unterminated,
+}
+
+impl fmt::Display for DropFilesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DropFilesError::TooShort { len } => write!(
+                f,
+                "the drop data ({} bytes) is too short for a `DROPFILES` header",
+                len
+            ),
+            DropFilesError::OffsetOutOfBounds { offset, len } => write!(
+                f,
+                "the file list offset ({}) lies outside of the drop data ({} bytes)",
+                offset, len
+            ),
+            DropFilesError::Unterminated => write!(f, "a dropped file name isn't terminated"),
+        }
+    }
+}
+
+/// Splits a list of null-terminated strings that ends with an empty string. The end of the data
+/// also ends the list, as long as it doesn't cut a name short.
+fn split_names<T: Copy + Default + PartialEq>(units: &[T]) -> Result<Vec<Vec<T>>, DropFilesError> {
+    let mut names = Vec::new();
+    let mut rest = units;
+    while !rest.is_empty() {
+        let end = match rest.iter().position(|&unit| unit == T::default()) {
+            Some(end) => end,
+            None => return Err(DropFilesError::Unterminated),
+        };
+        if end == 0 {
+            break;
+        }
+        names.push(rest[..end].to_vec());
+        rest = &rest[end + 1..];
+    }
+    Ok(names)
+}
+
+/// Parses the memory of a `CF_HDROP` global.
+///
+/// The block may be larger than its contents (`GlobalSize` rounds up), so anything after the
+/// empty name that ends the list is ignored.
+pub fn parse_drop_files(bytes: &[u8]) -> Result<DropFiles, DropFilesError> {
+    let too_short = DropFilesError::TooShort { len: bytes.len() };
+    if bytes.len() < DROPFILES_SIZE {
+        return Err(too_short);
+    }
+    let u32_at = |offset| read_u32(bytes, offset).ok_or(too_short);
+    let offset = u32_at(0)? as usize;
+    if offset < DROPFILES_SIZE || offset > bytes.len() {
+        return Err(DropFilesError::OffsetOutOfBounds {
+            offset,
+            len: bytes.len(),
+        });
+    }
+    let point = (u32_at(4)? as i32, u32_at(8)? as i32);
+    let non_client = u32_at(12)? != 0;
+    let wide = u32_at(16)? != 0;
+
+    let list = &bytes[offset..];
+    let names = if wide {
+        // A trailing odd byte can't be part of a name.
+        let units: Vec<u16> = list
+            .chunks(2)
+            .filter(|unit| unit.len() == 2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .collect();
+        FileNames::Wide(split_names(&units)?)
+    } else {
+        FileNames::Ansi(split_names(list)?)
+    };
+
+    Ok(DropFiles {
+        point,
+        non_client,
+        names,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A `CF_HDROP` block as Explorer hands it over: `DROPFILES { pFiles: 20, pt: (10, 20),
+    // fNC: FALSE, fWide: TRUE }`, two names, the empty name, and `GlobalSize` padding.
+    fn wide_block() -> Vec<u8> {
+        [
+            &b"\x14\0\0\0\x0a\0\0\0\x14\0\0\0\0\0\0\0\x01\0\0\0"[..],
+            b"C\0:\0\\\0a\0.\0t\0x\0t\0\0\0",
+            b"C\0:\0\\\0\xe9\0\x3d\xd8\x00\xde\0\0",
+            b"\0\0",
+            b"\xab\xab\xab\xab",
+        ]
+        .concat()
+    }
+
+    // The same list from an ANSI source, dropped on the non-client area.
+    fn ansi_block() -> Vec<u8> {
+        [
+            &b"\x14\0\0\0\xfe\xff\xff\xff\x05\0\0\0\x01\0\0\0\0\0\0\0"[..],
+            b"C:\\a.txt\0C:\\\xe9\0\0",
+        ]
+        .concat()
+    }
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().collect()
+    }
+
+    #[test]
+    fn parses_wide_names() {
+        let files = parse_drop_files(&wide_block()).unwrap();
+        assert_eq!(files.point, (10, 20));
+        assert!(!files.non_client);
+        assert_eq!(
+            files.names,
+            FileNames::Wide(vec![wide("C:\\a.txt"), wide("C:\\\u{e9}\u{1f600}")])
+        );
+        assert_eq!(files.names.len(), 2);
+    }
+
+    #[test]
+    fn parses_ansi_names() {
+        let files = parse_drop_files(&ansi_block()).unwrap();
+        assert_eq!(files.point, (-2, 5));
+        assert!(files.non_client);
+        assert_eq!(
+            files.names,
+            FileNames::Ansi(vec![b"C:\\a.txt".to_vec(), b"C:\\\xe9".to_vec()])
+        );
+    }
+
+    #[test]
+    fn end_of_block_ends_the_list() {
+        let (wide_data, ansi_data) = (wide_block(), ansi_block());
+        // Without the empty name, the end of the data still ends the list.
+        let files = parse_drop_files(&ansi_data[..ansi_data.len() - 1]).unwrap();
+        assert_eq!(files.names.len(), 2);
+        let files = parse_drop_files(&wide_data[..wide_data.len() - 6]).unwrap();
+        assert_eq!(files.names.len(), 2);
+        // A trailing odd byte is ignored.
+        let files = parse_drop_files(&wide_data[..wide_data.len() - 5]).unwrap();
+        assert_eq!(files.names.len(), 2);
+        // An empty list.
+        let files = parse_drop_files(&ansi_data[..DROPFILES_SIZE]).unwrap();
+        assert!(files.names.is_empty());
+    }
+
+    #[test]
+    fn the_list_may_start_after_the_header() {
+        let ansi_data = ansi_block();
+        let mut block = ansi_data[..DROPFILES_SIZE].to_vec();
+        block[0] = 24;
+        block.extend_from_slice(b"\xcc\xcc\xcc\xccx\0\0");
+        let files = parse_drop_files(&block).unwrap();
+        assert_eq!(files.names, FileNames::Ansi(vec![b"x".to_vec()]));
+    }
+
+    #[test]
+    fn rejects_malformed_blocks() {
+        let (wide_data, ansi_data) = (wide_block(), ansi_block());
+        assert_eq!(
+            parse_drop_files(&wide_data[..19]),
+            Err(DropFilesError::TooShort { len: 19 })
+        );
+
+        let mut block = ansi_data.to_vec();
+        block[0] = 16;
+        assert_eq!(
+            parse_drop_files(&block),
+            Err(DropFilesError::OffsetOutOfBounds {
+                offset: 16,
+                len: ansi_data.len(),
+            })
+        );
+        block[0..4].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(
+            parse_drop_files(&block),
+            Err(DropFilesError::OffsetOutOfBounds {
+                offset: 0xffff_ffff,
+                len: ansi_data.len(),
+            })
+        );
+
+        // The data ends in the middle of a name.
+        assert_eq!(
+            parse_drop_files(&ansi_data[..ansi_data.len() - 3]),
+            Err(DropFilesError::Unterminated)
+        );
+        assert_eq!(
+            parse_drop_files(&wide_data[..30]),
+            Err(DropFilesError::Unterminated)
+        );
+    }
+}
@@ -0,0 +1,581 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+#[cfg(test)]
+use std::collections::VecDeque;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+#[cfg(test)]
+use std::sync::Mutex;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+/// How urgently a woken task runs, relative to the other work of its queue. Mirrors
+/// `DispatcherQueuePriority`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Low,
+    Normal,
+    High,
+}
+
+impl Default for Priority {
+    #[inline]
+    fn default() -> Self {
+        Priority::Normal
+    }
+}
+
+/// Identifies a task of a `LocalExecutor`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TaskId(pub u64);
+
+/// Runs woken tasks on the thread of their executor.
+///
+/// Wakers may be called from any thread, so `post` must be too. However it was called, the queue
+/// later calls `LocalExecutor::run_task` for the task on the executor's thread, never from within
+/// `post`.
+pub trait WakeQueue: Send + Sync {
+    /// Asks for `task` to be run. Returns `false` if the queue no longer runs work, in which case
+    /// the task isn't polled again.
+    fn post(&self, task: TaskId, priority: Priority) -> bool;
+}
+
+struct TaskWaker {
+    task: TaskId,
+    priority: Priority,
+    queue: Arc<dyn WakeQueue>,
+    /// Set while a run of the task is posted, so that waking it repeatedly posts it once.
+    scheduled: AtomicBool,
+}
+
+impl TaskWaker {
+    fn wake(&self) {
+        if !self.scheduled.swap(true, Ordering::AcqRel) {
+            self.queue.post(self.task, self.priority);
+        }
+    }
+}
+
+static WAKER_VTABLE: RawWakerVTable =
+    RawWakerVTable::new(clone_waker, wake_waker, wake_waker_by_ref, drop_waker);
+
+unsafe fn clone_waker(data: *const ()) -> RawWaker {
+    let waker = Arc::from_raw(data as *const TaskWaker);
+    let clone = waker.clone();
+    // The reference of `data` stays with the waker being cloned.
+    mem::forget(waker);
+    RawWaker::new(Arc::into_raw(clone) as *const (), &WAKER_VTABLE)
+}
+
+unsafe fn wake_waker(data: *const ()) {
+    let waker = Arc::from_raw(data as *const TaskWaker);
+    waker.wake();
+}
+
+unsafe fn wake_waker_by_ref(data: *const ()) {
+    let waker = &*(data as *const TaskWaker);
+    waker.wake();
+}
+
+unsafe fn drop_waker(data: *const ()) {
+    drop(Arc::from_raw(data as *const TaskWaker));
+}
+
+fn to_waker(waker: &Arc<TaskWaker>) -> Waker {
+    let data = Arc::into_raw(waker.clone()) as *const ();
+    unsafe { Waker::from_raw(RawWaker::new(data, &WAKER_VTABLE)) }
+}
+
+struct Task {
+    future: Pin<Box<dyn Future<Output = ()>>>,
+    waker: Arc<TaskWaker>,
+}
+
+struct JoinState<T> {
+    output: Option<T>,
+    waker: Option<Waker>,
+}
+
+/// Runs a spawned future and hands its output to the `JoinHandle`.
+struct Completion<F: Future> {
+    future: Pin<Box<F>>,
+    join: Rc<RefCell<JoinState<F::Output>>>,
+}
+
+impl<F: Future> Future for Completion<F> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        match self.future.as_mut().poll(cx) {
+            Poll::Ready(output) => {
+                let waker = {
+                    let mut join = self.join.borrow_mut();
+                    join.output = Some(output);
+                    join.waker.take()
+                };
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// The output of a spawned task, available once the task completes.
+///
+/// Dropping the handle detaches the task: it keeps running and its output is dropped. A handle of
+/// a task whose executor went away never completes.
+pub struct JoinHandle<T> {
+    join: Rc<RefCell<JoinState<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Takes the output if the task has completed, without waiting.
+    pub fn try_take(&self) -> Option<T> {
+        self.join.borrow_mut().output.take()
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        let mut join = self.join.borrow_mut();
+        match join.output.take() {
+            Some(output) => Poll::Ready(output),
+            None => {
+                join.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Runs futures that stay on one thread.
+///
+/// Futures are polled only by `run_task`, which the `WakeQueue` calls when they are woken, and by
+/// `block_on`. They need not be `Send`: tasks never leave the executor, only their wakers do.
+pub struct LocalExecutor {
+    queue: Arc<dyn WakeQueue>,
+    tasks: RefCell<HashMap<TaskId, Task>>,
+    next_id: Cell<u64>,
+}
+
+impl LocalExecutor {
+    pub fn new(queue: Arc<dyn WakeQueue>) -> Self {
+        LocalExecutor {
+            queue,
+            tasks: RefCell::new(HashMap::new()),
+            next_id: Cell::new(0),
+        }
+    }
+
+    fn waker(&self, priority: Priority) -> Arc<TaskWaker> {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        Arc::new(TaskWaker {
+            task: TaskId(id),
+            priority,
+            queue: self.queue.clone(),
+            scheduled: AtomicBool::new(false),
+        })
+    }
+
+    /// Starts running `future` as a task. Its first poll is posted to the queue like any wakeup,
+    /// so nothing runs before `spawn_local` returns.
+    pub fn spawn_local<F>(&self, priority: Priority, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let join = Rc::new(RefCell::new(JoinState {
+            output: None,
+            waker: None,
+        }));
+        let waker = self.waker(priority);
+        let task = Task {
+            future: Box::pin(Completion {
+                future: Box::pin(future),
+                join: join.clone(),
+            }),
+            waker: waker.clone(),
+        };
+        self.tasks.borrow_mut().insert(waker.task, task);
+        waker.wake();
+        JoinHandle { join }
+    }
+
+    /// Polls a task that the queue was asked to run. Returns `false` if there is no such task,
+    /// e.g. because it completed after the run was posted.
+    pub fn run_task(&self, id: TaskId) -> bool {
+        // The task leaves the map while it's polled, so that it can spawn and wake tasks,
+        // including itself.
+        let mut task = match self.tasks.borrow_mut().remove(&id) {
+            Some(task) => task,
+            None => return false,
+        };
+        task.waker.scheduled.store(false, Ordering::Release);
+        let waker = to_waker(&task.waker);
+        let mut cx = Context::from_waker(&waker);
+        if task.future.as_mut().poll(&mut cx).is_pending() {
+            self.tasks.borrow_mut().insert(id, task);
+        }
+        true
+    }
+
+    /// The number of tasks that haven't completed.
+    pub fn task_count(&self) -> usize {
+        self.tasks.borrow().len()
+    }
+
+    /// Runs `future` to completion on the current thread, calling `pump` to run the queue while
+    /// the future waits. Spawned tasks keep running in the meantime.
+    ///
+    /// `pump` runs at least one piece of work of the queue, waiting for some if there is none, and
+    /// returns `false` if the queue stopped. `block_on` then gives up and returns `None`.
+    pub fn block_on<F, P>(&self, future: F, mut pump: P) -> Option<F::Output>
+    where
+        F: Future,
+        P: FnMut() -> bool,
+    {
+        // The future isn't in the map, so the runs its waker posts don't poll anything: they only
+        // make `pump` return.
+        let waker = self.waker(Priority::Normal);
+        let task_waker = to_waker(&waker);
+        let mut cx = Context::from_waker(&task_waker);
+        let mut future = Box::pin(future);
+        loop {
+            waker.scheduled.store(false, Ordering::Release);
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return Some(output);
+            }
+            while !waker.scheduled.load(Ordering::Acquire) {
+                if !pump() {
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// A `WakeQueue` that only runs tasks when told to, for driving an executor by hand in tests.
+///
+/// Runs are taken by priority, and in the order they were posted within a priority.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct ManualQueue {
+    posted: Mutex<VecDeque<(TaskId, Priority)>>,
+    closed: AtomicBool,
+}
+
+#[cfg(test)]
+impl ManualQueue {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Takes the next run off the queue.
+    pub fn next(&self) -> Option<TaskId> {
+        let mut posted = self.posted.lock().unwrap();
+        let priority = posted.iter().map(|&(_, priority)| priority).max()?;
+        let index = posted.iter().position(|&(_, p)| p == priority)?;
+        posted.remove(index).map(|(task, _)| task)
+    }
+
+    /// The number of runs waiting.
+    pub fn len(&self) -> usize {
+        self.posted.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Runs one posted task. Returns `false` if none was waiting.
+    pub fn run_one(&self, executor: &LocalExecutor) -> bool {
+        match self.next() {
+            Some(task) => {
+                executor.run_task(task);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Runs tasks until none are waiting, including tasks that are woken meanwhile. Returns how
+    /// many runs were taken.
+    pub fn run_until_idle(&self, executor: &LocalExecutor) -> usize {
+        let mut runs = 0;
+        while self.run_one(executor) {
+            runs += 1;
+        }
+        runs
+    }
+
+    /// Makes later posts fail, as a queue that shut down does.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+impl WakeQueue for ManualQueue {
+    fn post(&self, task: TaskId, priority: Priority) -> bool {
+        if self.closed.load(Ordering::Acquire) {
+            return false;
+        }
+        self.posted.lock().unwrap().push_back((task, priority));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    /// Returns `Pending` once, waking itself first.
+    struct YieldOnce(bool);
+
+    impl Future for YieldOnce {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+            if self.0 {
+                return Poll::Ready(());
+            }
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    /// Completes with `value` once it is set, keeping the waker of its last poll.
+    #[derive(Clone, Default)]
+    struct Slot {
+        value: Rc<Cell<Option<u32>>>,
+        waker: Rc<RefCell<Option<Waker>>>,
+    }
+
+    impl Future for Slot {
+        type Output = u32;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<u32> {
+            match self.value.get() {
+                Some(value) => Poll::Ready(value),
+                None => {
+                    *self.waker.borrow_mut() = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        }
+    }
+
+    /// Records `name` in `log` each time it is polled, and completes on the first poll.
+    struct Record {
+        log: Rc<RefCell<Vec<&'static str>>>,
+        name: &'static str,
+    }
+
+    impl Future for Record {
+        type Output = &'static str;
+
+        fn poll(self: Pin<&mut Self>, _: &mut Context) -> Poll<&'static str> {
+            self.log.borrow_mut().push(self.name);
+            Poll::Ready(self.name)
+        }
+    }
+
+    fn setup() -> (Arc<ManualQueue>, LocalExecutor) {
+        let queue = Arc::new(ManualQueue::new());
+        let executor = LocalExecutor::new(queue.clone());
+        (queue, executor)
+    }
+
+    #[test]
+    fn runs_by_priority_then_in_order() {
+        let (queue, executor) = setup();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let tasks = [
+            (Priority::Low, "low"),
+            (Priority::Normal, "normal 1"),
+            (Priority::High, "high"),
+            (Priority::Normal, "normal 2"),
+        ];
+        let handles: Vec<_> = tasks
+            .iter()
+            .map(|&(priority, name)| {
+                let log = log.clone();
+                executor.spawn_local(priority, Record { log, name })
+            })
+            .collect();
+
+        // Spawning only posts the first poll.
+        assert!(log.borrow().is_empty());
+        assert_eq!(queue.len(), 4);
+        assert_eq!(executor.task_count(), 4);
+
+        assert_eq!(queue.run_until_idle(&executor), 4);
+        assert_eq!(*log.borrow(), vec!["high", "normal 1", "normal 2", "low"]);
+        assert_eq!(executor.task_count(), 0);
+        let outputs: Vec<_> = handles.iter().map(|handle| handle.try_take()).collect();
+        assert_eq!(
+            outputs,
+            vec![
+                Some("low"),
+                Some("normal 1"),
+                Some("high"),
+                Some("normal 2")
+            ]
+        );
+        // The output is taken once.
+        assert_eq!(handles[0].try_take(), None);
+    }
+
+    #[test]
+    fn task_can_wake_itself() {
+        let (queue, executor) = setup();
+        let handle = executor.spawn_local(Priority::Normal, YieldOnce(false));
+        assert!(queue.run_one(&executor));
+        // Woken while it was being polled, and posted again.
+        assert_eq!(queue.len(), 1);
+        assert_eq!(handle.try_take(), None);
+        assert!(queue.run_one(&executor));
+        assert_eq!(handle.try_take(), Some(()));
+        assert!(!queue.run_one(&executor));
+    }
+
+    #[test]
+    fn waking_twice_posts_once() {
+        let (queue, executor) = setup();
+        let slot = Slot::default();
+        let handle = executor.spawn_local(Priority::Normal, slot.clone());
+        queue.run_until_idle(&executor);
+        assert!(queue.is_empty());
+
+        let waker = slot.waker.borrow_mut().take().unwrap();
+        waker.wake_by_ref();
+        waker.wake_by_ref();
+        assert_eq!(queue.len(), 1);
+        // Still pending, so it waits for another wakeup.
+        queue.run_until_idle(&executor);
+        assert_eq!(executor.task_count(), 1);
+
+        slot.value.set(Some(3));
+        waker.wake();
+        assert_eq!(queue.len(), 1);
+        queue.run_until_idle(&executor);
+        assert_eq!(handle.try_take(), Some(3));
+        assert_eq!(executor.task_count(), 0);
+    }
+
+    #[test]
+    fn wakes_from_other_threads() {
+        let (queue, executor) = setup();
+        let slot = Slot::default();
+        let handle = executor.spawn_local(Priority::Normal, slot.clone());
+        queue.run_until_idle(&executor);
+
+        slot.value.set(Some(7));
+        let waker = slot.waker.borrow_mut().take().unwrap();
+        thread::spawn(move || waker.wake()).join().unwrap();
+        assert_eq!(queue.len(), 1);
+        queue.run_until_idle(&executor);
+        assert_eq!(handle.try_take(), Some(7));
+    }
+
+    #[test]
+    fn tasks_await_other_tasks() {
+        let (queue, executor) = setup();
+        let slot = Slot::default();
+        let inner = executor.spawn_local(Priority::Low, slot.clone());
+        let outer = executor.spawn_local(Priority::High, inner);
+        queue.run_until_idle(&executor);
+        assert_eq!(outer.try_take(), None);
+
+        slot.value.set(Some(5));
+        slot.waker.borrow_mut().take().unwrap().wake();
+        // The inner task completes and wakes the outer one.
+        assert_eq!(queue.run_until_idle(&executor), 2);
+        assert_eq!(outer.try_take(), Some(5));
+    }
+
+    #[test]
+    fn stale_runs_are_ignored() {
+        let (queue, executor) = setup();
+        assert!(!executor.run_task(TaskId(42)));
+        let handle = executor.spawn_local(Priority::Normal, YieldOnce(true));
+        let task = queue.next().unwrap();
+        assert!(executor.run_task(task));
+        assert_eq!(handle.try_take(), Some(()));
+        assert!(!executor.run_task(task));
+    }
+
+    #[test]
+    fn block_on_pumps_the_queue() {
+        let (queue, executor) = setup();
+        let slot = Slot::default();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let record = executor.spawn_local(
+            Priority::Normal,
+            Record {
+                log: log.clone(),
+                name: "spawned",
+            },
+        );
+        let setter = slot.clone();
+        let mut pumps = 0;
+        let output = executor.block_on(slot, || {
+            pumps += 1;
+            // The first pump runs the spawned task; the second completes the future.
+            if !queue.run_one(&executor) {
+                setter.value.set(Some(9));
+                setter.waker.borrow_mut().take().unwrap().wake();
+            }
+            true
+        });
+        assert_eq!(output, Some(9));
+        assert_eq!(pumps, 2);
+        assert_eq!(record.try_take(), Some("spawned"));
+        assert_eq!(*log.borrow(), vec!["spawned"]);
+    }
+
+    #[test]
+    fn block_on_gives_up_when_the_queue_stops() {
+        let (_queue, executor) = setup();
+        assert_eq!(executor.block_on(Slot::default(), || false), None);
+        // Ready futures don't need the queue.
+        assert_eq!(executor.block_on(YieldOnce(true), || false), Some(()));
+    }
+
+    #[test]
+    fn closed_queue_runs_nothing() {
+        let (queue, executor) = setup();
+        let slot = Slot::default();
+        let waiting = executor.spawn_local(Priority::Normal, slot.clone());
+        queue.run_until_idle(&executor);
+
+        queue.close();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let never = executor.spawn_local(
+            Priority::High,
+            Record {
+                log: log.clone(),
+                name: "never",
+            },
+        );
+        slot.value.set(Some(1));
+        slot.waker.borrow_mut().take().unwrap().wake();
+
+        assert!(queue.is_empty());
+        assert_eq!(queue.run_until_idle(&executor), 0);
+        assert!(log.borrow().is_empty());
+        assert_eq!(never.try_take(), None);
+        assert_eq!(waiting.try_take(), None);
+    }
+}
@@ -0,0 +1,1229 @@
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+use std::time::Duration;
+
+use window_events::{LogicalPosition, Touch, TouchPhase};
+
+/// The kinds of gestures that `GestureRecognizer` can recognize.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum GestureKind {
+    Tap,
+    DoubleTap,
+    LongPress,
+    Pan,
+    Pinch,
+    Rotate,
+}
+
+/// The phase of a continuous gesture.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum GesturePhase {
+    Began,
+    Changed,
+    Ended,
+    /// The pointers were canceled, e.g. because the system took them over for an edge swipe.
+    Cancelled,
+}
+
+/// A recognized gesture. Positions are in logical pixels, in the coordinates of the samples.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Gesture {
+    Tap { position: LogicalPosition },
+    DoubleTap { position: LogicalPosition },
+    LongPress { position: LogicalPosition },
+    /// The pointers moved together. `translation` is the total movement of their centroid since
+    /// the pan began, including the movement it took to begin, and `velocity` its latest speed in
+    /// logical pixels per second.
+    Pan {
+        phase: GesturePhase,
+        translation: (f64, f64),
+        velocity: (f64, f64),
+    },
+    /// Two pointers moved apart or together. `scale` is their distance relative to when they
+    /// went down.
+    Pinch {
+        phase: GesturePhase,
+        scale: f64,
+        center: LogicalPosition,
+    },
+    /// Two pointers turned around each other. `angle` is the total rotation in radians, clockwise
+    /// on screen, since they went down.
+    Rotate {
+        phase: GesturePhase,
+        angle: f64,
+        center: LogicalPosition,
+    },
+}
+
+/// A pointer event fed to a `GestureRecognizer`.
+///
+/// Times only need to be consistent with each other, e.g. durations since the recognizer was
+/// created. They must not go backwards.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PointerSample {
+    pub id: u64,
+    pub phase: TouchPhase,
+    pub position: LogicalPosition,
+    pub time: Duration,
+}
+
+impl PointerSample {
+    pub fn from_touch(touch: &Touch, time: Duration) -> Self {
+        PointerSample {
+            id: touch.id,
+            phase: touch.phase,
+            position: touch.location,
+            time,
+        }
+    }
+}
+
+/// Thresholds and rules of a `GestureRecognizer`. Distances are in logical pixels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GestureConfig {
+    /// The gestures to recognize.
+    pub enabled: Vec<GestureKind>,
+    /// Pairs of continuous gestures that can be recognized at the same time. A gesture only
+    /// begins if it can run alongside every gesture already in progress. Long presses are
+    /// included: pairing `LongPress` with `Pan` allows dragging after a long press.
+    pub simultaneous: Vec<(GestureKind, GestureKind)>,
+    /// How far a pointer can move before it no longer counts as a tap or long press.
+    pub tap_slop: f64,
+    /// The longest a pointer can stay down for a tap.
+    pub tap_timeout: Duration,
+    /// The longest time from the end of a tap to the start of the second tap of a double tap.
+    pub double_tap_timeout: Duration,
+    /// How far apart the two taps of a double tap can be.
+    pub double_tap_slop: f64,
+    /// How long a pointer has to stay down for a long press.
+    pub long_press_timeout: Duration,
+    /// How far the pointers have to move for a pan to begin.
+    pub pan_threshold: f64,
+    /// How much the distance between two pointers has to change for a pinch to begin.
+    pub pinch_threshold: f64,
+    /// How far two pointers have to turn, in radians, for a rotation to begin.
+    pub rotate_threshold: f64,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        GestureConfig {
+            enabled: vec![
+                GestureKind::Tap,
+                GestureKind::DoubleTap,
+                GestureKind::LongPress,
+                GestureKind::Pan,
+                GestureKind::Pinch,
+                GestureKind::Rotate,
+            ],
+            simultaneous: vec![
+                (GestureKind::Pan, GestureKind::Pinch),
+                (GestureKind::Pan, GestureKind::Rotate),
+                (GestureKind::Pinch, GestureKind::Rotate),
+            ],
+            tap_slop: 10.0,
+            tap_timeout: Duration::from_millis(300),
+            double_tap_timeout: Duration::from_millis(300),
+            double_tap_slop: 40.0,
+            long_press_timeout: Duration::from_millis(500),
+            pan_threshold: 10.0,
+            pinch_threshold: 16.0,
+            rotate_threshold: 15.0 * PI / 180.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Tracked {
+    start: LogicalPosition,
+    position: LogicalPosition,
+}
+
+/// Everything between the first pointer going down and the last one going up.
+#[derive(Debug, Copy, Clone)]
+struct Session {
+    start_time: Duration,
+    max_pointers: usize,
+    /// A pointer moved beyond the tap slop, or a continuous gesture began.
+    moved: bool,
+    long_pressed: bool,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Pan {
+    /// The centroid that the translation is measured from.
+    anchor: (f64, f64),
+    /// The translation accumulated before the pointers last changed.
+    offset: (f64, f64),
+    translation: (f64, f64),
+    velocity: (f64, f64),
+    time: Duration,
+    active: bool,
+}
+
+/// The two pointers that pinches and rotations follow.
+#[derive(Debug, Copy, Clone)]
+struct Pair {
+    ids: (u64, u64),
+    /// The distance between the pointers when they went down.
+    distance: f64,
+    /// The latest angle of the line between the pointers, to unwrap the rotation.
+    angle: f64,
+    rotation: f64,
+    scale: f64,
+    center: LogicalPosition,
+    pinching: bool,
+    rotating: bool,
+}
+
+/// A tap that may still turn out to be the first half of a double tap.
+#[derive(Debug, Copy, Clone)]
+struct PendingTap {
+    position: LogicalPosition,
+    time: Duration,
+}
+
+/// Recognizes gestures in a stream of pointer events.
+///
+/// The recognizer is a deterministic state machine: it has no clock of its own, and the same
+/// samples always produce the same gestures. Long presses and single taps are decided by time
+/// passing rather than by an event, so `tick` has to be called by `next_deadline`.
+///
+/// A single tap waits for the double-tap timeout before it is reported, unless double taps are
+/// disabled. Once a pointer moves beyond the tap slop, only continuous gestures remain possible.
+#[derive(Debug)]
+pub struct GestureRecognizer {
+    config: GestureConfig,
+    pointers: BTreeMap<u64, Tracked>,
+    session: Option<Session>,
+    pan: Option<Pan>,
+    pair: Option<Pair>,
+    pending_tap: Option<PendingTap>,
+}
+
+fn distance(a: LogicalPosition, b: LogicalPosition) -> f64 {
+    (b.x - a.x).hypot(b.y - a.y)
+}
+
+/// The angle of the line from `a` to `b`.
+fn angle(a: LogicalPosition, b: LogicalPosition) -> f64 {
+    (b.y - a.y).atan2(b.x - a.x)
+}
+
+/// Brings an angle into (-π, π].
+fn normalize_angle(angle: f64) -> f64 {
+    let angle = angle % (2.0 * PI);
+    if angle > PI {
+        angle - 2.0 * PI
+    } else if angle <= -PI {
+        angle + 2.0 * PI
+    } else {
+        angle
+    }
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 * 1e-9
+}
+
+fn midpoint(a: LogicalPosition, b: LogicalPosition) -> LogicalPosition {
+    LogicalPosition::new((a.x + b.x) / 2.0, (a.y + b.y) / 2.0)
+}
+
+impl GestureRecognizer {
+    pub fn new(config: GestureConfig) -> Self {
+        GestureRecognizer {
+            config,
+            pointers: BTreeMap::new(),
+            session: None,
+            pan: None,
+            pair: None,
+            pending_tap: None,
+        }
+    }
+
+    #[inline]
+    pub fn config(&self) -> &GestureConfig {
+        &self.config
+    }
+
+    fn is_enabled(&self, kind: GestureKind) -> bool {
+        self.config.enabled.contains(&kind)
+    }
+
+    fn can_run_with(&self, kind: GestureKind, other: GestureKind) -> bool {
+        kind == other
+            || self.config.simultaneous.contains(&(kind, other))
+            || self.config.simultaneous.contains(&(other, kind))
+    }
+
+    /// The gestures in progress.
+    fn active(&self) -> Vec<GestureKind> {
+        let mut active = Vec::new();
+        if self.session.map_or(false, |session| session.long_pressed) {
+            active.push(GestureKind::LongPress);
+        }
+        if self.pan.map_or(false, |pan| pan.active) {
+            active.push(GestureKind::Pan);
+        }
+        if let Some(pair) = self.pair {
+            if pair.pinching {
+                active.push(GestureKind::Pinch);
+            }
+            if pair.rotating {
+                active.push(GestureKind::Rotate);
+            }
+        }
+        active
+    }
+
+    /// Whether a gesture can begin now.
+    fn can_begin(&self, kind: GestureKind) -> bool {
+        self.is_enabled(kind)
+            && self
+                .active()
+                .into_iter()
+                .all(|active| self.can_run_with(kind, active))
+    }
+
+    /// When `tick` next has to be called, if at all.
+    pub fn next_deadline(&self) -> Option<Duration> {
+        let long_press = match self.session {
+            Some(session) if self.long_press_possible(&session) => {
+                Some(session.start_time + self.config.long_press_timeout)
+            }
+            _ => None,
+        };
+        let tap = self
+            .pending_tap
+            .map(|tap| tap.time + self.config.double_tap_timeout);
+        match (long_press, tap) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn long_press_possible(&self, session: &Session) -> bool {
+        session.max_pointers == 1
+            && !session.moved
+            && !session.long_pressed
+            && self.can_begin(GestureKind::LongPress)
+    }
+
+    /// Recognizes the gestures that are decided by time passing: long presses, and single taps
+    /// that weren't followed by a second one.
+    pub fn tick(&mut self, time: Duration) -> Vec<Gesture> {
+        let mut gestures = Vec::new();
+        self.expire_pending_tap(time, &mut gestures);
+        if let Some(session) = self.session {
+            if self.long_press_possible(&session)
+                && time >= session.start_time + self.config.long_press_timeout
+            {
+                let position = self.pointers.values().next().unwrap().position;
+                self.session = Some(Session {
+                    long_pressed: true,
+                    ..session
+                });
+                self.fail_double_tap(&mut gestures);
+                gestures.push(Gesture::LongPress { position });
+            }
+        }
+        gestures
+    }
+
+    /// Reports the pending tap as a single tap once the second tap can no longer start in time.
+    fn expire_pending_tap(&mut self, time: Duration, gestures: &mut Vec<Gesture>) {
+        let tap = match self.pending_tap {
+            Some(tap) => tap,
+            None => return,
+        };
+        let deadline = tap.time + self.config.double_tap_timeout;
+        // A session that started in time may still be the second tap.
+        let second_started = self
+            .session
+            .map_or(false, |session| session.start_time <= deadline);
+        if time > deadline && !second_started {
+            self.fail_double_tap(gestures);
+        }
+    }
+
+    /// Reports the pending tap as a single tap, now that it can't become a double tap.
+    fn fail_double_tap(&mut self, gestures: &mut Vec<Gesture>) {
+        if let Some(tap) = self.pending_tap.take() {
+            if self.is_enabled(GestureKind::Tap) {
+                gestures.push(Gesture::Tap {
+                    position: tap.position,
+                });
+            }
+        }
+    }
+
+    /// Feeds a pointer event, returning the gestures it completes or updates.
+    pub fn handle(&mut self, sample: PointerSample) -> Vec<Gesture> {
+        let mut gestures = self.tick(sample.time);
+        match sample.phase {
+            TouchPhase::Started => self.pointer_down(sample, &mut gestures),
+            TouchPhase::Moved => self.pointer_moved(sample, &mut gestures),
+            TouchPhase::Ended => self.pointer_up(sample, &mut gestures),
+            TouchPhase::Cancelled => self.cancel(&mut gestures),
+        }
+        gestures
+    }
+
+    /// Abandons all pointers, cancelling the gestures in progress. Use this when the window
+    /// loses the pointers without being told, e.g. when it loses focus.
+    pub fn cancel_all(&mut self) -> Vec<Gesture> {
+        let mut gestures = Vec::new();
+        self.cancel(&mut gestures);
+        gestures
+    }
+
+    fn pointer_down(&mut self, sample: PointerSample, gestures: &mut Vec<Gesture>) {
+        if self.pointers.contains_key(&sample.id) {
+            return self.pointer_moved(sample, gestures);
+        }
+        self.pointers.insert(
+            sample.id,
+            Tracked {
+                start: sample.position,
+                position: sample.position,
+            },
+        );
+        let mut session = self.session.unwrap_or(Session {
+            start_time: sample.time,
+            max_pointers: 0,
+            moved: false,
+            long_pressed: false,
+        });
+        session.max_pointers = session.max_pointers.max(self.pointers.len());
+        self.session = Some(session);
+        if session.max_pointers > 1 {
+            self.fail_double_tap(gestures);
+        }
+        self.reanchor(sample.time, gestures);
+    }
+
+    fn pointer_moved(&mut self, sample: PointerSample, gestures: &mut Vec<Gesture>) {
+        let start = match self.pointers.get_mut(&sample.id) {
+            // Pointers often end where they last moved to. Such events would update gestures
+            // with no movement, and take the velocity of a pan down to 0.
+            Some(ref tracked) if tracked.position == sample.position => return,
+            Some(tracked) => {
+                tracked.position = sample.position;
+                tracked.start
+            }
+            None => return,
+        };
+        if distance(start, sample.position) > self.config.tap_slop {
+            self.mark_moved(gestures);
+        }
+        self.update_pair(gestures);
+        self.update_pan(sample.time, gestures);
+    }
+
+    fn pointer_up(&mut self, sample: PointerSample, gestures: &mut Vec<Gesture>) {
+        if !self.pointers.contains_key(&sample.id) {
+            return;
+        }
+        self.pointer_moved(sample, gestures);
+        self.pointers.remove(&sample.id);
+        if !self.pointers.is_empty() {
+            self.reanchor(sample.time, gestures);
+            return;
+        }
+
+        self.end_pair(GesturePhase::Ended, gestures);
+        self.end_pan(GesturePhase::Ended, gestures);
+        let session = match self.session.take() {
+            Some(session) => session,
+            None => return,
+        };
+        let is_tap = session.max_pointers == 1
+            && !session.moved
+            && !session.long_pressed
+            && sample
+                .time
+                .checked_sub(session.start_time)
+                .map_or(false, |held| held <= self.config.tap_timeout);
+        if !is_tap {
+            self.fail_double_tap(gestures);
+            return;
+        }
+        let position = sample.position;
+        if !self.can_begin(GestureKind::DoubleTap) {
+            if self.can_begin(GestureKind::Tap) {
+                gestures.push(Gesture::Tap { position });
+            }
+            return;
+        }
+        let is_second = self.pending_tap.map_or(false, |tap| {
+            session.start_time <= tap.time + self.config.double_tap_timeout
+                && distance(tap.position, position) <= self.config.double_tap_slop
+        });
+        if is_second {
+            self.pending_tap = None;
+            gestures.push(Gesture::DoubleTap { position });
+        } else {
+            self.fail_double_tap(gestures);
+            self.pending_tap = Some(PendingTap {
+                position,
+                time: sample.time,
+            });
+        }
+    }
+
+    fn cancel(&mut self, gestures: &mut Vec<Gesture>) {
+        self.end_pair(GesturePhase::Cancelled, gestures);
+        self.end_pan(GesturePhase::Cancelled, gestures);
+        self.pointers.clear();
+        if self.session.take().is_some() {
+            self.fail_double_tap(gestures);
+        }
+    }
+
+    /// Rules out taps and long presses for the rest of the session.
+    fn mark_moved(&mut self, gestures: &mut Vec<Gesture>) {
+        if let Some(ref mut session) = self.session {
+            if session.moved {
+                return;
+            }
+            session.moved = true;
+        }
+        self.fail_double_tap(gestures);
+    }
+
+    fn centroid(&self) -> (f64, f64) {
+        let count = self.pointers.len().max(1) as f64;
+        let (x, y) = self
+            .pointers
+            .values()
+            .fold((0.0, 0.0), |(x, y), tracked| {
+                (x + tracked.position.x, y + tracked.position.y)
+            });
+        (x / count, y / count)
+    }
+
+    fn first_two(&self) -> Option<((u64, LogicalPosition), (u64, LogicalPosition))> {
+        let mut pointers = self.pointers.iter();
+        match (pointers.next(), pointers.next()) {
+            (Some((&a, ta)), Some((&b, tb))) => Some(((a, ta.position), (b, tb.position))),
+            _ => None,
+        }
+    }
+
+    /// Restarts measuring after the set of pointers changed. A pan carries on without jumping;
+    /// pinches and rotations end if they lose one of their two pointers.
+    fn reanchor(&mut self, time: Duration, gestures: &mut Vec<Gesture>) {
+        let centroid = self.centroid();
+        self.pan = Some(match self.pan {
+            Some(pan) => Pan {
+                anchor: centroid,
+                offset: pan.translation,
+                time,
+                ..pan
+            },
+            None => Pan {
+                anchor: centroid,
+                offset: (0.0, 0.0),
+                translation: (0.0, 0.0),
+                velocity: (0.0, 0.0),
+                time,
+                active: false,
+            },
+        });
+
+        let first_two = self.first_two();
+        if self.pair.map(|pair| pair.ids) != first_two.map(|((a, _), (b, _))| (a, b)) {
+            self.end_pair(GesturePhase::Ended, gestures);
+            self.pair = first_two.map(|((a, pa), (b, pb))| Pair {
+                ids: (a, b),
+                distance: distance(pa, pb),
+                angle: angle(pa, pb),
+                rotation: 0.0,
+                scale: 1.0,
+                center: midpoint(pa, pb),
+                pinching: false,
+                rotating: false,
+            });
+        }
+    }
+
+    fn end_pair(&mut self, phase: GesturePhase, gestures: &mut Vec<Gesture>) {
+        if let Some(pair) = self.pair.take() {
+            if pair.pinching {
+                gestures.push(Gesture::Pinch {
+                    phase,
+                    scale: pair.scale,
+                    center: pair.center,
+                });
+            }
+            if pair.rotating {
+                gestures.push(Gesture::Rotate {
+                    phase,
+                    angle: pair.rotation,
+                    center: pair.center,
+                });
+            }
+        }
+    }
+
+    fn end_pan(&mut self, phase: GesturePhase, gestures: &mut Vec<Gesture>) {
+        if let Some(pan) = self.pan.take() {
+            if pan.active {
+                gestures.push(Gesture::Pan {
+                    phase,
+                    translation: pan.translation,
+                    velocity: pan.velocity,
+                });
+            }
+        }
+    }
+
+    fn update_pair(&mut self, gestures: &mut Vec<Gesture>) {
+        let (mut pair, a, b) = match (self.pair, self.first_two()) {
+            (Some(pair), Some(((_, a), (_, b)))) => (pair, a, b),
+            _ => return,
+        };
+        let current = angle(a, b);
+        pair.rotation += normalize_angle(current - pair.angle);
+        pair.angle = current;
+        pair.center = midpoint(a, b);
+        if pair.distance > 0.0 {
+            pair.scale = distance(a, b) / pair.distance;
+        }
+        self.pair = Some(pair);
+
+        let pinch_phase = if pair.pinching {
+            Some(GesturePhase::Changed)
+        } else if (distance(a, b) - pair.distance).abs() > self.config.pinch_threshold
+            && self.can_begin(GestureKind::Pinch)
+        {
+            Some(GesturePhase::Began)
+        } else {
+            None
+        };
+        if let Some(phase) = pinch_phase {
+            pair.pinching = true;
+            self.pair = Some(pair);
+            self.mark_moved(gestures);
+            gestures.push(Gesture::Pinch {
+                phase,
+                scale: pair.scale,
+                center: pair.center,
+            });
+        }
+
+        let rotate_phase = if pair.rotating {
+            Some(GesturePhase::Changed)
+        } else if pair.rotation.abs() > self.config.rotate_threshold
+            && self.can_begin(GestureKind::Rotate)
+        {
+            Some(GesturePhase::Began)
+        } else {
+            None
+        };
+        if let Some(phase) = rotate_phase {
+            pair.rotating = true;
+            self.pair = Some(pair);
+            self.mark_moved(gestures);
+            gestures.push(Gesture::Rotate {
+                phase,
+                angle: pair.rotation,
+                center: pair.center,
+            });
+        }
+    }
+
+    fn update_pan(&mut self, time: Duration, gestures: &mut Vec<Gesture>) {
+        let mut pan = match self.pan {
+            Some(pan) => pan,
+            None => return,
+        };
+        let (x, y) = self.centroid();
+        let translation = (
+            pan.offset.0 + x - pan.anchor.0,
+            pan.offset.1 + y - pan.anchor.1,
+        );
+        if time > pan.time {
+            let dt = seconds(time - pan.time);
+            pan.velocity = (
+                (translation.0 - pan.translation.0) / dt,
+                (translation.1 - pan.translation.1) / dt,
+            );
+            pan.time = time;
+        }
+        pan.translation = translation;
+        self.pan = Some(pan);
+
+        let phase = if pan.active {
+            GesturePhase::Changed
+        } else if translation.0.hypot(translation.1) > self.config.pan_threshold
+            && self.can_begin(GestureKind::Pan)
+        {
+            GesturePhase::Began
+        } else {
+            return;
+        };
+        pan.active = true;
+        self.pan = Some(pan);
+        self.mark_moved(gestures);
+        gestures.push(Gesture::Pan {
+            phase,
+            translation,
+            velocity: pan.velocity,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use window_events::TouchPhase::{Cancelled, Ended, Moved, Started};
+
+    /// A recorded pointer event: milliseconds, pointer id, phase and position.
+    type Event = (u64, u64, TouchPhase, f64, f64);
+
+    fn sample(&(ms, id, phase, x, y): &Event) -> PointerSample {
+        PointerSample {
+            id,
+            phase,
+            position: LogicalPosition::new(x, y),
+            time: Duration::from_millis(ms),
+        }
+    }
+
+    /// Feeds a recorded stream, returning the gestures each event produced.
+    fn play(recognizer: &mut GestureRecognizer, events: &[Event]) -> Vec<Vec<Gesture>> {
+        events
+            .iter()
+            .map(|event| recognizer.handle(sample(event)))
+            .collect()
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn at(x: f64, y: f64) -> LogicalPosition {
+        LogicalPosition::new(x, y)
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    fn assert_pan(
+        gesture: &Gesture,
+        expected: GesturePhase,
+        (tx, ty): (f64, f64),
+        (vx, vy): (f64, f64),
+    ) {
+        match *gesture {
+            Gesture::Pan {
+                phase,
+                translation,
+                velocity,
+            } if phase == expected
+                && close(translation.0, tx)
+                && close(translation.1, ty)
+                && close(velocity.0, vx)
+                && close(velocity.1, vy) => {}
+            ref gesture => panic!(
+                "expected a {:?} pan by ({}, {}) at ({}, {}), got {:?}",
+                expected, tx, ty, vx, vy, gesture
+            ),
+        }
+    }
+
+    #[test]
+    fn tap_waits_for_double_tap_timeout() {
+        let mut recognizer = GestureRecognizer::new(GestureConfig::default());
+        let gestures = play(
+            &mut recognizer,
+            &[
+                (0, 1, Started, 50.0, 50.0),
+                (40, 1, Moved, 53.0, 51.0),
+                (100, 1, Ended, 53.0, 51.0),
+            ],
+        );
+        assert!(gestures.iter().all(|gestures| gestures.is_empty()));
+        assert_eq!(recognizer.next_deadline(), Some(ms(400)));
+        assert_eq!(recognizer.tick(ms(400)), vec![]);
+        assert_eq!(
+            recognizer.tick(ms(401)),
+            vec![Gesture::Tap {
+                position: at(53.0, 51.0)
+            }]
+        );
+        assert_eq!(recognizer.next_deadline(), None);
+    }
+
+    #[test]
+    fn tap_without_double_tap() {
+        let config = GestureConfig {
+            enabled: vec![GestureKind::Tap],
+            ..GestureConfig::default()
+        };
+        let mut recognizer = GestureRecognizer::new(config);
+        let gestures = play(
+            &mut recognizer,
+            &[(0, 1, Started, 50.0, 50.0), (80, 1, Ended, 50.0, 50.0)],
+        );
+        assert_eq!(
+            gestures[1],
+            vec![Gesture::Tap {
+                position: at(50.0, 50.0)
+            }]
+        );
+        assert_eq!(recognizer.next_deadline(), None);
+    }
+
+    #[test]
+    fn double_tap() {
+        let mut recognizer = GestureRecognizer::new(GestureConfig::default());
+        let gestures = play(
+            &mut recognizer,
+            &[
+                (0, 1, Started, 50.0, 50.0),
+                (100, 1, Ended, 50.0, 50.0),
+                (250, 2, Started, 55.0, 52.0),
+                (330, 2, Ended, 55.0, 52.0),
+            ],
+        );
+        assert_eq!(
+            gestures,
+            vec![
+                vec![],
+                vec![],
+                vec![],
+                vec![Gesture::DoubleTap {
+                    position: at(55.0, 52.0)
+                }]
+            ]
+        );
+        assert_eq!(recognizer.next_deadline(), None);
+    }
+
+    #[test]
+    fn late_or_distant_second_tap_is_another_tap() {
+        let mut recognizer = GestureRecognizer::new(GestureConfig::default());
+        let gestures = play(
+            &mut recognizer,
+            &[
+                (0, 1, Started, 50.0, 50.0),
+                (100, 1, Ended, 50.0, 50.0),
+                // Too late to be the second tap.
+                (450, 2, Started, 50.0, 50.0),
+                (500, 2, Ended, 50.0, 50.0),
+                // Too far away.
+                (600, 3, Started, 150.0, 50.0),
+                (650, 3, Ended, 150.0, 50.0),
+            ],
+        );
+        assert_eq!(
+            gestures,
+            vec![
+                vec![],
+                vec![],
+                vec![Gesture::Tap {
+                    position: at(50.0, 50.0)
+                }],
+                vec![],
+                vec![],
+                vec![Gesture::Tap {
+                    position: at(50.0, 50.0)
+                }],
+            ]
+        );
+        assert_eq!(
+            recognizer.tick(ms(1000)),
+            vec![Gesture::Tap {
+                position: at(150.0, 50.0)
+            }]
+        );
+    }
+
+    #[test]
+    fn slow_or_moving_press_is_no_tap() {
+        let config = GestureConfig {
+            enabled: vec![GestureKind::Tap],
+            ..GestureConfig::default()
+        };
+        let mut recognizer = GestureRecognizer::new(config);
+        let gestures = play(
+            &mut recognizer,
+            &[
+                (0, 1, Started, 50.0, 50.0),
+                (301, 1, Ended, 50.0, 50.0),
+                (400, 1, Started, 50.0, 50.0),
+                (420, 1, Moved, 50.0, 61.0),
+                (440, 1, Ended, 50.0, 50.0),
+            ],
+        );
+        assert!(gestures.iter().all(|gestures| gestures.is_empty()));
+    }
+
+    #[test]
+    fn time_going_backwards_is_no_tap() {
+        let mut recognizer = GestureRecognizer::new(GestureConfig::default());
+        let gestures = play(
+            &mut recognizer,
+            &[(100, 1, Started, 50.0, 50.0), (50, 1, Ended, 50.0, 50.0)],
+        );
+        assert_eq!(gestures, vec![vec![], vec![]]);
+        assert_eq!(recognizer.next_deadline(), None);
+    }
+
+    #[test]
+    fn long_press() {
+        let mut recognizer = GestureRecognizer::new(GestureConfig::default());
+        play(
+            &mut recognizer,
+            &[(0, 1, Started, 50.0, 50.0), (200, 1, Moved, 52.0, 50.0)],
+        );
+        assert_eq!(recognizer.next_deadline(), Some(ms(500)));
+        assert_eq!(recognizer.tick(ms(499)), vec![]);
+        assert_eq!(
+            recognizer.tick(ms(500)),
+            vec![Gesture::LongPress {
+                position: at(52.0, 50.0)
+            }]
+        );
+        assert_eq!(recognizer.next_deadline(), None);
+        // Without pairing it with `Pan`, moving after a long press does nothing.
+        let gestures = play(
+            &mut recognizer,
+            &[(600, 1, Moved, 90.0, 50.0), (700, 1, Ended, 90.0, 50.0)],
+        );
+        assert_eq!(gestures, vec![vec![], vec![]]);
+    }
+
+    #[test]
+    fn drag_after_long_press() {
+        let mut config = GestureConfig::default();
+        config
+            .simultaneous
+            .push((GestureKind::LongPress, GestureKind::Pan));
+        let mut recognizer = GestureRecognizer::new(config);
+        let gestures = play(
+            &mut recognizer,
+            &[
+                (0, 1, Started, 50.0, 50.0),
+                (600, 1, Moved, 50.0, 80.0),
+                (700, 1, Ended, 50.0, 80.0),
+            ],
+        );
+        assert_eq!(
+            gestures[1][0],
+            Gesture::LongPress {
+                position: at(50.0, 50.0)
+            }
+        );
+        assert_pan(
+            &gestures[1][1],
+            GesturePhase::Began,
+            (0.0, 30.0),
+            (0.0, 50.0),
+        );
+        assert_pan(
+            &gestures[2][0],
+            GesturePhase::Ended,
+            (0.0, 30.0),
+            (0.0, 50.0),
+        );
+    }
+
+    #[test]
+    fn pan() {
+        let mut recognizer = GestureRecognizer::new(GestureConfig::default());
+        let gestures = play(
+            &mut recognizer,
+            &[
+                (0, 1, Started, 100.0, 100.0),
+                (16, 1, Moved, 104.0, 100.0),
+                (32, 1, Moved, 112.0, 100.0),
+                (48, 1, Moved, 120.0, 96.0),
+                (64, 1, Ended, 120.0, 96.0),
+            ],
+        );
+        assert_eq!(gestures[0], vec![]);
+        // Still within the pan threshold.
+        assert_eq!(gestures[1], vec![]);
+        assert_eq!(gestures[2].len(), 1);
+        assert_pan(
+            &gestures[2][0],
+            GesturePhase::Began,
+            (12.0, 0.0),
+            (500.0, 0.0),
+        );
+        assert_pan(
+            &gestures[3][0],
+            GesturePhase::Changed,
+            (20.0, -4.0),
+            (500.0, -250.0),
+        );
+        // Ending where it last moved to keeps the velocity.
+        assert_pan(
+            &gestures[4][0],
+            GesturePhase::Ended,
+            (20.0, -4.0),
+            (500.0, -250.0),
+        );
+        assert_eq!(recognizer.tick(ms(1000)), vec![]);
+    }
+
+    #[test]
+    fn pan_carries_on_when_pointers_change() {
+        let mut recognizer = GestureRecognizer::new(GestureConfig::default());
+        let gestures = play(
+            &mut recognizer,
+            &[
+                (0, 1, Started, 100.0, 100.0),
+                (20, 1, Moved, 120.0, 100.0),
+                // The centroid jumps to (150, 100), but the pan doesn't.
+                (40, 2, Started, 180.0, 100.0),
+                (60, 1, Moved, 130.0, 100.0),
+                (60, 2, Moved, 190.0, 100.0),
+                (80, 1, Ended, 130.0, 100.0),
+                (100, 2, Moved, 200.0, 110.0),
+                (120, 2, Ended, 200.0, 110.0),
+            ],
+        );
+        assert_pan(
+            &gestures[1][0],
+            GesturePhase::Began,
+            (20.0, 0.0),
+            (1000.0, 0.0),
+        );
+        assert_eq!(gestures[2], vec![]);
+        assert_pan(
+            &gestures[3][0],
+            GesturePhase::Changed,
+            (25.0, 0.0),
+            (250.0, 0.0),
+        );
+        assert_pan(
+            &gestures[4][0],
+            GesturePhase::Changed,
+            (30.0, 0.0),
+            (250.0, 0.0),
+        );
+        assert_eq!(gestures[5], vec![]);
+        assert_pan(
+            &gestures[6][0],
+            GesturePhase::Changed,
+            (40.0, 10.0),
+            (500.0, 500.0),
+        );
+        assert_pan(
+            &gestures[7][0],
+            GesturePhase::Ended,
+            (40.0, 10.0),
+            (500.0, 500.0),
+        );
+    }
+
+    #[test]
+    fn pinch_and_rotate() {
+        let mut recognizer = GestureRecognizer::new(GestureConfig::default());
+        let gestures = play(
+            &mut recognizer,
+            &[
+                (0, 1, Started, 100.0, 100.0),
+                (10, 2, Started, 200.0, 100.0),
+                (50, 2, Moved, 240.0, 100.0),
+                (80, 2, Moved, 100.0, 240.0),
+                (100, 1, Ended, 100.0, 100.0),
+                (120, 2, Ended, 100.0, 240.0),
+            ],
+        );
+        assert_eq!(gestures[0], vec![]);
+        assert_eq!(gestures[1], vec![]);
+
+        assert_eq!(
+            gestures[2][0],
+            Gesture::Pinch {
+                phase: GesturePhase::Began,
+                scale: 1.4,
+                center: at(170.0, 100.0),
+            }
+        );
+        assert_pan(
+            &gestures[2][1],
+            GesturePhase::Began,
+            (20.0, 0.0),
+            (500.0, 0.0),
+        );
+
+        assert_eq!(gestures[3].len(), 3);
+        assert_eq!(
+            gestures[3][0],
+            Gesture::Pinch {
+                phase: GesturePhase::Changed,
+                scale: 1.4,
+                center: at(100.0, 170.0),
+            }
+        );
+        match gestures[3][1] {
+            Gesture::Rotate {
+                phase: GesturePhase::Began,
+                angle,
+                center,
+            } => {
+                assert!(close(angle, PI / 2.0));
+                assert_eq!(center, at(100.0, 170.0));
+            }
+            ref gesture => panic!("expected a rotation, got {:?}", gesture),
+        }
+        assert_pan(
+            &gestures[3][2],
+            GesturePhase::Changed,
+            (-50.0, 70.0),
+            (-70.0 / 0.03, 70.0 / 0.03),
+        );
+
+        // Losing one of its pointers ends the pinch and the rotation, but not the pan.
+        assert_eq!(gestures[4].len(), 2);
+        assert_eq!(
+            gestures[4][0],
+            Gesture::Pinch {
+                phase: GesturePhase::Ended,
+                scale: 1.4,
+                center: at(100.0, 170.0),
+            }
+        );
+        match gestures[4][1] {
+            Gesture::Rotate {
+                phase: GesturePhase::Ended,
+                angle,
+                ..
+            } => assert!(close(angle, PI / 2.0)),
+            ref gesture => panic!("expected a rotation, got {:?}", gesture),
+        }
+        assert_pan(
+            &gestures[5][0],
+            GesturePhase::Ended,
+            (-50.0, 70.0),
+            (-70.0 / 0.03, 70.0 / 0.03),
+        );
+    }
+
+    #[test]
+    fn rotation_unwraps_past_half_turn() {
+        let config = GestureConfig {
+            enabled: vec![GestureKind::Rotate],
+            ..GestureConfig::default()
+        };
+        let mut recognizer = GestureRecognizer::new(config);
+        // The second pointer circles the first, through the angle where atan2 wraps around.
+        let gestures = play(
+            &mut recognizer,
+            &[
+                (0, 1, Started, 100.0, 100.0),
+                (0, 2, Started, 200.0, 100.0),
+                (10, 2, Moved, 100.0, 200.0),
+                (20, 2, Moved, 0.0, 100.0),
+                (30, 2, Moved, 100.0, 0.0),
+                (40, 2, Cancelled, 100.0, 0.0),
+            ],
+        );
+        let angles: Vec<_> = gestures[2..]
+            .iter()
+            .flat_map(|gestures| gestures.iter())
+            .map(|gesture| match *gesture {
+                Gesture::Rotate { phase, angle, .. } => (phase, angle),
+                ref gesture => panic!("expected a rotation, got {:?}", gesture),
+            })
+            .collect();
+        assert_eq!(angles.len(), 4);
+        let expected = [
+            (GesturePhase::Began, 0.5),
+            (GesturePhase::Changed, 1.0),
+            (GesturePhase::Changed, 1.5),
+            (GesturePhase::Cancelled, 1.5),
+        ];
+        for (&(phase, angle), &(expected_phase, turns)) in angles.iter().zip(expected.iter()) {
+            assert_eq!(phase, expected_phase);
+            assert!(close(angle, turns * PI), "{} != {}π", angle, turns);
+        }
+    }
+
+    #[test]
+    fn gestures_that_cant_run_together() {
+        let config = GestureConfig {
+            simultaneous: vec![],
+            ..GestureConfig::default()
+        };
+        let mut recognizer = GestureRecognizer::new(config);
+        let gestures = play(
+            &mut recognizer,
+            &[
+                (0, 1, Started, 100.0, 100.0),
+                (0, 2, Started, 200.0, 100.0),
+                // Spreading apart only moves the centroid by 20, so the pinch comes first.
+                (20, 2, Moved, 240.0, 100.0),
+                (40, 1, Moved, 100.0, 160.0),
+                (60, 1, Ended, 100.0, 160.0),
+                (60, 2, Ended, 240.0, 100.0),
+            ],
+        );
+        let kinds: Vec<_> = gestures
+            .iter()
+            .flat_map(|gestures| gestures.iter())
+            .map(|gesture| match *gesture {
+                Gesture::Pinch { phase, .. } => (GestureKind::Pinch, phase),
+                Gesture::Rotate { phase, .. } => (GestureKind::Rotate, phase),
+                Gesture::Pan { phase, .. } => (GestureKind::Pan, phase),
+                ref gesture => panic!("unexpected gesture {:?}", gesture),
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (GestureKind::Pinch, GesturePhase::Began),
+                (GestureKind::Pinch, GesturePhase::Changed),
+                (GestureKind::Pinch, GesturePhase::Ended),
+            ]
+        );
+    }
+
+    #[test]
+    fn cancel_ends_gestures() {
+        let mut recognizer = GestureRecognizer::new(GestureConfig::default());
+        let gestures = play(
+            &mut recognizer,
+            &[
+                (0, 1, Started, 100.0, 100.0),
+                (20, 1, Moved, 100.0, 120.0),
+                (40, 1, Cancelled, 100.0, 120.0),
+            ],
+        );
+        assert_pan(
+            &gestures[1][0],
+            GesturePhase::Began,
+            (0.0, 20.0),
+            (0.0, 1000.0),
+        );
+        assert_pan(
+            &gestures[2][0],
+            GesturePhase::Cancelled,
+            (0.0, 20.0),
+            (0.0, 1000.0),
+        );
+
+        play(
+            &mut recognizer,
+            &[(100, 1, Started, 0.0, 0.0), (120, 1, Moved, 0.0, 30.0)],
+        );
+        match recognizer.cancel_all()[..] {
+            [Gesture::Pan {
+                phase: GesturePhase::Cancelled,
+                ..
+            }] => (),
+            ref gestures => panic!("expected a cancelled pan, got {:?}", gestures),
+        }
+        assert_eq!(recognizer.cancel_all(), vec![]);
+        assert_eq!(recognizer.next_deadline(), None);
+    }
+}
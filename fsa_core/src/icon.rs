@@ -0,0 +1,1024 @@
+use image;
+use std::path::Path;
+use std::{fmt, fs, io, mem};
+
+use le_bytes::{read_u16, read_u32};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const ICONDIR_SIZE: usize = 6;
+const ICONDIRENTRY_SIZE: usize = 16;
+const BITMAPINFOHEADER_SIZE: usize = 40;
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+const PIXEL_SIZE: usize = 4;
+
+/// One image of an `Icon`, stored as straight RGBA.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IconImage {
+    pub(crate) rgba: Vec<u8>,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+}
+
+impl IconImage {
+    #[inline]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// An icon used for the window titlebar, taskbar, etc.
+///
+/// An icon can hold several images of different sizes, as `.ico` files do. When the icon is
+/// applied to a window, the image that best matches the size Windows asks for is used.
+pub struct Icon {
+    pub(crate) images: Vec<IconImage>,
+}
+
+impl Icon {
+    /// Creates an icon from a single image of straight RGBA pixels.
+    pub fn from_rgba(rgba: Vec<u8>, width: u32, height: u32) -> Result<Self, BadIcon> {
+        validate_rgba(&rgba, width, height)?;
+        Ok(Icon {
+            images: vec![IconImage {
+                rgba,
+                width,
+                height,
+            }],
+        })
+    }
+
+    /// Loads an icon from an `.ico` or `.png` file.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, IconLoadError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    /// Loads an icon from the contents of an `.ico` or `.png` file.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, IconLoadError> {
+        let images = if bytes.starts_with(PNG_SIGNATURE) {
+            vec![decode_png(bytes)?]
+        } else if is_ico(bytes) {
+            parse_ico(bytes)?
+        } else {
+            return Err(IconLoadError::UnknownFormat);
+        };
+        if images.is_empty() {
+            return Err(IconLoadError::NoImages);
+        }
+        Ok(Icon { images })
+    }
+
+    #[inline]
+    pub fn images(&self) -> &[IconImage] {
+        &self.images
+    }
+
+    /// Returns the image best suited to being displayed at `size`x`size` pixels.
+    pub fn best_image(&self, size: u32) -> &IconImage {
+        let sizes: Vec<_> = self.images.iter().map(|i| (i.width, i.height)).collect();
+        // An `Icon` always holds at least one image.
+        &self.images[select_best_size(&sizes, size).unwrap_or(0)]
+    }
+}
+
+/// Picks the index of the size that best matches a square of `desired` pixels.
+///
+/// Windows scales icons down much more gracefully than up, so the smallest image at least as big
+/// as `desired` is preferred. If every image is smaller, the biggest one is used. Among images of
+/// the same size the first one wins.
+///
+/// Returns `None` if `sizes` is empty.
+pub fn select_best_size(sizes: &[(u32, u32)], desired: u32) -> Option<usize> {
+    if sizes.is_empty() {
+        return None;
+    }
+    let extent = |&(w, h): &(u32, u32)| w.max(h);
+    let mut best = 0;
+    for (i, size) in sizes.iter().enumerate().skip(1) {
+        let (candidate, current) = (extent(size), extent(&sizes[best]));
+        let better = if current >= desired {
+            candidate >= desired && candidate < current
+        } else {
+            candidate > current
+        };
+        if better {
+            best = i;
+        }
+    }
+    Some(best)
+}
+
+/// Which of a window's icons an image is meant for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IconKind {
+    /// `ICON_SMALL`, shown in the title bar.
+    Small,
+    /// `ICON_BIG`, shown in the taskbar and the Alt+Tab switcher.
+    Big,
+    /// The badge drawn over the window's taskbar button.
+    Overlay,
+}
+
+/// Creates and destroys the native handles behind a window's icons.
+///
+/// Windows are backed by `CreateIcon`/`DestroyIcon`, but keeping this behind a trait lets the
+/// ownership rules of `WindowIcons` be checked against an allocator that counts live handles.
+pub trait IconAllocator {
+    type Handle: Copy;
+    type Error;
+
+    fn create(&mut self, image: &IconImage) -> Result<Self::Handle, Self::Error>;
+    fn destroy(&mut self, handle: Self::Handle);
+}
+
+/// The icon handles owned by a single window.
+///
+/// Every handle is destroyed exactly once: either when it is replaced, or when the `WindowIcons`
+/// is cleared or dropped.
+pub struct WindowIcons<A: IconAllocator> {
+    allocator: A,
+    small: Option<A::Handle>,
+    big: Option<A::Handle>,
+    overlay: Option<A::Handle>,
+}
+
+impl<A: IconAllocator> WindowIcons<A> {
+    pub fn new(allocator: A) -> Self {
+        WindowIcons {
+            allocator,
+            small: None,
+            big: None,
+            overlay: None,
+        }
+    }
+
+    #[inline]
+    pub fn allocator(&self) -> &A {
+        &self.allocator
+    }
+
+    #[inline]
+    pub fn handle(&self, kind: IconKind) -> Option<A::Handle> {
+        match kind {
+            IconKind::Small => self.small,
+            IconKind::Big => self.big,
+            IconKind::Overlay => self.overlay,
+        }
+    }
+
+    /// Replaces the icon of the given kind, or removes it if `image` is `None`.
+    ///
+    /// `apply` is called with the new handle while the previous one is still alive, so it can hand
+    /// the new icon to the window before the old one is destroyed. If creating the new handle or
+    /// applying it fails, the new handle is destroyed and the current icon is left untouched.
+    pub fn set<F>(
+        &mut self,
+        kind: IconKind,
+        image: Option<&IconImage>,
+        apply: F,
+    ) -> Result<(), A::Error>
+    where
+        F: FnOnce(Option<A::Handle>) -> Result<(), A::Error>,
+    {
+        let new = match image {
+            Some(image) => Some(self.allocator.create(image)?),
+            None => None,
+        };
+        if let Err(err) = apply(new) {
+            if let Some(new) = new {
+                self.allocator.destroy(new);
+            }
+            return Err(err);
+        }
+        let old = match kind {
+            IconKind::Small => mem::replace(&mut self.small, new),
+            IconKind::Big => mem::replace(&mut self.big, new),
+            IconKind::Overlay => mem::replace(&mut self.overlay, new),
+        };
+        if let Some(old) = old {
+            self.allocator.destroy(old);
+        }
+        Ok(())
+    }
+
+    /// Destroys every icon. `detach` is called for each kind that is set so the window can stop
+    /// referencing the handle first.
+    pub fn clear<F: FnMut(IconKind)>(&mut self, mut detach: F) {
+        for &kind in &[IconKind::Small, IconKind::Big, IconKind::Overlay] {
+            if self.handle(kind).is_some() {
+                detach(kind);
+            }
+        }
+        self.destroy_all();
+    }
+
+    fn destroy_all(&mut self) {
+        for handle in [self.small.take(), self.big.take(), self.overlay.take()].iter() {
+            if let Some(handle) = *handle {
+                self.allocator.destroy(handle);
+            }
+        }
+    }
+}
+
+impl<A: IconAllocator> Drop for WindowIcons<A> {
+    fn drop(&mut self) {
+        self.destroy_all();
+    }
+}
+
+/// An error produced when the RGBA data passed to `Icon::from_rgba` is invalid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BadIcon {
+    /// The length of the buffer isn't a multiple of 4, so it can't hold whole RGBA pixels.
+    ByteCountNotDivisibleBy4 { byte_count: usize },
+    /// The number of pixels in the buffer doesn't match `width * height`.
+    DimensionsVsPixelCount {
+        width: u32,
+        height: u32,
+        width_x_height: u64,
+        pixel_count: usize,
+    },
+    /// The icon has no pixels.
+    ZeroSize { width: u32, height: u32 },
+}
+
+impl fmt::Display for BadIcon {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BadIcon::ByteCountNotDivisibleBy4 { byte_count } => write!(
+                f,
+                "the length of the `rgba` argument ({}) isn't divisible by 4",
+                byte_count
+            ),
+            BadIcon::DimensionsVsPixelCount {
+                width,
+                height,
+                width_x_height,
+                pixel_count,
+            } => write!(
+                f,
+                "the specified dimensions ({}x{}) don't match the number of pixels supplied by \
+                 the `rgba` argument ({}); for those dimensions, the expected pixel count is {}",
+                width, height, pixel_count, width_x_height
+            ),
+            BadIcon::ZeroSize { width, height } => {
+                write!(f, "the icon is empty ({}x{})", width, height)
+            }
+        }
+    }
+}
+
+/// Checks that `rgba` holds exactly `width * height` pixels and that there is at least one.
+pub fn validate_rgba(rgba: &[u8], width: u32, height: u32) -> Result<(), BadIcon> {
+    if rgba.len() % PIXEL_SIZE != 0 {
+        return Err(BadIcon::ByteCountNotDivisibleBy4 {
+            byte_count: rgba.len(),
+        });
+    }
+    let pixel_count = rgba.len() / PIXEL_SIZE;
+    let width_x_height = width as u64 * height as u64;
+    if pixel_count as u64 != width_x_height {
+        return Err(BadIcon::DimensionsVsPixelCount {
+            width,
+            height,
+            width_x_height,
+            pixel_count,
+        });
+    }
+    if pixel_count == 0 {
+        return Err(BadIcon::ZeroSize { width, height });
+    }
+    Ok(())
+}
+
+/// Converts straight RGBA pixels into the two bitmaps `CreateIcon` takes: the color bitmap as
+/// 32 bpp BGRA, and the AND mask as a 1 bpp bitmap with each row padded to a `WORD` boundary.
+///
+/// A set bit in the mask marks a fully transparent pixel; Windows only falls back to the mask when
+/// it can't use the alpha channel of the color bitmap.
+///
+/// The input must have been checked with `validate_rgba`.
+pub fn rgba_to_bgra_and_mask(rgba: &[u8], width: u32, height: u32) -> (Vec<u8>, Vec<u8>) {
+    let (width, height) = (width as usize, height as usize);
+    let mask_stride = (width + 15) / 16 * 2;
+    let mut bgra = Vec::with_capacity(rgba.len());
+    let mut and_mask = vec![0u8; mask_stride * height];
+    for (i, pixel) in rgba.chunks(PIXEL_SIZE).enumerate() {
+        bgra.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+        if pixel[3] == 0 {
+            let (x, y) = (i % width, i / width);
+            and_mask[y * mask_stride + x / 8] |= 0x80 >> (x % 8);
+        }
+    }
+    (bgra, and_mask)
+}
+
+/// An error produced while loading an `Icon` from a file.
+#[derive(Debug)]
+pub enum IconLoadError {
+    Io(io::Error),
+    /// The data is neither an `.ico` nor a `.png` file.
+    UnknownFormat,
+    /// The file doesn't contain any images.
+    NoImages,
+    /// The `.ico` directory or one of its bitmaps is malformed.
+    InvalidIco(&'static str),
+    /// An `.ico` bitmap uses a bit depth or compression that isn't supported.
+    UnsupportedBitmap {
+        bit_count: u16,
+        compression: u32,
+    },
+    /// A PNG image could not be decoded.
+    Png(image::ImageError),
+}
+
+impl fmt::Display for IconLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IconLoadError::Io(ref err) => write!(f, "failed to read icon: {}", err),
+            IconLoadError::UnknownFormat => write!(f, "icon is neither an ICO nor a PNG file"),
+            IconLoadError::NoImages => write!(f, "icon file contains no images"),
+            IconLoadError::InvalidIco(reason) => write!(f, "invalid ICO file: {}", reason),
+            IconLoadError::UnsupportedBitmap {
+                bit_count,
+                compression,
+            } => write!(
+                f,
+                "unsupported ICO bitmap ({} bpp, compression {})",
+                bit_count, compression
+            ),
+            IconLoadError::Png(ref err) => write!(f, "failed to decode PNG icon: {}", err),
+        }
+    }
+}
+
+impl From<io::Error> for IconLoadError {
+    #[inline]
+    fn from(err: io::Error) -> Self {
+        IconLoadError::Io(err)
+    }
+}
+
+impl From<image::ImageError> for IconLoadError {
+    #[inline]
+    fn from(err: image::ImageError) -> Self {
+        IconLoadError::Png(err)
+    }
+}
+
+fn is_ico(bytes: &[u8]) -> bool {
+    // ICONDIR: reserved (0), type (1 for icons, 2 for cursors), image count
+    bytes.len() >= ICONDIR_SIZE
+        && read_u16(bytes, 0) == Some(0)
+        && (read_u16(bytes, 2) == Some(1) || read_u16(bytes, 2) == Some(2))
+}
+
+fn decode_png(bytes: &[u8]) -> Result<IconImage, IconLoadError> {
+    let rgba = image::load_from_memory_with_format(bytes, image::ImageFormat::PNG)?.to_rgba();
+    let (width, height) = rgba.dimensions();
+    Ok(IconImage {
+        rgba: rgba.into_raw(),
+        width,
+        height,
+    })
+}
+
+/// Parses every image of an `.ico` file.
+pub fn parse_ico(bytes: &[u8]) -> Result<Vec<IconImage>, IconLoadError> {
+    if !is_ico(bytes) {
+        return Err(IconLoadError::InvalidIco("missing ICONDIR header"));
+    }
+    let truncated = || IconLoadError::InvalidIco("truncated directory");
+    let count = read_u16(bytes, 4).ok_or_else(truncated)? as usize;
+    // `count` is at most 0xFFFF, so this can't overflow.
+    if bytes.len() < ICONDIR_SIZE + count * ICONDIRENTRY_SIZE {
+        return Err(truncated());
+    }
+    let mut images = Vec::with_capacity(count);
+    for i in 0..count {
+        let entry = ICONDIR_SIZE + i * ICONDIRENTRY_SIZE;
+        let size = read_u32(bytes, entry + 8).ok_or_else(truncated)? as usize;
+        let offset = read_u32(bytes, entry + 12).ok_or_else(truncated)? as usize;
+        let data = match offset.checked_add(size) {
+            Some(end) if end <= bytes.len() => &bytes[offset..end],
+            _ => return Err(IconLoadError::InvalidIco("image data out of bounds")),
+        };
+        if data.starts_with(PNG_SIGNATURE) {
+            images.push(decode_png(data)?);
+        } else {
+            images.push(decode_ico_bitmap(data)?);
+        }
+    }
+    Ok(images)
+}
+
+/// Decodes a DIB stored in an `.ico` file: a `BITMAPINFOHEADER` whose height covers both the
+/// color (XOR) bitmap and the 1 bpp transparency (AND) mask, followed by an optional palette and
+/// the two bottom-up bitmaps.
+fn decode_ico_bitmap(data: &[u8]) -> Result<IconImage, IconLoadError> {
+    let truncated = || IconLoadError::InvalidIco("truncated bitmap header");
+    if data.len() < BITMAPINFOHEADER_SIZE {
+        return Err(truncated());
+    }
+    let u32_at = |offset| read_u32(data, offset).ok_or_else(truncated);
+    let header_size = u32_at(0)? as usize;
+    let width = u32_at(4)? as i32;
+    let double_height = u32_at(8)? as i32;
+    let bit_count = read_u16(data, 14).ok_or_else(truncated)?;
+    let compression = u32_at(16)?;
+    let colors_used = u32_at(32)? as usize;
+    if header_size < BITMAPINFOHEADER_SIZE || header_size > data.len() {
+        return Err(IconLoadError::InvalidIco("bad bitmap header size"));
+    }
+    // The height covers both bitmaps, so an odd height can't be split between them.
+    if width <= 0 || double_height <= 0 || double_height % 2 != 0 {
+        return Err(IconLoadError::InvalidIco("bad bitmap dimensions"));
+    }
+    let supported = match (bit_count, compression) {
+        (1, BI_RGB) | (4, BI_RGB) | (8, BI_RGB) | (24, BI_RGB) | (32, BI_RGB) => true,
+        (32, BI_BITFIELDS) => true,
+        _ => false,
+    };
+    if !supported {
+        return Err(IconLoadError::UnsupportedBitmap {
+            bit_count,
+            compression,
+        });
+    }
+    let (width, height) = (width as usize, double_height as usize / 2);
+
+    let mut offset = header_size;
+    if compression == BI_BITFIELDS && header_size == BITMAPINFOHEADER_SIZE {
+        // Three DWORD color masks follow the header; icons always use the standard BGRA layout.
+        offset += 12;
+    }
+    let palette_len = if bit_count <= 8 {
+        if colors_used == 0 {
+            1 << bit_count
+        } else {
+            colors_used
+        }
+    } else {
+        0
+    };
+    let truncated = IconLoadError::InvalidIco("truncated bitmap data");
+    let palette_start = offset;
+    let color_start = match palette_len
+        .checked_mul(4)
+        .and_then(|n| n.checked_add(offset))
+    {
+        Some(start) if start <= data.len() => start,
+        _ => return Err(truncated),
+    };
+
+    // `width` and `height` are below 2^31 and `bit_count` is at most 32, so the strides can't
+    // overflow, but their products with `height` can.
+    let color_stride = (width * bit_count as usize + 31) / 32 * 4;
+    let mask_stride = (width + 31) / 32 * 4;
+    let mask_start = match color_stride
+        .checked_mul(height)
+        .and_then(|n| n.checked_add(color_start))
+    {
+        Some(start) if start <= data.len() => start,
+        _ => return Err(truncated),
+    };
+    // Some encoders omit the AND mask for 32 bpp images, relying on the alpha channel alone.
+    let has_mask = match mask_stride.checked_mul(height) {
+        Some(len) => len <= data.len() - mask_start,
+        None => false,
+    };
+    if !has_mask && bit_count != 32 {
+        return Err(truncated);
+    }
+    let rgba_len = width
+        .checked_mul(height)
+        .and_then(|n| n.checked_mul(PIXEL_SIZE))
+        .ok_or(IconLoadError::InvalidIco("bitmap too large"))?;
+
+    let mut rgba = vec![0u8; rgba_len];
+    let mut any_alpha = false;
+    for y in 0..height {
+        // Rows are stored bottom-up.
+        let row = &data[color_start + (height - 1 - y) * color_stride..][..color_stride];
+        for x in 0..width {
+            let (b, g, r, a) = match bit_count {
+                32 => (row[x * 4], row[x * 4 + 1], row[x * 4 + 2], row[x * 4 + 3]),
+                24 => (row[x * 3], row[x * 3 + 1], row[x * 3 + 2], 0xFF),
+                _ => {
+                    let bits = bit_count as usize;
+                    let bit = x * bits;
+                    let index = (row[bit / 8] >> (8 - bits - bit % 8)) as usize & ((1 << bits) - 1);
+                    if index >= palette_len {
+                        return Err(IconLoadError::InvalidIco("palette index out of range"));
+                    }
+                    let entry = palette_start + index * 4;
+                    (data[entry], data[entry + 1], data[entry + 2], 0xFF)
+                }
+            };
+            any_alpha |= bit_count == 32 && a != 0;
+            let i = (y * width + x) * 4;
+            rgba[i] = r;
+            rgba[i + 1] = g;
+            rgba[i + 2] = b;
+            rgba[i + 3] = a;
+        }
+    }
+
+    // The AND mask decides transparency unless the image carries its own alpha channel.
+    if !any_alpha {
+        for y in 0..height {
+            for x in 0..width {
+                let transparent = has_mask && {
+                    let row = mask_start + (height - 1 - y) * mask_stride;
+                    data[row + x / 8] & (0x80 >> (x % 8)) != 0
+                };
+                rgba[(y * width + x) * 4 + 3] = if transparent { 0 } else { 0xFF };
+            }
+        }
+    }
+
+    Ok(IconImage {
+        rgba,
+        width: width as u32,
+        height: height as u32,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use le_bytes::write_u32;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    static MULTI_ICO: &[u8] = include_bytes!("../fixtures/multi.ico");
+    static DEPTHS_ICO: &[u8] = include_bytes!("../fixtures/depths.ico");
+    static ICON_PNG: &[u8] = include_bytes!("../fixtures/icon.png");
+
+    fn pixel(image: &IconImage, x: u32, y: u32) -> [u8; 4] {
+        let i = ((y * image.width + x) * 4) as usize;
+        [
+            image.rgba[i],
+            image.rgba[i + 1],
+            image.rgba[i + 2],
+            image.rgba[i + 3],
+        ]
+    }
+
+    fn check_pixels<F: Fn(u32, u32) -> [u8; 4]>(image: &IconImage, expected: F) {
+        for y in 0..image.height {
+            for x in 0..image.width {
+                assert_eq!(pixel(image, x, y), expected(x, y), "pixel ({}, {})", x, y);
+            }
+        }
+    }
+
+    fn invalid_ico(result: Result<Vec<IconImage>, IconLoadError>) -> &'static str {
+        match result {
+            Err(IconLoadError::InvalidIco(reason)) => reason,
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("parsed invalid ICO"),
+        }
+    }
+
+    // The offset of the first image's data, read from its directory entry.
+    fn first_image_offset(ico: &[u8]) -> usize {
+        read_u32(ico, ICONDIR_SIZE + 12).unwrap() as usize
+    }
+
+    #[test]
+    fn parses_32bpp_and_png_images() {
+        let images = parse_ico(MULTI_ICO).unwrap();
+        let sizes: Vec<_> = images.iter().map(|i| (i.width, i.height)).collect();
+        assert_eq!(sizes, vec![(16, 16), (32, 32), (48, 48)]);
+        // The alpha channel wins over the AND mask.
+        check_pixels(&images[0], |x, y| {
+            [
+                (x * 16) as u8,
+                (y * 16) as u8,
+                0x80,
+                if x == y { 0 } else { 0xFF },
+            ]
+        });
+        check_pixels(&images[1], |x, _| [0x11, 0x22, 0x33, (x * 8) as u8]);
+        // This one has no AND mask at all.
+        check_pixels(&images[2], |_, y| {
+            [0xFF, 0, 0, if y < 24 { 0xFF } else { 0x40 }]
+        });
+    }
+
+    #[test]
+    fn parses_paletted_and_24bpp_images() {
+        let images = parse_ico(DEPTHS_ICO).unwrap();
+        let sizes: Vec<_> = images.iter().map(|i| (i.width, i.height)).collect();
+        assert_eq!(sizes, vec![(9, 2), (5, 3), (3, 3), (3, 2)]);
+        check_pixels(&images[0], |x, y| {
+            let c = if (x + y) % 2 == 1 { 0xFF } else { 0 };
+            [c, c, c, if x == 8 { 0 } else { 0xFF }]
+        });
+        check_pixels(&images[1], |x, y| {
+            let i = ((x + y * 5) % 16) as u8;
+            [
+                i * 17,
+                255 - i * 17,
+                i * 8,
+                if x == 0 && y == 0 { 0 } else { 0xFF },
+            ]
+        });
+        // Uses `biClrUsed` for a palette shorter than 256 entries.
+        check_pixels(&images[2], |x, y| {
+            let i = (x * 3 + y) as u8;
+            [i, 2 * i, 3 * i, 0xFF]
+        });
+        check_pixels(&images[3], |x, y| {
+            [
+                (x * 80) as u8,
+                (y * 100) as u8,
+                7,
+                if x == 2 && y == 1 { 0 } else { 0xFF },
+            ]
+        });
+    }
+
+    #[test]
+    fn loads_icons_from_bytes_and_paths() {
+        let icon = Icon::from_bytes(ICON_PNG).unwrap();
+        assert_eq!(icon.images().len(), 1);
+        check_pixels(&icon.images()[0], |x, y| {
+            [(x * 100) as u8, (y * 200) as u8, 50, (255 - x * 100) as u8]
+        });
+        assert_eq!(Icon::from_bytes(MULTI_ICO).unwrap().images().len(), 3);
+
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/depths.ico");
+        assert_eq!(Icon::from_path(path).unwrap().images().len(), 4);
+        match Icon::from_path(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/missing.ico")) {
+            Err(IconLoadError::Io(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_and_empty_files() {
+        match Icon::from_bytes(b"GIF89a") {
+            Err(IconLoadError::UnknownFormat) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match Icon::from_bytes(&[0, 0, 1, 0, 0, 0]) {
+            Err(IconLoadError::NoImages) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match Icon::from_bytes(&ICON_PNG[..40]) {
+            Err(IconLoadError::Png(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_directories() {
+        assert_eq!(invalid_ico(parse_ico(b"\x89PNG")), "missing ICONDIR header");
+        assert_eq!(
+            invalid_ico(parse_ico(&MULTI_ICO[..20])),
+            "truncated directory"
+        );
+
+        let mut ico = MULTI_ICO.to_vec();
+        write_u32(&mut ico, ICONDIR_SIZE + 8, MULTI_ICO.len() as u32);
+        assert_eq!(invalid_ico(parse_ico(&ico)), "image data out of bounds");
+        write_u32(&mut ico, ICONDIR_SIZE + 8, 0xFFFF_FFFF);
+        write_u32(&mut ico, ICONDIR_SIZE + 12, 0xFFFF_FFFF);
+        assert_eq!(invalid_ico(parse_ico(&ico)), "image data out of bounds");
+    }
+
+    #[test]
+    fn rejects_malformed_bitmaps() {
+        let header = first_image_offset(DEPTHS_ICO);
+        let patched = |offset: usize, value: u32| {
+            let mut ico = DEPTHS_ICO.to_vec();
+            write_u32(&mut ico, header + offset, value);
+            parse_ico(&ico)
+        };
+        assert_eq!(invalid_ico(patched(0, 12)), "bad bitmap header size");
+        assert_eq!(invalid_ico(patched(0, 0x1000)), "bad bitmap header size");
+        assert_eq!(invalid_ico(patched(4, 0)), "bad bitmap dimensions");
+        assert_eq!(
+            invalid_ico(patched(8, 0xFFFF_FFFE)),
+            "bad bitmap dimensions"
+        );
+        // A height of 1 can't hold both the color bitmap and the mask.
+        assert_eq!(invalid_ico(patched(8, 1)), "bad bitmap dimensions");
+        assert_eq!(invalid_ico(patched(8, 5)), "bad bitmap dimensions");
+        assert_eq!(
+            invalid_ico(patched(8, 0x7FFF_FFFE)),
+            "truncated bitmap data"
+        );
+        assert_eq!(
+            invalid_ico(patched(4, 0x7FFF_FFFF)),
+            "truncated bitmap data"
+        );
+        // A palette far bigger than the file, which would overflow if it weren't checked.
+        assert_eq!(
+            invalid_ico(patched(32, 0xFFFF_FFFF)),
+            "truncated bitmap data"
+        );
+
+        match patched(14, 16) {
+            Err(IconLoadError::UnsupportedBitmap {
+                bit_count: 16,
+                compression: 0,
+            }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_out_of_range_palette_indices() {
+        // Shrink the 1 bpp image's palette to a single color; every odd pixel then indexes past it.
+        let header = first_image_offset(DEPTHS_ICO);
+        let mut ico = DEPTHS_ICO.to_vec();
+        write_u32(&mut ico, header + 32, 1);
+        assert_eq!(invalid_ico(parse_ico(&ico)), "palette index out of range");
+    }
+
+    #[test]
+    fn requires_a_mask_below_32bpp() {
+        let header = first_image_offset(DEPTHS_ICO);
+        let size = read_u32(DEPTHS_ICO, ICONDIR_SIZE + 8).unwrap() as usize;
+        // The 9x2 image's mask is two rows of 4 bytes; drop the last one.
+        let mut ico = DEPTHS_ICO[..header + size - 4].to_vec();
+        write_u32(&mut ico, ICONDIR_SIZE + 8, size as u32 - 4);
+        assert_eq!(invalid_ico(parse_ico(&ico)), "truncated bitmap data");
+    }
+
+    #[test]
+    fn validates_rgba() {
+        assert_eq!(validate_rgba(&[0; 8], 2, 1), Ok(()));
+        assert_eq!(
+            validate_rgba(&[0; 7], 2, 1),
+            Err(BadIcon::ByteCountNotDivisibleBy4 { byte_count: 7 })
+        );
+        assert_eq!(
+            validate_rgba(&[0; 8], 3, 1),
+            Err(BadIcon::DimensionsVsPixelCount {
+                width: 3,
+                height: 1,
+                width_x_height: 3,
+                pixel_count: 2,
+            })
+        );
+        // The product is computed in 64 bits, so it can't wrap around to the pixel count.
+        assert_eq!(
+            validate_rgba(&[0; 4], 0x1_0000, 0x1_0000),
+            Err(BadIcon::DimensionsVsPixelCount {
+                width: 0x1_0000,
+                height: 0x1_0000,
+                width_x_height: 0x1_0000_0000,
+                pixel_count: 1,
+            })
+        );
+        assert_eq!(
+            validate_rgba(&[], 0, 5),
+            Err(BadIcon::ZeroSize {
+                width: 0,
+                height: 5,
+            })
+        );
+        assert_eq!(
+            Icon::from_rgba(vec![0; 3], 1, 1),
+            Err(BadIcon::ByteCountNotDivisibleBy4 { byte_count: 3 })
+        );
+        assert!(Icon::from_rgba(vec![0; 4], 1, 1).is_ok());
+    }
+
+    #[test]
+    fn converts_rgba_to_bgra_and_mask() {
+        for &width in &[1u32, 15, 16, 17, 33] {
+            let height = 3;
+            // Every pixel on the diagonal, and the whole last column, is transparent.
+            let transparent = |x: u32, y: u32| x == y || x == width - 1;
+            let mut rgba = Vec::new();
+            for y in 0..height {
+                for x in 0..width {
+                    let a = if transparent(x, y) { 0 } else { 0x80 };
+                    rgba.extend_from_slice(&[x as u8, y as u8, 0x55, a]);
+                }
+            }
+            let (bgra, mask) = rgba_to_bgra_and_mask(&rgba, width, height);
+
+            let stride = match width {
+                1 | 15 | 16 => 2,
+                17 => 4,
+                _ => 6,
+            };
+            assert_eq!(mask.len(), stride * height as usize, "width {}", width);
+            assert_eq!(bgra.len(), rgba.len());
+            for y in 0..height {
+                for x in 0..width {
+                    let i = ((y * width + x) * 4) as usize;
+                    assert_eq!(&bgra[i..i + 4], &[0x55, y as u8, x as u8, rgba[i + 3]]);
+                    let bit = mask[y as usize * stride + x as usize / 8] & (0x80 >> (x % 8));
+                    assert_eq!(
+                        bit != 0,
+                        transparent(x, y),
+                        "width {}, ({}, {})",
+                        width,
+                        x,
+                        y
+                    );
+                }
+                // Padding bits after the last pixel of a row stay clear.
+                for x in width..(stride * 8) as u32 {
+                    let bit = mask[y as usize * stride + x as usize / 8] & (0x80 >> (x % 8));
+                    assert_eq!(bit, 0, "width {}, padding bit {}", width, x);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn selects_best_size() {
+        let sizes = [(16, 16), (48, 48), (32, 32), (32, 32), (256, 256)];
+        assert_eq!(select_best_size(&sizes, 16), Some(0));
+        assert_eq!(select_best_size(&sizes, 20), Some(2));
+        assert_eq!(select_best_size(&sizes, 32), Some(2));
+        assert_eq!(select_best_size(&sizes, 33), Some(1));
+        assert_eq!(select_best_size(&sizes, 300), Some(4));
+        assert_eq!(select_best_size(&[(8, 8), (24, 24)], 64), Some(1));
+        // Non-square images are measured by their longer side.
+        assert_eq!(select_best_size(&[(64, 16), (20, 24)], 24), Some(1));
+        assert_eq!(select_best_size(&[], 32), None);
+
+        let icon = Icon::from_bytes(MULTI_ICO).unwrap();
+        assert_eq!(icon.best_image(24).width(), 32);
+        assert_eq!(icon.best_image(64).width(), 48);
+    }
+
+    #[derive(Default)]
+    struct Handles {
+        next: u32,
+        live: Vec<u32>,
+        fail_create: bool,
+    }
+
+    /// Hands out numbered handles and panics on a double destroy; `live` must be empty once the
+    /// `WindowIcons` is gone.
+    #[derive(Clone, Default)]
+    struct MockAllocator(Rc<RefCell<Handles>>);
+
+    impl MockAllocator {
+        fn live(&self) -> Vec<u32> {
+            self.0.borrow().live.clone()
+        }
+    }
+
+    impl IconAllocator for MockAllocator {
+        type Handle = u32;
+        type Error = &'static str;
+
+        fn create(&mut self, _: &IconImage) -> Result<u32, &'static str> {
+            let mut handles = self.0.borrow_mut();
+            if handles.fail_create {
+                return Err("create failed");
+            }
+            handles.next += 1;
+            let handle = handles.next;
+            handles.live.push(handle);
+            Ok(handle)
+        }
+
+        fn destroy(&mut self, handle: u32) {
+            let mut handles = self.0.borrow_mut();
+            let i = handles
+                .live
+                .iter()
+                .position(|&h| h == handle)
+                .expect("destroyed a handle that isn't live");
+            handles.live.remove(i);
+        }
+    }
+
+    fn image() -> IconImage {
+        Icon::from_rgba(vec![0; 4], 1, 1).unwrap().images[0].clone()
+    }
+
+    #[test]
+    fn replaces_icons_after_applying_them() {
+        let allocator = MockAllocator::default();
+        let mut icons = WindowIcons::new(allocator.clone());
+        let image = image();
+
+        icons
+            .set(IconKind::Small, Some(&image), |h| {
+                assert_eq!(h, Some(1));
+                Ok(())
+            })
+            .unwrap();
+        icons
+            .set(IconKind::Small, Some(&image), |h| {
+                assert_eq!(h, Some(2));
+                // The old handle is still alive while the window switches over.
+                assert_eq!(allocator.live(), vec![1, 2]);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(allocator.live(), vec![2]);
+        assert_eq!(icons.handle(IconKind::Small), Some(2));
+        assert_eq!(icons.handle(IconKind::Big), None);
+
+        icons
+            .set(IconKind::Small, None, |h| {
+                assert_eq!(h, None);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(icons.handle(IconKind::Small), None);
+        assert!(allocator.live().is_empty());
+    }
+
+    #[test]
+    fn keeps_the_old_icon_when_creating_fails() {
+        let allocator = MockAllocator::default();
+        let mut icons = WindowIcons::new(allocator.clone());
+        let image = image();
+        icons.set(IconKind::Big, Some(&image), |_| Ok(())).unwrap();
+
+        allocator.0.borrow_mut().fail_create = true;
+        let mut applied = false;
+        let result = icons.set(IconKind::Big, Some(&image), |_| {
+            applied = true;
+            Ok(())
+        });
+        assert_eq!(result, Err("create failed"));
+        assert!(!applied);
+        assert_eq!(icons.handle(IconKind::Big), Some(1));
+        assert_eq!(allocator.live(), vec![1]);
+    }
+
+    #[test]
+    fn destroys_the_new_icon_when_applying_fails() {
+        let allocator = MockAllocator::default();
+        let mut icons = WindowIcons::new(allocator.clone());
+        let image = image();
+        icons
+            .set(IconKind::Overlay, Some(&image), |_| Ok(()))
+            .unwrap();
+
+        let result = icons.set(IconKind::Overlay, Some(&image), |_| Err("apply failed"));
+        assert_eq!(result, Err("apply failed"));
+        assert_eq!(icons.handle(IconKind::Overlay), Some(1));
+        assert_eq!(allocator.live(), vec![1]);
+
+        // Failing to remove the icon keeps it too.
+        let result = icons.set(IconKind::Overlay, None, |_| Err("apply failed"));
+        assert_eq!(result, Err("apply failed"));
+        assert_eq!(icons.handle(IconKind::Overlay), Some(1));
+        assert_eq!(allocator.live(), vec![1]);
+    }
+
+    #[test]
+    fn clear_detaches_then_destroys_every_icon() {
+        let allocator = MockAllocator::default();
+        let mut icons = WindowIcons::new(allocator.clone());
+        let image = image();
+        icons
+            .set(IconKind::Small, Some(&image), |_| Ok(()))
+            .unwrap();
+        icons
+            .set(IconKind::Overlay, Some(&image), |_| Ok(()))
+            .unwrap();
+
+        let mut detached = Vec::new();
+        icons.clear(|kind| {
+            // Handles are only destroyed after every kind was detached.
+            assert_eq!(allocator.live().len(), 2);
+            detached.push(kind);
+        });
+        assert_eq!(detached, vec![IconKind::Small, IconKind::Overlay]);
+        assert!(allocator.live().is_empty());
+        assert_eq!(icons.handle(IconKind::Small), None);
+
+        // Nothing is left to detach or destroy the second time.
+        icons.clear(|_| panic!("nothing to detach"));
+    }
+
+    #[test]
+    fn drop_destroys_every_icon() {
+        let allocator = MockAllocator::default();
+        {
+            let mut icons = WindowIcons::new(allocator.clone());
+            let image = image();
+            for &kind in &[IconKind::Small, IconKind::Big, IconKind::Overlay] {
+                icons.set(kind, Some(&image), |_| Ok(())).unwrap();
+            }
+            icons.set(IconKind::Big, Some(&image), |_| Ok(())).unwrap();
+            assert_eq!(allocator.live(), vec![1, 3, 4]);
+        }
+        assert!(allocator.live().is_empty());
+    }
+}
@@ -0,0 +1,252 @@
+use image::{self, ImageError, ImageResult};
+
+use bitmap::Bitmap;
+
+/// How the image of a surface brush is scaled to fill the area being painted.
+///
+/// These mirror `CompositionStretch`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Stretch {
+    /// The image keeps its original size.
+    None,
+    /// The image is scaled to fill the area, ignoring its aspect ratio.
+    Fill,
+    /// The image is scaled to fit inside the area, keeping its aspect ratio.
+    Uniform,
+    /// The image is scaled to cover the area, keeping its aspect ratio. Parts of the image may be
+    /// clipped.
+    UniformToFill,
+}
+
+/// Options for painting an image with a surface brush.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ImageBrushOptions {
+    /// The default is `Stretch::Uniform`.
+    pub stretch: Stretch,
+    /// Where the image is placed horizontally when it doesn't fill the area: `0.0` aligns its left
+    /// edge with the left edge of the area, `1.0` aligns the right edges.
+    ///
+    /// The default is `0.5`.
+    pub horizontal_alignment_ratio: f32,
+    /// Where the image is placed vertically when it doesn't fill the area: `0.0` aligns the top
+    /// edges, `1.0` aligns the bottom edges.
+    ///
+    /// The default is `0.5`.
+    pub vertical_alignment_ratio: f32,
+}
+
+impl Default for ImageBrushOptions {
+    #[inline]
+    fn default() -> Self {
+        ImageBrushOptions {
+            stretch: Stretch::Uniform,
+            horizontal_alignment_ratio: 0.5,
+            vertical_alignment_ratio: 0.5,
+        }
+    }
+}
+
+/// An axis-aligned rectangle in pixels.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Computes where an image of size `content` ends up when painted into an area of size `bounds`.
+///
+/// The returned rectangle is relative to the top-left corner of the area and may extend past it
+/// for `Stretch::None` and `Stretch::UniformToFill`.
+pub fn stretch_rect(content: (f32, f32), bounds: (f32, f32), options: &ImageBrushOptions) -> Rect {
+    let (content_w, content_h) = content;
+    let (bounds_w, bounds_h) = bounds;
+    let (width, height) = if content_w <= 0.0 || content_h <= 0.0 {
+        (0.0, 0.0)
+    } else {
+        match options.stretch {
+            Stretch::None => (content_w, content_h),
+            Stretch::Fill => (bounds_w, bounds_h),
+            Stretch::Uniform => {
+                let scale = (bounds_w / content_w).min(bounds_h / content_h);
+                (content_w * scale, content_h * scale)
+            }
+            Stretch::UniformToFill => {
+                let scale = (bounds_w / content_w).max(bounds_h / content_h);
+                (content_w * scale, content_h * scale)
+            }
+        }
+    };
+    Rect {
+        x: (bounds_w - width) * options.horizontal_alignment_ratio,
+        y: (bounds_h - height) * options.vertical_alignment_ratio,
+        width,
+        height,
+    }
+}
+
+/// Decodes a PNG, JPEG or BMP image into a premultiplied BGRA bitmap.
+pub fn decode_premultiplied(bytes: &[u8]) -> ImageResult<Bitmap> {
+    let rgba = image::load_from_memory(bytes)?.to_rgba();
+    let (width, height) = rgba.dimensions();
+    Bitmap::from_rgba(&rgba.into_raw(), width, height).ok_or(ImageError::DimensionError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: f32, y: f32, width: f32, height: f32) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn stretched(stretch: Stretch, content: (f32, f32), bounds: (f32, f32)) -> Rect {
+        let options = ImageBrushOptions {
+            stretch,
+            ..Default::default()
+        };
+        stretch_rect(content, bounds, &options)
+    }
+
+    #[test]
+    fn stretch_modes() {
+        let (content, bounds) = ((100.0, 50.0), (200.0, 200.0));
+        assert_eq!(
+            stretched(Stretch::None, content, bounds),
+            rect(50.0, 75.0, 100.0, 50.0)
+        );
+        assert_eq!(
+            stretched(Stretch::Fill, content, bounds),
+            rect(0.0, 0.0, 200.0, 200.0)
+        );
+        assert_eq!(
+            stretched(Stretch::Uniform, content, bounds),
+            rect(0.0, 50.0, 200.0, 100.0)
+        );
+        // Clipped on the left and right.
+        assert_eq!(
+            stretched(Stretch::UniformToFill, content, bounds),
+            rect(-100.0, 0.0, 400.0, 200.0)
+        );
+        assert_eq!(ImageBrushOptions::default().stretch, Stretch::Uniform);
+    }
+
+    #[test]
+    fn alignment_ratios() {
+        let aligned = |stretch, horizontal, vertical| {
+            let options = ImageBrushOptions {
+                stretch,
+                horizontal_alignment_ratio: horizontal,
+                vertical_alignment_ratio: vertical,
+            };
+            stretch_rect((100.0, 50.0), (200.0, 200.0), &options)
+        };
+        assert_eq!(
+            aligned(Stretch::Uniform, 0.5, 0.0),
+            rect(0.0, 0.0, 200.0, 100.0)
+        );
+        assert_eq!(
+            aligned(Stretch::Uniform, 0.5, 1.0),
+            rect(0.0, 100.0, 200.0, 100.0)
+        );
+        assert_eq!(
+            aligned(Stretch::None, 1.0, 0.0),
+            rect(100.0, 0.0, 100.0, 50.0)
+        );
+        assert_eq!(
+            aligned(Stretch::None, 0.25, 0.75),
+            rect(25.0, 112.5, 100.0, 50.0)
+        );
+        // Content larger than the area moves the other way.
+        assert_eq!(
+            aligned(Stretch::UniformToFill, 0.0, 0.0),
+            rect(0.0, 0.0, 400.0, 200.0)
+        );
+        assert_eq!(
+            aligned(Stretch::UniformToFill, 1.0, 1.0),
+            rect(-200.0, 0.0, 400.0, 200.0)
+        );
+    }
+
+    #[test]
+    fn empty_content_or_bounds() {
+        for &stretch in &[
+            Stretch::None,
+            Stretch::Fill,
+            Stretch::Uniform,
+            Stretch::UniformToFill,
+        ] {
+            assert_eq!(
+                stretched(stretch, (0.0, 50.0), (200.0, 100.0)),
+                rect(100.0, 50.0, 0.0, 0.0)
+            );
+            assert_eq!(
+                stretched(stretch, (50.0, -1.0), (200.0, 100.0)),
+                rect(100.0, 50.0, 0.0, 0.0)
+            );
+        }
+        let bounds = (0.0, 100.0);
+        assert_eq!(
+            stretched(Stretch::None, (100.0, 50.0), bounds),
+            rect(-50.0, 25.0, 100.0, 50.0)
+        );
+        assert_eq!(
+            stretched(Stretch::Fill, (100.0, 50.0), bounds),
+            rect(0.0, 0.0, 0.0, 100.0)
+        );
+        assert_eq!(
+            stretched(Stretch::Uniform, (100.0, 50.0), bounds),
+            rect(0.0, 50.0, 0.0, 0.0)
+        );
+        assert_eq!(
+            stretched(Stretch::UniformToFill, (100.0, 50.0), bounds),
+            rect(-100.0, 0.0, 200.0, 100.0)
+        );
+    }
+
+    #[test]
+    fn decodes_png_premultiplied() {
+        let bitmap = decode_premultiplied(include_bytes!("../fixtures/image_rgba.png")).unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (2, 2));
+        // Opaque red, half-transparent green, transparent blue and quarter-opaque white.
+        assert_eq!(bitmap.pixel(0, 0), [0, 0, 255, 255]);
+        assert_eq!(bitmap.pixel(1, 0), [0, 128, 0, 128]);
+        assert_eq!(bitmap.pixel(0, 1), [0, 0, 0, 0]);
+        assert_eq!(bitmap.pixel(1, 1), [64, 64, 64, 64]);
+    }
+
+    #[test]
+    fn decodes_bmp() {
+        let bitmap = decode_premultiplied(include_bytes!("../fixtures/image_rgb.bmp")).unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (3, 1));
+        assert_eq!(bitmap.pixel(0, 0), [30, 20, 10, 255]);
+        assert_eq!(bitmap.pixel(1, 0), [60, 50, 40, 255]);
+        assert_eq!(bitmap.pixel(2, 0), [90, 80, 70, 255]);
+    }
+
+    #[test]
+    fn decodes_jpeg() {
+        let bitmap = decode_premultiplied(include_bytes!("../fixtures/image_gray.jpg")).unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (8, 8));
+        // Mid gray, give or take the rounding of the compression.
+        for pixel in bitmap.data.chunks(4) {
+            assert_eq!(pixel[3], 255);
+            for &channel in &pixel[..3] {
+                assert!((126..=130).contains(&channel), "{:?}", pixel);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_bad_data() {
+        assert!(decode_premultiplied(b"not an image").is_err());
+        let png = include_bytes!("../fixtures/image_rgba.png");
+        assert!(decode_premultiplied(&png[..png.len() / 2]).is_err());
+    }
+}
@@ -0,0 +1,159 @@
+//! The vtables of the composition interop interfaces, as `windows.ui.composition.interop.h`
+//! declares them.
+//!
+//! The platform code declares the interfaces with WinRT's macros, which can't be built here. These
+//! plain declarations describe the same layouts, so that the slot of each method can be tested on
+//! any platform, and the platform code checks that its vtables are the same size.
+#![allow(non_snake_case, clippy::upper_case_acronyms)]
+
+use std::os::raw::c_void;
+
+type HRESULT = i32;
+type BOOL = i32;
+type DWORD = u32;
+type HANDLE = *mut c_void;
+type HWND = *mut c_void;
+
+/// `SIZE`, the only structure that an interop method takes by value.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SIZE {
+    pub cx: i32,
+    pub cy: i32,
+}
+
+/// The first three slots of every COM vtable.
+#[repr(C)]
+pub struct IUnknownVtbl {
+    pub QueryInterface:
+        unsafe extern "system" fn(*mut c_void, *const c_void, *mut *mut c_void) -> HRESULT,
+    pub AddRef: unsafe extern "system" fn(*mut c_void) -> u32,
+    pub Release: unsafe extern "system" fn(*mut c_void) -> u32,
+}
+
+#[repr(C)]
+pub struct ICompositorDesktopInteropVtbl {
+    pub parent: IUnknownVtbl,
+    pub CreateDesktopWindowTarget:
+        unsafe extern "system" fn(*mut c_void, HWND, BOOL, *mut *mut c_void) -> HRESULT,
+    pub EnsureOnThread: unsafe extern "system" fn(*mut c_void, DWORD) -> HRESULT,
+}
+
+#[repr(C)]
+pub struct ICompositorInteropVtbl {
+    pub parent: IUnknownVtbl,
+    pub CreateCompositionSurfaceForHandle:
+        unsafe extern "system" fn(*mut c_void, HANDLE, *mut *mut c_void) -> HRESULT,
+    pub CreateCompositionSurfaceForSwapChain:
+        unsafe extern "system" fn(*mut c_void, *mut c_void, *mut *mut c_void) -> HRESULT,
+    pub CreateGraphicsDevice:
+        unsafe extern "system" fn(*mut c_void, *mut c_void, *mut *mut c_void) -> HRESULT,
+}
+
+#[repr(C)]
+pub struct ICompositionDrawingSurfaceInteropVtbl {
+    pub parent: IUnknownVtbl,
+    pub BeginDraw: unsafe extern "system" fn(
+        *mut c_void,
+        *const c_void,
+        *const c_void,
+        *mut *mut c_void,
+        *mut c_void,
+    ) -> HRESULT,
+    pub EndDraw: unsafe extern "system" fn(*mut c_void) -> HRESULT,
+    pub Resize: unsafe extern "system" fn(*mut c_void, SIZE) -> HRESULT,
+    pub Scroll:
+        unsafe extern "system" fn(*mut c_void, *const c_void, *const c_void, i32, i32) -> HRESULT,
+    pub ResumeDraw: unsafe extern "system" fn(*mut c_void) -> HRESULT,
+    pub SuspendDraw: unsafe extern "system" fn(*mut c_void) -> HRESULT,
+}
+
+#[repr(C)]
+pub struct ICompositionGraphicsDeviceInteropVtbl {
+    pub parent: IUnknownVtbl,
+    pub GetRenderingDevice: unsafe extern "system" fn(*mut c_void, *mut *mut c_void) -> HRESULT,
+    pub SetRenderingDevice: unsafe extern "system" fn(*mut c_void, *mut c_void) -> HRESULT,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::{size_of, MaybeUninit};
+    use std::ptr;
+
+    /// The index of a method in a vtable, counting from `QueryInterface`.
+    macro_rules! slot {
+        ($vtbl:ty, $($field:ident).+) => {{
+            let vtbl = MaybeUninit::<$vtbl>::uninit();
+            let base = vtbl.as_ptr();
+            let field = unsafe { ptr::addr_of!((*base).$($field).+) };
+            (field as usize - base as usize) / size_of::<usize>()
+        }};
+    }
+
+    #[test]
+    fn unknown_slots() {
+        assert_eq!(slot!(IUnknownVtbl, QueryInterface), 0);
+        assert_eq!(slot!(IUnknownVtbl, AddRef), 1);
+        assert_eq!(slot!(IUnknownVtbl, Release), 2);
+        assert_eq!(slot!(ICompositorInteropVtbl, parent.Release), 2);
+        assert_eq!(size_of::<IUnknownVtbl>(), 3 * size_of::<usize>());
+    }
+
+    #[test]
+    fn compositor_desktop_interop_slots() {
+        assert_eq!(
+            slot!(ICompositorDesktopInteropVtbl, CreateDesktopWindowTarget),
+            3
+        );
+        assert_eq!(slot!(ICompositorDesktopInteropVtbl, EnsureOnThread), 4);
+        assert_eq!(
+            size_of::<ICompositorDesktopInteropVtbl>(),
+            5 * size_of::<usize>()
+        );
+    }
+
+    #[test]
+    fn compositor_interop_slots() {
+        assert_eq!(
+            slot!(ICompositorInteropVtbl, CreateCompositionSurfaceForHandle),
+            3
+        );
+        assert_eq!(
+            slot!(ICompositorInteropVtbl, CreateCompositionSurfaceForSwapChain),
+            4
+        );
+        assert_eq!(slot!(ICompositorInteropVtbl, CreateGraphicsDevice), 5);
+        assert_eq!(size_of::<ICompositorInteropVtbl>(), 6 * size_of::<usize>());
+    }
+
+    #[test]
+    fn drawing_surface_interop_slots() {
+        assert_eq!(slot!(ICompositionDrawingSurfaceInteropVtbl, BeginDraw), 3);
+        assert_eq!(slot!(ICompositionDrawingSurfaceInteropVtbl, EndDraw), 4);
+        assert_eq!(slot!(ICompositionDrawingSurfaceInteropVtbl, Resize), 5);
+        assert_eq!(slot!(ICompositionDrawingSurfaceInteropVtbl, Scroll), 6);
+        assert_eq!(slot!(ICompositionDrawingSurfaceInteropVtbl, ResumeDraw), 7);
+        assert_eq!(slot!(ICompositionDrawingSurfaceInteropVtbl, SuspendDraw), 8);
+        assert_eq!(
+            size_of::<ICompositionDrawingSurfaceInteropVtbl>(),
+            9 * size_of::<usize>()
+        );
+    }
+
+    #[test]
+    fn graphics_device_interop_slots() {
+        assert_eq!(
+            slot!(ICompositionGraphicsDeviceInteropVtbl, GetRenderingDevice),
+            3
+        );
+        assert_eq!(
+            slot!(ICompositionGraphicsDeviceInteropVtbl, SetRenderingDevice),
+            4
+        );
+        assert_eq!(
+            size_of::<ICompositionGraphicsDeviceInteropVtbl>(),
+            5 * size_of::<usize>()
+        );
+    }
+}
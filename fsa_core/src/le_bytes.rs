@@ -0,0 +1,81 @@
+//! Little-endian integers in byte buffers, as the structures that Windows hands out store them.
+
+use std::mem;
+
+/// The bytes from `offset` to `offset + len`, if `bytes` holds all of them.
+#[inline]
+fn field(bytes: &[u8], offset: usize, len: usize) -> Option<&[u8]> {
+    bytes.get(offset..offset.checked_add(len)?)
+}
+
+/// The `u16` at `offset`, or `None` if `bytes` ends before it does.
+#[inline]
+pub fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    let mut value = [0; 2];
+    value.copy_from_slice(field(bytes, offset, 2)?);
+    Some(u16::from_le_bytes(value))
+}
+
+/// The `u32` at `offset`, or `None` if `bytes` ends before it does.
+#[inline]
+pub fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let mut value = [0; 4];
+    value.copy_from_slice(field(bytes, offset, 4)?);
+    Some(u32::from_le_bytes(value))
+}
+
+/// The pointer-sized integer at `offset`, as the current process lays it out, or `None` if
+/// `bytes` ends before it does.
+#[inline]
+pub fn read_usize(bytes: &[u8], offset: usize) -> Option<usize> {
+    let mut value = [0; mem::size_of::<usize>()];
+    value.copy_from_slice(field(bytes, offset, mem::size_of::<usize>())?);
+    Some(usize::from_le_bytes(value))
+}
+
+/// Stores `value` at `offset`. Panics if `bytes` ends before it would.
+#[inline]
+pub fn write_u16(bytes: &mut [u8], offset: usize, value: u16) {
+    bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Stores `value` at `offset`. Panics if `bytes` ends before it would.
+#[inline]
+pub fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_little_endian() {
+        let bytes = [0x01, 0x02, 0x03, 0x04, 0x05];
+        assert_eq!(read_u16(&bytes, 0), Some(0x0201));
+        assert_eq!(read_u16(&bytes, 3), Some(0x0504));
+        assert_eq!(read_u32(&bytes, 1), Some(0x0504_0302));
+        let bytes = [0xFF; 16];
+        assert_eq!(read_usize(&bytes, 8), Some(usize::max_value()));
+    }
+
+    #[test]
+    fn reads_past_the_end_are_none() {
+        let bytes = [0; 4];
+        assert_eq!(read_u16(&bytes, 3), None);
+        assert_eq!(read_u32(&bytes, 1), None);
+        assert_eq!(read_u32(&bytes, 4), None);
+        assert_eq!(read_usize(&bytes, 0), None);
+        assert_eq!(read_u32(&bytes, usize::max_value()), None);
+        assert_eq!(read_u16(&[], 0), None);
+    }
+
+    #[test]
+    fn writes_little_endian() {
+        let mut bytes = [0; 6];
+        write_u16(&mut bytes, 0, 0xBEEF);
+        write_u32(&mut bytes, 2, 0x1234_5678);
+        assert_eq!(bytes, [0xEF, 0xBE, 0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(read_u32(&bytes, 2), Some(0x1234_5678));
+    }
+}
@@ -0,0 +1,47 @@
+//! The parts of the windowing code that don't call into Win32 or WinRT: layout and decoding,
+//! parsers for the data Windows hands out, and the models that the platform code drives. They
+//! build and test on any platform.
+
+// These lints suggest std APIs that are newer than the nightly the crate is built with.
+#![allow(
+    unknown_lints,
+    clippy::cast_abs_to_unsigned,
+    clippy::legacy_numeric_constants,
+    clippy::manual_div_ceil,
+    clippy::manual_is_multiple_of,
+    clippy::match_like_matches_macro,
+    clippy::mem_replace_with_default,
+    clippy::unnecessary_map_or
+)]
+
+#[macro_use]
+extern crate bitflags;
+extern crate image;
+extern crate rusttype;
+
+pub mod app;
+pub mod bitmap;
+pub mod clipboard_formats;
+pub mod cursor;
+pub mod drop_files;
+pub mod executor;
+pub mod gestures;
+pub mod icon;
+pub mod image_brush;
+pub mod interop_vtables;
+mod le_bytes;
+pub mod lifecycle;
+pub mod pointer_info;
+pub mod raw_input;
+pub mod scroll_model;
+pub mod surface_model;
+pub mod svg_path;
+pub mod text;
+pub mod text_input;
+pub mod thread_affinity;
+pub mod timers;
+pub mod user_events;
+pub mod window_class;
+pub mod window_events;
+pub mod window_style;
+pub mod work_queue;
@@ -0,0 +1,159 @@
+/// Where a window is in its lifetime, from the point of view of the state attached to its `HWND`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Lifecycle {
+    /// The state has been allocated, but `CreateWindowExW` hasn't delivered `WM_NCCREATE` yet.
+    Unattached,
+    /// The state is attached to the `HWND`, but `CreateWindowExW` hasn't returned.
+    Creating,
+    /// The window is fully created.
+    Alive,
+    /// `WM_DESTROY` has been received. Child windows are being destroyed and the `HWND` is still
+    /// valid.
+    Destroying,
+    /// `WM_NCDESTROY` has been received, or creation failed. The state is no longer reachable from
+    /// the `HWND` and can be freed.
+    Destroyed,
+}
+
+/// Something that moves a window along its lifecycle.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LifecycleEvent {
+    /// `WM_NCCREATE` attached the state to the `HWND`.
+    Attach,
+    /// `CreateWindowExW` returned a window.
+    CreateReturned,
+    /// `CreateWindowExW` returned null.
+    CreateFailed,
+    /// `WM_DESTROY` was received.
+    Destroy,
+    /// `WM_NCDESTROY` detached the state from the `HWND`.
+    Detach,
+}
+
+/// A lifecycle event that isn't possible in the current state.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InvalidTransition {
+    pub from: Lifecycle,
+    pub event: LifecycleEvent,
+}
+
+impl Lifecycle {
+    /// Returns the state that `event` leads to.
+    pub fn transition(self, event: LifecycleEvent) -> Result<Lifecycle, InvalidTransition> {
+        use self::Lifecycle::*;
+        use self::LifecycleEvent::*;
+
+        match (self, event) {
+            (Unattached, Attach) => Ok(Creating),
+            // `WM_NCCREATE` never arrived (e.g. the class doesn't exist), so nothing references
+            // the state.
+            (Unattached, CreateFailed) => Ok(Destroyed),
+            (Creating, CreateReturned) => Ok(Alive),
+            // A window can be destroyed from inside `WM_CREATE` before `CreateWindowExW` returns.
+            (Creating, Destroy) | (Alive, Destroy) => Ok(Destroying),
+            (Creating, Detach) | (Alive, Detach) | (Destroying, Detach) => Ok(Destroyed),
+            // When creation is aborted after `WM_NCCREATE`, `WM_NCDESTROY` has already torn the
+            // state down by the time `CreateWindowExW` returns null.
+            (Destroyed, CreateFailed) => Ok(Destroyed),
+            (from, event) => Err(InvalidTransition { from, event }),
+        }
+    }
+
+    /// Whether messages for the window should be routed to its event handler.
+    #[inline]
+    pub fn dispatches_events(self) -> bool {
+        match self {
+            Lifecycle::Creating | Lifecycle::Alive | Lifecycle::Destroying => true,
+            Lifecycle::Unattached | Lifecycle::Destroyed => false,
+        }
+    }
+
+    /// Whether the state is still attached to the `HWND`, and so must not be freed.
+    #[inline]
+    pub fn is_attached(self) -> bool {
+        self.dispatches_events()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lifecycle::*;
+    use super::LifecycleEvent::*;
+    use super::*;
+
+    const STATES: [Lifecycle; 5] = [Unattached, Creating, Alive, Destroying, Destroyed];
+    const EVENTS: [LifecycleEvent; 5] = [Attach, CreateReturned, CreateFailed, Destroy, Detach];
+
+    /// Every valid transition, as `(from, event, to)`.
+    const VALID: [(Lifecycle, LifecycleEvent, Lifecycle); 9] = [
+        (Unattached, Attach, Creating),
+        (Unattached, CreateFailed, Destroyed),
+        (Creating, CreateReturned, Alive),
+        (Creating, Destroy, Destroying),
+        (Creating, Detach, Destroyed),
+        (Alive, Destroy, Destroying),
+        (Alive, Detach, Destroyed),
+        (Destroying, Detach, Destroyed),
+        (Destroyed, CreateFailed, Destroyed),
+    ];
+
+    #[test]
+    fn transitions() {
+        for &from in &STATES {
+            for &event in &EVENTS {
+                let expected = VALID
+                    .iter()
+                    .find(|&&(valid_from, valid_event, _)| {
+                        valid_from == from && valid_event == event
+                    })
+                    .map(|&(_, _, to)| to)
+                    .ok_or(InvalidTransition { from, event });
+                assert_eq!(
+                    from.transition(event),
+                    expected,
+                    "{:?} on {:?}",
+                    event,
+                    from
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn dispatching_and_attachment() {
+        let expected = [
+            (Unattached, false),
+            (Creating, true),
+            (Alive, true),
+            (Destroying, true),
+            (Destroyed, false),
+        ];
+        for &(state, attached) in &expected {
+            assert_eq!(state.dispatches_events(), attached, "{:?}", state);
+            assert_eq!(state.is_attached(), attached, "{:?}", state);
+        }
+    }
+
+    #[test]
+    fn full_lifetimes() {
+        let run = |events: &[LifecycleEvent]| {
+            events
+                .iter()
+                .try_fold(Unattached, |state, &event| state.transition(event))
+        };
+        assert_eq!(
+            run(&[Attach, CreateReturned, Destroy, Detach]),
+            Ok(Destroyed)
+        );
+        // Destroyed from inside `WM_CREATE`.
+        assert_eq!(run(&[Attach, Destroy, Detach, CreateFailed]), Ok(Destroyed));
+        assert_eq!(run(&[CreateFailed]), Ok(Destroyed));
+        assert_eq!(
+            run(&[Attach, CreateReturned, Detach, Destroy]),
+            Err(InvalidTransition {
+                from: Destroyed,
+                event: Destroy,
+            })
+        );
+    }
+}
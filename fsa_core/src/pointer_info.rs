@@ -0,0 +1,452 @@
+use window_events::{
+    ContactArea, DeviceId, LogicalPosition, LogicalSize, Pen, PenButtons, Touch, TouchPhase,
+    WindowEvent,
+};
+
+const POINTER_FLAG_INCONTACT: u32 = 0x0000_0004;
+const POINTER_FLAG_FIRSTBUTTON: u32 = 0x0000_0010;
+const POINTER_FLAG_CANCELED: u32 = 0x0000_8000;
+const POINTER_FLAG_DOWN: u32 = 0x0001_0000;
+const POINTER_FLAG_UPDATE: u32 = 0x0002_0000;
+const POINTER_FLAG_UP: u32 = 0x0004_0000;
+
+const TOUCH_MASK_CONTACTAREA: u32 = 0x01;
+const TOUCH_MASK_ORIENTATION: u32 = 0x02;
+const TOUCH_MASK_PRESSURE: u32 = 0x04;
+
+const PEN_FLAG_BARREL: u32 = 0x01;
+const PEN_FLAG_INVERTED: u32 = 0x02;
+const PEN_FLAG_ERASER: u32 = 0x04;
+
+const PEN_MASK_PRESSURE: u32 = 0x01;
+const PEN_MASK_ROTATION: u32 = 0x02;
+const PEN_MASK_TILT_X: u32 = 0x04;
+const PEN_MASK_TILT_Y: u32 = 0x08;
+
+/// The largest pressure that touch and pen digitizers report.
+const MAX_PRESSURE: f64 = 1024.0;
+
+/// The fields of a `POINTER_INFO` that events are made of. Positions are in physical pixels of
+/// the client area, rather than the screen coordinates of the original.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PointerInfo {
+    pub id: u32,
+    /// `POINTER_FLAG_*` values.
+    pub flags: u32,
+    /// The `sourceDevice` handle.
+    pub device: usize,
+    pub position: (i32, i32),
+}
+
+/// The fields of a `POINTER_TOUCH_INFO` besides its `POINTER_INFO`. The contact rectangle is in
+/// physical pixels of the client area.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TouchInfo {
+    /// `TOUCH_MASK_*` values, saying which of the other fields are valid.
+    pub mask: u32,
+    /// Left, top, right and bottom.
+    pub contact: (i32, i32, i32, i32),
+    pub orientation: u32,
+    pub pressure: u32,
+}
+
+/// The fields of a `POINTER_PEN_INFO` besides its `POINTER_INFO`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PenInfo {
+    /// `PEN_FLAG_*` values.
+    pub flags: u32,
+    /// `PEN_MASK_*` values, saying which of the other fields are valid.
+    pub mask: u32,
+    pub pressure: u32,
+    pub rotation: u32,
+    pub tilt_x: i32,
+    pub tilt_y: i32,
+}
+
+/// The phase of a pointer message. Canceled pointers, e.g. a touch that turned out to be the
+/// palm resting on the screen, end with `Cancelled` instead of `Ended`.
+pub fn pointer_phase(flags: u32) -> Option<TouchPhase> {
+    if flags & POINTER_FLAG_CANCELED != 0 {
+        Some(TouchPhase::Cancelled)
+    } else if flags & POINTER_FLAG_DOWN != 0 {
+        Some(TouchPhase::Started)
+    } else if flags & POINTER_FLAG_UP != 0 {
+        Some(TouchPhase::Ended)
+    } else if flags & POINTER_FLAG_UPDATE != 0 {
+        Some(TouchPhase::Moved)
+    } else {
+        None
+    }
+}
+
+fn location(pointer: &PointerInfo, dpi_factor: f64) -> LogicalPosition {
+    let (x, y) = pointer.position;
+    LogicalPosition::from_physical(x as f64, y as f64, dpi_factor)
+}
+
+fn pressure(pressure: u32) -> f64 {
+    (pressure as f64 / MAX_PRESSURE).min(1.0)
+}
+
+/// Makes a touch event out of the information of a touch pointer, or `None` if the message isn't
+/// a down, update or up.
+pub fn touch_event(pointer: &PointerInfo, touch: &TouchInfo, dpi_factor: f64) -> Option<Touch> {
+    let phase = pointer_phase(pointer.flags)?;
+    let contact = if touch.mask & TOUCH_MASK_CONTACTAREA != 0 {
+        let (left, top, right, bottom) = touch.contact;
+        Some(ContactArea {
+            position: LogicalPosition::from_physical(left as f64, top as f64, dpi_factor),
+            size: LogicalSize::new(
+                (right - left).max(0) as f64 / dpi_factor,
+                (bottom - top).max(0) as f64 / dpi_factor,
+            ),
+        })
+    } else {
+        None
+    };
+    Some(Touch {
+        device_id: DeviceId(pointer.device as u64),
+        phase,
+        location: location(pointer, dpi_factor),
+        id: pointer.id as u64,
+        pressure: if touch.mask & TOUCH_MASK_PRESSURE != 0 {
+            Some(pressure(touch.pressure))
+        } else {
+            None
+        },
+        contact,
+        orientation: if touch.mask & TOUCH_MASK_ORIENTATION != 0 {
+            Some(touch.orientation as f64)
+        } else {
+            None
+        },
+    })
+}
+
+/// Makes a pen event out of the information of a pen pointer, or `None` if the message isn't a
+/// down, update or up.
+pub fn pen_event(pointer: &PointerInfo, pen: &PenInfo, dpi_factor: f64) -> Option<Pen> {
+    let phase = pointer_phase(pointer.flags)?;
+    let tilt_x = if pen.mask & PEN_MASK_TILT_X != 0 {
+        Some(pen.tilt_x as f64)
+    } else {
+        None
+    };
+    let tilt_y = if pen.mask & PEN_MASK_TILT_Y != 0 {
+        Some(pen.tilt_y as f64)
+    } else {
+        None
+    };
+    let tilt = match (tilt_x, tilt_y) {
+        (None, None) => None,
+        (x, y) => Some((x.unwrap_or(0.0), y.unwrap_or(0.0))),
+    };
+    Some(Pen {
+        device_id: DeviceId(pointer.device as u64),
+        phase,
+        location: location(pointer, dpi_factor),
+        id: pointer.id as u64,
+        in_contact: pointer.flags & POINTER_FLAG_INCONTACT != 0,
+        pressure: if pen.mask & PEN_MASK_PRESSURE != 0 {
+            Some(pressure(pen.pressure))
+        } else {
+            None
+        },
+        tilt,
+        rotation: if pen.mask & PEN_MASK_ROTATION != 0 {
+            Some(pen.rotation as f64)
+        } else {
+            None
+        },
+        buttons: PenButtons {
+            barrel: pen.flags & PEN_FLAG_BARREL != 0,
+            eraser: pen.flags & PEN_FLAG_ERASER != 0,
+            inverted: pen.flags & PEN_FLAG_INVERTED != 0,
+        },
+    })
+}
+
+/// Makes a pressure event out of the information of a touchpad pointer, or `None` if the
+/// touchpad doesn't report pressure or the message isn't a down, update or up.
+///
+/// The stage is 1 while the touchpad is clicked down and 0 otherwise. Touchpad pointers still
+/// move the cursor, so their messages go on to `DefWindowProcW` after this.
+pub fn touchpad_pressure_event(pointer: &PointerInfo, touch: &TouchInfo) -> Option<WindowEvent> {
+    pointer_phase(pointer.flags)?;
+    if touch.mask & TOUCH_MASK_PRESSURE == 0 {
+        return None;
+    }
+    // The pointer reports the pressure it had before it was lifted.
+    if pointer.flags & (POINTER_FLAG_UP | POINTER_FLAG_CANCELED) != 0 {
+        return Some(WindowEvent::TouchpadPressure {
+            device_id: DeviceId(pointer.device as u64),
+            pressure: 0.0,
+            stage: 0,
+        });
+    }
+    let clicked = pointer.flags & POINTER_FLAG_FIRSTBUTTON != 0;
+    Some(WindowEvent::TouchpadPressure {
+        device_id: DeviceId(pointer.device as u64),
+        pressure: pressure(touch.pressure) as f32,
+        stage: clicked as i64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pointer(flags: u32) -> PointerInfo {
+        PointerInfo {
+            id: 7,
+            flags,
+            device: 0x1_0042,
+            position: (300, 150),
+        }
+    }
+
+    fn touch_info(mask: u32) -> TouchInfo {
+        TouchInfo {
+            mask,
+            contact: (290, 140, 310, 170),
+            orientation: 90,
+            pressure: 512,
+        }
+    }
+
+    fn pen_info(flags: u32, mask: u32) -> PenInfo {
+        PenInfo {
+            flags,
+            mask,
+            pressure: 256,
+            rotation: 45,
+            tilt_x: -30,
+            tilt_y: 20,
+        }
+    }
+
+    #[test]
+    fn phases() {
+        assert_eq!(
+            pointer_phase(POINTER_FLAG_DOWN | POINTER_FLAG_INCONTACT),
+            Some(TouchPhase::Started)
+        );
+        assert_eq!(pointer_phase(POINTER_FLAG_UPDATE), Some(TouchPhase::Moved));
+        assert_eq!(pointer_phase(POINTER_FLAG_UP), Some(TouchPhase::Ended));
+        assert_eq!(
+            pointer_phase(POINTER_FLAG_UP | POINTER_FLAG_CANCELED),
+            Some(TouchPhase::Cancelled)
+        );
+        assert_eq!(
+            pointer_phase(POINTER_FLAG_UPDATE | POINTER_FLAG_CANCELED),
+            Some(TouchPhase::Cancelled)
+        );
+        assert_eq!(pointer_phase(POINTER_FLAG_INCONTACT), None);
+        assert_eq!(pointer_phase(0), None);
+    }
+
+    #[test]
+    fn touch_with_every_field() {
+        let mask = TOUCH_MASK_CONTACTAREA | TOUCH_MASK_ORIENTATION | TOUCH_MASK_PRESSURE;
+        let touch = touch_event(&pointer(POINTER_FLAG_DOWN), &touch_info(mask), 2.0).unwrap();
+        assert_eq!(
+            touch,
+            Touch {
+                device_id: DeviceId(0x1_0042),
+                phase: TouchPhase::Started,
+                location: LogicalPosition::new(150.0, 75.0),
+                id: 7,
+                pressure: Some(0.5),
+                contact: Some(ContactArea {
+                    position: LogicalPosition::new(145.0, 70.0),
+                    size: LogicalSize::new(10.0, 15.0),
+                }),
+                orientation: Some(90.0),
+            }
+        );
+    }
+
+    #[test]
+    fn touch_mask_bits() {
+        let event =
+            |mask| touch_event(&pointer(POINTER_FLAG_UPDATE), &touch_info(mask), 1.0).unwrap();
+
+        let touch = event(0);
+        assert_eq!(
+            (touch.pressure, touch.contact, touch.orientation),
+            (None, None, None)
+        );
+        assert_eq!(touch.location, LogicalPosition::new(300.0, 150.0));
+
+        let touch = event(TOUCH_MASK_CONTACTAREA);
+        assert!(touch.contact.is_some());
+        assert_eq!((touch.pressure, touch.orientation), (None, None));
+
+        let touch = event(TOUCH_MASK_ORIENTATION);
+        assert_eq!(touch.orientation, Some(90.0));
+        assert_eq!((touch.pressure, touch.contact), (None, None));
+
+        let touch = event(TOUCH_MASK_PRESSURE);
+        assert_eq!(touch.pressure, Some(0.5));
+        assert_eq!((touch.contact, touch.orientation), (None, None));
+    }
+
+    #[test]
+    fn normalizes_pressure() {
+        let event = |pressure| {
+            let info = TouchInfo {
+                pressure,
+                ..touch_info(TOUCH_MASK_PRESSURE)
+            };
+            touch_event(&pointer(POINTER_FLAG_UPDATE), &info, 1.0)
+                .unwrap()
+                .pressure
+        };
+        assert_eq!(event(0), Some(0.0));
+        assert_eq!(event(256), Some(0.25));
+        assert_eq!(event(1024), Some(1.0));
+        // Out of range values are clamped.
+        assert_eq!(event(4000), Some(1.0));
+    }
+
+    #[test]
+    fn inverted_contact_rect_is_empty() {
+        let info = TouchInfo {
+            contact: (20, 30, 10, 10),
+            ..touch_info(TOUCH_MASK_CONTACTAREA)
+        };
+        let touch = touch_event(&pointer(POINTER_FLAG_UPDATE), &info, 1.0).unwrap();
+        assert_eq!(
+            touch.contact,
+            Some(ContactArea {
+                position: LogicalPosition::new(20.0, 30.0),
+                size: LogicalSize::new(0.0, 0.0),
+            })
+        );
+    }
+
+    #[test]
+    fn no_touch_without_phase() {
+        assert_eq!(
+            touch_event(&pointer(POINTER_FLAG_INCONTACT), &touch_info(0), 1.0),
+            None
+        );
+        assert_eq!(
+            pen_event(&pointer(POINTER_FLAG_INCONTACT), &pen_info(0, 0), 1.0),
+            None
+        );
+    }
+
+    #[test]
+    fn pen_with_every_field() {
+        let mask = PEN_MASK_PRESSURE | PEN_MASK_ROTATION | PEN_MASK_TILT_X | PEN_MASK_TILT_Y;
+        let flags = POINTER_FLAG_UPDATE | POINTER_FLAG_INCONTACT;
+        let pen = pen_event(&pointer(flags), &pen_info(PEN_FLAG_BARREL, mask), 1.5).unwrap();
+        assert_eq!(
+            pen,
+            Pen {
+                device_id: DeviceId(0x1_0042),
+                phase: TouchPhase::Moved,
+                location: LogicalPosition::new(200.0, 100.0),
+                id: 7,
+                in_contact: true,
+                pressure: Some(0.25),
+                tilt: Some((-30.0, 20.0)),
+                rotation: Some(45.0),
+                buttons: PenButtons {
+                    barrel: true,
+                    eraser: false,
+                    inverted: false,
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn pen_mask_bits() {
+        let event =
+            |mask| pen_event(&pointer(POINTER_FLAG_UPDATE), &pen_info(0, mask), 1.0).unwrap();
+
+        let pen = event(0);
+        assert_eq!((pen.pressure, pen.tilt, pen.rotation), (None, None, None));
+        // Hovering, out of contact.
+        assert!(!pen.in_contact);
+
+        assert_eq!(event(PEN_MASK_PRESSURE).pressure, Some(0.25));
+        assert_eq!(event(PEN_MASK_ROTATION).rotation, Some(45.0));
+        // An axis the pen doesn't report is 0.
+        assert_eq!(event(PEN_MASK_TILT_X).tilt, Some((-30.0, 0.0)));
+        assert_eq!(event(PEN_MASK_TILT_Y).tilt, Some((0.0, 20.0)));
+    }
+
+    #[test]
+    fn pen_buttons() {
+        let buttons = |flags| {
+            pen_event(&pointer(POINTER_FLAG_DOWN), &pen_info(flags, 0), 1.0)
+                .unwrap()
+                .buttons
+        };
+        assert_eq!(buttons(0), PenButtons::default());
+        assert_eq!(
+            buttons(PEN_FLAG_BARREL),
+            PenButtons {
+                barrel: true,
+                ..PenButtons::default()
+            }
+        );
+        assert_eq!(
+            buttons(PEN_FLAG_ERASER | PEN_FLAG_INVERTED),
+            PenButtons {
+                eraser: true,
+                inverted: true,
+                ..PenButtons::default()
+            }
+        );
+        assert_eq!(
+            buttons(PEN_FLAG_INVERTED),
+            PenButtons {
+                inverted: true,
+                ..PenButtons::default()
+            }
+        );
+    }
+
+    #[test]
+    fn touchpad_pressure() {
+        let event = |flags, mask| touchpad_pressure_event(&pointer(flags), &touch_info(mask));
+        let pressure = |pressure, stage| {
+            Some(WindowEvent::TouchpadPressure {
+                device_id: DeviceId(0x1_0042),
+                pressure,
+                stage,
+            })
+        };
+        assert_eq!(
+            event(POINTER_FLAG_DOWN, TOUCH_MASK_PRESSURE),
+            pressure(0.5, 0)
+        );
+        assert_eq!(
+            event(
+                POINTER_FLAG_UPDATE | POINTER_FLAG_FIRSTBUTTON,
+                TOUCH_MASK_PRESSURE
+            ),
+            pressure(0.5, 1)
+        );
+        assert_eq!(
+            event(
+                POINTER_FLAG_UP | POINTER_FLAG_FIRSTBUTTON,
+                TOUCH_MASK_PRESSURE
+            ),
+            pressure(0.0, 0)
+        );
+        assert_eq!(
+            event(
+                POINTER_FLAG_UPDATE | POINTER_FLAG_CANCELED,
+                TOUCH_MASK_PRESSURE
+            ),
+            pressure(0.0, 0)
+        );
+        assert_eq!(event(POINTER_FLAG_UPDATE, TOUCH_MASK_CONTACTAREA), None);
+        assert_eq!(event(0, TOUCH_MASK_PRESSURE), None);
+    }
+}
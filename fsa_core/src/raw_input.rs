@@ -0,0 +1,438 @@
+use std::{fmt, mem};
+
+use le_bytes::{read_u16, read_u32, read_usize};
+
+use window_events::{
+    DeviceEvent, ElementState, KeyboardInput, ModifiersState, MouseScrollDelta,
+};
+
+/// Size in bytes of a `RAWINPUTHEADER`: two `DWORD`s, a `HANDLE` and a `WPARAM`.
+pub const RAWINPUTHEADER_SIZE: usize = 8 + 2 * mem::size_of::<usize>();
+/// Size in bytes of a `RAWMOUSE`.
+const RAWMOUSE_SIZE: usize = 24;
+/// Size in bytes of a `RAWKEYBOARD`.
+const RAWKEYBOARD_SIZE: usize = 16;
+/// Size in bytes of the `dwSizeHid` and `dwCount` fields that start a `RAWHID`.
+const RAWHID_HEADER_SIZE: usize = 8;
+
+const RIM_TYPEMOUSE: u32 = 0;
+const RIM_TYPEKEYBOARD: u32 = 1;
+const RIM_TYPEHID: u32 = 2;
+
+const MOUSE_MOVE_ABSOLUTE: u16 = 0x01;
+const RI_MOUSE_WHEEL: u16 = 0x0400;
+const RI_MOUSE_HWHEEL: u16 = 0x0800;
+const WHEEL_DELTA: f32 = 120.0;
+
+const RI_KEY_BREAK: u16 = 0x01;
+const RI_KEY_E0: u16 = 0x02;
+const RI_KEY_E1: u16 = 0x04;
+/// The virtual key of the fake keys that make up the escape sequences of some keys.
+const VK_FAKE: u16 = 0xFF;
+
+/// The axes of mouse motion in `DeviceEvent::Motion`.
+pub const AXIS_X: u32 = 0;
+pub const AXIS_Y: u32 = 1;
+
+/// A `RAWMOUSE`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RawMouse {
+    pub flags: u16,
+    pub button_flags: u16,
+    pub button_data: u16,
+    pub raw_buttons: u32,
+    pub last_x: i32,
+    pub last_y: i32,
+    pub extra_information: u32,
+}
+
+/// A `RAWKEYBOARD`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RawKeyboard {
+    pub make_code: u16,
+    pub flags: u16,
+    pub virtual_key: u16,
+    pub message: u32,
+    pub extra_information: u32,
+}
+
+/// A `RAWHID`: `count` reports of `size` bytes each.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawHid {
+    pub size: usize,
+    pub count: usize,
+    pub data: Vec<u8>,
+}
+
+impl RawHid {
+    /// The individual input reports.
+    pub fn reports(&self) -> impl Iterator<Item = &[u8]> {
+        // `data` holds exactly `count` reports. `chunks` panics on a size of 0, which only comes
+        // with empty data.
+        self.data.chunks(self.size.max(1))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RawData {
+    Mouse(RawMouse),
+    Keyboard(RawKeyboard),
+    Hid(RawHid),
+}
+
+/// A `RAWINPUT` structure, as read with `GetRawInputData`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawInput {
+    /// The device that produced the input. Input injected with `SendInput` has no device and a
+    /// handle of 0.
+    pub device: usize,
+    pub data: RawData,
+}
+
+/// An error produced while parsing a `RAWINPUT` structure.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RawInputError {
+    /// The buffer ends before the structure it should hold.
+    TooShort { expected: usize, len: usize },
+    /// The header names a type of device other than a mouse, keyboard or HID.
+    UnknownType(u32),
+}
+
+impl fmt::Display for RawInputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RawInputError::TooShort { expected, len } => write!(
+                f,
+                "the raw input ({} bytes) is too short, expected at least {} bytes",
+                len, expected
+            ),
+            RawInputError::UnknownType(kind) => write!(f, "unknown raw input type {}", kind),
+        }
+    }
+}
+
+fn too_short(bytes: &[u8], expected: usize) -> RawInputError {
+    RawInputError::TooShort {
+        expected,
+        len: bytes.len(),
+    }
+}
+
+fn check_len(bytes: &[u8], expected: usize) -> Result<(), RawInputError> {
+    if bytes.len() < expected {
+        return Err(too_short(bytes, expected));
+    }
+    Ok(())
+}
+
+/// Parses a `RAWINPUT` structure in the layout of the current process.
+pub fn parse_raw_input(bytes: &[u8]) -> Result<RawInput, RawInputError> {
+    check_len(bytes, RAWINPUTHEADER_SIZE)?;
+    let error = too_short(bytes, RAWINPUTHEADER_SIZE);
+    let kind = read_u32(bytes, 0).ok_or(error)?;
+    let device = read_usize(bytes, 8).ok_or(error)?;
+    let body = &bytes[RAWINPUTHEADER_SIZE..];
+
+    let data = match kind {
+        RIM_TYPEMOUSE => {
+            let error = too_short(bytes, RAWINPUTHEADER_SIZE + RAWMOUSE_SIZE);
+            let u16_at = |offset| read_u16(body, offset).ok_or(error);
+            let u32_at = |offset| read_u32(body, offset).ok_or(error);
+            // `usButtonFlags` and `usButtonData` share a union with a `ULONG`, which aligns them
+            // to 4 bytes.
+            RawData::Mouse(RawMouse {
+                flags: u16_at(0)?,
+                button_flags: u16_at(4)?,
+                button_data: u16_at(6)?,
+                raw_buttons: u32_at(8)?,
+                last_x: u32_at(12)? as i32,
+                last_y: u32_at(16)? as i32,
+                extra_information: u32_at(20)?,
+            })
+        }
+        RIM_TYPEKEYBOARD => {
+            let error = too_short(bytes, RAWINPUTHEADER_SIZE + RAWKEYBOARD_SIZE);
+            let u16_at = |offset| read_u16(body, offset).ok_or(error);
+            let u32_at = |offset| read_u32(body, offset).ok_or(error);
+            RawData::Keyboard(RawKeyboard {
+                make_code: u16_at(0)?,
+                flags: u16_at(2)?,
+                virtual_key: u16_at(6)?,
+                message: u32_at(8)?,
+                extra_information: u32_at(12)?,
+            })
+        }
+        RIM_TYPEHID => {
+            let error = too_short(bytes, RAWINPUTHEADER_SIZE + RAWHID_HEADER_SIZE);
+            let size = read_u32(body, 0).ok_or(error)? as usize;
+            let count = read_u32(body, 4).ok_or(error)? as usize;
+            let expected = size
+                .checked_mul(count)
+                .and_then(|len| len.checked_add(RAWINPUTHEADER_SIZE + RAWHID_HEADER_SIZE))
+                .unwrap_or(usize::max_value());
+            check_len(bytes, expected)?;
+            RawData::Hid(RawHid {
+                size,
+                count,
+                data: body[RAWHID_HEADER_SIZE..RAWHID_HEADER_SIZE + size * count].to_vec(),
+            })
+        }
+        kind => return Err(RawInputError::UnknownType(kind)),
+    };
+
+    Ok(RawInput { device, data })
+}
+
+/// Turns raw mouse input into device events.
+///
+/// Relative motion is reported as `MouseMotion`, without the pointer acceleration that applies
+/// to the cursor. Devices that report absolute positions, like pen tablets and remote desktop
+/// sessions, only produce `Motion` events with the position on each axis, normalized to
+/// 0..65535.
+pub fn mouse_events(mouse: &RawMouse) -> Vec<DeviceEvent> {
+    let mut events = Vec::new();
+    if mouse.flags & MOUSE_MOVE_ABSOLUTE != 0 {
+        events.push(DeviceEvent::Motion {
+            axis: AXIS_X,
+            value: mouse.last_x as f64,
+        });
+        events.push(DeviceEvent::Motion {
+            axis: AXIS_Y,
+            value: mouse.last_y as f64,
+        });
+    } else if mouse.last_x != 0 || mouse.last_y != 0 {
+        let (x, y) = (mouse.last_x as f64, mouse.last_y as f64);
+        if x != 0.0 {
+            events.push(DeviceEvent::Motion { axis: AXIS_X, value: x });
+        }
+        if y != 0.0 {
+            events.push(DeviceEvent::Motion { axis: AXIS_Y, value: y });
+        }
+        events.push(DeviceEvent::MouseMotion { delta: (x, y) });
+    }
+
+    if mouse.button_flags & RI_MOUSE_WHEEL != 0 {
+        let lines = mouse.button_data as i16 as f32 / WHEEL_DELTA;
+        events.push(DeviceEvent::MouseWheel {
+            delta: MouseScrollDelta::LineDelta(0.0, lines),
+        });
+    }
+    if mouse.button_flags & RI_MOUSE_HWHEEL != 0 {
+        let lines = mouse.button_data as i16 as f32 / WHEEL_DELTA;
+        events.push(DeviceEvent::MouseWheel {
+            delta: MouseScrollDelta::LineDelta(lines, 0.0),
+        });
+    }
+
+    // Buttons 1 to 5 each have a down flag followed by an up flag, starting at bit 0.
+    for button in 0..5 {
+        let down = 1 << (button * 2);
+        if mouse.button_flags & down != 0 {
+            events.push(DeviceEvent::Button {
+                button: button + 1,
+                state: ElementState::Pressed,
+            });
+        }
+        if mouse.button_flags & (down << 1) != 0 {
+            events.push(DeviceEvent::Button {
+                button: button + 1,
+                state: ElementState::Released,
+            });
+        }
+    }
+    events
+}
+
+/// Turns raw keyboard input into a device event.
+///
+/// The scan code carries the `0xE0` or `0xE1` prefix of extended keys in its second byte, e.g.
+/// `0xE01D` for the right control key. Raw input doesn't track modifiers, so they are left
+/// empty. The fake keys of escape sequences produce no event.
+pub fn keyboard_event(keyboard: &RawKeyboard) -> Option<DeviceEvent> {
+    if keyboard.virtual_key == VK_FAKE {
+        return None;
+    }
+    let prefix = if keyboard.flags & RI_KEY_E0 != 0 {
+        0xE000
+    } else if keyboard.flags & RI_KEY_E1 != 0 {
+        0xE100
+    } else {
+        0
+    };
+    let state = if keyboard.flags & RI_KEY_BREAK != 0 {
+        ElementState::Released
+    } else {
+        ElementState::Pressed
+    };
+    Some(DeviceEvent::Key(KeyboardInput {
+        scancode: prefix | keyboard.make_code as u32,
+        state,
+        virtual_keycode: match keyboard.virtual_key {
+            0 => None,
+            key => Some(key as i32),
+        },
+        modifiers: ModifiersState::default(),
+    }))
+}
+
+/// Turns raw input into device events.
+pub fn device_events(input: &RawInput) -> Vec<DeviceEvent> {
+    match input.data {
+        RawData::Mouse(ref mouse) => mouse_events(mouse),
+        RawData::Keyboard(ref keyboard) => keyboard_event(keyboard).into_iter().collect(),
+        RawData::Hid(ref hid) => hid
+            .reports()
+            .map(|report| DeviceEvent::HidReport(report.to_vec()))
+            .collect(),
+    }
+}
+
+// The fixtures hold `RAWINPUT` structures as `GetRawInputData` returns them to a 64-bit process.
+#[cfg(all(test, target_pointer_width = "64"))]
+mod tests {
+    use super::*;
+
+    static MOUSE: &[u8] = include_bytes!("../fixtures/raw_mouse_x64.bin");
+    static KEYBOARD: &[u8] = include_bytes!("../fixtures/raw_keyboard_x64.bin");
+    static HID: &[u8] = include_bytes!("../fixtures/raw_hid_x64.bin");
+
+    #[test]
+    fn parses_mouse() {
+        let input = parse_raw_input(MOUSE).unwrap();
+        assert_eq!(input.device, 0x10045);
+        let mouse = match input.data {
+            RawData::Mouse(mouse) => mouse,
+            data => panic!("expected mouse input, got {:?}", data),
+        };
+        assert_eq!(
+            mouse,
+            RawMouse {
+                flags: 0,
+                button_flags: 0x0401,
+                button_data: 0xFF88,
+                raw_buttons: 0,
+                last_x: -3,
+                last_y: 5,
+                extra_information: 0,
+            }
+        );
+        assert_eq!(
+            device_events(&input),
+            vec![
+                DeviceEvent::Motion {
+                    axis: AXIS_X,
+                    value: -3.0
+                },
+                DeviceEvent::Motion {
+                    axis: AXIS_Y,
+                    value: 5.0
+                },
+                DeviceEvent::MouseMotion { delta: (-3.0, 5.0) },
+                DeviceEvent::MouseWheel {
+                    delta: MouseScrollDelta::LineDelta(0.0, -1.0)
+                },
+                DeviceEvent::Button {
+                    button: 1,
+                    state: ElementState::Pressed
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_keyboard() {
+        let input = parse_raw_input(KEYBOARD).unwrap();
+        assert_eq!(input.device, 0x10043);
+        assert_eq!(
+            input.data,
+            RawData::Keyboard(RawKeyboard {
+                make_code: 0x1D,
+                flags: RI_KEY_BREAK | RI_KEY_E0,
+                virtual_key: 0x11,
+                message: 0x0101,
+                extra_information: 0,
+            })
+        );
+        // The right control key being released.
+        assert_eq!(
+            device_events(&input),
+            vec![DeviceEvent::Key(KeyboardInput {
+                scancode: 0xE01D,
+                state: ElementState::Released,
+                virtual_keycode: Some(0x11),
+                modifiers: ModifiersState::default(),
+            })]
+        );
+    }
+
+    #[test]
+    fn parses_hid() {
+        let input = parse_raw_input(HID).unwrap();
+        assert_eq!(input.device, 0x200A1);
+        match input.data {
+            RawData::Hid(ref hid) => {
+                assert_eq!((hid.size, hid.count), (4, 2));
+                assert_eq!(
+                    hid.reports().collect::<Vec<_>>(),
+                    vec![&[0x01, 0x80, 0x7F, 0x00][..], &[0x01, 0x81, 0x7F, 0x04][..]]
+                );
+            }
+            ref data => panic!("expected HID input, got {:?}", data),
+        }
+        assert_eq!(
+            device_events(&input),
+            vec![
+                DeviceEvent::HidReport(vec![0x01, 0x80, 0x7F, 0x00]),
+                DeviceEvent::HidReport(vec![0x01, 0x81, 0x7F, 0x04]),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        assert_eq!(
+            parse_raw_input(&MOUSE[..RAWINPUTHEADER_SIZE - 1]),
+            Err(RawInputError::TooShort {
+                expected: RAWINPUTHEADER_SIZE,
+                len: RAWINPUTHEADER_SIZE - 1
+            })
+        );
+        assert_eq!(
+            parse_raw_input(&MOUSE[..MOUSE.len() - 4]),
+            Err(RawInputError::TooShort {
+                expected: MOUSE.len(),
+                len: MOUSE.len() - 4
+            })
+        );
+        assert_eq!(
+            parse_raw_input(&KEYBOARD[..30]),
+            Err(RawInputError::TooShort {
+                expected: KEYBOARD.len(),
+                len: 30
+            })
+        );
+        // The HID header is there, but only one of its two reports.
+        assert_eq!(
+            parse_raw_input(&HID[..HID.len() - 1]),
+            Err(RawInputError::TooShort {
+                expected: HID.len(),
+                len: HID.len() - 1
+            })
+        );
+
+        // A report count that would overflow the expected length.
+        let mut hid = HID.to_vec();
+        hid[RAWINPUTHEADER_SIZE..RAWINPUTHEADER_SIZE + 8].copy_from_slice(&[0xFF; 8]);
+        match parse_raw_input(&hid) {
+            Err(RawInputError::TooShort { .. }) => (),
+            result => panic!("expected a truncation error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_type() {
+        let mut mouse = MOUSE.to_vec();
+        mouse[0] = 3;
+        assert_eq!(parse_raw_input(&mouse), Err(RawInputError::UnknownType(3)));
+    }
+}
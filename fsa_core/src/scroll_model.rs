@@ -0,0 +1,735 @@
+use window_events::{ModifiersState, MouseScrollDelta};
+
+/// The fraction of its velocity that inertia loses every second. This is the default
+/// `PositionInertiaDecayRate` of `InteractionTracker`.
+pub const INERTIA_DECAY_RATE: f64 = 0.95;
+
+/// Inertia ends once it is this close to where it comes to rest, in pixels.
+const REST_EPSILON: f64 = 0.5;
+
+const VK_SPACE: i32 = 0x20;
+const VK_PRIOR: i32 = 0x21;
+const VK_NEXT: i32 = 0x22;
+const VK_END: i32 = 0x23;
+const VK_HOME: i32 = 0x24;
+const VK_LEFT: i32 = 0x25;
+const VK_UP: i32 = 0x26;
+const VK_RIGHT: i32 = 0x27;
+const VK_DOWN: i32 = 0x28;
+
+/// Where inertia may come to rest along one axis.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapPoints {
+    /// Anywhere.
+    None,
+    /// Every `interval` pixels from `start`, in content pixels.
+    Repeating { start: f64, interval: f64 },
+    /// At one of the positions, in content pixels.
+    Mandatory(Vec<f64>),
+}
+
+impl Default for SnapPoints {
+    #[inline]
+    fn default() -> Self {
+        SnapPoints::None
+    }
+}
+
+impl SnapPoints {
+    /// The snap point closest to `position`. Positions are in content pixels.
+    pub fn snap(&self, position: f64) -> f64 {
+        match *self {
+            SnapPoints::None => position,
+            SnapPoints::Repeating { start, interval } => {
+                if interval <= 0.0 {
+                    return position;
+                }
+                start + ((position - start) / interval).round() * interval
+            }
+            SnapPoints::Mandatory(ref points) => points
+                .iter()
+                .cloned()
+                .fold(None, |closest: Option<f64>, point| match closest {
+                    Some(closest) if (closest - position).abs() <= (point - position).abs() => {
+                        Some(closest)
+                    }
+                    _ => Some(point),
+                })
+                .unwrap_or(position),
+        }
+    }
+
+    /// The snap point closest to `to` that lies past `from` in the direction of `to`, so that
+    /// scrolling by less than the distance between snap points still moves to the next one.
+    pub fn snap_towards(&self, from: f64, to: f64) -> f64 {
+        let snapped = self.snap(to);
+        let forward = to > from;
+        if to == from || (forward && snapped > from) || (!forward && snapped < from) {
+            return snapped;
+        }
+        match *self {
+            SnapPoints::None => to,
+            SnapPoints::Repeating { start, interval } => {
+                let steps = (from - start) / interval;
+                if forward {
+                    start + (steps.floor() + 1.0) * interval
+                } else {
+                    start + (steps.ceil() - 1.0) * interval
+                }
+            }
+            SnapPoints::Mandatory(ref points) => points
+                .iter()
+                .cloned()
+                .filter(|&point| if forward { point > from } else { point < from })
+                .fold(None, |closest: Option<f64>, point| match closest {
+                    Some(closest) if (closest - to).abs() <= (point - to).abs() => Some(closest),
+                    _ => Some(point),
+                })
+                .unwrap_or(snapped),
+        }
+    }
+}
+
+/// The parameters of a scroll viewer, shared by `ScrollModel` and the Composition scroll viewer.
+/// Sizes are in the pixels of the visuals being scrolled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScrollConfig {
+    pub min_scale: f64,
+    pub max_scale: f64,
+    pub snap_x: SnapPoints,
+    pub snap_y: SnapPoints,
+    /// How far one line of a mouse wheel or an arrow key scrolls.
+    pub line_size: f64,
+    /// The factor one line of the mouse wheel zooms by while control is held.
+    pub zoom_step: f64,
+}
+
+impl Default for ScrollConfig {
+    fn default() -> Self {
+        ScrollConfig {
+            min_scale: 1.0,
+            max_scale: 1.0,
+            snap_x: SnapPoints::None,
+            snap_y: SnapPoints::None,
+            line_size: 48.0,
+            zoom_step: 1.1,
+        }
+    }
+}
+
+/// Scrolling requested with the keyboard.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ScrollCommand {
+    LineUp,
+    LineDown,
+    LineLeft,
+    LineRight,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+}
+
+impl ScrollCommand {
+    /// The command of a key, given its virtual-key code.
+    pub fn from_key(virtual_keycode: i32, modifiers: ModifiersState) -> Option<Self> {
+        Some(match virtual_keycode {
+            VK_UP => ScrollCommand::LineUp,
+            VK_DOWN => ScrollCommand::LineDown,
+            VK_LEFT => ScrollCommand::LineLeft,
+            VK_RIGHT => ScrollCommand::LineRight,
+            VK_PRIOR => ScrollCommand::PageUp,
+            VK_NEXT => ScrollCommand::PageDown,
+            VK_SPACE if modifiers.shift => ScrollCommand::PageUp,
+            VK_SPACE => ScrollCommand::PageDown,
+            VK_HOME => ScrollCommand::Home,
+            VK_END => ScrollCommand::End,
+            _ => return None,
+        })
+    }
+}
+
+/// `1 - INERTIA_DECAY_RATE`, as a natural logarithm: velocity is `v0 * e^(k * t)`.
+fn decay_exponent() -> f64 {
+    (1.0 - INERTIA_DECAY_RATE).ln()
+}
+
+/// Where inertia starting at `position` with `velocity`, in pixels per second, comes to rest.
+pub fn natural_resting_position(position: f64, velocity: f64) -> f64 {
+    position - velocity / decay_exponent()
+}
+
+/// The velocity that makes inertia come to rest at `to`.
+pub fn velocity_to_reach(from: f64, to: f64) -> f64 {
+    (from - to) * decay_exponent()
+}
+
+/// The position of inertia `time` seconds after it started at `position` with `velocity`.
+pub fn inertia_position(position: f64, velocity: f64, time: f64) -> f64 {
+    let k = decay_exponent();
+    position + velocity * ((k * time).exp() - 1.0) / k
+}
+
+/// The time in seconds it takes inertia to come within `REST_EPSILON` of where it comes to
+/// rest.
+pub fn inertia_duration(velocity: f64) -> f64 {
+    // The distance left is `|v| * e^(k * t) / -k`.
+    let k = decay_exponent();
+    let distance = velocity.abs() / -k;
+    if distance <= REST_EPSILON {
+        0.0
+    } else {
+        (REST_EPSILON / distance).ln() / k
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Inertia {
+    start: (f64, f64),
+    velocity: (f64, f64),
+    target: (f64, f64),
+    elapsed: f64,
+    duration: f64,
+}
+
+/// The state of a scroll viewer: the position of its viewport over the content, the zoom, and
+/// inertia in progress.
+///
+/// Positions are the top-left corner of the viewport, in pixels of the scaled content, as in
+/// `InteractionTracker`. They are kept between 0 and the size of the scaled content minus the
+/// size of the viewport.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScrollModel {
+    config: ScrollConfig,
+    viewport: (f64, f64),
+    content: (f64, f64),
+    position: (f64, f64),
+    scale: f64,
+    inertia: Option<Inertia>,
+}
+
+impl ScrollModel {
+    pub fn new(config: ScrollConfig, viewport: (f64, f64), content: (f64, f64)) -> Self {
+        let scale = 1.0f64.max(config.min_scale).min(config.max_scale);
+        ScrollModel {
+            config,
+            viewport,
+            content,
+            position: (0.0, 0.0),
+            scale,
+            inertia: None,
+        }
+    }
+
+    #[inline]
+    pub fn config(&self) -> &ScrollConfig {
+        &self.config
+    }
+
+    #[inline]
+    pub fn position(&self) -> (f64, f64) {
+        self.position
+    }
+
+    #[inline]
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    #[inline]
+    pub fn viewport(&self) -> (f64, f64) {
+        self.viewport
+    }
+
+    #[inline]
+    pub fn content(&self) -> (f64, f64) {
+        self.content
+    }
+
+    pub fn set_viewport(&mut self, viewport: (f64, f64)) {
+        self.viewport = viewport;
+        self.position = self.clamp(self.position);
+    }
+
+    pub fn set_content(&mut self, content: (f64, f64)) {
+        self.content = content;
+        self.position = self.clamp(self.position);
+    }
+
+    /// The largest position, where the viewport shows the bottom-right corner of the content.
+    pub fn max_position(&self) -> (f64, f64) {
+        (
+            (self.content.0 * self.scale - self.viewport.0).max(0.0),
+            (self.content.1 * self.scale - self.viewport.1).max(0.0),
+        )
+    }
+
+    pub fn clamp(&self, position: (f64, f64)) -> (f64, f64) {
+        let max = self.max_position();
+        (
+            position.0.max(0.0).min(max.0),
+            position.1.max(0.0).min(max.1),
+        )
+    }
+
+    /// Moves a resting position to the nearest snap points, within the bounds.
+    pub fn snap(&self, position: (f64, f64)) -> (f64, f64) {
+        // Snap points are in content pixels, positions in scaled pixels.
+        let scale = self.scale;
+        self.clamp((
+            self.config.snap_x.snap(position.0 / scale) * scale,
+            self.config.snap_y.snap(position.1 / scale) * scale,
+        ))
+    }
+
+    /// Moves the target of a scroll from `from` to the next snap points in its direction, within
+    /// the bounds.
+    pub fn snap_towards(&self, from: (f64, f64), to: (f64, f64)) -> (f64, f64) {
+        let scale = self.scale;
+        self.clamp((
+            self.config.snap_x.snap_towards(from.0 / scale, to.0 / scale) * scale,
+            self.config.snap_y.snap_towards(from.1 / scale, to.1 / scale) * scale,
+        ))
+    }
+
+    /// Where inertia with `velocity` would come to rest from the current position, once snapped
+    /// and kept within the bounds.
+    pub fn resting_position(&self, velocity: (f64, f64)) -> (f64, f64) {
+        self.snap((
+            natural_resting_position(self.position.0, velocity.0),
+            natural_resting_position(self.position.1, velocity.1),
+        ))
+    }
+
+    /// Takes over the position and scale of something that moves on its own, like an
+    /// `InteractionTracker`. Stops inertia.
+    pub fn sync(&mut self, position: (f64, f64), scale: f64) {
+        self.inertia = None;
+        self.scale = scale.max(self.config.min_scale).min(self.config.max_scale);
+        self.position = self.clamp(position);
+    }
+
+    /// Moves to a position immediately, stopping inertia.
+    pub fn scroll_to(&mut self, position: (f64, f64)) {
+        self.inertia = None;
+        self.position = self.clamp(position);
+    }
+
+    /// Starts inertia towards the resting position of `velocity`, the speed of a pan as the
+    /// pointers left the screen.
+    ///
+    /// The velocity is adjusted so that inertia comes to rest exactly on the snap point.
+    pub fn fling(&mut self, velocity: (f64, f64)) {
+        let target = self.resting_position(velocity);
+        self.animate_to(target);
+    }
+
+    /// Glides to a position with the same curve as inertia. Positions between snap points are
+    /// allowed.
+    pub fn animate_to(&mut self, target: (f64, f64)) {
+        let target = self.clamp(target);
+        let velocity = (
+            velocity_to_reach(self.position.0, target.0),
+            velocity_to_reach(self.position.1, target.1),
+        );
+        let duration = inertia_duration(velocity.0).max(inertia_duration(velocity.1));
+        self.inertia = if duration > 0.0 {
+            Some(Inertia {
+                start: self.position,
+                velocity,
+                target,
+                elapsed: 0.0,
+                duration,
+            })
+        } else {
+            self.position = target;
+            None
+        };
+    }
+
+    /// Whether inertia is in progress.
+    #[inline]
+    pub fn is_inertial(&self) -> bool {
+        self.inertia.is_some()
+    }
+
+    /// The position inertia comes to rest at, if it is in progress.
+    pub fn inertia_target(&self) -> Option<(f64, f64)> {
+        self.inertia.map(|inertia| inertia.target)
+    }
+
+    /// Advances inertia by `dt` seconds. Returns whether it is still in progress.
+    pub fn step(&mut self, dt: f64) -> bool {
+        let mut inertia = match self.inertia {
+            Some(inertia) => inertia,
+            None => return false,
+        };
+        inertia.elapsed += dt;
+        if inertia.elapsed >= inertia.duration {
+            self.position = inertia.target;
+            self.inertia = None;
+            return false;
+        }
+        self.position = self.clamp((
+            inertia_position(inertia.start.0, inertia.velocity.0, inertia.elapsed),
+            inertia_position(inertia.start.1, inertia.velocity.1, inertia.elapsed),
+        ));
+        self.inertia = Some(inertia);
+        true
+    }
+
+    /// Zooms to `scale`, within the configured limits, keeping the point `center` of the
+    /// viewport over the same spot of the content.
+    pub fn zoom_to(&mut self, scale: f64, center: (f64, f64)) {
+        let scale = scale.max(self.config.min_scale).min(self.config.max_scale);
+        let ratio = scale / self.scale;
+        self.inertia = None;
+        self.scale = scale;
+        self.position = self.clamp((
+            (self.position.0 + center.0) * ratio - center.0,
+            (self.position.1 + center.1) * ratio - center.1,
+        ));
+    }
+
+    /// Where a mouse wheel event scrolls to, from the position inertia is heading for if it is in
+    /// progress. With snap points, it scrolls at least to the next one. Shift turns vertical
+    /// scrolling into horizontal scrolling. Control zooms instead; that returns `None` and leaves
+    /// zooming to `wheel_zoom`.
+    pub fn wheel_target(
+        &self,
+        delta: MouseScrollDelta,
+        modifiers: ModifiersState,
+    ) -> Option<(f64, f64)> {
+        if modifiers.ctrl {
+            return None;
+        }
+        // The content moves with the wheel: turning it forward shows what is above.
+        let (dx, dy) = match delta {
+            MouseScrollDelta::LineDelta(x, y) => (
+                -x as f64 * self.config.line_size,
+                -y as f64 * self.config.line_size,
+            ),
+            MouseScrollDelta::PixelDelta(delta) => (-delta.x, -delta.y),
+        };
+        let (dx, dy) = if modifiers.shift { (dy, dx) } else { (dx, dy) };
+        let from = self.inertia_target().unwrap_or(self.position);
+        Some(self.snap_towards(from, (from.0 + dx, from.1 + dy)))
+    }
+
+    /// The scale a mouse wheel event with control held zooms to.
+    pub fn wheel_zoom(&self, delta: MouseScrollDelta) -> f64 {
+        let lines = match delta {
+            MouseScrollDelta::LineDelta(_, y) => y as f64,
+            MouseScrollDelta::PixelDelta(delta) => delta.y / self.config.line_size,
+        };
+        let scale = self.scale * self.config.zoom_step.powf(lines);
+        scale.max(self.config.min_scale).min(self.config.max_scale)
+    }
+
+    /// Where a keyboard command scrolls to. Paging keeps one line of the previous page in view.
+    /// With snap points, it scrolls at least to the next one.
+    pub fn command_target(&self, command: ScrollCommand) -> (f64, f64) {
+        let from = self.inertia_target().unwrap_or(self.position);
+        let line = self.config.line_size;
+        let page = (
+            (self.viewport.0 - line).max(line),
+            (self.viewport.1 - line).max(line),
+        );
+        let target = match command {
+            ScrollCommand::LineUp => (from.0, from.1 - line),
+            ScrollCommand::LineDown => (from.0, from.1 + line),
+            ScrollCommand::LineLeft => (from.0 - line, from.1),
+            ScrollCommand::LineRight => (from.0 + line, from.1),
+            ScrollCommand::PageUp => (from.0, from.1 - page.1),
+            ScrollCommand::PageDown => (from.0, from.1 + page.1),
+            ScrollCommand::Home => (from.0, 0.0),
+            ScrollCommand::End => (from.0, self.max_position().1),
+        };
+        self.snap_towards(from, target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use window_events::LogicalPosition;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    /// A 100x100 viewport over a 100x1000 page.
+    fn page(snap_y: SnapPoints) -> ScrollModel {
+        let config = ScrollConfig {
+            snap_y,
+            ..ScrollConfig::default()
+        };
+        ScrollModel::new(config, (100.0, 100.0), (100.0, 1000.0))
+    }
+
+    fn every_100() -> SnapPoints {
+        SnapPoints::Repeating {
+            start: 0.0,
+            interval: 100.0,
+        }
+    }
+
+    fn shift() -> ModifiersState {
+        ModifiersState {
+            shift: true,
+            ..ModifiersState::default()
+        }
+    }
+
+    #[test]
+    fn inertia_curve() {
+        // Velocity decays to 5% every second.
+        let k = (0.05f64).ln();
+        assert!(close(natural_resting_position(0.0, 1000.0), -1000.0 / k));
+        assert!(close(
+            natural_resting_position(50.0, -1000.0),
+            50.0 + 1000.0 / k
+        ));
+
+        let velocity = velocity_to_reach(10.0, 110.0);
+        assert!(velocity > 0.0);
+        assert!(close(natural_resting_position(10.0, velocity), 110.0));
+        assert!(close(inertia_position(10.0, velocity, 0.0), 10.0));
+        assert!(inertia_position(10.0, velocity, 0.5) < inertia_position(10.0, velocity, 1.0));
+        assert!(close(inertia_position(10.0, velocity, 50.0), 110.0));
+
+        assert_eq!(inertia_duration(0.0), 0.0);
+        let duration = inertia_duration(velocity);
+        assert!((110.0 - inertia_position(10.0, velocity, duration) - REST_EPSILON).abs() < 1e-6);
+        assert_eq!(inertia_duration(-velocity), duration);
+    }
+
+    #[test]
+    fn snap_points() {
+        assert_eq!(SnapPoints::None.snap(123.0), 123.0);
+        assert_eq!(every_100().snap(149.0), 100.0);
+        assert_eq!(every_100().snap(151.0), 200.0);
+        let offset = SnapPoints::Repeating {
+            start: 30.0,
+            interval: 100.0,
+        };
+        assert_eq!(offset.snap(0.0), 30.0);
+        assert_eq!(offset.snap(-40.0), -70.0);
+        let empty = SnapPoints::Repeating {
+            start: 0.0,
+            interval: 0.0,
+        };
+        assert_eq!(empty.snap(42.0), 42.0);
+
+        let mandatory = SnapPoints::Mandatory(vec![0.0, 50.0, 300.0]);
+        assert_eq!(mandatory.snap(20.0), 0.0);
+        assert_eq!(mandatory.snap(200.0), 300.0);
+        // Ties go to the first point.
+        assert_eq!(mandatory.snap(25.0), 0.0);
+        assert_eq!(SnapPoints::Mandatory(vec![]).snap(25.0), 25.0);
+    }
+
+    #[test]
+    fn snap_towards_moves_at_least_one_point() {
+        let repeating = every_100();
+        assert_eq!(repeating.snap_towards(100.0, 148.0), 200.0);
+        assert_eq!(repeating.snap_towards(100.0, 52.0), 0.0);
+        assert_eq!(repeating.snap_towards(100.0, 260.0), 300.0);
+        assert_eq!(repeating.snap_towards(130.0, 140.0), 200.0);
+        assert_eq!(repeating.snap_towards(130.0, 120.0), 100.0);
+        assert_eq!(repeating.snap_towards(100.0, 100.0), 100.0);
+
+        let mandatory = SnapPoints::Mandatory(vec![0.0, 100.0, 400.0]);
+        assert_eq!(mandatory.snap_towards(100.0, 120.0), 400.0);
+        assert_eq!(mandatory.snap_towards(400.0, 399.0), 100.0);
+        // Nothing further in that direction.
+        assert_eq!(mandatory.snap_towards(400.0, 420.0), 400.0);
+
+        assert_eq!(SnapPoints::None.snap_towards(100.0, 120.0), 120.0);
+    }
+
+    #[test]
+    fn bounds() {
+        let mut model = page(SnapPoints::None);
+        assert_eq!(model.max_position(), (0.0, 900.0));
+        assert_eq!(model.clamp((-10.0, 950.0)), (0.0, 900.0));
+        model.scroll_to((0.0, 800.0));
+        model.set_content((100.0, 500.0));
+        assert_eq!(model.position(), (0.0, 400.0));
+        model.set_viewport((100.0, 600.0));
+        assert_eq!(model.position(), (0.0, 0.0));
+        assert_eq!(model.max_position(), (0.0, 0.0));
+    }
+
+    #[test]
+    fn fling_comes_to_rest_on_a_snap_point() {
+        let mut model = page(every_100());
+        model.fling((0.0, 400.0));
+        assert!(model.is_inertial());
+        assert_eq!(model.inertia_target(), Some((0.0, 100.0)));
+
+        let mut last = 0.0;
+        let mut frames = 0;
+        while model.step(1.0 / 60.0) {
+            let y = model.position().1;
+            assert!(y >= last && y <= 100.0, "{} after {}", y, last);
+            last = y;
+            frames += 1;
+            assert!(frames < 1000);
+        }
+        assert_eq!(model.position(), (0.0, 100.0));
+        assert!(!model.is_inertial());
+        assert!(!model.step(1.0 / 60.0));
+
+        // Flinging past the end stops at the end.
+        model.fling((0.0, 100_000.0));
+        assert_eq!(model.inertia_target(), Some((0.0, 900.0)));
+        // Scrolling stops inertia.
+        model.scroll_to((0.0, 300.0));
+        assert!(!model.is_inertial());
+        assert_eq!(model.position(), (0.0, 300.0));
+    }
+
+    #[test]
+    fn animate_to_a_close_target_jumps() {
+        let mut model = page(SnapPoints::None);
+        model.animate_to((0.0, 0.2));
+        assert!(!model.is_inertial());
+        assert_eq!(model.position(), (0.0, 0.2));
+        model.animate_to((0.0, 250.0));
+        assert_eq!(model.inertia_target(), Some((0.0, 250.0)));
+        model.sync((0.0, 120.0), 1.0);
+        assert!(!model.is_inertial());
+        assert_eq!(model.position(), (0.0, 120.0));
+    }
+
+    #[test]
+    fn zoom_keeps_center_in_place() {
+        let config = ScrollConfig {
+            max_scale: 4.0,
+            ..ScrollConfig::default()
+        };
+        let mut model = ScrollModel::new(config, (100.0, 100.0), (100.0, 100.0));
+        model.zoom_to(2.0, (50.0, 50.0));
+        assert_eq!(model.scale(), 2.0);
+        assert_eq!(model.position(), (50.0, 50.0));
+        assert_eq!(model.max_position(), (100.0, 100.0));
+        // Limited to the configured scales.
+        model.zoom_to(10.0, (0.0, 0.0));
+        assert_eq!(model.scale(), 4.0);
+        assert_eq!(model.position(), (100.0, 100.0));
+        model.zoom_to(0.1, (0.0, 0.0));
+        assert_eq!(model.scale(), 1.0);
+        assert_eq!(model.position(), (0.0, 0.0));
+
+        model.zoom_to(2.0, (0.0, 0.0));
+        assert!(close(
+            model.wheel_zoom(MouseScrollDelta::LineDelta(0.0, 1.0)),
+            2.2
+        ));
+        assert!(close(
+            model.wheel_zoom(MouseScrollDelta::LineDelta(0.0, -1.0)),
+            2.0 / 1.1
+        ));
+        let pixels = MouseScrollDelta::PixelDelta(LogicalPosition::new(0.0, 48.0));
+        assert!(close(model.wheel_zoom(pixels), 2.2));
+        assert_eq!(
+            model.wheel_zoom(MouseScrollDelta::LineDelta(0.0, 100.0)),
+            4.0
+        );
+
+        // Snap points are in content pixels.
+        let config = ScrollConfig {
+            max_scale: 4.0,
+            snap_y: every_100(),
+            ..ScrollConfig::default()
+        };
+        let mut model = ScrollModel::new(config, (100.0, 100.0), (100.0, 1000.0));
+        model.zoom_to(2.0, (0.0, 0.0));
+        assert_eq!(model.snap((0.0, 290.0)), (0.0, 200.0));
+        assert_eq!(model.snap((0.0, 310.0)), (0.0, 400.0));
+    }
+
+    #[test]
+    fn wheel() {
+        let mut model = page(SnapPoints::None);
+        model.scroll_to((0.0, 100.0));
+        let down = MouseScrollDelta::LineDelta(0.0, -1.0);
+        assert_eq!(
+            model.wheel_target(down, ModifiersState::default()),
+            Some((0.0, 148.0))
+        );
+        let up = MouseScrollDelta::PixelDelta(LogicalPosition::new(0.0, 30.0));
+        assert_eq!(
+            model.wheel_target(up, ModifiersState::default()),
+            Some((0.0, 70.0))
+        );
+        let ctrl = ModifiersState {
+            ctrl: true,
+            ..ModifiersState::default()
+        };
+        assert_eq!(model.wheel_target(down, ctrl), None);
+
+        // Shift scrolls sideways.
+        let mut wide = ScrollModel::new(ScrollConfig::default(), (100.0, 100.0), (1000.0, 1000.0));
+        wide.scroll_to((100.0, 100.0));
+        assert_eq!(wide.wheel_target(down, shift()), Some((148.0, 100.0)));
+
+        // With snap points, the next point, counting from where inertia is heading.
+        let mut model = page(every_100());
+        assert_eq!(
+            model.wheel_target(down, ModifiersState::default()),
+            Some((0.0, 100.0))
+        );
+        model.animate_to((0.0, 100.0));
+        assert_eq!(
+            model.wheel_target(down, ModifiersState::default()),
+            Some((0.0, 200.0))
+        );
+    }
+
+    #[test]
+    fn keyboard() {
+        let none = ModifiersState::default();
+        assert_eq!(
+            ScrollCommand::from_key(VK_UP, none),
+            Some(ScrollCommand::LineUp)
+        );
+        assert_eq!(
+            ScrollCommand::from_key(VK_RIGHT, none),
+            Some(ScrollCommand::LineRight)
+        );
+        assert_eq!(
+            ScrollCommand::from_key(VK_NEXT, none),
+            Some(ScrollCommand::PageDown)
+        );
+        assert_eq!(
+            ScrollCommand::from_key(VK_SPACE, none),
+            Some(ScrollCommand::PageDown)
+        );
+        assert_eq!(
+            ScrollCommand::from_key(VK_SPACE, shift()),
+            Some(ScrollCommand::PageUp)
+        );
+        assert_eq!(
+            ScrollCommand::from_key(VK_END, none),
+            Some(ScrollCommand::End)
+        );
+        assert_eq!(ScrollCommand::from_key(0x41, none), None);
+
+        let mut model = page(SnapPoints::None);
+        model.scroll_to((0.0, 400.0));
+        assert_eq!(model.command_target(ScrollCommand::LineDown), (0.0, 448.0));
+        assert_eq!(model.command_target(ScrollCommand::LineUp), (0.0, 352.0));
+        // A page keeps one line of the previous one in view.
+        assert_eq!(model.command_target(ScrollCommand::PageDown), (0.0, 452.0));
+        assert_eq!(model.command_target(ScrollCommand::PageUp), (0.0, 348.0));
+        assert_eq!(model.command_target(ScrollCommand::Home), (0.0, 0.0));
+        assert_eq!(model.command_target(ScrollCommand::End), (0.0, 900.0));
+        assert_eq!(model.command_target(ScrollCommand::LineLeft), (0.0, 400.0));
+
+        let model = page(every_100());
+        assert_eq!(model.command_target(ScrollCommand::LineDown), (0.0, 100.0));
+        assert_eq!(model.command_target(ScrollCommand::End), (0.0, 900.0));
+    }
+}
@@ -0,0 +1,634 @@
+use std::cmp;
+
+/// The most rects a `DirtyRegion` keeps before merging them.
+pub const DEFAULT_MAX_DIRTY_RECTS: usize = 8;
+
+/// An axis-aligned rectangle of whole pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct PixelRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl PixelRect {
+    #[inline]
+    pub fn new(x: i32, y: i32, width: u32, height: u32) -> Self {
+        PixelRect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// The rect from `(left, top)` to `(right, bottom)`, exclusive. Empty if they're reversed.
+    pub fn from_edges(left: i32, top: i32, right: i32, bottom: i32) -> Self {
+        PixelRect {
+            x: left,
+            y: top,
+            width: cmp::max(right - left, 0) as u32,
+            height: cmp::max(bottom - top, 0) as u32,
+        }
+    }
+
+    #[inline]
+    pub fn right(&self) -> i32 {
+        self.x + self.width as i32
+    }
+
+    #[inline]
+    pub fn bottom(&self) -> i32 {
+        self.y + self.height as i32
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    #[inline]
+    pub fn area(&self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+
+    /// The pixels in both rects, if there are any.
+    pub fn intersection(&self, other: &PixelRect) -> Option<PixelRect> {
+        let rect = PixelRect::from_edges(
+            cmp::max(self.x, other.x),
+            cmp::max(self.y, other.y),
+            cmp::min(self.right(), other.right()),
+            cmp::min(self.bottom(), other.bottom()),
+        );
+        if rect.is_empty() {
+            None
+        } else {
+            Some(rect)
+        }
+    }
+
+    /// The smallest rect that holds both rects. Empty rects don't count.
+    pub fn union(&self, other: &PixelRect) -> PixelRect {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        PixelRect::from_edges(
+            cmp::min(self.x, other.x),
+            cmp::min(self.y, other.y),
+            cmp::max(self.right(), other.right()),
+            cmp::max(self.bottom(), other.bottom()),
+        )
+    }
+
+    /// Whether every pixel of `other` is in this rect. Empty rects are in every rect.
+    pub fn contains(&self, other: &PixelRect) -> bool {
+        other.is_empty()
+            || (other.x >= self.x
+                && other.y >= self.y
+                && other.right() <= self.right()
+                && other.bottom() <= self.bottom())
+    }
+
+    /// The rect moved by `(dx, dy)`.
+    #[inline]
+    pub fn offset(&self, dx: i32, dy: i32) -> PixelRect {
+        PixelRect::new(self.x + dx, self.y + dy, self.width, self.height)
+    }
+}
+
+/// The parts of a surface that need redrawing, as a few rects.
+///
+/// Rects that overlap enough are merged as they're added, and once there are more than the limit,
+/// the two whose union covers the fewest extra pixels are merged. The region may grow to cover
+/// pixels that weren't added, but never loses any that were.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirtyRegion {
+    rects: Vec<PixelRect>,
+    max_rects: usize,
+}
+
+impl Default for DirtyRegion {
+    #[inline]
+    fn default() -> Self {
+        DirtyRegion::new(DEFAULT_MAX_DIRTY_RECTS)
+    }
+}
+
+/// The pixels that the union of two rects covers on top of theirs. Overlapping pixels are
+/// counted once, so this is zero when the rects tile their union.
+fn union_waste(a: &PixelRect, b: &PixelRect) -> u64 {
+    let overlap = a.intersection(b).map_or(0, |rect| rect.area());
+    (a.union(b).area() + overlap).saturating_sub(a.area() + b.area())
+}
+
+impl DirtyRegion {
+    /// A clean region that keeps at most `max_rects` rects, and at least one.
+    pub fn new(max_rects: usize) -> Self {
+        DirtyRegion {
+            rects: Vec::new(),
+            max_rects: cmp::max(max_rects, 1),
+        }
+    }
+
+    #[inline]
+    pub fn rects(&self) -> &[PixelRect] {
+        &self.rects
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    /// The smallest rect that holds the whole region.
+    pub fn bounds(&self) -> Option<PixelRect> {
+        if self.rects.is_empty() {
+            return None;
+        }
+        Some(
+            self.rects
+                .iter()
+                .fold(PixelRect::default(), |bounds, rect| bounds.union(rect)),
+        )
+    }
+
+    pub fn add(&mut self, rect: PixelRect) {
+        if rect.is_empty() || self.rects.iter().any(|dirty| dirty.contains(&rect)) {
+            return;
+        }
+        let mut rect = rect;
+        self.rects.retain(|dirty| !rect.contains(dirty));
+        // Absorb the rects whose union with this one covers no extra pixels. The union may in
+        // turn absorb others.
+        while let Some(index) = self
+            .rects
+            .iter()
+            .position(|dirty| union_waste(dirty, &rect) == 0)
+        {
+            rect = rect.union(&self.rects.swap_remove(index));
+        }
+        self.rects.push(rect);
+        while self.rects.len() > self.max_rects {
+            self.merge_cheapest_pair();
+        }
+    }
+
+    fn merge_cheapest_pair(&mut self) {
+        let mut best = (0, 1, u64::max_value());
+        for i in 0..self.rects.len() {
+            for j in i + 1..self.rects.len() {
+                let waste = union_waste(&self.rects[i], &self.rects[j]);
+                if waste < best.2 {
+                    best = (i, j, waste);
+                }
+            }
+        }
+        let (i, j, _) = best;
+        let merged = self.rects[i].union(&self.rects[j]);
+        // `j` is past `i`, so removing it first leaves `i` in place.
+        self.rects.swap_remove(j);
+        self.rects.swap_remove(i);
+        self.rects.retain(|rect| !merged.contains(rect));
+        self.rects.push(merged);
+    }
+
+    /// Marks the pixels of `rect` as drawn: dirty rects inside it are removed. Rects it only
+    /// overlaps stay dirty as a whole.
+    pub fn remove_covered(&mut self, rect: &PixelRect) {
+        self.rects.retain(|dirty| !rect.contains(dirty));
+    }
+
+    /// Drops the parts of the region outside `bounds`.
+    pub fn clip(&mut self, bounds: &PixelRect) {
+        let rects = self
+            .rects
+            .drain(..)
+            .filter_map(|rect| rect.intersection(bounds))
+            .collect::<Vec<_>>();
+        self.rects = rects;
+    }
+
+    /// Follows the pixels of `area` as they move by `(dx, dy)`, within `area`. Dirty pixels move
+    /// along, and the strips that scrolling uncovers become dirty.
+    pub fn scroll(&mut self, area: &PixelRect, dx: i32, dy: i32) {
+        if area.is_empty() {
+            return;
+        }
+        let mut rects = Vec::with_capacity(self.rects.len() + 2);
+        for rect in self.rects.drain(..) {
+            if let Some(inside) = rect.intersection(area) {
+                if let Some(moved) = inside.offset(dx, dy).intersection(area) {
+                    rects.push(moved);
+                }
+                // Pixels outside the area didn't move. This keeps the whole rect rather than
+                // cutting the area out of it.
+                if inside != rect {
+                    rects.push(rect);
+                }
+            } else {
+                rects.push(rect);
+            }
+        }
+        if dx > 0 {
+            rects.push(PixelRect::from_edges(
+                area.x,
+                area.y,
+                cmp::min(area.x + dx, area.right()),
+                area.bottom(),
+            ));
+        } else if dx < 0 {
+            rects.push(PixelRect::from_edges(
+                cmp::max(area.right() + dx, area.x),
+                area.y,
+                area.right(),
+                area.bottom(),
+            ));
+        }
+        if dy > 0 {
+            rects.push(PixelRect::from_edges(
+                area.x,
+                area.y,
+                area.right(),
+                cmp::min(area.y + dy, area.bottom()),
+            ));
+        } else if dy < 0 {
+            rects.push(PixelRect::from_edges(
+                area.x,
+                cmp::max(area.bottom() + dy, area.y),
+                area.right(),
+                area.bottom(),
+            ));
+        }
+        for rect in rects {
+            self.add(rect);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.rects.clear();
+    }
+
+    /// Takes the dirty rects, leaving the region clean.
+    pub fn take(&mut self) -> Vec<PixelRect> {
+        self.rects.drain(..).collect()
+    }
+}
+
+/// The kind of composition surface to allocate for an image.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SurfaceKind {
+    /// A slot in a shared atlas surface.
+    Atlas,
+    /// A `CompositionDrawingSurface` of its own.
+    Drawing,
+    /// A `CompositionVirtualDrawingSurface`, for images larger than a texture may be. Only the
+    /// parts that are drawn take memory.
+    Virtual,
+}
+
+/// Decides how images of each size are stored.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SurfacePolicy {
+    /// Images up to this size on both sides share atlas surfaces.
+    pub max_atlas_item: u32,
+    /// The width and height of atlas surfaces.
+    pub atlas_size: u32,
+    /// Images up to this size on both sides get a drawing surface; larger ones a virtual
+    /// surface. 4096 is within the texture limits of every Direct3D 11 device.
+    pub max_drawing_size: u32,
+}
+
+impl Default for SurfacePolicy {
+    #[inline]
+    fn default() -> Self {
+        SurfacePolicy {
+            max_atlas_item: 128,
+            atlas_size: 1024,
+            max_drawing_size: 4096,
+        }
+    }
+}
+
+impl SurfacePolicy {
+    pub fn kind(&self, width: u32, height: u32) -> SurfaceKind {
+        let largest = cmp::max(width, height);
+        if largest <= self.max_atlas_item && largest <= self.atlas_size {
+            SurfaceKind::Atlas
+        } else if largest <= self.max_drawing_size {
+            SurfaceKind::Drawing
+        } else {
+            SurfaceKind::Virtual
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Shelf {
+    y: i32,
+    height: u32,
+    /// Where the next slot of the shelf starts.
+    used: u32,
+    /// The slots in use.
+    slots: Vec<PixelRect>,
+}
+
+/// Packs images into an atlas of fixed size, in shelves: rows as high as their tallest slot,
+/// filled from left to right.
+///
+/// A shelf's space is reused once all of its slots are freed. Slots go to the lowest shelf that
+/// has room, so that images of similar heights end up together.
+#[derive(Debug, Clone)]
+pub struct ShelfAtlas {
+    width: u32,
+    height: u32,
+    shelves: Vec<Shelf>,
+}
+
+impl ShelfAtlas {
+    pub fn new(width: u32, height: u32) -> Self {
+        ShelfAtlas {
+            width,
+            height,
+            shelves: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Whether no slots are in use.
+    pub fn is_empty(&self) -> bool {
+        self.shelves.iter().all(|shelf| shelf.slots.is_empty())
+    }
+
+    /// Finds room for a `width` by `height` image. Returns `None` if the atlas is too full.
+    pub fn allocate(&mut self, width: u32, height: u32) -> Option<PixelRect> {
+        if width == 0 || height == 0 || width > self.width || height > self.height {
+            return None;
+        }
+        let atlas_width = self.width;
+        let best = self
+            .shelves
+            .iter_mut()
+            .filter(|shelf| {
+                let fits = shelf.height >= height && atlas_width - shelf.used >= width;
+                // An empty shelf can be rebuilt for any height that fits it.
+                fits || (shelf.slots.is_empty() && shelf.height >= height)
+            })
+            .min_by_key(|shelf| shelf.height);
+        if let Some(shelf) = best {
+            if shelf.slots.is_empty() {
+                shelf.used = 0;
+            }
+            let rect = PixelRect::new(shelf.used as i32, shelf.y, width, height);
+            shelf.used += width;
+            shelf.slots.push(rect);
+            return Some(rect);
+        }
+        let y = self
+            .shelves
+            .last()
+            .map_or(0, |shelf| shelf.y + shelf.height as i32);
+        if y as u32 + height > self.height {
+            return None;
+        }
+        let rect = PixelRect::new(0, y, width, height);
+        self.shelves.push(Shelf {
+            y,
+            height,
+            used: width,
+            slots: vec![rect],
+        });
+        Some(rect)
+    }
+
+    /// Frees a slot returned by `allocate`. Returns `false` if it isn't a slot in use, e.g. if
+    /// it was already freed.
+    pub fn free(&mut self, rect: &PixelRect) -> bool {
+        let shelf = match self.shelves.iter_mut().find(|shelf| shelf.y == rect.y) {
+            Some(shelf) => shelf,
+            None => return false,
+        };
+        match shelf.slots.iter().position(|slot| slot == rect) {
+            Some(index) => shelf.slots.swap_remove(index),
+            None => return false,
+        };
+        // Empty shelves at the end give their height back to new shelves.
+        while self
+            .shelves
+            .last()
+            .map_or(false, |shelf| shelf.slots.is_empty())
+        {
+            self.shelves.pop();
+        }
+        true
+    }
+
+    /// Frees every slot.
+    pub fn clear(&mut self) {
+        self.shelves.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i32, y: i32, width: u32, height: u32) -> PixelRect {
+        PixelRect::new(x, y, width, height)
+    }
+
+    #[test]
+    fn rect_geometry() {
+        assert_eq!(PixelRect::from_edges(10, 10, 5, 20), rect(10, 10, 0, 10));
+        assert!(PixelRect::from_edges(10, 10, 5, 20).is_empty());
+        let a = rect(0, 0, 10, 10);
+        assert_eq!(a.intersection(&rect(5, -5, 10, 10)), Some(rect(5, 0, 5, 5)));
+        // Rects that only share an edge don't intersect.
+        assert_eq!(a.intersection(&rect(10, 0, 10, 10)), None);
+        assert_eq!(a.union(&rect(20, 5, 5, 10)), rect(0, 0, 25, 15));
+        assert_eq!(a.union(&rect(50, 50, 0, 3)), a);
+        assert!(a.contains(&rect(2, 2, 8, 8)));
+        assert!(!a.contains(&rect(2, 2, 9, 8)));
+        assert!(a.contains(&rect(100, 100, 0, 0)));
+        assert_eq!(a.offset(-3, 4), rect(-3, 4, 10, 10));
+    }
+
+    #[test]
+    fn merges_rects_that_tile_or_contain() {
+        let mut region = DirtyRegion::default();
+        region.add(rect(0, 0, 10, 10));
+        region.add(rect(10, 0, 10, 10));
+        assert_eq!(region.rects(), &[rect(0, 0, 20, 10)]);
+        // Contained and empty rects change nothing.
+        region.add(rect(2, 2, 3, 3));
+        region.add(rect(50, 50, 0, 10));
+        assert_eq!(region.rects(), &[rect(0, 0, 20, 10)]);
+
+        region.add(rect(100, 100, 10, 10));
+        assert_eq!(region.rects().len(), 2);
+        // A rect that holds the others replaces them.
+        region.add(rect(-5, -5, 200, 200));
+        assert_eq!(region.rects(), &[rect(-5, -5, 200, 200)]);
+    }
+
+    #[test]
+    fn merges_the_cheapest_pair_past_the_limit() {
+        let mut region = DirtyRegion::new(2);
+        let added = [
+            rect(0, 0, 10, 10),
+            rect(100, 0, 10, 10),
+            rect(0, 200, 10, 10),
+        ];
+        for &rect in &added {
+            region.add(rect);
+        }
+        // The two rects on the same row cost the fewest extra pixels.
+        assert_eq!(region.rects(), &[rect(0, 200, 10, 10), rect(0, 0, 110, 10)]);
+        for added in &added {
+            assert!(region.rects().iter().any(|dirty| dirty.contains(added)));
+        }
+        assert_eq!(region.bounds(), Some(rect(0, 0, 110, 210)));
+
+        // Never fewer than one rect.
+        let mut region = DirtyRegion::new(0);
+        region.add(rect(0, 0, 1, 1));
+        region.add(rect(5, 5, 1, 1));
+        assert_eq!(region.rects(), &[rect(0, 0, 6, 6)]);
+    }
+
+    #[test]
+    fn clips_and_removes_covered_rects() {
+        let mut region = DirtyRegion::default();
+        assert_eq!(region.bounds(), None);
+        region.add(rect(-5, -5, 10, 10));
+        region.add(rect(50, 50, 10, 10));
+        region.add(rect(200, 0, 10, 10));
+        region.clip(&rect(0, 0, 100, 100));
+        assert_eq!(region.rects(), &[rect(0, 0, 5, 5), rect(50, 50, 10, 10)]);
+
+        // A rect that is only partly drawn stays dirty.
+        region.remove_covered(&rect(55, 55, 20, 20));
+        assert_eq!(region.rects().len(), 2);
+        region.remove_covered(&rect(0, 0, 20, 20));
+        assert_eq!(region.rects(), &[rect(50, 50, 10, 10)]);
+
+        assert_eq!(region.take(), vec![rect(50, 50, 10, 10)]);
+        assert!(region.is_empty());
+        region.add(rect(0, 0, 1, 1));
+        region.clear();
+        assert!(region.is_empty());
+    }
+
+    #[test]
+    fn scrolling_moves_dirty_rects_and_uncovers_strips() {
+        let area = rect(0, 0, 100, 100);
+        let mut region = DirtyRegion::default();
+        region.add(rect(0, 50, 10, 10));
+        region.add(rect(200, 200, 10, 10));
+        region.scroll(&area, 0, -10);
+        assert_eq!(
+            region.rects(),
+            &[
+                rect(0, 40, 10, 10),
+                rect(200, 200, 10, 10),
+                rect(0, 90, 100, 10)
+            ]
+        );
+
+        // A rect that sticks out of the area stays where it is, and its part inside moves out.
+        let mut region = DirtyRegion::default();
+        region.add(rect(90, 0, 20, 10));
+        region.scroll(&area, 20, 0);
+        assert_eq!(region.rects(), &[rect(90, 0, 20, 10), rect(0, 0, 20, 100)]);
+
+        // Scrolling further than the area uncovers all of it.
+        let mut region = DirtyRegion::default();
+        region.scroll(&area, -150, 0);
+        assert_eq!(region.rects(), &[area]);
+        region.scroll(&rect(0, 0, 0, 10), 5, 5);
+        assert_eq!(region.rects(), &[area]);
+    }
+
+    #[test]
+    fn surface_kinds() {
+        let policy = SurfacePolicy::default();
+        assert_eq!(policy.kind(128, 16), SurfaceKind::Atlas);
+        assert_eq!(policy.kind(16, 129), SurfaceKind::Drawing);
+        assert_eq!(policy.kind(4096, 4096), SurfaceKind::Drawing);
+        assert_eq!(policy.kind(4097, 1), SurfaceKind::Virtual);
+    }
+
+    #[test]
+    fn allocates_in_shelves() {
+        let mut atlas = ShelfAtlas::new(100, 100);
+        assert!(atlas.is_empty());
+        assert_eq!(atlas.allocate(30, 20), Some(rect(0, 0, 30, 20)));
+        // Shorter images share the shelf.
+        assert_eq!(atlas.allocate(30, 10), Some(rect(30, 0, 30, 10)));
+        // Wider than what's left of it.
+        assert_eq!(atlas.allocate(50, 20), Some(rect(0, 20, 50, 20)));
+        assert_eq!(atlas.allocate(20, 40), Some(rect(0, 40, 20, 40)));
+        // The only shelf high enough.
+        assert_eq!(atlas.allocate(10, 30), Some(rect(20, 40, 10, 30)));
+        assert!(!atlas.is_empty());
+
+        assert_eq!(atlas.allocate(0, 10), None);
+        assert_eq!(atlas.allocate(101, 1), None);
+        assert_eq!(atlas.allocate(10, 21), Some(rect(30, 40, 10, 21)));
+        // No shelf is high enough and there is no room for another.
+        assert_eq!(atlas.allocate(10, 41), None);
+
+        atlas.clear();
+        assert!(atlas.is_empty());
+        assert_eq!(atlas.allocate(10, 10), Some(rect(0, 0, 10, 10)));
+    }
+
+    #[test]
+    fn fails_when_full() {
+        let mut atlas = ShelfAtlas::new(100, 50);
+        assert_eq!(atlas.size(), (100, 50));
+        assert_eq!(atlas.allocate(100, 30), Some(rect(0, 0, 100, 30)));
+        assert_eq!(atlas.allocate(100, 30), None);
+        assert_eq!(atlas.allocate(50, 20), Some(rect(0, 30, 50, 20)));
+        assert_eq!(atlas.allocate(50, 20), Some(rect(50, 30, 50, 20)));
+        assert_eq!(atlas.allocate(1, 1), None);
+    }
+
+    #[test]
+    fn free_rejects_rects_not_in_use() {
+        let mut atlas = ShelfAtlas::new(100, 100);
+        let a = atlas.allocate(30, 20).unwrap();
+        let b = atlas.allocate(30, 10).unwrap();
+        assert!(!atlas.free(&rect(10, 0, 30, 20)));
+        assert!(!atlas.free(&rect(0, 0, 30, 10)));
+        assert!(!atlas.free(&rect(0, 50, 30, 20)));
+        assert!(atlas.free(&a));
+        // A second free of the same slot.
+        assert!(!atlas.free(&a));
+        assert!(!atlas.is_empty());
+        assert!(atlas.free(&b));
+        assert!(!atlas.free(&b));
+        assert!(atlas.is_empty());
+    }
+
+    #[test]
+    fn reuses_freed_space() {
+        let mut atlas = ShelfAtlas::new(100, 100);
+        let a = atlas.allocate(50, 20).unwrap();
+        let b = atlas.allocate(50, 30).unwrap();
+        assert_eq!(b, rect(0, 20, 50, 30));
+        // An empty shelf is filled from the left again, by images up to its height.
+        assert!(atlas.free(&a));
+        assert_eq!(atlas.allocate(80, 10), Some(rect(0, 0, 80, 10)));
+        // The last shelf gives its height back once it's empty.
+        assert!(atlas.free(&b));
+        assert_eq!(atlas.allocate(100, 80), Some(rect(0, 20, 100, 80)));
+    }
+}
@@ -0,0 +1,796 @@
+use std::f64::consts::PI;
+use std::fmt;
+
+/// A point in path coordinates.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Point {
+    #[inline]
+    pub fn new(x: f64, y: f64) -> Self {
+        Point { x, y }
+    }
+}
+
+/// One piece of a path, in absolute coordinates.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PathSegment {
+    /// Starts a figure.
+    MoveTo(Point),
+    LineTo(Point),
+    /// A quadratic Bézier curve through a control point to an end point.
+    QuadTo(Point, Point),
+    /// A cubic Bézier curve through two control points to an end point.
+    CubicTo(Point, Point, Point),
+    /// Closes the figure with a line back to its start.
+    Close,
+}
+
+/// Which areas a path fills where its figures overlap or cross themselves.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FillRule {
+    /// Areas the path winds around a non-zero number of times. The default in SVG.
+    NonZero,
+    /// Areas the path winds around an odd number of times.
+    EvenOdd,
+}
+
+impl Default for FillRule {
+    #[inline]
+    fn default() -> Self {
+        FillRule::NonZero
+    }
+}
+
+/// What the parser found where it failed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PathErrorKind {
+    /// Path data has to start with a moveto.
+    ExpectedMoveTo,
+    ExpectedCommand,
+    ExpectedNumber,
+    /// Arc flags are a single `0` or `1`.
+    ExpectedFlag,
+}
+
+/// Path data that couldn't be parsed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PathError {
+    pub kind: PathErrorKind,
+    /// The byte offset of the problem in the path data.
+    pub offset: usize,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let expected = match self.kind {
+            PathErrorKind::ExpectedMoveTo => "a moveto command",
+            PathErrorKind::ExpectedCommand => "a path command",
+            PathErrorKind::ExpectedNumber => "a number",
+            PathErrorKind::ExpectedFlag => "an arc flag",
+        };
+        write!(
+            f,
+            "expected {} at byte {} of the path data",
+            expected, self.offset
+        )
+    }
+}
+
+/// A path made of lines and Bézier curves, built segment by segment or parsed from SVG path
+/// data.
+///
+/// Every figure starts with a `MoveTo`: drawing after a `Close`, or before any `move_to`, first
+/// moves to the current point. Arcs are stored as the cubic curves that approximate them.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PathData {
+    segments: Vec<PathSegment>,
+    /// Where the current figure started.
+    start: Point,
+    current: Point,
+    in_figure: bool,
+}
+
+impl PathData {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Parses the `d` attribute of an SVG `<path>`, with all of its commands in both their
+    /// absolute and relative forms.
+    pub fn parse(data: &str) -> Result<Self, PathError> {
+        parse(data)
+    }
+
+    #[inline]
+    pub fn segments(&self) -> &[PathSegment] {
+        &self.segments
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Where the next segment starts.
+    #[inline]
+    pub fn current_point(&self) -> Point {
+        self.current
+    }
+
+    /// The smallest box that holds every point of the path, control points included, as its
+    /// top-left and bottom-right corners.
+    pub fn bounds(&self) -> Option<(Point, Point)> {
+        let mut points = self.segments.iter().flat_map(|segment| {
+            let points: Vec<Point> = match *segment {
+                PathSegment::MoveTo(p) | PathSegment::LineTo(p) => vec![p],
+                PathSegment::QuadTo(c, p) => vec![c, p],
+                PathSegment::CubicTo(c1, c2, p) => vec![c1, c2, p],
+                PathSegment::Close => vec![],
+            };
+            points
+        });
+        let first = points.next()?;
+        Some(points.fold((first, first), |(min, max), p| {
+            (
+                Point::new(min.x.min(p.x), min.y.min(p.y)),
+                Point::new(max.x.max(p.x), max.y.max(p.y)),
+            )
+        }))
+    }
+
+    pub fn move_to(&mut self, to: Point) {
+        self.segments.push(PathSegment::MoveTo(to));
+        self.start = to;
+        self.current = to;
+        self.in_figure = true;
+    }
+
+    fn begin_figure(&mut self) {
+        if !self.in_figure {
+            let current = self.current;
+            self.move_to(current);
+        }
+    }
+
+    pub fn line_to(&mut self, to: Point) {
+        self.begin_figure();
+        self.segments.push(PathSegment::LineTo(to));
+        self.current = to;
+    }
+
+    pub fn quad_to(&mut self, control: Point, to: Point) {
+        self.begin_figure();
+        self.segments.push(PathSegment::QuadTo(control, to));
+        self.current = to;
+    }
+
+    pub fn cubic_to(&mut self, control1: Point, control2: Point, to: Point) {
+        self.begin_figure();
+        self.segments
+            .push(PathSegment::CubicTo(control1, control2, to));
+        self.current = to;
+    }
+
+    /// Draws an elliptical arc to `to`, like the SVG arc command: the ellipse has radii `radii`
+    /// and is rotated by `x_rotation` degrees, and the flags choose one of the four arcs that
+    /// connect the points. Radii too small to reach `to` are scaled up; a radius of zero draws a
+    /// line.
+    pub fn arc_to(
+        &mut self,
+        radii: (f64, f64),
+        x_rotation: f64,
+        large_arc: bool,
+        sweep: bool,
+        to: Point,
+    ) {
+        if to == self.current {
+            return;
+        }
+        if radii.0 == 0.0 || radii.1 == 0.0 {
+            self.line_to(to);
+            return;
+        }
+        for curve in arc_to_cubics(self.current, radii, x_rotation, large_arc, sweep, to) {
+            self.cubic_to(curve[0], curve[1], curve[2]);
+        }
+    }
+
+    /// Closes the current figure. The next figure starts where this one did, unless it moves.
+    pub fn close(&mut self) {
+        if self.in_figure {
+            self.segments.push(PathSegment::Close);
+            self.current = self.start;
+            self.in_figure = false;
+        }
+    }
+}
+
+/// Approximates an SVG elliptical arc from `from` to `to` with cubic Bézier curves, each spanning
+/// at most a quarter turn, as `[control1, control2, end]`. The last curve ends exactly at `to`.
+///
+/// The radii must not be zero. Returns no curves if the end points are the same, since SVG omits
+/// such arcs.
+pub fn arc_to_cubics(
+    from: Point,
+    radii: (f64, f64),
+    x_rotation: f64,
+    large_arc: bool,
+    sweep: bool,
+    to: Point,
+) -> Vec<[Point; 3]> {
+    if from == to {
+        return Vec::new();
+    }
+    // The conversion from endpoint to center parameterization of the SVG implementation notes.
+    let (mut rx, mut ry) = (radii.0.abs(), radii.1.abs());
+    let (sin_phi, cos_phi) = x_rotation.to_radians().sin_cos();
+    let half_dx = (from.x - to.x) / 2.0;
+    let half_dy = (from.y - to.y) / 2.0;
+    let x1 = cos_phi * half_dx + sin_phi * half_dy;
+    let y1 = -sin_phi * half_dx + cos_phi * half_dy;
+
+    let lambda = (x1 * x1) / (rx * rx) + (y1 * y1) / (ry * ry);
+    if lambda > 1.0 {
+        let scale = lambda.sqrt();
+        rx *= scale;
+        ry *= scale;
+    }
+    let (rx2, ry2) = (rx * rx, ry * ry);
+    let numerator = rx2 * ry2 - rx2 * y1 * y1 - ry2 * x1 * x1;
+    let denominator = rx2 * y1 * y1 + ry2 * x1 * x1;
+    let mut coefficient = (numerator.max(0.0) / denominator).sqrt();
+    if large_arc == sweep {
+        coefficient = -coefficient;
+    }
+    let center_x1 = coefficient * rx * y1 / ry;
+    let center_y1 = -coefficient * ry * x1 / rx;
+    let center = Point::new(
+        cos_phi * center_x1 - sin_phi * center_y1 + (from.x + to.x) / 2.0,
+        sin_phi * center_x1 + cos_phi * center_y1 + (from.y + to.y) / 2.0,
+    );
+
+    let angle = |ux: f64, uy: f64, vx: f64, vy: f64| (ux * vy - uy * vx).atan2(ux * vx + uy * vy);
+    let start_x = (x1 - center_x1) / rx;
+    let start_y = (y1 - center_y1) / ry;
+    let end_x = (-x1 - center_x1) / rx;
+    let end_y = (-y1 - center_y1) / ry;
+    let theta = angle(1.0, 0.0, start_x, start_y);
+    let mut sweep_angle = angle(start_x, start_y, end_x, end_y);
+    if !sweep && sweep_angle > 0.0 {
+        sweep_angle -= 2.0 * PI;
+    } else if sweep && sweep_angle < 0.0 {
+        sweep_angle += 2.0 * PI;
+    }
+
+    let point_at = |t: f64| {
+        let (sin, cos) = t.sin_cos();
+        Point::new(
+            center.x + rx * cos * cos_phi - ry * sin * sin_phi,
+            center.y + rx * cos * sin_phi + ry * sin * cos_phi,
+        )
+    };
+    let tangent_at = |t: f64| {
+        let (sin, cos) = t.sin_cos();
+        Point::new(
+            -rx * sin * cos_phi - ry * cos * sin_phi,
+            -rx * sin * sin_phi + ry * cos * cos_phi,
+        )
+    };
+    // Quarter turns, with some slack so that rounding doesn't split a quarter turn in two.
+    let count = ((sweep_angle.abs() / (PI / 2.0)) - 1e-9).ceil().max(1.0) as usize;
+    let step = sweep_angle / count as f64;
+    let handle = 4.0 / 3.0 * (step / 4.0).tan();
+    let mut curves = Vec::with_capacity(count);
+    let mut start = from;
+    for index in 0..count {
+        let t1 = theta + step * index as f64;
+        let t2 = t1 + step;
+        let end = if index + 1 == count { to } else { point_at(t2) };
+        let (d1, d2) = (tangent_at(t1), tangent_at(t2));
+        curves.push([
+            Point::new(start.x + handle * d1.x, start.y + handle * d1.y),
+            Point::new(end.x - handle * d2.x, end.y - handle * d2.y),
+            end,
+        ]);
+        start = end;
+    }
+    curves
+}
+
+struct Parser<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+fn is_whitespace(byte: u8) -> bool {
+    byte == b' ' || byte == b'\t' || byte == b'\r' || byte == b'\n' || byte == 0x0C
+}
+
+fn is_command(byte: u8) -> bool {
+    b"MmLlHhVvCcSsQqTtAaZz".contains(&byte)
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.position).cloned()
+    }
+
+    fn error(&self, kind: PathErrorKind) -> PathError {
+        PathError {
+            kind,
+            offset: self.position,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().map_or(false, is_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    /// Skips whitespace with at most one comma in it.
+    fn skip_separator(&mut self) {
+        self.skip_whitespace();
+        if self.peek() == Some(b',') {
+            self.position += 1;
+            self.skip_whitespace();
+        }
+    }
+
+    fn at_number(&self) -> bool {
+        match self.peek() {
+            Some(byte) => byte.is_ascii_digit() || byte == b'.' || byte == b'-' || byte == b'+',
+            None => false,
+        }
+    }
+
+    fn skip_digits(&mut self) -> usize {
+        let start = self.position;
+        while self.peek().map_or(false, |byte| byte.is_ascii_digit()) {
+            self.position += 1;
+        }
+        self.position - start
+    }
+
+    /// Reads a number. Numbers needn't be separated where the next one starts with a sign or a
+    /// second decimal point, as in `1-2` or `0.5.5`.
+    fn number(&mut self) -> Result<f64, PathError> {
+        self.skip_whitespace();
+        let start = self.position;
+        if let Some(b'-') | Some(b'+') = self.peek() {
+            self.position += 1;
+        }
+        let mut digits = self.skip_digits();
+        if self.peek() == Some(b'.') {
+            self.position += 1;
+            digits += self.skip_digits();
+        }
+        if digits == 0 {
+            self.position = start;
+            return Err(self.error(PathErrorKind::ExpectedNumber));
+        }
+        if let Some(b'e') | Some(b'E') = self.peek() {
+            let mantissa_end = self.position;
+            self.position += 1;
+            if let Some(b'-') | Some(b'+') = self.peek() {
+                self.position += 1;
+            }
+            if self.skip_digits() == 0 {
+                self.position = mantissa_end;
+            }
+        }
+        // Everything read is ASCII.
+        let text = ::std::str::from_utf8(&self.data[start..self.position]).unwrap();
+        let value = text.parse::<f64>().map_err(|_| PathError {
+            kind: PathErrorKind::ExpectedNumber,
+            offset: start,
+        })?;
+        self.skip_separator();
+        Ok(value)
+    }
+
+    fn point(&mut self, base: Point) -> Result<Point, PathError> {
+        let x = self.number()?;
+        let y = self.number()?;
+        Ok(Point::new(base.x + x, base.y + y))
+    }
+
+    /// Reads an arc flag, which needn't be separated from what follows, as in `a1 1 0 00 1 1`.
+    fn flag(&mut self) -> Result<bool, PathError> {
+        self.skip_whitespace();
+        let flag = match self.peek() {
+            Some(b'0') => false,
+            Some(b'1') => true,
+            _ => return Err(self.error(PathErrorKind::ExpectedFlag)),
+        };
+        self.position += 1;
+        self.skip_separator();
+        Ok(flag)
+    }
+}
+
+/// `point` mirrored through `center`.
+fn reflect(point: Point, center: Point) -> Point {
+    Point::new(2.0 * center.x - point.x, 2.0 * center.y - point.y)
+}
+
+fn parse(data: &str) -> Result<PathData, PathError> {
+    let mut parser = Parser {
+        data: data.as_bytes(),
+        position: 0,
+    };
+    let mut path = PathData::new();
+    // The command that numbers without a command of their own repeat.
+    let mut previous: Option<u8> = None;
+    // The second control point of the last cubic curve and the control point of the last
+    // quadratic one, which the smooth commands reflect.
+    let mut last_cubic: Option<Point> = None;
+    let mut last_quad: Option<Point> = None;
+
+    parser.skip_whitespace();
+    while parser.peek().is_some() {
+        let command = match parser.peek() {
+            Some(byte) if is_command(byte) => {
+                parser.position += 1;
+                byte
+            }
+            _ => match previous {
+                Some(command) if parser.at_number() => command,
+                _ => return Err(parser.error(PathErrorKind::ExpectedCommand)),
+            },
+        };
+        if previous.is_none() && path.is_empty() && command != b'M' && command != b'm' {
+            return Err(PathError {
+                kind: PathErrorKind::ExpectedMoveTo,
+                offset: parser.position - 1,
+            });
+        }
+        parser.skip_whitespace();
+
+        let current = path.current_point();
+        let base = if command.is_ascii_lowercase() {
+            current
+        } else {
+            Point::default()
+        };
+        let (mut cubic, mut quad) = (None, None);
+        let kind = command.to_ascii_uppercase();
+        match kind {
+            b'M' => {
+                path.move_to(parser.point(base)?);
+                // Further pairs of a moveto are lines.
+                previous = Some(if command == b'm' { b'l' } else { b'L' });
+            }
+            b'L' => path.line_to(parser.point(base)?),
+            b'H' => {
+                let x = parser.number()?;
+                path.line_to(Point::new(base.x + x, current.y));
+            }
+            b'V' => {
+                let y = parser.number()?;
+                path.line_to(Point::new(current.x, base.y + y));
+            }
+            b'C' => {
+                let control1 = parser.point(base)?;
+                let control2 = parser.point(base)?;
+                let to = parser.point(base)?;
+                path.cubic_to(control1, control2, to);
+                cubic = Some(control2);
+            }
+            b'S' => {
+                let control1 = last_cubic.map_or(current, |control| reflect(control, current));
+                let control2 = parser.point(base)?;
+                let to = parser.point(base)?;
+                path.cubic_to(control1, control2, to);
+                cubic = Some(control2);
+            }
+            b'Q' => {
+                let control = parser.point(base)?;
+                let to = parser.point(base)?;
+                path.quad_to(control, to);
+                quad = Some(control);
+            }
+            b'T' => {
+                let control = last_quad.map_or(current, |control| reflect(control, current));
+                let to = parser.point(base)?;
+                path.quad_to(control, to);
+                quad = Some(control);
+            }
+            b'A' => {
+                let rx = parser.number()?;
+                let ry = parser.number()?;
+                let x_rotation = parser.number()?;
+                let large_arc = parser.flag()?;
+                let sweep = parser.flag()?;
+                let to = parser.point(base)?;
+                path.arc_to((rx, ry), x_rotation, large_arc, sweep, to);
+            }
+            _ => {
+                path.close();
+                // Numbers can't follow a closepath.
+                previous = None;
+            }
+        }
+        if kind != b'M' && kind != b'Z' {
+            previous = Some(command);
+        }
+        last_cubic = cubic;
+        last_quad = quad;
+        parser.skip_whitespace();
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use self::PathSegment::*;
+    use super::*;
+
+    fn p(x: f64, y: f64) -> Point {
+        Point::new(x, y)
+    }
+
+    fn segments(data: &str) -> Vec<PathSegment> {
+        PathData::parse(data).unwrap().segments().to_vec()
+    }
+
+    fn error(data: &str) -> (PathErrorKind, usize) {
+        let error = PathData::parse(data).unwrap_err();
+        (error.kind, error.offset)
+    }
+
+    fn assert_near(actual: Point, expected: Point) {
+        assert!(
+            (actual.x - expected.x).abs() < 1e-9 && (actual.y - expected.y).abs() < 1e-9,
+            "{:?} isn't {:?}",
+            actual,
+            expected
+        );
+    }
+
+    /// The end points of the curves that an arc became, after the moveto.
+    fn arc_ends(data: &str) -> Vec<Point> {
+        segments(data)[1..]
+            .iter()
+            .map(|segment| match *segment {
+                CubicTo(_, _, to) => to,
+                ref other => panic!("{:?} isn't a curve", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn absolute_commands() {
+        let path =
+            PathData::parse("M10 20 L30 40 H50 V60 C1 2 3 4 5 6 S7 8 9 10 Q11 12 13 14 T15 16 Z")
+                .unwrap();
+        assert_eq!(
+            path.segments(),
+            &[
+                MoveTo(p(10.0, 20.0)),
+                LineTo(p(30.0, 40.0)),
+                LineTo(p(50.0, 40.0)),
+                LineTo(p(50.0, 60.0)),
+                CubicTo(p(1.0, 2.0), p(3.0, 4.0), p(5.0, 6.0)),
+                // The first control point reflects the last one of the previous curve.
+                CubicTo(p(7.0, 8.0), p(7.0, 8.0), p(9.0, 10.0)),
+                QuadTo(p(11.0, 12.0), p(13.0, 14.0)),
+                QuadTo(p(15.0, 16.0), p(15.0, 16.0)),
+                Close,
+            ]
+        );
+        assert_eq!(path.current_point(), p(10.0, 20.0));
+    }
+
+    #[test]
+    fn relative_commands() {
+        assert_eq!(
+            segments("m10 20 l5 5 h10 v-5 c1 1 2 2 3 3 s1 1 2 2 q1 0 2 2 t2 0 z"),
+            vec![
+                MoveTo(p(10.0, 20.0)),
+                LineTo(p(15.0, 25.0)),
+                LineTo(p(25.0, 25.0)),
+                LineTo(p(25.0, 20.0)),
+                CubicTo(p(26.0, 21.0), p(27.0, 22.0), p(28.0, 23.0)),
+                CubicTo(p(29.0, 24.0), p(29.0, 24.0), p(30.0, 25.0)),
+                QuadTo(p(31.0, 25.0), p(32.0, 27.0)),
+                QuadTo(p(33.0, 29.0), p(34.0, 27.0)),
+                Close,
+            ]
+        );
+        // Relative commands after a closepath start from the figure's start.
+        assert_eq!(
+            segments("M1 1 L5 1 Z l0 1"),
+            vec![
+                MoveTo(p(1.0, 1.0)),
+                LineTo(p(5.0, 1.0)),
+                Close,
+                MoveTo(p(1.0, 1.0)),
+                LineTo(p(1.0, 2.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn smooth_commands_without_a_curve_to_reflect() {
+        // The control point is the current point when the previous segment isn't of the same
+        // kind.
+        assert_eq!(
+            segments("M0 0 S1 1 2 2 T4 0 L5 5 s1 0 2 0"),
+            vec![
+                MoveTo(p(0.0, 0.0)),
+                CubicTo(p(0.0, 0.0), p(1.0, 1.0), p(2.0, 2.0)),
+                QuadTo(p(2.0, 2.0), p(4.0, 0.0)),
+                LineTo(p(5.0, 5.0)),
+                CubicTo(p(5.0, 5.0), p(6.0, 5.0), p(7.0, 5.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn implicit_repeats() {
+        // Further pairs of a moveto are lines, relative if the moveto is.
+        assert_eq!(
+            segments("M0 0 10 0 10 10"),
+            vec![
+                MoveTo(p(0.0, 0.0)),
+                LineTo(p(10.0, 0.0)),
+                LineTo(p(10.0, 10.0)),
+            ]
+        );
+        assert_eq!(
+            segments("m1 1 2 2 l1 0 1 0"),
+            vec![
+                MoveTo(p(1.0, 1.0)),
+                LineTo(p(3.0, 3.0)),
+                LineTo(p(4.0, 3.0)),
+                LineTo(p(5.0, 3.0)),
+            ]
+        );
+        assert_eq!(
+            segments("M0 0 H1 2 v3 4"),
+            vec![
+                MoveTo(p(0.0, 0.0)),
+                LineTo(p(1.0, 0.0)),
+                LineTo(p(2.0, 0.0)),
+                LineTo(p(2.0, 3.0)),
+                LineTo(p(2.0, 7.0)),
+            ]
+        );
+        // Each repeat of a relative command starts where the last one ended.
+        assert_eq!(
+            segments("M0 0 c1 1 2 2 3 3 1 1 2 2 3 3"),
+            vec![
+                MoveTo(p(0.0, 0.0)),
+                CubicTo(p(1.0, 1.0), p(2.0, 2.0), p(3.0, 3.0)),
+                CubicTo(p(4.0, 4.0), p(5.0, 5.0), p(6.0, 6.0)),
+            ]
+        );
+        assert_eq!(
+            segments("M0 0 Q1 1 2 0 3 -1 4 0"),
+            vec![
+                MoveTo(p(0.0, 0.0)),
+                QuadTo(p(1.0, 1.0), p(2.0, 0.0)),
+                QuadTo(p(3.0, -1.0), p(4.0, 0.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn packed_numbers_and_flags() {
+        assert_eq!(
+            segments("M1-2L.5.5-1e1,2E-1"),
+            vec![
+                MoveTo(p(1.0, -2.0)),
+                LineTo(p(0.5, 0.5)),
+                LineTo(p(-10.0, 0.2)),
+            ]
+        );
+        assert_eq!(
+            segments(" \t\r\nM +1 , 2\n\x0cL3,4 "),
+            vec![MoveTo(p(1.0, 2.0)), LineTo(p(3.0, 4.0))]
+        );
+        let spaced = segments("M0 0 a1 1 0 0 0 1 1");
+        assert_eq!(segments("M0 0a1 1 0 00 1 1"), spaced);
+        assert_eq!(segments("M0 0a1,1,0,0,0,1,1"), spaced);
+        assert_eq!(segments("M0,0a1 1 0 001,1"), spaced);
+    }
+
+    #[test]
+    fn empty_data() {
+        assert!(PathData::parse("").unwrap().is_empty());
+        assert!(PathData::parse(" \n ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn error_offsets() {
+        assert_eq!(error("L1 1"), (PathErrorKind::ExpectedMoveTo, 0));
+        assert_eq!(error("  l1 1"), (PathErrorKind::ExpectedMoveTo, 2));
+        assert_eq!(error("  x"), (PathErrorKind::ExpectedCommand, 2));
+        assert_eq!(error("M1"), (PathErrorKind::ExpectedNumber, 2));
+        assert_eq!(error("M1 2 L3,x"), (PathErrorKind::ExpectedNumber, 8));
+        assert_eq!(error("M0 0 -"), (PathErrorKind::ExpectedNumber, 5));
+        assert_eq!(error("M0 0 L1 1 X"), (PathErrorKind::ExpectedCommand, 10));
+        // Numbers can't follow a closepath.
+        assert_eq!(error("M0 0 Z 1 1"), (PathErrorKind::ExpectedCommand, 7));
+        assert_eq!(
+            error("M0 0 A1 1 0 2 0 1 1"),
+            (PathErrorKind::ExpectedFlag, 12)
+        );
+        assert_eq!(error("M0 0 A1 1 0 1"), (PathErrorKind::ExpectedFlag, 13));
+        assert_eq!(
+            PathData::parse("M0 0 A1 1 0 2 0 1 1")
+                .unwrap_err()
+                .to_string(),
+            "expected an arc flag at byte 12 of the path data"
+        );
+    }
+
+    #[test]
+    fn arcs_end_at_their_end_point() {
+        let ends = arc_ends("M10 10 A20 20 0 0 1 50 10");
+        // Half a turn, in two quarters, through the top since the sweep is positive.
+        assert_eq!(ends.len(), 2);
+        assert_near(ends[0], p(30.0, -10.0));
+        assert_eq!(ends[1], p(50.0, 10.0));
+        assert_eq!(
+            segments("m10 10 a20 20 0 0 1 40 0"),
+            segments("M10 10 A20 20 0 0 1 50 10")
+        );
+        let ends = arc_ends("M10 10 A20 20 0 0 0 50 10");
+        assert_near(ends[0], p(30.0, 30.0));
+
+        // The small and large arcs between two points a radius apart. With a positive sweep, the
+        // large one goes around a center above the chord.
+        let small = arc_ends("M0 0 A10 10 0 0 1 10 0");
+        assert_eq!(small, vec![p(10.0, 0.0)]);
+        let large = arc_ends("M0 0 A10 10 0 1 1 10 0");
+        assert_eq!(large.len(), 4);
+        assert_eq!(large[3], p(10.0, 0.0));
+        let center = p(5.0, -75f64.sqrt());
+        for end in &large {
+            let radius = (end.x - center.x).hypot(end.y - center.y);
+            assert!((radius - 10.0).abs() < 1e-9, "{:?}", end);
+        }
+
+        let mut path = PathData::new();
+        path.move_to(p(3.0, 4.0));
+        path.arc_to((5.0, 5.0), 0.0, false, true, p(13.0, 4.0));
+        assert_eq!(path.current_point(), p(13.0, 4.0));
+    }
+
+    #[test]
+    fn degenerate_arcs() {
+        // An arc to the current point is left out, and one with a zero radius is a line.
+        assert_eq!(segments("M5 5 A1 1 0 0 0 5 5"), vec![MoveTo(p(5.0, 5.0))]);
+        assert_eq!(
+            segments("M0 0 A0 5 0 0 0 10 0"),
+            vec![MoveTo(p(0.0, 0.0)), LineTo(p(10.0, 0.0))]
+        );
+        assert!(arc_to_cubics(p(1.0, 1.0), (1.0, 1.0), 0.0, false, false, p(1.0, 1.0)).is_empty());
+    }
+
+    #[test]
+    fn radii_scale_up_to_reach_the_end_point() {
+        // A radius of 1 can't span 10 units, so the arc becomes a half circle of radius 5.
+        let ends = arc_ends("M0 0 A1 1 0 0 1 10 0");
+        assert_eq!(ends.len(), 2);
+        assert_near(ends[0], p(5.0, -5.0));
+        assert_eq!(ends[1], p(10.0, 0.0));
+        // The sign of the radii doesn't matter.
+        assert_eq!(
+            segments("M0 0 A-1 -1 0 0 1 10 0"),
+            segments("M0 0 A1 1 0 0 1 10 0")
+        );
+        // Both radii scale by the same factor: rotated a quarter turn, the radius of 2 runs
+        // along the chord and becomes 5, and the radius of 1 becomes 2.5.
+        let ends = arc_ends("M0 0 A1 2 90 0 1 10 0");
+        assert_eq!(ends.len(), 2);
+        assert_near(ends[0], p(5.0, -2.5));
+        assert_eq!(ends[1], p(10.0, 0.0));
+    }
+}
@@ -0,0 +1,431 @@
+use rusttype::{point, Font, PositionedGlyph, Scale};
+
+use bitmap::{Bitmap, Rgba};
+
+/// Horizontal alignment of each line within the layout box.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TextAlignment {
+    Left,
+    Center,
+    Right,
+}
+
+impl Default for TextAlignment {
+    #[inline]
+    fn default() -> Self {
+        TextAlignment::Left
+    }
+}
+
+/// Describes how a string should be laid out.
+#[derive(Clone)]
+pub struct TextFormat {
+    pub font: Font<'static>,
+    /// Font size in pixels (the height of one em).
+    pub size: f32,
+    /// Maximum line width in pixels. If this is `None`, lines only break at `'\n'`.
+    pub wrap_width: Option<f32>,
+    pub alignment: TextAlignment,
+}
+
+impl TextFormat {
+    /// Loads the font from the bytes of a TrueType or OpenType file.
+    ///
+    /// Returns `None` if the font could not be parsed.
+    pub fn from_font_bytes(bytes: Vec<u8>, size: f32) -> Option<Self> {
+        let font = Font::from_bytes(bytes).ok()?;
+        Some(TextFormat {
+            font,
+            size,
+            wrap_width: None,
+            alignment: TextAlignment::Left,
+        })
+    }
+}
+
+/// A line produced by `TextLayout`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineMetrics {
+    /// Index of the line's first glyph in `TextLayout::glyphs`.
+    pub first_glyph: usize,
+    pub glyph_count: usize,
+    /// Left edge of the line after alignment.
+    pub x: f32,
+    /// Position of the baseline.
+    pub baseline: f32,
+    /// Width of the line, excluding trailing whitespace.
+    pub width: f32,
+}
+
+/// A string that has been shaped and broken into lines.
+pub struct TextLayout {
+    glyphs: Vec<PositionedGlyph<'static>>,
+    lines: Vec<LineMetrics>,
+    width: f32,
+    height: f32,
+}
+
+// A glyph and its pen position relative to the start of its line.
+struct LineGlyph {
+    c: char,
+    x: f32,
+}
+
+struct Line {
+    glyphs: Vec<LineGlyph>,
+    width: f32,
+}
+
+impl TextLayout {
+    pub fn new(text: &str, format: &TextFormat) -> Self {
+        let scale = Scale::uniform(format.size);
+        let v_metrics = format.font.v_metrics(scale);
+        let line_height = v_metrics.ascent - v_metrics.descent + v_metrics.line_gap;
+
+        let mut lines = Vec::new();
+        for paragraph in text.split('\n') {
+            break_paragraph(paragraph.trim_end_matches('\r'), format, scale, &mut lines);
+        }
+
+        let width = match format.wrap_width {
+            Some(wrap_width) => wrap_width,
+            None => lines.iter().fold(0.0, |w: f32, line| w.max(line.width)),
+        };
+
+        let mut glyphs = Vec::new();
+        let mut metrics = Vec::with_capacity(lines.len());
+        for (i, line) in lines.into_iter().enumerate() {
+            let x = match format.alignment {
+                TextAlignment::Left => 0.0,
+                TextAlignment::Center => ((width - line.width) / 2.0).max(0.0),
+                TextAlignment::Right => (width - line.width).max(0.0),
+            };
+            let baseline = v_metrics.ascent + line_height * i as f32;
+            metrics.push(LineMetrics {
+                first_glyph: glyphs.len(),
+                glyph_count: line.glyphs.len(),
+                x,
+                baseline,
+                width: line.width,
+            });
+            for g in line.glyphs {
+                glyphs.push(
+                    format
+                        .font
+                        .glyph(g.c)
+                        .scaled(scale)
+                        .positioned(point(x + g.x, baseline)),
+                );
+            }
+        }
+
+        TextLayout {
+            glyphs,
+            height: line_height * metrics.len() as f32,
+            lines: metrics,
+            width,
+        }
+    }
+
+    #[inline]
+    pub fn glyphs(&self) -> &[PositionedGlyph<'static>] {
+        &self.glyphs
+    }
+
+    #[inline]
+    pub fn lines(&self) -> &[LineMetrics] {
+        &self.lines
+    }
+
+    /// Width of the layout box in pixels.
+    #[inline]
+    pub fn width(&self) -> f32 {
+        self.width
+    }
+
+    /// Height of the layout box in pixels.
+    #[inline]
+    pub fn height(&self) -> f32 {
+        self.height
+    }
+
+    /// Rasterizes the glyphs into a bitmap just big enough to hold the layout box.
+    pub fn rasterize(&self, color: Rgba) -> Bitmap {
+        let mut bitmap = Bitmap::new(self.width.ceil() as u32, self.height.ceil() as u32);
+        for glyph in &self.glyphs {
+            if let Some(bb) = glyph.pixel_bounding_box() {
+                glyph.draw(|x, y, coverage| {
+                    let a = (color.a as f32 * coverage).round() as u8;
+                    let px = Rgba { a, ..color }.to_premultiplied_bgra();
+                    bitmap.blend_pixel(bb.min.x + x as i32, bb.min.y + y as i32, px);
+                });
+            }
+        }
+        bitmap
+    }
+}
+
+/// Breaks a paragraph into lines at word boundaries, falling back to breaking between characters
+/// when a single word doesn't fit.
+fn break_paragraph(paragraph: &str, format: &TextFormat, scale: Scale, lines: &mut Vec<Line>) {
+    let max_width = format.wrap_width.unwrap_or(std::f32::INFINITY);
+    let mut line = Line {
+        glyphs: Vec::new(),
+        width: 0.0,
+    };
+    let mut pen = 0.0;
+    let mut prev: Option<char> = None;
+
+    for word in split_words(paragraph) {
+        let fits = {
+            let mut x = pen;
+            let mut p = prev;
+            let mut right = pen;
+            for c in word.chars() {
+                if let Some(p) = p {
+                    x += format.font.pair_kerning(scale, p, c);
+                }
+                let advance = advance_width(format, scale, c);
+                if !c.is_whitespace() {
+                    right = x + advance;
+                }
+                x += advance;
+                p = Some(c);
+            }
+            right <= max_width
+        };
+        if !fits && !line.glyphs.is_empty() {
+            lines.push(line);
+            line = Line {
+                glyphs: Vec::new(),
+                width: 0.0,
+            };
+            pen = 0.0;
+            prev = None;
+        }
+
+        for c in word.chars() {
+            if let Some(p) = prev {
+                pen += format.font.pair_kerning(scale, p, c);
+            }
+            let advance = advance_width(format, scale, c);
+            if !c.is_whitespace() && pen + advance > max_width && !line.glyphs.is_empty() {
+                lines.push(line);
+                line = Line {
+                    glyphs: Vec::new(),
+                    width: 0.0,
+                };
+                pen = 0.0;
+            }
+            line.glyphs.push(LineGlyph { c, x: pen });
+            if !c.is_whitespace() {
+                line.width = pen + advance;
+            }
+            pen += advance;
+            prev = Some(c);
+        }
+    }
+
+    lines.push(line);
+}
+
+#[inline]
+fn advance_width(format: &TextFormat, scale: Scale, c: char) -> f32 {
+    format.font.glyph(c).scaled(scale).h_metrics().advance_width
+}
+
+/// Splits a string into words, each followed by the whitespace after it.
+fn split_words(s: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    let mut in_space = false;
+    for (i, c) in s.char_indices() {
+        if c.is_whitespace() {
+            in_space = true;
+        } else if in_space {
+            words.push(&s[start..i]);
+            start = i;
+            in_space = false;
+        }
+    }
+    if start < s.len() {
+        words.push(&s[start..]);
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    static FONT: &[u8] = include_bytes!("../fixtures/DejaVuSansMono.ttf");
+
+    fn format(size: f32) -> TextFormat {
+        TextFormat::from_font_bytes(FONT.to_vec(), size).unwrap()
+    }
+
+    // Every glyph of the monospace test font has the same advance.
+    fn advance(format: &TextFormat) -> f32 {
+        advance_width(format, Scale::uniform(format.size), 'a')
+    }
+
+    fn line_texts(text: &str, layout: &TextLayout) -> Vec<String> {
+        let chars: Vec<char> = text.chars().filter(|&c| c != '\n' && c != '\r').collect();
+        layout
+            .lines()
+            .iter()
+            .map(|l| {
+                chars[l.first_glyph..l.first_glyph + l.glyph_count]
+                    .iter()
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Encodes the alpha channel of `bitmap` as a binary PGM image.
+    fn alpha_pgm(bitmap: &Bitmap) -> Vec<u8> {
+        let mut pgm = format!("P5\n{} {}\n255\n", bitmap.width, bitmap.height).into_bytes();
+        pgm.extend(bitmap.data.chunks(4).map(|px| px[3]));
+        pgm
+    }
+
+    /// Compares `bitmap`'s coverage with a golden image in `fixtures`. Run the tests with
+    /// `UPDATE_GOLDEN=1` to rewrite the golden images after an intended rendering change.
+    fn check_golden(bitmap: &Bitmap, name: &str) {
+        let path = format!("{}/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+        let actual = alpha_pgm(bitmap);
+        if env::var_os("UPDATE_GOLDEN").is_some() {
+            fs::write(&path, &actual).unwrap();
+        }
+        let expected = fs::read(&path).unwrap();
+        assert!(actual == expected, "rendering differs from {}", name);
+    }
+
+    #[test]
+    fn rejects_invalid_font() {
+        assert!(TextFormat::from_font_bytes(vec![0; 64], 16.0).is_none());
+    }
+
+    #[test]
+    fn single_line_metrics() {
+        let format = format(20.0);
+        let v = format.font.v_metrics(Scale::uniform(20.0));
+        let layout = TextLayout::new("abc", &format);
+        assert_eq!(layout.glyphs().len(), 3);
+        assert_eq!(layout.lines().len(), 1);
+        let line = &layout.lines()[0];
+        assert_eq!(line.first_glyph, 0);
+        assert_eq!(line.x, 0.0);
+        assert_eq!(line.baseline, v.ascent);
+        assert!((line.width - 3.0 * advance(&format)).abs() < 1e-3);
+        assert_eq!(layout.width(), line.width);
+        assert_eq!(layout.height(), v.ascent - v.descent + v.line_gap);
+        for (i, g) in layout.glyphs().iter().enumerate() {
+            assert!((g.position().x - i as f32 * advance(&format)).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn explicit_line_breaks() {
+        let format = format(16.0);
+        let text = "ab\r\ncde\n\nf";
+        let layout = TextLayout::new(text, &format);
+        assert_eq!(line_texts(text, &layout), vec!["ab", "cde", "", "f"]);
+        let line_height = layout.height() / 4.0;
+        for (i, line) in layout.lines().iter().enumerate().skip(1) {
+            assert!((line.baseline - layout.lines()[i - 1].baseline - line_height).abs() < 1e-3);
+        }
+        assert_eq!(layout.lines()[2].width, 0.0);
+        assert!((layout.width() - 3.0 * advance(&format)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn wraps_at_word_boundaries() {
+        let mut format = format(16.0);
+        let advance = advance(&format);
+        format.wrap_width = Some(7.5 * advance);
+        let text = "aaa bbb ccc dd";
+        let layout = TextLayout::new(text, &format);
+        assert_eq!(line_texts(text, &layout), vec!["aaa bbb ", "ccc dd"]);
+        // Trailing whitespace doesn't count towards the line width.
+        assert!((layout.lines()[0].width - 7.0 * advance).abs() < 1e-3);
+        assert!((layout.lines()[1].width - 6.0 * advance).abs() < 1e-3);
+        assert_eq!(layout.width(), 7.5 * advance);
+    }
+
+    #[test]
+    fn breaks_long_words_between_characters() {
+        let mut format = format(16.0);
+        format.wrap_width = Some(3.5 * advance(&format));
+        let text = "abcdefgh ij";
+        let layout = TextLayout::new(text, &format);
+        assert_eq!(line_texts(text, &layout), vec!["abc", "def", "gh ", "ij"]);
+    }
+
+    #[test]
+    fn aligns_lines() {
+        let mut format = format(16.0);
+        let advance = advance(&format);
+        format.wrap_width = Some(10.0 * advance);
+        let text = "abcd\nab";
+
+        format.alignment = TextAlignment::Center;
+        let layout = TextLayout::new(text, &format);
+        assert!((layout.lines()[0].x - 3.0 * advance).abs() < 1e-3);
+        assert!((layout.lines()[1].x - 4.0 * advance).abs() < 1e-3);
+        let first = layout.glyphs()[layout.lines()[1].first_glyph].position().x;
+        assert!((first - 4.0 * advance).abs() < 1e-3);
+
+        format.alignment = TextAlignment::Right;
+        let layout = TextLayout::new(text, &format);
+        assert!((layout.lines()[0].x - 6.0 * advance).abs() < 1e-3);
+        assert!((layout.lines()[1].x - 8.0 * advance).abs() < 1e-3);
+
+        // Lines wider than the box stay at its left edge.
+        format.wrap_width = Some(0.5 * advance);
+        let layout = TextLayout::new("a", &format);
+        assert_eq!(layout.lines()[0].x, 0.0);
+    }
+
+    #[test]
+    fn rasterizes_premultiplied_color() {
+        let format = format(24.0);
+        let layout = TextLayout::new("Hg", &format);
+        let bitmap = layout.rasterize(Rgba::new(255, 0, 0, 128));
+        assert_eq!(bitmap.width, layout.width().ceil() as u32);
+        assert_eq!(bitmap.height, layout.height().ceil() as u32);
+        let mut covered = 0;
+        for px in bitmap.data.chunks(4) {
+            assert_eq!(&px[..2], &[0, 0]);
+            assert_eq!(px[2], px[3]);
+            assert!(px[3] <= 128);
+            if px[3] > 0 {
+                covered += 1;
+            }
+        }
+        assert!(covered > 0);
+    }
+
+    #[test]
+    fn golden_single_line() {
+        let layout = TextLayout::new("Hg, Wqy!", &format(16.0));
+        check_golden(
+            &layout.rasterize(Rgba::new(0, 0, 0, 255)),
+            "text_single_line.pgm",
+        );
+    }
+
+    #[test]
+    fn golden_wrapped_centered() {
+        let mut format = format(14.0);
+        format.wrap_width = Some(80.0);
+        format.alignment = TextAlignment::Center;
+        let layout = TextLayout::new("The quick brown fox jumps", &format);
+        check_golden(
+            &layout.rasterize(Rgba::new(0, 0, 0, 255)),
+            "text_wrapped_centered.pgm",
+        );
+    }
+}
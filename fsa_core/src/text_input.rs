@@ -0,0 +1,269 @@
+use std::char;
+
+/// `ATTR_TARGET_CONVERTED`: the clause being converted, after conversion.
+const ATTR_TARGET_CONVERTED: u8 = 0x01;
+/// `ATTR_TARGET_NOTCONVERTED`: the clause being converted, before conversion.
+const ATTR_TARGET_NOTCONVERTED: u8 = 0x03;
+
+/// Composition events from an input method editor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ime {
+    /// A composition has started.
+    Start,
+    /// The text being composed has changed. It isn't part of the input yet and should be drawn
+    /// in place, e.g. underlined.
+    ///
+    /// The range is in bytes of the text: the clause being converted, or an empty range at the
+    /// caret. It is `None` when the caret should be hidden.
+    Preedit(String, Option<(usize, usize)>),
+    /// The composition produced text to insert. An empty `Preedit` comes first to clear the text
+    /// that it replaces.
+    Commit(String),
+    /// The composition has ended, with or without committing text.
+    End,
+}
+
+/// Assembles the UTF-16 code units that `WM_CHAR` delivers one at a time into characters.
+///
+/// Characters outside of the Basic Multilingual Plane arrive as two messages, one per surrogate.
+/// Surrogates that aren't part of a valid pair decode to U+FFFD.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Utf16Assembler {
+    high_surrogate: Option<u16>,
+}
+
+/// The characters completed by one code unit. There are at most two: a replacement for a high
+/// surrogate that wasn't followed by a low one, and the character of the new unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AssembledChars {
+    chars: [Option<char>; 2],
+    next: usize,
+}
+
+impl Iterator for AssembledChars {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        while self.next < self.chars.len() {
+            self.next += 1;
+            if let Some(c) = self.chars[self.next - 1] {
+                return Some(c);
+            }
+        }
+        None
+    }
+}
+
+#[inline]
+fn is_high_surrogate(unit: u16) -> bool {
+    (0xD800..=0xDBFF).contains(&unit)
+}
+
+#[inline]
+fn is_low_surrogate(unit: u16) -> bool {
+    (0xDC00..=0xDFFF).contains(&unit)
+}
+
+impl Utf16Assembler {
+    #[inline]
+    pub fn new() -> Self {
+        Utf16Assembler::default()
+    }
+
+    /// Feeds one code unit, returning the characters it completes.
+    pub fn push(&mut self, unit: u16) -> AssembledChars {
+        let mut chars = [None, None];
+        match self.high_surrogate.take() {
+            Some(high) if is_low_surrogate(unit) => {
+                let c = 0x10000 + ((high as u32 - 0xD800) << 10) + (unit as u32 - 0xDC00);
+                chars[1] = char::from_u32(c);
+            }
+            pending => {
+                if pending.is_some() {
+                    chars[0] = Some(char::REPLACEMENT_CHARACTER);
+                }
+                if is_high_surrogate(unit) {
+                    self.high_surrogate = Some(unit);
+                } else if is_low_surrogate(unit) {
+                    chars[1] = Some(char::REPLACEMENT_CHARACTER);
+                } else {
+                    chars[1] = char::from_u32(unit as u32);
+                }
+            }
+        }
+        AssembledChars { chars, next: 0 }
+    }
+
+    /// Whether half of a surrogate pair is waiting for the other half.
+    #[inline]
+    pub fn is_pending(&self) -> bool {
+        self.high_surrogate.is_some()
+    }
+
+    /// Drops a pending high surrogate, e.g. when focus moves away in the middle of a pair.
+    #[inline]
+    pub fn reset(&mut self) {
+        self.high_surrogate = None;
+    }
+}
+
+/// Converts an offset in UTF-16 code units to a byte offset in `text`. Offsets that fall inside
+/// a surrogate pair round down to the start of the character; offsets past the end are `None`.
+pub fn utf16_to_byte_offset(text: &str, utf16_offset: usize) -> Option<usize> {
+    let mut units = 0;
+    for (byte, c) in text.char_indices() {
+        if units + c.len_utf16() > utf16_offset {
+            return Some(byte);
+        }
+        units += c.len_utf16();
+    }
+    if units == utf16_offset {
+        Some(text.len())
+    } else {
+        None
+    }
+}
+
+#[inline]
+fn is_target_clause(attribute: u8) -> bool {
+    attribute == ATTR_TARGET_CONVERTED || attribute == ATTR_TARGET_NOTCONVERTED
+}
+
+/// Works out the range to highlight in a composition string, in bytes.
+///
+/// `attributes` holds one `ATTR_*` value per UTF-16 unit (`GCS_COMPATTR`) and `cursor` is the
+/// caret in UTF-16 units (`GCS_CURSORPOS`). The clause being converted is highlighted if there is
+/// one, otherwise the range is empty at the caret.
+pub fn preedit_range(
+    text: &str,
+    attributes: &[u8],
+    cursor: Option<usize>,
+) -> Option<(usize, usize)> {
+    if let Some(start) = attributes.iter().position(|&attribute| is_target_clause(attribute)) {
+        let end = attributes[start..]
+            .iter()
+            .position(|&attribute| !is_target_clause(attribute))
+            .map_or(attributes.len(), |len| start + len);
+        if let (Some(start), Some(end)) = (
+            utf16_to_byte_offset(text, start),
+            utf16_to_byte_offset(text, end),
+        ) {
+            return Some((start, end));
+        }
+    }
+    cursor
+        .and_then(|cursor| utf16_to_byte_offset(text, cursor))
+        .map(|cursor| (cursor, cursor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `units` one at a time, as `WM_CHAR` would.
+    fn assemble(assembler: &mut Utf16Assembler, units: &[u16]) -> String {
+        units
+            .iter()
+            .flat_map(|&unit| assembler.push(unit))
+            .collect()
+    }
+
+    #[test]
+    fn assembles_basic_multilingual_plane() {
+        let mut assembler = Utf16Assembler::new();
+        let units: Vec<u16> = "a\u{e9}\u{4e2d}\u{fffd}".encode_utf16().collect();
+        assert_eq!(assemble(&mut assembler, &units), "a\u{e9}\u{4e2d}\u{fffd}");
+        assert!(!assembler.is_pending());
+    }
+
+    #[test]
+    fn assembles_surrogate_pairs() {
+        let mut assembler = Utf16Assembler::new();
+        assert_eq!(assembler.push(0xD83D).count(), 0);
+        assert!(assembler.is_pending());
+        assert_eq!(assembler.push(0xDE00).collect::<String>(), "\u{1f600}");
+        assert!(!assembler.is_pending());
+
+        let text = "x\u{10000}\u{10ffff}\u{1f600}y";
+        let units: Vec<u16> = text.encode_utf16().collect();
+        assert_eq!(assemble(&mut assembler, &units), text);
+    }
+
+    #[test]
+    fn replaces_lone_surrogates() {
+        let mut assembler = Utf16Assembler::new();
+        // A low surrogate on its own.
+        assert_eq!(assemble(&mut assembler, &[0xDC00, 0x61]), "\u{fffd}a");
+        // A high surrogate followed by something other than a low one.
+        assert_eq!(assemble(&mut assembler, &[0xD800, 0x62]), "\u{fffd}b");
+        // Two high surrogates: the first is replaced, the second pairs up.
+        assert_eq!(
+            assemble(&mut assembler, &[0xD83D, 0xD83D, 0xDE00]),
+            "\u{fffd}\u{1f600}"
+        );
+        // A high surrogate followed by a lone low one.
+        assert_eq!(
+            assemble(&mut assembler, &[0xD800, 0xDC00, 0xDC00]),
+            "\u{10000}\u{fffd}"
+        );
+        assert!(!assembler.is_pending());
+    }
+
+    #[test]
+    fn reset_drops_pending_surrogate() {
+        let mut assembler = Utf16Assembler::new();
+        assert_eq!(assembler.push(0xD83D).count(), 0);
+        assembler.reset();
+        assert!(!assembler.is_pending());
+        assert_eq!(assemble(&mut assembler, &[0xDE00]), "\u{fffd}");
+    }
+
+    #[test]
+    fn converts_utf16_offsets() {
+        // 'a' is 1 byte and 1 unit, 'é' 2 and 1, '中' 3 and 1, '😀' 4 and 2.
+        let text = "a\u{e9}\u{4e2d}\u{1f600}b";
+        let expected = [
+            Some(0),
+            Some(1),
+            Some(3),
+            Some(6),
+            Some(6),
+            Some(10),
+            Some(11),
+            None,
+        ];
+        for (offset, &byte) in expected.iter().enumerate() {
+            assert_eq!(
+                utf16_to_byte_offset(text, offset),
+                byte,
+                "offset {}",
+                offset
+            );
+        }
+        assert_eq!(utf16_to_byte_offset("", 0), Some(0));
+        assert_eq!(utf16_to_byte_offset("", 1), None);
+    }
+
+    #[test]
+    fn preedit_ranges() {
+        let text = "\u{3042}\u{1f600}\u{3044}";
+        // The target clause covers the surrogate pair.
+        assert_eq!(
+            preedit_range(
+                text,
+                &[0, ATTR_TARGET_CONVERTED, ATTR_TARGET_CONVERTED, 0],
+                None
+            ),
+            Some((3, 7))
+        );
+        // A target clause that runs to the end.
+        assert_eq!(
+            preedit_range(text, &[0, 0, 0, ATTR_TARGET_NOTCONVERTED], Some(0)),
+            Some((7, 10))
+        );
+        // Without a target clause, an empty range at the caret.
+        assert_eq!(preedit_range(text, &[0, 0, 0, 0], Some(3)), Some((7, 7)));
+        assert_eq!(preedit_range(text, &[], Some(9)), None);
+        assert_eq!(preedit_range(text, &[], None), None);
+    }
+}
@@ -0,0 +1,154 @@
+use std::fmt;
+#[cfg(not(windows))]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(windows)]
+#[link(name = "kernel32")]
+extern "system" {
+    fn GetCurrentThreadId() -> u32;
+}
+
+/// The id of the current thread, as returned by `GetCurrentThreadId`.
+#[cfg(windows)]
+pub fn current_thread_id() -> u32 {
+    unsafe { GetCurrentThreadId() }
+}
+
+/// The id of the current thread. Elsewhere than on Windows, threads are numbered as they first
+/// ask for their id.
+#[cfg(not(windows))]
+pub fn current_thread_id() -> u32 {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+    thread_local!(static ID: u32 = NEXT_ID.fetch_add(1, Ordering::Relaxed) as u32);
+    ID.with(|&id| id)
+}
+
+/// A thread-affine object was used from a thread other than its own.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct WrongThread {
+    /// What was used, e.g. `"compositor"`.
+    pub what: &'static str,
+    /// The id of the thread the object belongs to.
+    pub owner: u32,
+    /// The id of the thread that used it.
+    pub current: u32,
+}
+
+impl fmt::Display for WrongThread {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "the {} belongs to thread {} and can't be used from thread {}",
+            self.what, self.owner, self.current
+        )
+    }
+}
+
+/// A value that can only be used on the thread it belongs to, but can travel to other threads.
+///
+/// Composition objects are like this: any thread can hold and release them, but calling them from
+/// a thread other than the compositor's fails with `RPC_E_WRONG_THREAD`. Holding them as
+/// `ThreadBound` turns such a call into a `WrongThread` error naming both threads, before the call
+/// is made.
+#[derive(Clone)]
+pub struct ThreadBound<T> {
+    value: T,
+    owner: u32,
+    what: &'static str,
+}
+
+// The value is only reachable on its owner thread, which `get` checks for itself; elsewhere it can
+// only be cloned and dropped, which `new` requires to be safe.
+unsafe impl<T> Send for ThreadBound<T> {}
+
+impl<T> ThreadBound<T> {
+    /// Binds `value` to the current thread. `what` names it in errors.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be safe to clone and drop on any thread.
+    pub unsafe fn new(value: T, what: &'static str) -> Self {
+        ThreadBound {
+            value,
+            owner: current_thread_id(),
+            what,
+        }
+    }
+
+    /// The id of the thread the value belongs to.
+    #[inline]
+    pub fn owner(&self) -> u32 {
+        self.owner
+    }
+
+    /// Fails unless the current thread is the owner thread.
+    pub fn check(&self) -> Result<(), WrongThread> {
+        check_for(self.what, self.owner, current_thread_id())
+    }
+
+    /// The value, if the current thread is the owner thread.
+    pub fn get(&self) -> Result<&T, WrongThread> {
+        self.check()?;
+        Ok(&self.value)
+    }
+}
+
+/// Fails unless `current` is `owner`.
+fn check_for(what: &'static str, owner: u32, current: u32) -> Result<(), WrongThread> {
+    if current == owner {
+        Ok(())
+    } else {
+        Err(WrongThread {
+            what,
+            owner,
+            current,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn checks_threads() {
+        assert_eq!(check_for("compositor", 7, 7), Ok(()));
+        let error = check_for("compositor", 7, 8).unwrap_err();
+        assert_eq!(
+            error,
+            WrongThread {
+                what: "compositor",
+                owner: 7,
+                current: 8,
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "the compositor belongs to thread 7 and can't be used from thread 8"
+        );
+    }
+
+    #[test]
+    fn bound_value_is_only_reachable_on_its_thread() {
+        let bound = unsafe { ThreadBound::new(5, "compositor") };
+        assert_eq!(bound.owner(), current_thread_id());
+        assert_eq!(bound.get(), Ok(&5));
+
+        let owner = bound.owner();
+        let moved = bound.clone();
+        let (current, result) = thread::spawn(move || (current_thread_id(), moved.get().cloned()))
+            .join()
+            .unwrap();
+        assert_ne!(current, owner);
+        assert_eq!(
+            result,
+            Err(WrongThread {
+                what: "compositor",
+                owner,
+                current,
+            })
+        );
+        assert_eq!(bound.check(), Ok(()));
+    }
+}
@@ -0,0 +1,938 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap};
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::rc::{Rc, Weak};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+/// Repeating timers never fire more often than this, so that a zero interval can't keep `fire`
+/// from returning.
+pub const MIN_INTERVAL: Duration = Duration::from_millis(1);
+
+/// The frame period used when the refresh rate of the display isn't known.
+pub const DEFAULT_FRAME_PERIOD: Duration = Duration::from_micros(16_667);
+
+/// Tells time and wakes `Timers` up when the next timer is due.
+pub trait TimerDriver {
+    /// The current time, from an arbitrary origin.
+    fn now(&self) -> Duration;
+    /// Asks for `Timers::fire` to be called at `deadline` or soon after, replacing the previous
+    /// request. `None` cancels it.
+    fn arm(&self, deadline: Option<Duration>);
+}
+
+/// A `TimerDriver` whose time only moves when told to. Call `Timers::fire` after moving it.
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: Cell<Duration>,
+    armed: Cell<Option<Duration>>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.set(self.now.get() + by);
+    }
+
+    /// The deadline of the last `arm` request.
+    pub fn armed(&self) -> Option<Duration> {
+        self.armed.get()
+    }
+}
+
+impl TimerDriver for ManualClock {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+
+    fn arm(&self, deadline: Option<Duration>) {
+        self.armed.set(deadline);
+    }
+}
+
+/// Identifies a timer of a `TimerQueue`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TimerId(pub u64);
+
+#[derive(Debug, Copy, Clone)]
+struct TimerEntry {
+    deadline: Duration,
+    interval: Option<Duration>,
+}
+
+/// Orders timers by deadline, and timers with the same deadline in the order they were
+/// scheduled.
+#[derive(Debug, Default)]
+pub struct TimerQueue {
+    order: BTreeSet<(Duration, TimerId)>,
+    timers: HashMap<TimerId, TimerEntry>,
+    next_id: u64,
+}
+
+impl TimerQueue {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Schedules a timer that is due at `deadline`, and then every `interval` if there is one.
+    pub fn schedule(&mut self, deadline: Duration, interval: Option<Duration>) -> TimerId {
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+        let interval = interval.map(|interval| interval.max(MIN_INTERVAL));
+        self.order.insert((deadline, id));
+        self.timers.insert(id, TimerEntry { deadline, interval });
+        id
+    }
+
+    /// Removes a timer. Returns `false` if it wasn't scheduled.
+    pub fn cancel(&mut self, id: TimerId) -> bool {
+        match self.timers.remove(&id) {
+            Some(entry) => {
+                self.order.remove(&(entry.deadline, id));
+                true
+            }
+            None => false,
+        }
+    }
+
+    #[inline]
+    pub fn is_scheduled(&self, id: TimerId) -> bool {
+        self.timers.contains_key(&id)
+    }
+
+    /// When the timer is due next.
+    pub fn deadline(&self, id: TimerId) -> Option<Duration> {
+        self.timers.get(&id).map(|entry| entry.deadline)
+    }
+
+    pub fn next_deadline(&self) -> Option<Duration> {
+        self.order.iter().next().map(|&(deadline, _)| deadline)
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    /// Takes the earliest timer that is due at `now`.
+    ///
+    /// A one-shot timer is removed. A repeating timer is scheduled again one interval after its
+    /// deadline, so that it doesn't drift; if it fell behind by more than an interval, the ticks
+    /// it missed are skipped rather than fired in a burst.
+    pub fn pop_due(&mut self, now: Duration) -> Option<TimerId> {
+        let (deadline, id) = match self.order.iter().next() {
+            Some(&(deadline, id)) if deadline <= now => (deadline, id),
+            _ => return None,
+        };
+        self.order.remove(&(deadline, id));
+        let interval = self.timers[&id].interval;
+        match interval {
+            Some(interval) => {
+                let mut next = deadline + interval;
+                if next <= now {
+                    let behind = duration_to_nanos(now - deadline);
+                    let ticks = behind / duration_to_nanos(interval) + 1;
+                    next = deadline + nanos_to_duration(duration_to_nanos(interval) * ticks);
+                }
+                self.order.insert((next, id));
+                self.timers.get_mut(&id).unwrap().deadline = next;
+            }
+            None => {
+                self.timers.remove(&id);
+            }
+        }
+        Some(id)
+    }
+}
+
+fn duration_to_nanos(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000_000 + duration.subsec_nanos() as u64
+}
+
+fn nanos_to_duration(nanos: u64) -> Duration {
+    Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+}
+
+/// Runs closures at points in time, as told by a `TimerDriver`.
+///
+/// Due timers run in the order of their deadlines, and timers with the same deadline in the order
+/// they were scheduled. Callbacks may schedule and cancel timers, including their own.
+pub struct Timers<D: TimerDriver> {
+    driver: D,
+    queue: RefCell<TimerQueue>,
+    callbacks: RefCell<HashMap<TimerId, Box<dyn FnMut()>>>,
+    armed: Cell<Option<Duration>>,
+}
+
+impl<D: TimerDriver> Timers<D> {
+    pub fn new(driver: D) -> Self {
+        Timers {
+            driver,
+            queue: RefCell::new(TimerQueue::new()),
+            callbacks: RefCell::new(HashMap::new()),
+            armed: Cell::new(None),
+        }
+    }
+
+    #[inline]
+    pub fn driver(&self) -> &D {
+        &self.driver
+    }
+
+    #[inline]
+    pub fn now(&self) -> Duration {
+        self.driver.now()
+    }
+
+    /// Asks the driver for a wakeup at the next deadline, unless it was already asked.
+    fn rearm(&self) {
+        let next = self.queue.borrow().next_deadline();
+        if next != self.armed.get() {
+            self.armed.set(next);
+            self.driver.arm(next);
+        }
+    }
+
+    fn schedule(
+        &self,
+        deadline: Duration,
+        interval: Option<Duration>,
+        callback: Box<dyn FnMut()>,
+    ) -> TimerId {
+        let id = self.queue.borrow_mut().schedule(deadline, interval);
+        self.callbacks.borrow_mut().insert(id, callback);
+        self.rearm();
+        id
+    }
+
+    /// Runs `f` once, `delay` from now.
+    pub fn set_timeout<F>(&self, delay: Duration, f: F) -> TimerId
+    where
+        F: FnOnce() + 'static,
+    {
+        self.set_deadline(self.now() + delay, f)
+    }
+
+    /// Runs `f` once at `deadline`.
+    pub fn set_deadline<F>(&self, deadline: Duration, f: F) -> TimerId
+    where
+        F: FnOnce() + 'static,
+    {
+        let mut f = Some(f);
+        self.schedule(
+            deadline,
+            None,
+            Box::new(move || {
+                if let Some(f) = f.take() {
+                    f();
+                }
+            }),
+        )
+    }
+
+    /// Runs `f` every `interval`, starting one interval from now.
+    pub fn set_interval<F>(&self, interval: Duration, f: F) -> TimerId
+    where
+        F: FnMut() + 'static,
+    {
+        self.schedule(self.now() + interval, Some(interval), Box::new(f))
+    }
+
+    /// Stops a timer. Returns `false` if it had already fired or been cancelled.
+    pub fn cancel(&self, id: TimerId) -> bool {
+        let scheduled = self.queue.borrow_mut().cancel(id);
+        // A one-shot timer that is due but hasn't run yet is no longer in the queue.
+        let pending = self.callbacks.borrow_mut().remove(&id).is_some();
+        self.rearm();
+        scheduled || pending
+    }
+
+    #[inline]
+    pub fn is_scheduled(&self, id: TimerId) -> bool {
+        self.queue.borrow().is_scheduled(id)
+    }
+
+    /// The number of timers that are scheduled.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.queue.borrow().len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Runs the timers that are due. Timers scheduled by the callbacks run at the next `fire`
+    /// at the earliest, even if they are already due.
+    pub fn fire(&self) {
+        let now = self.now();
+        let due = {
+            let mut queue = self.queue.borrow_mut();
+            let mut due = Vec::new();
+            while let Some(id) = queue.pop_due(now) {
+                due.push(id);
+            }
+            due
+        };
+        for id in due {
+            // The callback leaves the map while it runs, so that it can use the timers.
+            let callback = self.callbacks.borrow_mut().remove(&id);
+            if let Some(mut callback) = callback {
+                callback();
+                if self.queue.borrow().is_scheduled(id) {
+                    self.callbacks.borrow_mut().insert(id, callback);
+                }
+            }
+        }
+        // The driver has fired, so whatever it was armed for is over, even if the next deadline
+        // is the same.
+        let next = self.queue.borrow().next_deadline();
+        self.armed.set(next);
+        self.driver.arm(next);
+    }
+}
+
+/// Wakes a task at a deadline, through a one-shot timer.
+struct Alarm<D: TimerDriver + 'static> {
+    timers: Rc<Timers<D>>,
+    waker: Rc<RefCell<Option<Waker>>>,
+    timer: Option<(TimerId, Duration)>,
+}
+
+impl<D: TimerDriver + 'static> Alarm<D> {
+    fn new(timers: Rc<Timers<D>>) -> Self {
+        Alarm {
+            timers,
+            waker: Rc::new(RefCell::new(None)),
+            timer: None,
+        }
+    }
+
+    fn cancel(&mut self) {
+        if let Some((id, _)) = self.timer.take() {
+            self.timers.cancel(id);
+        }
+    }
+
+    fn poll_until(&mut self, deadline: Duration, cx: &mut Context) -> Poll<()> {
+        if self.timers.now() >= deadline {
+            self.cancel();
+            return Poll::Ready(());
+        }
+        *self.waker.borrow_mut() = Some(cx.waker().clone());
+        let scheduled = match self.timer {
+            Some((id, at)) => at == deadline && self.timers.is_scheduled(id),
+            None => false,
+        };
+        if !scheduled {
+            self.cancel();
+            let waker = self.waker.clone();
+            let id = self.timers.set_deadline(deadline, move || {
+                if let Some(waker) = waker.borrow_mut().take() {
+                    waker.wake();
+                }
+            });
+            self.timer = Some((id, deadline));
+        }
+        Poll::Pending
+    }
+}
+
+impl<D: TimerDriver + 'static> Drop for Alarm<D> {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// A future that completes at a deadline.
+pub struct Sleep<D: TimerDriver + 'static> {
+    deadline: Duration,
+    alarm: Alarm<D>,
+}
+
+impl<D: TimerDriver + 'static> Sleep<D> {
+    /// Completes `delay` from now.
+    pub fn new(timers: Rc<Timers<D>>, delay: Duration) -> Self {
+        let deadline = timers.now() + delay;
+        Sleep {
+            deadline,
+            alarm: Alarm::new(timers),
+        }
+    }
+
+    #[inline]
+    pub fn deadline(&self) -> Duration {
+        self.deadline
+    }
+}
+
+impl<D: TimerDriver + 'static> Future for Sleep<D> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+        this.alarm.poll_until(this.deadline, cx)
+    }
+}
+
+/// Ticks at a fixed period. Ticks that are missed because nobody waited for them are skipped,
+/// as with repeating timers.
+pub struct Interval<D: TimerDriver + 'static> {
+    period: Duration,
+    next: Duration,
+    alarm: Alarm<D>,
+}
+
+impl<D: TimerDriver + 'static> Interval<D> {
+    /// Ticks first one period from now.
+    pub fn new(timers: Rc<Timers<D>>, period: Duration) -> Self {
+        let period = period.max(MIN_INTERVAL);
+        let next = timers.now() + period;
+        Interval {
+            period,
+            next,
+            alarm: Alarm::new(timers),
+        }
+    }
+
+    #[inline]
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    pub fn poll_tick(&mut self, cx: &mut Context) -> Poll<()> {
+        match self.alarm.poll_until(self.next, cx) {
+            Poll::Ready(()) => {
+                let now = self.alarm.timers.now();
+                let period = duration_to_nanos(self.period);
+                let behind = duration_to_nanos(now - self.next);
+                self.next += nanos_to_duration(period * (behind / period + 1));
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// Waits for the next tick.
+    pub fn tick(&mut self) -> Tick<'_, D> {
+        Tick { interval: self }
+    }
+}
+
+/// The future returned by `Interval::tick`.
+pub struct Tick<'a, D: TimerDriver + 'static> {
+    interval: &'a mut Interval<D>,
+}
+
+impl<'a, D: TimerDriver + 'static> Future for Tick<'a, D> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        self.get_mut().interval.poll_tick(cx)
+    }
+}
+
+/// The time of a frame, passed to frame callbacks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FrameTime {
+    /// When the frame started, on the clock of the `TimerDriver`.
+    pub time: Duration,
+    /// The time since the previous frame, or the frame period for the first frame after the
+    /// callbacks were idle.
+    pub delta: Duration,
+}
+
+/// Identifies a callback of `FrameCallbacks`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FrameCallbackId(pub u64);
+
+type FrameCallback = Box<dyn FnMut(FrameTime) -> bool>;
+
+struct FrameState {
+    callbacks: Vec<(FrameCallbackId, FrameCallback)>,
+    /// While the callbacks run, they are out of the list: these are their ids, and the ids of
+    /// the ones that were removed meanwhile.
+    running: Option<Vec<FrameCallbackId>>,
+    removed: Vec<FrameCallbackId>,
+    next_id: u64,
+    last: Option<Duration>,
+    timer: Option<TimerId>,
+}
+
+/// Runs callbacks once per frame, in the order they were added, for as long as they return
+/// `true`.
+///
+/// The frames are driven by a repeating timer at the frame period, which only runs while there
+/// are callbacks, so an idle thread isn't woken up every frame. The timer isn't synchronized with
+/// the display: frames are as regular as the `TimerDriver` makes them.
+pub struct FrameCallbacks<D: TimerDriver + 'static> {
+    timers: Rc<Timers<D>>,
+    period: Duration,
+    state: Rc<RefCell<FrameState>>,
+}
+
+impl<D: TimerDriver + 'static> FrameCallbacks<D> {
+    pub fn new(timers: Rc<Timers<D>>, period: Duration) -> Self {
+        FrameCallbacks {
+            timers,
+            period: period.max(MIN_INTERVAL),
+            state: Rc::new(RefCell::new(FrameState {
+                callbacks: Vec::new(),
+                running: None,
+                removed: Vec::new(),
+                next_id: 0,
+                last: None,
+                timer: None,
+            })),
+        }
+    }
+
+    #[inline]
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Runs `f` at every frame, starting with the next one, until it returns `false`.
+    pub fn add<F>(&self, f: F) -> FrameCallbackId
+    where
+        F: FnMut(FrameTime) -> bool + 'static,
+    {
+        let id = {
+            let mut state = self.state.borrow_mut();
+            let id = FrameCallbackId(state.next_id);
+            state.next_id += 1;
+            state.callbacks.push((id, Box::new(f)));
+            id
+        };
+        self.start();
+        id
+    }
+
+    /// Stops running a callback. Returns `false` if it wasn't running.
+    pub fn remove(&self, id: FrameCallbackId) -> bool {
+        let mut state = self.state.borrow_mut();
+        let removed = match state.callbacks.iter().position(|&(other, _)| other == id) {
+            Some(index) => {
+                let _ = state.callbacks.remove(index);
+                true
+            }
+            None => {
+                let running = match state.running {
+                    Some(ref running) => running.contains(&id),
+                    None => false,
+                };
+                // Running callbacks are filtered once they are done.
+                if running && !state.removed.contains(&id) {
+                    state.removed.push(id);
+                    true
+                } else {
+                    false
+                }
+            }
+        };
+        if state.callbacks.is_empty() && state.running.is_none() {
+            if let Some(timer) = state.timer.take() {
+                self.timers.cancel(timer);
+            }
+            state.last = None;
+        }
+        removed
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.state.borrow().callbacks.is_empty()
+    }
+
+    fn start(&self) {
+        if self.state.borrow().timer.is_some() {
+            return;
+        }
+        let timers = Rc::downgrade(&self.timers);
+        let state = Rc::downgrade(&self.state);
+        let period = self.period;
+        let timer = self.timers.set_interval(period, move || {
+            run_frame(&timers, &state, period);
+        });
+        self.state.borrow_mut().timer = Some(timer);
+    }
+}
+
+fn run_frame<D: TimerDriver + 'static>(
+    timers: &Weak<Timers<D>>,
+    state: &Weak<RefCell<FrameState>>,
+    period: Duration,
+) {
+    let (timers, state) = match (timers.upgrade(), state.upgrade()) {
+        (Some(timers), Some(state)) => (timers, state),
+        _ => return,
+    };
+    let now = timers.now();
+    let (mut callbacks, frame) = {
+        let mut state = state.borrow_mut();
+        let delta = state.last.map(|last| now - last).unwrap_or(period);
+        state.last = Some(now);
+        let callbacks = mem::replace(&mut state.callbacks, Vec::new());
+        state.running = Some(callbacks.iter().map(|&(id, _)| id).collect());
+        (callbacks, FrameTime { time: now, delta })
+    };
+    // The callbacks run out of the state, so that they can add and remove callbacks.
+    let mut index = 0;
+    while index < callbacks.len() {
+        let removed = state.borrow().removed.contains(&callbacks[index].0);
+        if removed || !(callbacks[index].1)(frame) {
+            let (id, _) = callbacks.remove(index);
+            if let Some(ref mut running) = state.borrow_mut().running {
+                running.retain(|&other| other != id);
+            }
+        } else {
+            index += 1;
+        }
+    }
+    let mut state = state.borrow_mut();
+    let removed = mem::replace(&mut state.removed, Vec::new());
+    callbacks.retain(|&(id, _)| !removed.contains(&id));
+    // Callbacks added meanwhile run from the next frame on, after the older ones.
+    let added = mem::replace(&mut state.callbacks, callbacks);
+    state.callbacks.extend(added);
+    state.running = None;
+    if state.callbacks.is_empty() {
+        if let Some(timer) = state.timer.take() {
+            timers.cancel(timer);
+        }
+        state.last = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use executor::{LocalExecutor, ManualQueue, Priority};
+    use std::sync::Arc;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    type Log = Rc<RefCell<Vec<&'static str>>>;
+
+    fn timers() -> (Rc<Timers<ManualClock>>, Log) {
+        (
+            Rc::new(Timers::new(ManualClock::new())),
+            Rc::new(RefCell::new(Vec::new())),
+        )
+    }
+
+    /// A callback that records `name`.
+    fn record(log: &Log, name: &'static str) -> impl FnMut() + 'static {
+        let log = log.clone();
+        move || log.borrow_mut().push(name)
+    }
+
+    fn advance(timers: &Timers<ManualClock>, by: u64) {
+        timers.driver().advance(ms(by));
+        timers.fire();
+    }
+
+    #[test]
+    fn queue_orders_by_deadline_then_schedule() {
+        let mut queue = TimerQueue::new();
+        let late = queue.schedule(ms(30), None);
+        let first = queue.schedule(ms(10), None);
+        let second = queue.schedule(ms(10), None);
+        assert_eq!(queue.next_deadline(), Some(ms(10)));
+        assert_eq!(queue.pop_due(ms(9)), None);
+        assert_eq!(queue.pop_due(ms(40)), Some(first));
+        assert_eq!(queue.pop_due(ms(40)), Some(second));
+        assert_eq!(queue.pop_due(ms(40)), Some(late));
+        assert_eq!(queue.pop_due(ms(40)), None);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn queue_repeats_without_drift_and_skips_missed_ticks() {
+        let mut queue = TimerQueue::new();
+        let id = queue.schedule(ms(10), Some(ms(10)));
+        // Fired late, the next tick is still one interval after the deadline.
+        assert_eq!(queue.pop_due(ms(13)), Some(id));
+        assert_eq!(queue.deadline(id), Some(ms(20)));
+        // Three ticks behind: one fires, the missed ones are skipped.
+        assert_eq!(queue.pop_due(ms(47)), Some(id));
+        assert_eq!(queue.pop_due(ms(47)), None);
+        assert_eq!(queue.deadline(id), Some(ms(50)));
+        // Exactly on a later tick, that tick is skipped too.
+        assert_eq!(queue.pop_due(ms(60)), Some(id));
+        assert_eq!(queue.deadline(id), Some(ms(70)));
+
+        let zero = queue.schedule(ms(0), Some(ms(0)));
+        assert_eq!(queue.pop_due(ms(0)), Some(zero));
+        assert_eq!(queue.deadline(zero), Some(MIN_INTERVAL));
+        assert!(queue.cancel(zero));
+        assert!(!queue.cancel(zero));
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn fires_in_order_and_arms_the_driver() {
+        let (timers, log) = timers();
+        timers.set_timeout(ms(30), record(&log, "30"));
+        timers.set_timeout(ms(10), record(&log, "10 a"));
+        timers.set_deadline(ms(10), record(&log, "10 b"));
+        let cancelled = timers.set_timeout(ms(20), record(&log, "cancelled"));
+        assert_eq!(timers.driver().armed(), Some(ms(10)));
+        assert_eq!(timers.len(), 4);
+
+        assert!(timers.cancel(cancelled));
+        assert!(!timers.cancel(cancelled));
+        advance(&timers, 5);
+        assert!(log.borrow().is_empty());
+        advance(&timers, 5);
+        assert_eq!(*log.borrow(), vec!["10 a", "10 b"]);
+        assert_eq!(timers.driver().armed(), Some(ms(30)));
+        // Firing late runs everything that is due.
+        advance(&timers, 100);
+        assert_eq!(*log.borrow(), vec!["10 a", "10 b", "30"]);
+        assert_eq!(timers.driver().armed(), None);
+        assert!(timers.is_empty());
+    }
+
+    #[test]
+    fn intervals_repeat_and_skip_missed_ticks() {
+        let (timers, log) = timers();
+        let id = timers.set_interval(ms(10), record(&log, "tick"));
+        for _ in 0..3 {
+            advance(&timers, 10);
+        }
+        assert_eq!(log.borrow().len(), 3);
+        // 35 ms late: one tick, then back on the 10 ms grid.
+        advance(&timers, 35);
+        assert_eq!(log.borrow().len(), 4);
+        assert_eq!(timers.driver().armed(), Some(ms(70)));
+        assert!(timers.is_scheduled(id));
+        assert!(timers.cancel(id));
+        assert!(!timers.is_scheduled(id));
+        advance(&timers, 100);
+        assert_eq!(log.borrow().len(), 4);
+    }
+
+    #[test]
+    fn callbacks_can_use_the_timers() {
+        let (timers, log) = timers();
+        let inner = timers.clone();
+        let inner_log = log.clone();
+        // Schedules a timer that is already due: it waits for the next `fire`.
+        timers.set_timeout(ms(0), move || {
+            inner.set_timeout(ms(0), record(&inner_log, "scheduled"));
+            inner_log.borrow_mut().push("first");
+        });
+        timers.fire();
+        assert_eq!(*log.borrow(), vec!["first"]);
+        timers.fire();
+        assert_eq!(*log.borrow(), vec!["first", "scheduled"]);
+
+        // An interval that cancels itself.
+        let inner = timers.clone();
+        let inner_log = log.clone();
+        let own = Rc::new(Cell::new(None));
+        let own_id = own.clone();
+        let id = timers.set_interval(ms(10), move || {
+            inner_log.borrow_mut().push("once");
+            assert!(inner.cancel(own_id.get().unwrap()));
+        });
+        own.set(Some(id));
+        advance(&timers, 10);
+        advance(&timers, 10);
+        assert_eq!(log.borrow().last(), Some(&"once"));
+        assert_eq!(log.borrow().len(), 3);
+        assert!(timers.is_empty());
+
+        // A timer that is due in the same `fire` can still be cancelled by an earlier one.
+        let inner = timers.clone();
+        let victim = Rc::new(Cell::new(None));
+        let target = victim.clone();
+        timers.set_timeout(ms(5), move || assert!(inner.cancel(target.get().unwrap())));
+        victim.set(Some(timers.set_timeout(ms(5), record(&log, "victim"))));
+        advance(&timers, 5);
+        assert!(!log.borrow().contains(&"victim"));
+    }
+
+    #[test]
+    fn sleep_wakes_its_task() {
+        let (timers, _) = timers();
+        let queue = Arc::new(ManualQueue::new());
+        let executor = LocalExecutor::new(queue.clone());
+        let sleep = Sleep::new(timers.clone(), ms(50));
+        assert_eq!(sleep.deadline(), ms(50));
+        let task = executor.spawn_local(Priority::Normal, sleep);
+        queue.run_until_idle(&executor);
+        assert_eq!(task.try_take(), None);
+        assert_eq!(timers.driver().armed(), Some(ms(50)));
+
+        advance(&timers, 49);
+        assert!(queue.is_empty());
+        advance(&timers, 1);
+        assert_eq!(queue.len(), 1);
+        queue.run_until_idle(&executor);
+        assert_eq!(task.try_take(), Some(()));
+        assert!(timers.is_empty());
+
+        // Dropping a sleep cancels its timer.
+        let sleep = Sleep::new(timers.clone(), ms(10));
+        let task = executor.spawn_local(Priority::Normal, sleep);
+        queue.run_until_idle(&executor);
+        assert_eq!(timers.len(), 1);
+        drop(task);
+        drop(executor);
+        assert!(timers.is_empty());
+    }
+
+    /// Counts the ticks of an interval for as long as it runs.
+    struct Ticker(Interval<ManualClock>, Rc<Cell<u32>>);
+
+    impl Future for Ticker {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+            while let Poll::Ready(()) = self.0.poll_tick(cx) {
+                self.1.set(self.1.get() + 1);
+            }
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn interval_skips_missed_ticks() {
+        let (timers, _) = timers();
+        let queue = Arc::new(ManualQueue::new());
+        let executor = LocalExecutor::new(queue.clone());
+        let ticks = Rc::new(Cell::new(0));
+        let interval = Interval::new(timers.clone(), ms(10));
+        assert_eq!(interval.period(), ms(10));
+        let _task = executor.spawn_local(Priority::Normal, Ticker(interval, ticks.clone()));
+        queue.run_until_idle(&executor);
+
+        for _ in 0..3 {
+            advance(&timers, 10);
+            queue.run_until_idle(&executor);
+        }
+        assert_eq!(ticks.get(), 3);
+        advance(&timers, 35);
+        queue.run_until_idle(&executor);
+        assert_eq!(ticks.get(), 4);
+        assert_eq!(timers.driver().armed(), Some(ms(70)));
+    }
+
+    fn frames() -> (Rc<Timers<ManualClock>>, FrameCallbacks<ManualClock>) {
+        let (timers, _) = timers();
+        let frames = FrameCallbacks::new(timers.clone(), ms(16));
+        (timers, frames)
+    }
+
+    #[test]
+    fn frame_callbacks_run_until_they_return_false() {
+        let (timers, frames) = frames();
+        assert!(timers.is_empty());
+        let times = Rc::new(RefCell::new(Vec::new()));
+        let log = times.clone();
+        let mut count = 0;
+        let id = frames.add(move |frame| {
+            log.borrow_mut().push(frame);
+            count += 1;
+            count < 3
+        });
+        // The frame timer only runs while there are callbacks.
+        assert_eq!(timers.len(), 1);
+
+        // Deltas are measured between the times the frames actually ran.
+        advance(&timers, 16);
+        advance(&timers, 20);
+        advance(&timers, 12);
+        advance(&timers, 16);
+        assert_eq!(
+            *times.borrow(),
+            vec![
+                FrameTime {
+                    time: ms(16),
+                    delta: ms(16)
+                },
+                FrameTime {
+                    time: ms(36),
+                    delta: ms(20)
+                },
+                FrameTime {
+                    time: ms(48),
+                    delta: ms(12)
+                },
+            ]
+        );
+        assert!(frames.is_empty());
+        assert!(timers.is_empty());
+        assert!(!frames.remove(id));
+
+        // After being idle, the first delta is the period again.
+        let log = times.clone();
+        frames.add(move |frame| {
+            log.borrow_mut().push(frame);
+            false
+        });
+        advance(&timers, 100);
+        assert_eq!(times.borrow().last().unwrap().delta, ms(16));
+    }
+
+    #[test]
+    fn frame_callbacks_can_add_and_remove_callbacks() {
+        let (timers, frames) = frames();
+        let frames = Rc::new(frames);
+        let log: Log = Rc::new(RefCell::new(Vec::new()));
+
+        let second = Rc::new(Cell::new(None));
+        let (inner, target, inner_log) = (frames.clone(), second.clone(), log.clone());
+        let mut added = false;
+        let first = frames.add(move |_| {
+            inner_log.borrow_mut().push("first");
+            if !added {
+                added = true;
+                let log = inner_log.clone();
+                inner.add(move |_| {
+                    log.borrow_mut().push("added");
+                    true
+                });
+            } else {
+                // Removes a callback that runs later in the same frame.
+                inner.remove(target.get().unwrap());
+            }
+            true
+        });
+        let inner_log = log.clone();
+        second.set(Some(frames.add(move |_| {
+            inner_log.borrow_mut().push("second");
+            true
+        })));
+
+        advance(&timers, 16);
+        // The callback added during the frame runs from the next one on.
+        assert_eq!(*log.borrow(), vec!["first", "second"]);
+        log.borrow_mut().clear();
+        advance(&timers, 16);
+        assert_eq!(*log.borrow(), vec!["first", "added"]);
+
+        assert!(frames.remove(first));
+        assert!(!frames.remove(first));
+        assert!(!frames.remove(second.get().unwrap()));
+        assert!(!frames.is_empty());
+        log.borrow_mut().clear();
+        advance(&timers, 16);
+        assert_eq!(*log.borrow(), vec!["added"]);
+    }
+}
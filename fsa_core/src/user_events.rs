@@ -0,0 +1,407 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Wakes the thread of an event loop up so that it drains its user events.
+pub trait LoopSignal: Send + Sync {
+    /// Returns `false` if the wakeup couldn't be posted.
+    fn signal(&self) -> bool;
+}
+
+/// The event loop has exited. Contains the event that couldn't be sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventLoopClosed<T>(pub T);
+
+impl<T> fmt::Display for EventLoopClosed<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the event loop has exited")
+    }
+}
+
+struct Channel<T> {
+    events: VecDeque<T>,
+    /// Set while a wakeup is posted and hasn't been handled yet, so that a burst of events posts
+    /// a single wakeup.
+    signaled: bool,
+    closed: bool,
+}
+
+/// Sends user events to an event loop from any thread.
+pub struct EventLoopProxy<T> {
+    channel: Arc<Mutex<Channel<T>>>,
+    signal: Arc<dyn LoopSignal>,
+}
+
+impl<T> Clone for EventLoopProxy<T> {
+    fn clone(&self) -> Self {
+        EventLoopProxy {
+            channel: self.channel.clone(),
+            signal: self.signal.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for EventLoopProxy<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("EventLoopProxy { .. }")
+    }
+}
+
+impl<T> EventLoopProxy<T> {
+    /// Queues `event` for the loop's handler, which receives the events of all proxies in the
+    /// order they were sent. Fails once the loop has exited.
+    pub fn send_event(&self, event: T) -> Result<(), EventLoopClosed<T>> {
+        let signal = {
+            let mut channel = self.channel.lock().unwrap();
+            if channel.closed {
+                return Err(EventLoopClosed(event));
+            }
+            channel.events.push_back(event);
+            let signal = !channel.signaled;
+            channel.signaled = true;
+            signal
+        };
+        if signal && !self.signal.signal() {
+            // The loop is going away. The event stays queued for `close`, and the next event
+            // tries again.
+            self.channel.lock().unwrap().signaled = false;
+        }
+        Ok(())
+    }
+}
+
+/// The loop's end of the proxies.
+pub struct UserEventReceiver<T> {
+    channel: Arc<Mutex<Channel<T>>>,
+}
+
+/// Creates the receiving end of an event loop's user events, and a proxy that sends to it and
+/// wakes the loop with `signal`.
+pub fn user_event_channel<T>(
+    signal: Arc<dyn LoopSignal>,
+) -> (EventLoopProxy<T>, UserEventReceiver<T>) {
+    let channel = Arc::new(Mutex::new(Channel {
+        events: VecDeque::new(),
+        signaled: false,
+        closed: false,
+    }));
+    (
+        EventLoopProxy {
+            channel: channel.clone(),
+            signal,
+        },
+        UserEventReceiver { channel },
+    )
+}
+
+impl<T> UserEventReceiver<T> {
+    /// Takes the events that arrived, in order. Call it when the loop is signaled; events sent
+    /// from now on signal again.
+    pub fn drain(&self) -> Vec<T> {
+        let mut channel = self.channel.lock().unwrap();
+        channel.signaled = false;
+        channel.events.drain(..).collect()
+    }
+
+    /// Makes proxies fail from now on, and returns the events that weren't drained yet.
+    pub fn close(&self) -> Vec<T> {
+        let mut channel = self.channel.lock().unwrap();
+        channel.closed = true;
+        channel.events.drain(..).collect()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.channel.lock().unwrap().closed
+    }
+}
+
+impl<T> Drop for UserEventReceiver<T> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// A handler of an `EventSink`.
+pub type Handler<E> = Box<dyn FnMut(E)>;
+
+/// Delivers events to a handler one at a time.
+///
+/// Handlers often make Windows send messages synchronously, whose events would reach the handler
+/// while it runs. Those events are queued and delivered once the handler returns, in order, so it
+/// never runs reentrantly. Events that arrive before there is a handler wait for it.
+pub struct EventSink<E> {
+    handler: RefCell<Option<Handler<E>>>,
+    pending: RefCell<VecDeque<E>>,
+    dispatching: Cell<bool>,
+}
+
+impl<E> Default for EventSink<E> {
+    fn default() -> Self {
+        EventSink {
+            handler: RefCell::new(None),
+            pending: RefCell::new(VecDeque::new()),
+            dispatching: Cell::new(false),
+        }
+    }
+}
+
+impl<E> EventSink<E> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the handler and delivers the events that were waiting for it.
+    pub fn set_handler(&self, handler: Handler<E>) {
+        *self.handler.borrow_mut() = Some(handler);
+        self.flush();
+    }
+
+    /// Removes the handler. Events wait again until the next one is set. The handler can't take
+    /// itself out while it runs, but it can set another one.
+    pub fn take_handler(&self) -> Option<Handler<E>> {
+        self.handler.borrow_mut().take()
+    }
+
+    /// The number of events waiting for a handler.
+    pub fn pending(&self) -> usize {
+        self.pending.borrow().len()
+    }
+
+    pub fn send(&self, event: E) {
+        self.pending.borrow_mut().push_back(event);
+        self.flush();
+    }
+
+    fn flush(&self) {
+        if self.dispatching.get() {
+            return;
+        }
+        self.dispatching.set(true);
+        // Dispatching ends even if the handler panics, so that later events are delivered.
+        let _dispatching = ResetOnDrop(&self.dispatching);
+        loop {
+            // The handler leaves its cell while it runs, so that it can replace itself.
+            let handler = match self.handler.borrow_mut().take() {
+                Some(handler) => handler,
+                None => break,
+            };
+            let mut running = RestoreHandler {
+                slot: &self.handler,
+                handler: Some(handler),
+            };
+            let event = self.pending.borrow_mut().pop_front();
+            if let (Some(event), Some(handler)) = (event, running.handler.as_mut()) {
+                handler(event);
+            }
+            drop(running);
+            if self.pending.borrow().is_empty() {
+                break;
+            }
+        }
+    }
+}
+
+/// Clears a flag when dropped.
+struct ResetOnDrop<'a>(&'a Cell<bool>);
+
+impl<'a> Drop for ResetOnDrop<'a> {
+    fn drop(&mut self) {
+        self.0.set(false);
+    }
+}
+
+/// Puts a running handler back in its cell when dropped, unless it set another one.
+struct RestoreHandler<'a, E: 'a> {
+    slot: &'a RefCell<Option<Handler<E>>>,
+    handler: Option<Handler<E>>,
+}
+
+impl<'a, E> Drop for RestoreHandler<'a, E> {
+    fn drop(&mut self) {
+        let mut slot = self.slot.borrow_mut();
+        if slot.is_none() {
+            *slot = self.handler.take();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    /// Stands in for the message queue of the loop's thread: each signal posts a wakeup message.
+    #[derive(Default)]
+    struct Pump {
+        messages: Mutex<usize>,
+        dead: AtomicBool,
+    }
+
+    impl LoopSignal for Pump {
+        fn signal(&self) -> bool {
+            if self.dead.load(Ordering::SeqCst) {
+                return false;
+            }
+            *self.messages.lock().unwrap() += 1;
+            true
+        }
+    }
+
+    impl Pump {
+        /// Handles the posted wakeups, as the loop would, returning the events they drained.
+        fn run<T>(&self, receiver: &UserEventReceiver<T>) -> Vec<T> {
+            let mut events = Vec::new();
+            loop {
+                {
+                    let mut messages = self.messages.lock().unwrap();
+                    if *messages == 0 {
+                        break;
+                    }
+                    *messages -= 1;
+                }
+                events.extend(receiver.drain());
+            }
+            events
+        }
+    }
+
+    #[test]
+    fn delivers_events_in_order_with_one_wakeup_per_burst() {
+        let pump = Arc::new(Pump::default());
+        let (proxy, receiver) = user_event_channel::<u32>(pump.clone());
+        let senders: Vec<_> = (0..4)
+            .map(|thread| {
+                let proxy = proxy.clone();
+                thread::spawn(move || {
+                    for i in 0..100 {
+                        proxy.send_event(thread * 1000 + i).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for sender in senders {
+            sender.join().unwrap();
+        }
+        assert_eq!(*pump.messages.lock().unwrap(), 1);
+
+        let events = pump.run(&receiver);
+        assert_eq!(events.len(), 400);
+        // Each proxy's events arrive in the order they were sent.
+        for thread in 0..4 {
+            let sent: Vec<_> = events
+                .iter()
+                .filter(|&&event| event / 1000 == thread)
+                .cloned()
+                .collect();
+            assert_eq!(
+                sent,
+                (0..100).map(|i| thread * 1000 + i).collect::<Vec<_>>()
+            );
+        }
+
+        // Once drained, the next event signals again.
+        proxy.send_event(1).unwrap();
+        proxy.send_event(2).unwrap();
+        assert_eq!(*pump.messages.lock().unwrap(), 1);
+        assert_eq!(pump.run(&receiver), vec![1, 2]);
+        assert_eq!(pump.run(&receiver), vec![]);
+    }
+
+    #[test]
+    fn send_fails_after_close() {
+        let pump = Arc::new(Pump::default());
+        let (proxy, receiver) = user_event_channel(pump.clone());
+        proxy.send_event("queued").unwrap();
+        assert!(!receiver.is_closed());
+        assert_eq!(receiver.close(), vec!["queued"]);
+        assert!(receiver.is_closed());
+        assert_eq!(proxy.send_event("late"), Err(EventLoopClosed("late")));
+        assert_eq!(EventLoopClosed(()).to_string(), "the event loop has exited");
+
+        // Dropping the receiver closes it too.
+        let (proxy, receiver) = user_event_channel(pump);
+        drop(receiver);
+        assert_eq!(proxy.send_event(1), Err(EventLoopClosed(1)));
+    }
+
+    #[test]
+    fn failed_signal_is_retried() {
+        let pump = Arc::new(Pump::default());
+        let (proxy, receiver) = user_event_channel(pump.clone());
+        pump.dead.store(true, Ordering::SeqCst);
+        proxy.send_event(1).unwrap();
+        assert_eq!(*pump.messages.lock().unwrap(), 0);
+        pump.dead.store(false, Ordering::SeqCst);
+        proxy.send_event(2).unwrap();
+        assert_eq!(pump.run(&receiver), vec![1, 2]);
+    }
+
+    fn recording_sink() -> (Rc<EventSink<u32>>, Rc<RefCell<Vec<u32>>>) {
+        (Rc::new(EventSink::new()), Rc::new(RefCell::new(Vec::new())))
+    }
+
+    #[test]
+    fn sink_queues_events_sent_while_dispatching() {
+        let (sink, log) = recording_sink();
+        sink.send(1);
+        assert_eq!(sink.pending(), 1);
+
+        let (inner, inner_log) = (sink.clone(), log.clone());
+        sink.set_handler(Box::new(move |event| {
+            inner_log.borrow_mut().push(event);
+            if event == 1 {
+                // Delivered after this call returns, not from within it.
+                inner.send(10);
+                inner.send(11);
+                assert_eq!(*inner_log.borrow(), vec![1]);
+            }
+        }));
+        assert_eq!(*log.borrow(), vec![1, 10, 11]);
+        sink.send(2);
+        assert_eq!(*log.borrow(), vec![1, 10, 11, 2]);
+        assert_eq!(sink.pending(), 0);
+
+        assert!(sink.take_handler().is_some());
+        sink.send(3);
+        assert_eq!(sink.pending(), 1);
+        assert_eq!(*log.borrow(), vec![1, 10, 11, 2]);
+    }
+
+    #[test]
+    fn handler_can_replace_itself() {
+        let (sink, log) = recording_sink();
+        let (inner, inner_log) = (sink.clone(), log.clone());
+        sink.set_handler(Box::new(move |event| {
+            let log = inner_log.clone();
+            inner.set_handler(Box::new(move |event| log.borrow_mut().push(event * 100)));
+            inner_log.borrow_mut().push(event);
+        }));
+        sink.send(1);
+        sink.send(2);
+        assert_eq!(*log.borrow(), vec![1, 200]);
+    }
+
+    #[test]
+    fn sink_survives_a_panicking_handler() {
+        let (sink, log) = recording_sink();
+        let inner_log = log.clone();
+        sink.set_handler(Box::new(move |event| {
+            if event == 1 {
+                panic!("handler failed");
+            }
+            inner_log.borrow_mut().push(event);
+        }));
+        sink.send(2);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| sink.send(1)));
+        assert!(result.is_err());
+
+        // Not stuck dispatching, and the handler is still there.
+        sink.send(3);
+        assert_eq!(*log.borrow(), vec![2, 3]);
+        assert_eq!(sink.pending(), 0);
+    }
+}
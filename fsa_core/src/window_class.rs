@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use std::fmt;
+
+/// Prefix of the names generated for window classes that weren't given an explicit name.
+pub const GENERATED_CLASS_PREFIX: &str = "rust-winui window class";
+
+/// The parts of a `WNDCLASSEXW` that can differ between windows. Handles are stored as integers
+/// so that keys can be compared and hashed without touching Win32.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ClassKey {
+    pub style: u32,
+    pub icon: usize,
+    pub small_icon: usize,
+    pub cursor: usize,
+    pub background: usize,
+}
+
+/// Registers and unregisters window classes with the OS.
+pub trait ClassRegistrar {
+    type Error;
+
+    fn register(&mut self, name: &str, key: &ClassKey) -> Result<(), Self::Error>;
+    fn unregister(&mut self, name: &str);
+}
+
+/// An error produced while acquiring a window class.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClassError<E> {
+    /// A class with the requested name is already registered with a different configuration.
+    NameConflict(String),
+    /// The OS refused to register the class.
+    Registration(E),
+}
+
+impl<E: fmt::Debug> fmt::Display for ClassError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClassError::NameConflict(ref name) => write!(
+                f,
+                "window class `{}` is already registered with a different configuration",
+                name
+            ),
+            ClassError::Registration(ref err) => {
+                write!(f, "failed to register window class: {:?}", err)
+            }
+        }
+    }
+}
+
+struct ClassEntry {
+    key: ClassKey,
+    ref_count: usize,
+    generated: bool,
+}
+
+/// Keeps track of the window classes in use, sharing one class between all windows with the same
+/// configuration and unregistering it once the last of them is gone.
+pub struct ClassRegistry<R: ClassRegistrar> {
+    registrar: R,
+    classes: HashMap<String, ClassEntry>,
+    generated_by_key: HashMap<ClassKey, String>,
+    next_id: u32,
+}
+
+impl<R: ClassRegistrar> ClassRegistry<R> {
+    pub fn new(registrar: R) -> Self {
+        ClassRegistry {
+            registrar,
+            classes: HashMap::new(),
+            generated_by_key: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    #[inline]
+    pub fn registrar(&self) -> &R {
+        &self.registrar
+    }
+
+    /// Returns the name of a registered class matching `key`, registering it if needed, and adds
+    /// a reference to it.
+    ///
+    /// If `name` is `None`, the class gets a generated name and is shared with every other
+    /// unnamed request for the same configuration. Every successful call must be balanced by a
+    /// call to `release`.
+    pub fn acquire(
+        &mut self,
+        key: ClassKey,
+        name: Option<&str>,
+    ) -> Result<String, ClassError<R::Error>> {
+        let (name, generated) = match name {
+            Some(name) => (name.to_owned(), false),
+            None => match self.generated_by_key.get(&key) {
+                Some(name) => (name.clone(), true),
+                None => {
+                    // Skip names that callers took for classes of their own.
+                    let name = loop {
+                        let name = format!("{} {}", GENERATED_CLASS_PREFIX, self.next_id);
+                        self.next_id += 1;
+                        if !self.classes.contains_key(&name) {
+                            break name;
+                        }
+                    };
+                    (name, true)
+                }
+            },
+        };
+
+        if let Some(entry) = self.classes.get_mut(&name) {
+            if entry.key != key {
+                return Err(ClassError::NameConflict(name));
+            }
+            entry.ref_count += 1;
+            return Ok(name);
+        }
+
+        self.registrar
+            .register(&name, &key)
+            .map_err(ClassError::Registration)?;
+        if generated {
+            self.generated_by_key.insert(key, name.clone());
+        }
+        self.classes.insert(
+            name.clone(),
+            ClassEntry {
+                key,
+                ref_count: 1,
+                generated,
+            },
+        );
+        Ok(name)
+    }
+
+    /// Drops a reference to a class, unregistering it when no references remain.
+    ///
+    /// Names that aren't registered through this registry are ignored.
+    pub fn release(&mut self, name: &str) {
+        let unregister = match self.classes.get_mut(name) {
+            Some(entry) => {
+                entry.ref_count -= 1;
+                entry.ref_count == 0
+            }
+            None => false,
+        };
+        if unregister {
+            let entry = self.classes.remove(name).unwrap();
+            if entry.generated {
+                self.generated_by_key.remove(&entry.key);
+            }
+            self.registrar.unregister(name);
+        }
+    }
+
+    /// The number of live references to a class, or `0` if it isn't registered.
+    pub fn ref_count(&self, name: &str) -> usize {
+        self.classes.get(name).map_or(0, |entry| entry.ref_count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records every call and keeps the set of registered names, like the OS would.
+    #[derive(Default)]
+    struct FakeRegistrar {
+        registered: Vec<String>,
+        calls: Vec<String>,
+        fail: bool,
+    }
+
+    impl ClassRegistrar for FakeRegistrar {
+        type Error = &'static str;
+
+        fn register(&mut self, name: &str, _: &ClassKey) -> Result<(), &'static str> {
+            if self.fail || self.registered.iter().any(|n| n == name) {
+                return Err("class already exists");
+            }
+            self.registered.push(name.to_owned());
+            self.calls.push(format!("register {}", name));
+            Ok(())
+        }
+
+        fn unregister(&mut self, name: &str) {
+            let i = self
+                .registered
+                .iter()
+                .position(|n| n == name)
+                .expect("unregistered a class that isn't registered");
+            self.registered.remove(i);
+            self.calls.push(format!("unregister {}", name));
+        }
+    }
+
+    fn key(style: u32) -> ClassKey {
+        ClassKey {
+            style,
+            icon: 0,
+            small_icon: 0,
+            cursor: 0,
+            background: 0,
+        }
+    }
+
+    fn generated(id: u32) -> String {
+        format!("{} {}", GENERATED_CLASS_PREFIX, id)
+    }
+
+    #[test]
+    fn shares_generated_classes_by_key() {
+        let mut registry = ClassRegistry::new(FakeRegistrar::default());
+        let a = registry.acquire(key(1), None).unwrap();
+        let b = registry.acquire(key(1), None).unwrap();
+        let c = registry.acquire(key(2), None).unwrap();
+        assert_eq!(a, generated(0));
+        assert_eq!(b, a);
+        assert_eq!(c, generated(1));
+        assert_eq!(registry.ref_count(&a), 2);
+        assert_eq!(registry.ref_count(&c), 1);
+        assert_eq!(
+            registry.registrar().calls,
+            vec![format!("register {}", a), format!("register {}", c)]
+        );
+    }
+
+    #[test]
+    fn generated_names_skip_names_in_use() {
+        let mut registry = ClassRegistry::new(FakeRegistrar::default());
+        let taken = registry.acquire(key(1), Some(&generated(0))).unwrap();
+        let name = registry.acquire(key(2), None).unwrap();
+        assert_eq!(name, generated(1));
+        assert_eq!(registry.ref_count(&taken), 1);
+        assert_eq!(registry.ref_count(&name), 1);
+        // The same configuration as the named class still gets a class of its own.
+        assert_eq!(registry.acquire(key(1), None).unwrap(), generated(2));
+    }
+
+    #[test]
+    fn unregisters_on_last_release() {
+        let mut registry = ClassRegistry::new(FakeRegistrar::default());
+        let name = registry.acquire(key(1), None).unwrap();
+        registry.acquire(key(1), None).unwrap();
+
+        registry.release(&name);
+        assert_eq!(registry.ref_count(&name), 1);
+        assert_eq!(registry.registrar().registered, vec![name.clone()]);
+
+        registry.release(&name);
+        assert_eq!(registry.ref_count(&name), 0);
+        assert!(registry.registrar().registered.is_empty());
+
+        // The key gets a fresh class the next time around.
+        let again = registry.acquire(key(1), None).unwrap();
+        assert_eq!(again, generated(1));
+        assert_eq!(
+            registry.registrar().calls,
+            vec![
+                format!("register {}", name),
+                format!("unregister {}", name),
+                format!("register {}", again),
+            ]
+        );
+    }
+
+    #[test]
+    fn named_classes() {
+        let mut registry = ClassRegistry::new(FakeRegistrar::default());
+        assert_eq!(registry.acquire(key(1), Some("main")).unwrap(), "main");
+        assert_eq!(registry.acquire(key(1), Some("main")).unwrap(), "main");
+        assert_eq!(
+            registry.acquire(key(2), Some("main")),
+            Err(ClassError::NameConflict("main".to_owned()))
+        );
+        assert_eq!(registry.ref_count("main"), 2);
+
+        // A named class isn't shared with unnamed requests for the same key.
+        let unnamed = registry.acquire(key(1), None).unwrap();
+        assert_eq!(unnamed, generated(0));
+
+        registry.release("main");
+        registry.release("main");
+        assert_eq!(registry.registrar().registered, vec![unnamed]);
+    }
+
+    #[test]
+    fn release_ignores_unknown_names() {
+        let mut registry = ClassRegistry::new(FakeRegistrar::default());
+        registry.release("unknown");
+        assert!(registry.registrar().calls.is_empty());
+    }
+
+    #[test]
+    fn failed_registration_takes_no_reference() {
+        let mut registry = ClassRegistry::new(FakeRegistrar::default());
+        registry.registrar.fail = true;
+        assert_eq!(
+            registry.acquire(key(1), Some("main")),
+            Err(ClassError::Registration("class already exists"))
+        );
+        assert_eq!(
+            registry.acquire(key(1), None),
+            Err(ClassError::Registration("class already exists"))
+        );
+        assert_eq!(registry.ref_count("main"), 0);
+        assert_eq!(registry.ref_count(&generated(0)), 0);
+
+        registry.registrar.fail = false;
+        assert_eq!(registry.acquire(key(1), Some("main")).unwrap(), "main");
+        let name = registry.acquire(key(1), None).unwrap();
+        assert_eq!(registry.ref_count(&name), 1);
+        registry.release("main");
+        registry.release(&name);
+        assert!(registry.registrar().registered.is_empty());
+    }
+}
@@ -0,0 +1,326 @@
+// The code in this file is derived from the winit project ( https://github.com/rust-windowing/winit ), 
+// created by the winit contributors including Pierre Krieger and Francesca Plebani. 
+// It has been extensively modified to remove most functionality not needed by the present project.
+// winit is licensed under Apache License 2.0 which can be found in this project as "LICENSE_winit"
+
+use std::path::PathBuf;
+
+use app::WindowId;
+use text_input::Ime;
+
+/// Describes an event received by the handler of an `EventLoop`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event<T> {
+    /// An event from one of the windows attached to the loop.
+    WindowEvent { window_id: WindowId, event: WindowEvent },
+    /// An event sent through an `EventLoopProxy`.
+    UserEvent(T),
+    /// The loop has exited. No events follow.
+    LoopDestroyed,
+}
+
+/// Describes an event from a `Window`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WindowEvent {
+    /// The size of the window has changed. Contains the client area's new dimensions.
+    Resized(LogicalSize),
+    /// The position of the window has changed. Contains the window's new position.
+    Moved(LogicalPosition),
+    /// The window has been requested to close, e.g. with its close button. It stays open until
+    /// its `Window` is dropped.
+    CloseRequested,
+    /// The window has been destroyed.
+    Destroyed,
+    /// A file has been dropped into the window, at `position` in the client area.
+    /// 
+    /// When the user drops multiple files at once, this event will be emitted for each file
+    /// separately.
+    DroppedFile { path: PathBuf, position: LogicalPosition },
+    /// A file is being hovered over the window. `position` is where the drag entered the client
+    /// area.
+    /// 
+    /// When the user hovers multiple files at once, this event will be emitted for each file
+    /// separately.
+    HoveredFile { path: PathBuf, position: LogicalPosition },
+    /// A file was hovered, but has exited the window.
+    /// 
+    /// There will be a single `HoveredFileCancelled` event triggered even if multiple files were
+    /// hovered.
+    HoveredFileCancelled,
+    /// The window received a unicode character.
+    ReceivedCharacter(char),
+    /// An input method editor is composing text for the window. Only sent once
+    /// `Window::set_ime_allowed` has enabled it.
+    Ime(Ime),
+    /// The window gained or lost focus.
+    ///
+    /// The parameter is true if the window has gained focus, and false if it has lost focus.
+    Focused(bool),
+    /// An event from the keyboard has been received.
+    KeyboardInput { device_id: DeviceId, input: KeyboardInput },
+    /// The cursor has moved on the window.
+    CursorMoved {
+        device_id: DeviceId,
+        /// (x,y) coords in pixels relative to the top-left corner of the window. Because the range of this data is
+        /// limited by the display area and it may have been transformed by the OS to implement effects such as cursor
+        /// acceleration, it should not be used to implement non-cursor-like interactions such as 3D camera control.
+        position: LogicalPosition,
+        modifiers: ModifiersState
+    },
+    /// The cursor has entered the window.
+    CursorEntered { device_id: DeviceId },
+    /// The cursor has left the window.
+    CursorLeft { device_id: DeviceId },
+    /// A mouse wheel movement or touchpad scroll occurred.
+    MouseWheel { device_id: DeviceId, delta: MouseScrollDelta, phase: TouchPhase, modifiers: ModifiersState },
+    /// An mouse button press has been received.
+    MouseInput { device_id: DeviceId, state: ElementState, button: MouseButton, modifiers: ModifiersState },
+    /// Touchpad pressure event.
+    ///
+    /// Only sent by touchpads that report pressure through pointer messages.
+    /// The parameters are: pressure level (value between 0 and 1 representing how hard the touchpad
+    /// is being pressed) and stage (integer representing the click level).
+    TouchpadPressure { device_id: DeviceId, pressure: f32, stage: i64 },
+    /// Motion on some analog axis. May report data redundant to other, more specific events.
+    AxisMotion { device_id: DeviceId, axis: AxisId, value: f64 },
+    /// The window needs to be redrawn.
+    Refresh,
+    /// Touch event has been received
+    Touch(Touch),
+    /// Pen event has been received
+    Pen(Pen),
+    /// The DPI factor of the window has changed.
+    ///
+    /// The following user actions can cause DPI changes:
+    ///
+    /// * Changing the display's resolution.
+    /// * Changing the display's DPI factor (e.g. in Control Panel on Windows).
+    /// * Moving the window to a display with a different DPI factor.
+    ///
+    /// For more information about DPI in general, see the [`dpi`](dpi/index.html) module.
+    HiDpiFactorChanged(f64),
+}
+
+/// Describes an event from a device, received through raw input.
+///
+/// Device events are not tied to a window and arrive even when the cursor is clipped or hidden.
+/// They describe what the hardware did: mouse motion is not accelerated and keys are not
+/// translated into characters.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeviceEvent {
+    /// A device of a registered kind was connected.
+    Added,
+    /// A device of a registered kind was disconnected.
+    Removed,
+    /// Change in physical position of a pointing device, in unspecified units.
+    MouseMotion { delta: (f64, f64) },
+    /// Physical scroll event.
+    MouseWheel { delta: MouseScrollDelta },
+    /// Motion on some analog axis.
+    Motion { axis: AxisId, value: f64 },
+    /// A button of a mouse has been pressed or released. Buttons are numbered from 1.
+    Button { button: ButtonId, state: ElementState },
+    /// A key of a keyboard has been pressed or released.
+    Key(KeyboardInput),
+    /// An input report of a HID device, such as a game controller. Its layout is given by the
+    /// report descriptor of the device.
+    HidReport(Vec<u8>),
+}
+
+/// A size represented in logical pixels.
+///
+/// The size is stored as floats, so please be careful. Casting floats to integers truncates the fractional part,
+/// which can cause noticable issues. To help with that, an `Into<(u32, u32)>` implementation is provided which
+/// does the rounding for you.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LogicalSize {
+    pub width: f64,
+    pub height: f64,
+}
+
+impl LogicalSize {
+    #[inline]
+    pub fn new(width: f64, height: f64) -> Self {
+        LogicalSize { width, height }
+    }
+}
+
+impl From<(u32, u32)> for LogicalSize {
+    #[inline]
+    fn from((width, height): (u32, u32)) -> Self {
+        Self::new(width as f64, height as f64)
+    }
+}
+
+/// A position represented in logical pixels.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LogicalPosition {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl LogicalPosition {
+    #[inline]
+    pub fn new(x: f64, y: f64) -> Self {
+        LogicalPosition { x, y }
+    }
+
+    /// Converts a position in physical pixels to logical pixels.
+    #[inline]
+    pub fn from_physical(x: f64, y: f64, dpi_factor: f64) -> Self {
+        LogicalPosition::new(x / dpi_factor, y / dpi_factor)
+    }
+}
+
+/// Identifier of an input device.
+///
+/// Whenever you receive an event arising from a particular input device, this event contains a
+/// `DeviceId` which identifies its origin. Note that devices may be virtual (representing an
+/// on-screen cursor and keyboard focus) or physical. Virtual devices typically aggregate inputs
+/// from multiple physical devices.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DeviceId(pub u64);
+
+impl DeviceId {
+    /// The virtual device standing in for the system cursor and keyboard focus.
+    pub const VIRTUAL: DeviceId = DeviceId(0);
+}
+
+/// Hardware-dependent keyboard scan code.
+pub type ScanCode = u32;
+
+/// Identifier for a specific analog axis on some device.
+pub type AxisId = u32;
+
+/// Identifier for a specific button on some device.
+pub type ButtonId = u32;
+
+/// Describes a keyboard input event.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct KeyboardInput {
+    /// Identifies the physical key pressed.
+    pub scancode: ScanCode,
+    pub state: ElementState,
+    /// The Win32 virtual-key code (`VK_*`) of the key, if it has one.
+    pub virtual_keycode: Option<i32>,
+    pub modifiers: ModifiersState,
+}
+
+/// Describes the input state of a key.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ElementState {
+    Pressed,
+    Released,
+}
+
+/// Describes a button of a mouse controller.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+    Other(u8),
+}
+
+/// Describes a difference in the mouse scroll wheel state.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MouseScrollDelta {
+    /// Amount in lines or rows to scroll in the horizontal and vertical directions.
+    ///
+    /// Positive values indicate movement forward (away from the user) or rightwards.
+    LineDelta(f32, f32),
+    /// Amount in pixels to scroll in the horizontal and vertical direction.
+    ///
+    /// Scroll events are expressed as a `PixelDelta` if supported by the device (eg. a touchpad)
+    /// and platform.
+    PixelDelta(LogicalPosition),
+}
+
+/// Describes touch-screen input state.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
+pub enum TouchPhase {
+    Started,
+    Moved,
+    Ended,
+    Cancelled,
+}
+
+/// Represents a touch event.
+///
+/// Every time the user touches the screen, a new `Started` event with a unique identifier for the
+/// finger is generated. When the finger is lifted, an `Ended` event is generated with the same
+/// finger id.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Touch {
+    pub device_id: DeviceId,
+    pub phase: TouchPhase,
+    pub location: LogicalPosition,
+    /// unique identifier of a finger.
+    pub id: u64,
+    /// How hard the finger presses, from 0 to 1, if the digitizer reports it.
+    pub pressure: Option<f64>,
+    /// The area the finger covers, if the digitizer reports it.
+    pub contact: Option<ContactArea>,
+    /// The angle of the finger in degrees, clockwise from pointing up, if the digitizer reports
+    /// it.
+    pub orientation: Option<f64>,
+}
+
+/// The area of the screen a touch covers, in the client area of the window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ContactArea {
+    /// The top-left corner of the area.
+    pub position: LogicalPosition,
+    pub size: LogicalSize,
+}
+
+/// Represents a pen event.
+///
+/// Pens are reported while they hover over the digitizer as well as while they touch it, so
+/// `Moved` events can come without a `Started` event. `in_contact` tells them apart.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pen {
+    pub device_id: DeviceId,
+    pub phase: TouchPhase,
+    pub location: LogicalPosition,
+    /// unique identifier of the pen while it is in range.
+    pub id: u64,
+    /// Whether the tip or eraser touches the digitizer.
+    pub in_contact: bool,
+    /// How hard the pen presses, from 0 to 1, if the pen reports it.
+    pub pressure: Option<f64>,
+    /// The tilt of the pen in degrees, from -90 to 90, along the x and y axes. An axis the pen
+    /// doesn't report is 0. `None` if it reports neither.
+    pub tilt: Option<(f64, f64)>,
+    /// The rotation of the pen around its own axis in degrees, clockwise, if the pen reports it.
+    pub rotation: Option<f64>,
+    pub buttons: PenButtons,
+}
+
+/// The state of the buttons of a pen.
+#[derive(Default, Debug, Hash, PartialEq, Eq, Clone, Copy)]
+pub struct PenButtons {
+    /// The button on the side of the pen is pressed.
+    pub barrel: bool,
+    /// The eraser button is pressed.
+    pub eraser: bool,
+    /// The pen is upside down, with the eraser towards the digitizer.
+    pub inverted: bool,
+}
+
+/// Represents the current state of the keyboard modifiers.
+///
+/// Each field of this struct represents a modifier and is `true` if this modifier is active.
+#[derive(Default, Debug, Hash, PartialEq, Eq, Clone, Copy)]
+pub struct ModifiersState {
+    /// The "shift" key
+    pub shift: bool,
+    /// The "control" key
+    pub ctrl: bool,
+    /// The "alt" key
+    pub alt: bool,
+    /// The "logo" key
+    ///
+    /// This is the "windows" key on PC.
+    pub logo: bool,
+}
@@ -0,0 +1,271 @@
+use std::fmt;
+
+// Window styles from winuser.h.
+const WS_MAXIMIZEBOX: u32 = 0x0001_0000;
+const WS_MINIMIZEBOX: u32 = 0x0002_0000;
+const WS_SIZEBOX: u32 = 0x0004_0000;
+const WS_SYSMENU: u32 = 0x0008_0000;
+const WS_BORDER: u32 = 0x0080_0000;
+const WS_CAPTION: u32 = 0x00C0_0000;
+const WS_MAXIMIZE: u32 = 0x0100_0000;
+const WS_CLIPCHILDREN: u32 = 0x0200_0000;
+const WS_CLIPSIBLINGS: u32 = 0x0400_0000;
+const WS_VISIBLE: u32 = 0x1000_0000;
+const WS_CHILD: u32 = 0x4000_0000;
+const WS_POPUP: u32 = 0x8000_0000;
+const WS_EX_TOPMOST: u32 = 0x0000_0008;
+const WS_EX_TOOLWINDOW: u32 = 0x0000_0080;
+const WS_EX_WINDOWEDGE: u32 = 0x0000_0100;
+const WS_EX_APPWINDOW: u32 = 0x0004_0000;
+const WS_EX_LAYERED: u32 = 0x0008_0000;
+const WS_EX_NOREDIRECTIONBITMAP: u32 = 0x0020_0000;
+
+bitflags! {
+    pub struct WindowFlags: u32 {
+        const RESIZABLE      = 1 << 0;
+        const DECORATIONS    = 1 << 1;
+        const VISIBLE        = 1 << 2;
+        const ON_TASKBAR     = 1 << 3;
+        const ALWAYS_ON_TOP  = 1 << 4;
+        const NO_BACK_BUFFER = 1 << 5;
+        const TRANSPARENT    = 1 << 6;
+        const CHILD          = 1 << 7;
+        const MAXIMIZED      = 1 << 8;
+
+        /// Marker flag for fullscreen. Should always match `WindowState::fullscreen`, but is
+        /// included here to make masking easier.
+        const MARKER_FULLSCREEN = 1 << 9;
+
+        /// The `WM_SIZE` event contains some parameters that can effect the state of `WindowFlags`.
+        /// In most cases, it's okay to let those parameters change the state. However, when we're
+        /// running the `WindowFlags::apply_diff` function, we *don't* want those parameters to
+        /// effect our stored state, because the purpose of `apply_diff` is to update the actual
+        /// window's state to match our stored state. This controls whether to accept those changes.
+        const MARKER_RETAIN_STATE_ON_SIZE = 1 << 10;
+
+        /// A `WS_POPUP` window, such as a menu or a tooltip.
+        const POPUP          = 1 << 11;
+        /// A tool window: it has a small caption and is left out of the taskbar and Alt+Tab.
+        const TOOL_WINDOW    = 1 << 12;
+
+        const FULLSCREEN_AND_MASK = !(
+            WindowFlags::DECORATIONS.bits |
+            WindowFlags::RESIZABLE.bits |
+            WindowFlags::MAXIMIZED.bits
+        );
+        const NO_DECORATIONS_AND_MASK = !WindowFlags::RESIZABLE.bits;
+        const INVISIBLE_AND_MASK = !WindowFlags::MAXIMIZED.bits;
+    }
+}
+
+/// Pairs of flags that can't be set together.
+const CONFLICTS: &[(WindowFlags, WindowFlags)] = &[
+    // `WS_CHILD` and `WS_POPUP` are mutually exclusive.
+    (WindowFlags::CHILD, WindowFlags::POPUP),
+    // Child windows live inside their parent, so anything that concerns top-level windows is
+    // meaningless for them.
+    (WindowFlags::CHILD, WindowFlags::ON_TASKBAR),
+    (WindowFlags::CHILD, WindowFlags::TOOL_WINDOW),
+    (WindowFlags::CHILD, WindowFlags::ALWAYS_ON_TOP),
+    // `WS_EX_APPWINDOW` forces a taskbar button, which is what `WS_EX_TOOLWINDOW` suppresses.
+    (WindowFlags::TOOL_WINDOW, WindowFlags::ON_TASKBAR),
+];
+
+/// Two window flags that were set together but can't be combined.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct StyleError {
+    pub first: WindowFlags,
+    pub second: WindowFlags,
+}
+
+impl fmt::Display for StyleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "window flags `{:?}` and `{:?}` can't be combined",
+            self.first, self.second
+        )
+    }
+}
+
+impl WindowFlags {
+    /// Checks that no incompatible flags are set together.
+    pub fn validate(self) -> Result<(), StyleError> {
+        for &(first, second) in CONFLICTS {
+            if self.contains(first | second) {
+                return Err(StyleError { first, second });
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the `WS_*` and `WS_EX_*` styles for the flags, after validating them.
+    pub fn to_window_styles(self) -> Result<(u32, u32), StyleError> {
+        self.validate()?;
+
+        let (mut style, mut style_ex) = (0, 0);
+
+        if self.contains(WindowFlags::RESIZABLE) {
+            style |= WS_SIZEBOX | WS_MAXIMIZEBOX;
+        }
+        if self.contains(WindowFlags::DECORATIONS) {
+            style |= WS_CAPTION | WS_MINIMIZEBOX | WS_BORDER;
+            style_ex = WS_EX_WINDOWEDGE;
+        }
+        if self.contains(WindowFlags::VISIBLE) {
+            style |= WS_VISIBLE;
+        }
+        if self.contains(WindowFlags::ON_TASKBAR) {
+            style_ex |= WS_EX_APPWINDOW;
+        }
+        if self.contains(WindowFlags::ALWAYS_ON_TOP) {
+            style_ex |= WS_EX_TOPMOST;
+        }
+        if self.contains(WindowFlags::NO_BACK_BUFFER) {
+            style_ex |= WS_EX_NOREDIRECTIONBITMAP;
+        }
+        if self.contains(WindowFlags::TRANSPARENT) {
+            // Is this necessary? The docs say that WS_EX_LAYERED requires a windows class without
+            // CS_OWNDC, and Winit windows have that flag set.
+            style_ex |= WS_EX_LAYERED;
+        }
+        if self.contains(WindowFlags::CHILD) {
+            style |= WS_CHILD;
+        }
+        if self.contains(WindowFlags::POPUP) {
+            style |= WS_POPUP;
+        }
+        if self.contains(WindowFlags::TOOL_WINDOW) {
+            style_ex |= WS_EX_TOOLWINDOW;
+        }
+        if self.contains(WindowFlags::MAXIMIZED) {
+            style |= WS_MAXIMIZE;
+        }
+
+        // Files are dropped through the OLE drop target that `drag_and_drop` registers, so windows
+        // don't take `WS_EX_ACCEPTFILES` and never see `WM_DROPFILES`.
+        style |= WS_CLIPSIBLINGS | WS_CLIPCHILDREN | WS_SYSMENU;
+
+        Ok((style, style_ex))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Added to every window.
+    const ALWAYS: u32 = WS_CLIPSIBLINGS | WS_CLIPCHILDREN | WS_SYSMENU;
+
+    #[test]
+    fn maps_flags_to_styles() {
+        assert_eq!(WindowFlags::empty().to_window_styles(), Ok((ALWAYS, 0)));
+        let cases = [
+            (WindowFlags::RESIZABLE, WS_SIZEBOX | WS_MAXIMIZEBOX, 0),
+            (
+                WindowFlags::DECORATIONS,
+                WS_CAPTION | WS_MINIMIZEBOX | WS_BORDER,
+                WS_EX_WINDOWEDGE,
+            ),
+            (WindowFlags::VISIBLE, WS_VISIBLE, 0),
+            (WindowFlags::ON_TASKBAR, 0, WS_EX_APPWINDOW),
+            (WindowFlags::ALWAYS_ON_TOP, 0, WS_EX_TOPMOST),
+            (WindowFlags::NO_BACK_BUFFER, 0, WS_EX_NOREDIRECTIONBITMAP),
+            (WindowFlags::TRANSPARENT, 0, WS_EX_LAYERED),
+            (WindowFlags::CHILD, WS_CHILD, 0),
+            (WindowFlags::POPUP, WS_POPUP, 0),
+            (WindowFlags::TOOL_WINDOW, 0, WS_EX_TOOLWINDOW),
+            (WindowFlags::MAXIMIZED, WS_MAXIMIZE, 0),
+            // Markers don't affect the styles.
+            (WindowFlags::MARKER_FULLSCREEN, 0, 0),
+            (WindowFlags::MARKER_RETAIN_STATE_ON_SIZE, 0, 0),
+        ];
+        for &(flags, style, style_ex) in cases.iter() {
+            assert_eq!(
+                flags.to_window_styles(),
+                Ok((style | ALWAYS, style_ex)),
+                "{:?}",
+                flags
+            );
+        }
+    }
+
+    #[test]
+    fn combines_styles() {
+        let flags = WindowFlags::RESIZABLE
+            | WindowFlags::DECORATIONS
+            | WindowFlags::VISIBLE
+            | WindowFlags::ON_TASKBAR;
+        assert_eq!(
+            flags.to_window_styles(),
+            Ok((
+                WS_SIZEBOX
+                    | WS_MAXIMIZEBOX
+                    | WS_CAPTION
+                    | WS_MINIMIZEBOX
+                    | WS_BORDER
+                    | WS_VISIBLE
+                    | ALWAYS,
+                WS_EX_WINDOWEDGE | WS_EX_APPWINDOW,
+            ))
+        );
+        // A popup with its frame stripped, as `Window::new` creates it.
+        let flags = WindowFlags::POPUP | WindowFlags::ALWAYS_ON_TOP | WindowFlags::TOOL_WINDOW;
+        assert_eq!(
+            flags.to_window_styles(),
+            Ok((WS_POPUP | ALWAYS, WS_EX_TOPMOST | WS_EX_TOOLWINDOW))
+        );
+    }
+
+    #[test]
+    fn rejects_every_conflict() {
+        for &(first, second) in CONFLICTS {
+            let flags = first | second | WindowFlags::VISIBLE;
+            assert_eq!(flags.validate(), Err(StyleError { first, second }));
+            assert_eq!(flags.to_window_styles(), Err(StyleError { first, second }));
+            // Either flag alone is fine.
+            assert!(first.to_window_styles().is_ok());
+            assert!(second.to_window_styles().is_ok());
+        }
+        // The first conflict in the table is reported.
+        let flags = WindowFlags::CHILD | WindowFlags::POPUP | WindowFlags::ON_TASKBAR;
+        assert_eq!(
+            flags.validate(),
+            Err(StyleError {
+                first: WindowFlags::CHILD,
+                second: WindowFlags::POPUP,
+            })
+        );
+    }
+
+    #[test]
+    fn kind_flags_are_valid() {
+        // The combinations `Window::new` builds for each kind of window.
+        let kinds = [
+            WindowFlags::ON_TASKBAR,
+            WindowFlags::empty(),
+            WindowFlags::POPUP,
+            WindowFlags::CHILD,
+            WindowFlags::TOOL_WINDOW,
+        ];
+        let common =
+            WindowFlags::RESIZABLE | WindowFlags::NO_BACK_BUFFER | WindowFlags::TRANSPARENT;
+        for &kind in kinds.iter() {
+            assert!((kind | common).validate().is_ok(), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn masks() {
+        let all = WindowFlags::RESIZABLE | WindowFlags::DECORATIONS | WindowFlags::MAXIMIZED;
+        assert_eq!(all & WindowFlags::FULLSCREEN_AND_MASK, WindowFlags::empty());
+        assert_eq!(
+            all & WindowFlags::NO_DECORATIONS_AND_MASK,
+            WindowFlags::DECORATIONS | WindowFlags::MAXIMIZED
+        );
+        assert_eq!(
+            all & WindowFlags::INVISIBLE_AND_MASK,
+            WindowFlags::RESIZABLE | WindowFlags::DECORATIONS
+        );
+    }
+}
@@ -0,0 +1,446 @@
+#[cfg(test)]
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use executor::Priority;
+
+/// A closure posted to a `WorkQueue`.
+pub type Work = Box<dyn FnOnce() + Send>;
+
+/// A queue that runs closures on its thread, in the order of their priority.
+///
+/// `post` may be called from any thread. The work runs later on the queue's thread, never from
+/// within `post`.
+pub trait WorkQueue: Send + Sync {
+    /// Queues `work`. Returns `false` if the queue is shutting down, in which case `work` is
+    /// dropped without running.
+    fn post(&self, priority: Priority, work: Work) -> bool;
+}
+
+/// The work behind a `Remote` was dropped without running, because its queue shut down.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Canceled;
+
+impl fmt::Display for Canceled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the queue shut down before the work ran")
+    }
+}
+
+struct Slot<T> {
+    value: Option<Result<T, Canceled>>,
+    waker: Option<Waker>,
+}
+
+/// A value that another thread produces. Resolves to `Err(Canceled)` if the `Completer` is
+/// dropped without a value.
+pub struct Remote<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+/// The sending side of a `Remote`.
+pub struct Completer<T> {
+    slot: Option<Arc<Mutex<Slot<T>>>>,
+}
+
+/// Creates a `Remote` and the `Completer` that resolves it.
+pub fn remote<T>() -> (Completer<T>, Remote<T>) {
+    let slot = Arc::new(Mutex::new(Slot {
+        value: None,
+        waker: None,
+    }));
+    (Completer { slot: Some(slot.clone()) }, Remote { slot })
+}
+
+impl<T> Completer<T> {
+    fn finish(&mut self, value: Result<T, Canceled>) {
+        if let Some(slot) = self.slot.take() {
+            let waker = {
+                let mut slot = slot.lock().unwrap();
+                slot.value = Some(value);
+                slot.waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+
+    pub fn complete(mut self, value: T) {
+        self.finish(Ok(value));
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        self.finish(Err(Canceled));
+    }
+}
+
+impl<T> Remote<T> {
+    /// Takes the value if it has arrived, without waiting.
+    pub fn try_take(&self) -> Option<Result<T, Canceled>> {
+        self.slot.lock().unwrap().value.take()
+    }
+}
+
+impl<T> Future for Remote<T> {
+    type Output = Result<T, Canceled>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T, Canceled>> {
+        let mut slot = self.slot.lock().unwrap();
+        match slot.value.take() {
+            Some(value) => Poll::Ready(value),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Posts work to a queue from any thread.
+#[derive(Clone)]
+pub struct QueueHandle {
+    queue: Arc<dyn WorkQueue>,
+}
+
+impl QueueHandle {
+    pub fn new(queue: Arc<dyn WorkQueue>) -> Self {
+        QueueHandle { queue }
+    }
+
+    /// Runs `f` on the queue's thread. Returns `false` if the queue is shutting down.
+    pub fn post<F>(&self, priority: Priority, f: F) -> bool
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.queue.post(priority, Box::new(f))
+    }
+
+    /// Runs `f` on the queue's thread and resolves to what it returns.
+    pub fn run<F, T>(&self, priority: Priority, f: F) -> Remote<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (completer, remote) = remote();
+        // If the queue refuses the work, dropping it drops the completer, which cancels the
+        // remote.
+        self.post(priority, move || completer.complete(f()));
+        remote
+    }
+}
+
+/// Asserts that a value never leaves its thread, though it travels through a queue that requires
+/// `Send`.
+struct StaysOnThread<T>(T);
+
+unsafe impl<T> Send for StaysOnThread<T> {}
+
+impl<T> StaysOnThread<T> {
+    fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+/// Posts work that needn't be `Send` to the queue of the current thread.
+///
+/// The handle can't leave the thread, so the work it posts runs, or is dropped, on the thread it
+/// came from.
+pub struct LocalQueueHandle {
+    handle: QueueHandle,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl LocalQueueHandle {
+    /// # Safety
+    ///
+    /// The queue must run its work on the current thread.
+    pub unsafe fn new(handle: QueueHandle) -> Self {
+        LocalQueueHandle {
+            handle,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// A handle to the same queue that other threads can use.
+    #[inline]
+    pub fn handle(&self) -> &QueueHandle {
+        &self.handle
+    }
+
+    /// Runs `f` on this thread later. Returns `false` if the queue is shutting down.
+    pub fn post<F>(&self, priority: Priority, f: F) -> bool
+    where
+        F: FnOnce() + 'static,
+    {
+        let f = StaysOnThread(f);
+        self.handle.post(priority, move || f.into_inner()())
+    }
+}
+
+#[cfg(test)]
+struct ManualState {
+    posted: VecDeque<(Priority, Work)>,
+    shut_down: bool,
+}
+
+/// A `WorkQueue` that only runs work when told to, for driving a queue by hand in tests.
+///
+/// Work is taken by priority, and in the order it was posted within a priority. Shutting down
+/// behaves like `ShutdownQueueAsync`: new work is refused, and work that is already queued still
+/// runs.
+#[cfg(test)]
+pub(crate) struct ManualWorkQueue {
+    state: Mutex<ManualState>,
+}
+
+#[cfg(test)]
+impl Default for ManualWorkQueue {
+    fn default() -> Self {
+        ManualWorkQueue {
+            state: Mutex::new(ManualState {
+                posted: VecDeque::new(),
+                shut_down: false,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+impl ManualWorkQueue {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The number of closures waiting.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().posted.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_shut_down(&self) -> bool {
+        self.state.lock().unwrap().shut_down
+    }
+
+    fn next(&self) -> Option<Work> {
+        let mut state = self.state.lock().unwrap();
+        let priority = state.posted.iter().map(|&(priority, _)| priority).max()?;
+        let index = state.posted.iter().position(|&(p, _)| p == priority)?;
+        state.posted.remove(index).map(|(_, work)| work)
+    }
+
+    /// Runs one closure. Returns `false` if none was waiting.
+    pub fn run_one(&self) -> bool {
+        // The lock is released before the work runs, so that it can post more.
+        match self.next() {
+            Some(work) => {
+                work();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Runs closures until none are waiting, including ones posted meanwhile. Returns how many
+    /// ran.
+    pub fn run_until_idle(&self) -> usize {
+        let mut runs = 0;
+        while self.run_one() {
+            runs += 1;
+        }
+        runs
+    }
+
+    /// Refuses new work from now on and runs what is queued. Returns how many closures ran.
+    pub fn shutdown(&self) -> usize {
+        self.state.lock().unwrap().shut_down = true;
+        self.run_until_idle()
+    }
+}
+
+#[cfg(test)]
+impl WorkQueue for ManualWorkQueue {
+    fn post(&self, priority: Priority, work: Work) -> bool {
+        {
+            let mut state = self.state.lock().unwrap();
+            if !state.shut_down {
+                state.posted.push_back((priority, work));
+                return true;
+            }
+        }
+        // Refused work is dropped outside of the lock, as dropping it can post more.
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use executor::{LocalExecutor, ManualQueue};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::thread;
+
+    fn setup() -> (Arc<ManualWorkQueue>, QueueHandle) {
+        let queue = Arc::new(ManualWorkQueue::new());
+        let handle = QueueHandle::new(queue.clone());
+        (queue, handle)
+    }
+
+    /// Posts to the queue when dropped.
+    struct PostOnDrop(QueueHandle, Arc<Mutex<Vec<bool>>>);
+
+    impl Drop for PostOnDrop {
+        fn drop(&mut self) {
+            let posted = self.0.post(Priority::Normal, || ());
+            self.1.lock().unwrap().push(posted);
+        }
+    }
+
+    #[test]
+    fn handles_are_send_and_sync() {
+        fn send_sync<T: Send + Sync>() {}
+        fn send<T: Send>() {}
+        send_sync::<QueueHandle>();
+        send::<Remote<u32>>();
+        send::<Completer<u32>>();
+    }
+
+    #[test]
+    fn runs_by_priority_then_in_order() {
+        let (queue, handle) = setup();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let work = [
+            (Priority::Low, 1),
+            (Priority::High, 2),
+            (Priority::Normal, 3),
+            (Priority::High, 4),
+        ];
+        for &(priority, n) in &work {
+            let log = log.clone();
+            assert!(handle.post(priority, move || log.lock().unwrap().push(n)));
+        }
+        // Posting from another thread.
+        let other = handle.clone();
+        let log2 = log.clone();
+        thread::spawn(move || other.post(Priority::Normal, move || log2.lock().unwrap().push(5)))
+            .join()
+            .unwrap();
+
+        assert!(log.lock().unwrap().is_empty());
+        assert_eq!(queue.len(), 5);
+        assert_eq!(queue.run_until_idle(), 5);
+        assert_eq!(*log.lock().unwrap(), vec![2, 4, 3, 5, 1]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn remote_resolves_to_the_result() {
+        let (queue, handle) = setup();
+        let other = handle.clone();
+        let remote = thread::spawn(move || other.run(Priority::Normal, || 6 * 7))
+            .join()
+            .unwrap();
+        assert_eq!(remote.try_take(), None);
+        queue.run_until_idle();
+        assert_eq!(remote.try_take(), Some(Ok(42)));
+        assert_eq!(remote.try_take(), None);
+    }
+
+    #[test]
+    fn remote_wakes_a_task_of_another_queue() {
+        let (queue, handle) = setup();
+        let wake_queue = Arc::new(ManualQueue::new());
+        let executor = LocalExecutor::new(wake_queue.clone());
+        let task = executor.spawn_local(Priority::Normal, handle.run(Priority::Normal, || "done"));
+        wake_queue.run_until_idle(&executor);
+        assert_eq!(task.try_take(), None);
+        assert!(wake_queue.is_empty());
+
+        queue.run_until_idle();
+        assert_eq!(wake_queue.len(), 1);
+        wake_queue.run_until_idle(&executor);
+        assert_eq!(task.try_take(), Some(Ok("done")));
+    }
+
+    #[test]
+    fn dropped_completer_cancels() {
+        let (completer, canceled) = remote::<u32>();
+        drop(completer);
+        assert_eq!(canceled.try_take(), Some(Err(Canceled)));
+
+        let (completer, completed) = remote();
+        completer.complete(1);
+        assert_eq!(completed.try_take(), Some(Ok(1)));
+        assert_eq!(
+            Canceled.to_string(),
+            "the queue shut down before the work ran"
+        );
+    }
+
+    #[test]
+    fn shutdown_drains_queued_work() {
+        let (queue, handle) = setup();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let first = handle.run(Priority::Low, || 1);
+        let inner = handle.clone();
+        let log2 = log.clone();
+        // Work queued before the shutdown still runs, but can't queue more.
+        handle.post(Priority::High, move || {
+            let posted = inner.post(Priority::High, || ());
+            log2.lock().unwrap().push(posted);
+        });
+
+        assert!(!queue.is_shut_down());
+        assert_eq!(queue.shutdown(), 2);
+        assert!(queue.is_shut_down());
+        assert_eq!(*log.lock().unwrap(), vec![false]);
+        assert_eq!(first.try_take(), Some(Ok(1)));
+
+        // Work after the shutdown is refused and canceled.
+        assert!(!handle.post(Priority::High, || panic!("ran after the shutdown")));
+        let late = handle.run(Priority::High, || 2);
+        assert_eq!(late.try_take(), Some(Err(Canceled)));
+        assert_eq!(queue.run_until_idle(), 0);
+    }
+
+    #[test]
+    fn refused_work_is_dropped_outside_the_lock() {
+        let (queue, handle) = setup();
+        queue.shutdown();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let guard = PostOnDrop(handle.clone(), log.clone());
+        assert!(!handle.post(Priority::Normal, move || drop(guard)));
+        assert_eq!(*log.lock().unwrap(), vec![false]);
+    }
+
+    #[test]
+    fn local_handle_posts_work_that_isnt_send() {
+        let (queue, handle) = setup();
+        let local = unsafe { LocalQueueHandle::new(handle) };
+        let count = Rc::new(RefCell::new(0));
+        let counter = count.clone();
+        assert!(local.post(Priority::Normal, move || *counter.borrow_mut() += 1));
+        assert!(local.handle().post(Priority::Normal, || ()));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(*count.borrow(), 0);
+        queue.run_until_idle();
+        assert_eq!(*count.borrow(), 1);
+
+        queue.shutdown();
+        let counter = count.clone();
+        assert!(!local.post(Priority::Normal, move || *counter.borrow_mut() += 1));
+        // The refused closure was dropped here, releasing its reference.
+        assert_eq!(Rc::strong_count(&count), 1);
+    }
+}
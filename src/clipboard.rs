@@ -0,0 +1,225 @@
+use image;
+use std::marker::PhantomData;
+use std::{fmt, ptr, slice};
+use winapi::shared::minwindef::{HGLOBAL, UINT};
+use winapi::um::winbase::{
+    GlobalAlloc, GlobalFree, GlobalLock, GlobalSize, GlobalUnlock, GMEM_MOVEABLE,
+};
+use winapi::um::winuser;
+
+use clipboard_formats::{
+    decode_dib, decode_html, decode_png, encode_dibv5, encode_html, encode_png, ClipboardImage,
+    DibError, HtmlFormatError, HtmlFragment,
+};
+use window::{to_wide, WinError, Window};
+
+/// An error produced while reading or writing the clipboard.
+#[derive(Debug)]
+pub enum ClipboardError {
+    Win(WinError),
+    Html(HtmlFormatError),
+    Dib(DibError),
+    Png(image::ImageError),
+}
+
+impl fmt::Display for ClipboardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClipboardError::Win(ref err) => write!(f, "clipboard error: {:?}", err),
+            ClipboardError::Html(ref err) => err.fmt(f),
+            ClipboardError::Dib(ref err) => err.fmt(f),
+            ClipboardError::Png(ref err) => write!(f, "failed to encode or decode PNG: {}", err),
+        }
+    }
+}
+
+impl From<WinError> for ClipboardError {
+    fn from(err: WinError) -> Self {
+        ClipboardError::Win(err)
+    }
+}
+
+impl From<HtmlFormatError> for ClipboardError {
+    fn from(err: HtmlFormatError) -> Self {
+        ClipboardError::Html(err)
+    }
+}
+
+impl From<DibError> for ClipboardError {
+    fn from(err: DibError) -> Self {
+        ClipboardError::Dib(err)
+    }
+}
+
+impl From<image::ImageError> for ClipboardError {
+    fn from(err: image::ImageError) -> Self {
+        ClipboardError::Png(err)
+    }
+}
+
+/// Looks up the id of a registered clipboard format.
+fn registered_format(name: &str) -> Result<UINT, WinError> {
+    match unsafe { winuser::RegisterClipboardFormatW(to_wide(name).as_ptr()) } {
+        0 => Err(WinError::from_last_error()),
+        format => Ok(format),
+    }
+}
+
+/// The clipboard, opened for a window. It is closed again when this is dropped.
+///
+/// Only one window can have the clipboard open at a time, so keep it open briefly.
+pub struct Clipboard<'a> {
+    // `OpenClipboard` associates the clipboard with the calling thread. Borrowing the window
+    // keeps this on the window's thread.
+    _window: PhantomData<&'a Window>,
+}
+
+impl Window {
+    /// Opens the clipboard. Data written to it is owned by this window.
+    pub fn clipboard(&self) -> Result<Clipboard, WinError> {
+        if unsafe { winuser::OpenClipboard(self.hwnd()) } == 0 {
+            return Err(WinError::from_last_error());
+        }
+        Ok(Clipboard {
+            _window: PhantomData,
+        })
+    }
+}
+
+impl<'a> Clipboard<'a> {
+    /// Removes everything from the clipboard and makes the window its owner. This has to be done
+    /// before writing; each `set_*` call then adds a format, so that the same content can be
+    /// offered in several formats.
+    pub fn clear(&mut self) -> Result<(), WinError> {
+        if unsafe { winuser::EmptyClipboard() } == 0 {
+            return Err(WinError::from_last_error());
+        }
+        Ok(())
+    }
+
+    /// Whether the clipboard holds data of a format.
+    fn has_format(&self, format: UINT) -> bool {
+        unsafe { winuser::IsClipboardFormatAvailable(format) != 0 }
+    }
+
+    /// Copies the data of a format, if the clipboard holds it.
+    fn get(&self, format: UINT) -> Result<Option<Vec<u8>>, WinError> {
+        if !self.has_format(format) {
+            return Ok(None);
+        }
+        unsafe {
+            let handle = winuser::GetClipboardData(format);
+            if handle.is_null() {
+                return Err(WinError::from_last_error());
+            }
+            let memory = GlobalLock(handle) as *const u8;
+            if memory.is_null() {
+                return Err(WinError::from_last_error());
+            }
+            let data = slice::from_raw_parts(memory, GlobalSize(handle)).to_vec();
+            GlobalUnlock(handle);
+            Ok(Some(data))
+        }
+    }
+
+    /// Puts data of a format on the clipboard.
+    fn set(&mut self, format: UINT, data: &[u8]) -> Result<(), WinError> {
+        unsafe {
+            let handle: HGLOBAL = GlobalAlloc(GMEM_MOVEABLE, data.len());
+            if handle.is_null() {
+                return Err(WinError::from_last_error());
+            }
+            let memory = GlobalLock(handle) as *mut u8;
+            if memory.is_null() {
+                let err = WinError::from_last_error();
+                GlobalFree(handle);
+                return Err(err);
+            }
+            ptr::copy_nonoverlapping(data.as_ptr(), memory, data.len());
+            GlobalUnlock(handle);
+            // The clipboard owns the memory once this succeeds.
+            if winuser::SetClipboardData(format, handle).is_null() {
+                let err = WinError::from_last_error();
+                GlobalFree(handle);
+                return Err(err);
+            }
+            Ok(())
+        }
+    }
+
+    /// Reads `CF_UNICODETEXT`.
+    pub fn get_text(&self) -> Result<Option<String>, ClipboardError> {
+        let data = match self.get(winuser::CF_UNICODETEXT)? {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+        let units: Vec<u16> = data
+            .chunks(2)
+            .filter(|unit| unit.len() == 2)
+            .map(|unit| unit[0] as u16 | (unit[1] as u16) << 8)
+            .take_while(|&unit| unit != 0)
+            .collect();
+        Ok(Some(String::from_utf16_lossy(&units)))
+    }
+
+    /// Writes `CF_UNICODETEXT`.
+    pub fn set_text(&mut self, text: &str) -> Result<(), ClipboardError> {
+        let data: Vec<u8> = to_wide(text)
+            .iter()
+            .flat_map(|&unit| vec![unit as u8, (unit >> 8) as u8])
+            .collect();
+        Ok(self.set(winuser::CF_UNICODETEXT, &data)?)
+    }
+
+    /// Reads the `HTML Format` entry.
+    pub fn get_html(&self) -> Result<Option<HtmlFragment>, ClipboardError> {
+        match self.get(registered_format("HTML Format")?)? {
+            Some(data) => Ok(Some(decode_html(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Writes an HTML fragment as an `HTML Format` entry. Applications that don't read HTML need
+    /// a `set_text` alongside it.
+    pub fn set_html(
+        &mut self,
+        fragment: &str,
+        source_url: Option<&str>,
+    ) -> Result<(), ClipboardError> {
+        let format = registered_format("HTML Format")?;
+        Ok(self.set(format, &encode_html(fragment, source_url))?)
+    }
+
+    /// Reads an image, preferring the lossless `PNG` format, then `CF_DIBV5` and `CF_DIB`.
+    /// Windows converts between the two bitmap formats itself.
+    pub fn get_image(&self) -> Result<Option<ClipboardImage>, ClipboardError> {
+        if let Some(data) = self.get(registered_format("PNG")?)? {
+            return Ok(Some(decode_png(&data)?));
+        }
+        for &format in &[winuser::CF_DIBV5, winuser::CF_DIB] {
+            if let Some(data) = self.get(format)? {
+                return Ok(Some(decode_dib(&data)?));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Writes an image as both `PNG` and `CF_DIBV5`, so that it keeps its alpha channel in
+    /// applications that understand either.
+    pub fn set_image(&mut self, image: &ClipboardImage) -> Result<(), ClipboardError> {
+        // Encode both first, so that an invalid image leaves the clipboard alone.
+        let dib = encode_dibv5(image)?;
+        let png = encode_png(image)?;
+        self.set(registered_format("PNG")?, &png)?;
+        self.set(winuser::CF_DIBV5, &dib)?;
+        Ok(())
+    }
+}
+
+impl<'a> Drop for Clipboard<'a> {
+    fn drop(&mut self) {
+        unsafe {
+            winuser::CloseClipboard();
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use std::ptr;
+use winapi::ctypes::c_void;
+use winapi::shared::ntdef::HANDLE;
+use winapi::shared::windef::{POINT, RECT, SIZE};
+use winapi::um::unknwnbase::IUnknown as RawUnknown;
+use winapi::Interface;
+use winrt::windows::ui::composition::{
+    CompositionDrawingSurface, Compositor, ICompositionGraphicsDevice, ICompositionSurface,
+};
+use winrt::{ComInterface, IUnknown};
+
+use nresult::{check_hresult, NResult};
+use windows_ui_composition_interop::{
+    ICompositionDrawingSurfaceInterop, ICompositionGraphicsDeviceInterop, ICompositorInterop,
+};
+
+/// Creates a graphics device that draws surfaces with `rendering_device`, an `ID3D11Device` or
+/// `ID2D1Device`. The graphics device keeps its own reference to the rendering device.
+///
+/// `rendering_device` must be a valid pointer.
+pub unsafe fn create_graphics_device<T: Interface>(
+    compositor: &Compositor,
+    rendering_device: *mut T,
+) -> NResult<ICompositionGraphicsDevice> {
+    let mut interop = compositor.query_interface::<ICompositorInterop>()?;
+    let mut ret: *mut <ICompositionGraphicsDevice as ComInterface>::TAbi = ptr::null_mut();
+    check_hresult(interop.CreateGraphicsDevice(rendering_device as *mut IUnknown, &mut ret))?;
+    Ok(ICompositionGraphicsDevice::wrap_com(ret))
+}
+
+/// Creates a surface that shows the composition swap chain or surface behind `handle`, as
+/// created with `DCompositionCreateSurfaceHandle`.
+///
+/// `handle` must stay open for as long as the surface is used.
+pub unsafe fn create_surface_for_handle(
+    compositor: &Compositor,
+    handle: HANDLE,
+) -> NResult<ICompositionSurface> {
+    let mut interop = compositor.query_interface::<ICompositorInterop>()?;
+    let mut ret: *mut <ICompositionSurface as ComInterface>::TAbi = ptr::null_mut();
+    check_hresult(interop.CreateCompositionSurfaceForHandle(handle, &mut ret))?;
+    Ok(ICompositionSurface::wrap_com(ret))
+}
+
+/// Creates a surface that shows `swap_chain`, an `IDXGISwapChain1` created for composition. The
+/// surface keeps its own reference to the swap chain.
+///
+/// `swap_chain` must be a valid pointer.
+pub unsafe fn create_surface_for_swap_chain<T: Interface>(
+    compositor: &Compositor,
+    swap_chain: *mut T,
+) -> NResult<ICompositionSurface> {
+    let mut interop = compositor.query_interface::<ICompositorInterop>()?;
+    let mut ret: *mut <ICompositionSurface as ComInterface>::TAbi = ptr::null_mut();
+    check_hresult(
+        interop.CreateCompositionSurfaceForSwapChain(swap_chain as *mut IUnknown, &mut ret),
+    )?;
+    Ok(ICompositionSurface::wrap_com(ret))
+}
+
+/// The rendering device of a graphics device, as the interface `T`. The caller owns the returned
+/// reference and has to release it.
+pub fn rendering_device<T: Interface>(device: &ICompositionGraphicsDevice) -> NResult<*mut T> {
+    let mut interop = device.query_interface::<ICompositionGraphicsDeviceInterop>()?;
+    unsafe {
+        let mut unknown: *mut IUnknown = ptr::null_mut();
+        check_hresult(interop.GetRenderingDevice(&mut unknown))?;
+        let unknown = unknown as *mut RawUnknown;
+        let mut ret: *mut c_void = ptr::null_mut();
+        let hr = (*unknown).QueryInterface(&T::uuidof(), &mut ret);
+        (*unknown).Release();
+        check_hresult(hr)?;
+        Ok(ret as *mut T)
+    }
+}
+
+/// Replaces the rendering device of a graphics device, e.g. after the old one was lost. Surfaces
+/// of the device are redrawn with the new one.
+///
+/// `rendering_device` must be a valid pointer.
+pub unsafe fn set_rendering_device<T: Interface>(
+    device: &ICompositionGraphicsDevice,
+    rendering_device: *mut T,
+) -> NResult<()> {
+    let mut interop = device.query_interface::<ICompositionGraphicsDeviceInterop>()?;
+    check_hresult(interop.SetRenderingDevice(rendering_device as *mut IUnknown))
+}
+
+/// Draws into a `CompositionDrawingSurface` through its native interface.
+pub struct DrawingSurfaceInterop {
+    interop: ICompositionDrawingSurfaceInterop,
+}
+
+impl DrawingSurfaceInterop {
+    pub fn new(surface: &CompositionDrawingSurface) -> NResult<Self> {
+        Ok(DrawingSurfaceInterop {
+            interop: surface.query_interface::<ICompositionDrawingSurfaceInterop>()?,
+        })
+    }
+
+    /// Starts drawing `update_rect`, or the whole surface if it's `None`, with the rendering
+    /// device's interface `T`: an `ID3D11Texture2D`, an `IDXGISurface` or an `ID2D1DeviceContext`.
+    /// The drawing ends when the returned session does.
+    pub fn begin_draw<T: Interface>(
+        &mut self,
+        update_rect: Option<&RECT>,
+    ) -> NResult<DrawSession<T>> {
+        let mut object: *mut c_void = ptr::null_mut();
+        let mut offset = POINT { x: 0, y: 0 };
+        unsafe {
+            check_hresult(self.interop.BeginDraw(
+                update_rect.map_or(ptr::null(), |rect| rect as *const RECT),
+                &T::uuidof(),
+                &mut object,
+                &mut offset,
+            ))?;
+        }
+        Ok(DrawSession {
+            interop: &mut self.interop,
+            object: object as *mut T,
+            offset,
+            ended: false,
+        })
+    }
+
+    /// Resizes the surface to `width` by `height` pixels, discarding its contents.
+    pub fn resize(&mut self, width: i32, height: i32) -> NResult<()> {
+        unsafe {
+            check_hresult(self.interop.Resize(SIZE {
+                cx: width,
+                cy: height,
+            }))
+        }
+    }
+
+    /// Moves the pixels of `scroll_rect`, or of the whole surface if it's `None`, by `(dx, dy)`.
+    /// Only pixels inside `clip_rect`, if given, are changed.
+    pub fn scroll(
+        &mut self,
+        scroll_rect: Option<&RECT>,
+        clip_rect: Option<&RECT>,
+        dx: i32,
+        dy: i32,
+    ) -> NResult<()> {
+        unsafe {
+            check_hresult(self.interop.Scroll(
+                scroll_rect.map_or(ptr::null(), |rect| rect as *const RECT),
+                clip_rect.map_or(ptr::null(), |rect| rect as *const RECT),
+                dx,
+                dy,
+            ))
+        }
+    }
+}
+
+/// Drawing in progress on a surface. Dropping the session ends it like `end` does, ignoring
+/// errors.
+pub struct DrawSession<'a, T> {
+    interop: &'a mut ICompositionDrawingSurfaceInterop,
+    object: *mut T,
+    offset: POINT,
+    ended: bool,
+}
+
+impl<'a, T> DrawSession<'a, T> {
+    /// The object to draw with. It's only valid until the session ends.
+    #[inline]
+    pub fn object(&self) -> *mut T {
+        self.object
+    }
+
+    /// Where the update rect starts within the object, which may be an atlas shared by several
+    /// surfaces.
+    #[inline]
+    pub fn offset(&self) -> POINT {
+        self.offset
+    }
+
+    /// Lets the rendering device be used elsewhere until `resume_draw`, e.g. to draw another
+    /// surface.
+    pub fn suspend_draw(&mut self) -> NResult<()> {
+        unsafe { check_hresult(self.interop.SuspendDraw()) }
+    }
+
+    pub fn resume_draw(&mut self) -> NResult<()> {
+        unsafe { check_hresult(self.interop.ResumeDraw()) }
+    }
+
+    /// Ends the drawing and commits it to the surface.
+    pub fn end(mut self) -> NResult<()> {
+        self.finish()
+    }
+
+    fn finish(&mut self) -> NResult<()> {
+        self.ended = true;
+        unsafe {
+            (*(self.object as *mut RawUnknown)).Release();
+            check_hresult(self.interop.EndDraw())
+        }
+    }
+}
+
+impl<'a, T> Drop for DrawSession<'a, T> {
+    fn drop(&mut self) {
+        if !self.ended {
+            let _ = self.finish();
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use std::mem::transmute;
+use std::ptr;
+use winapi::shared::windef::RECT;
+use winapi::shared::winerror::E_FAIL;
+use winapi::um::d3d11::{
+    D3D11CreateDevice, ID3D11Device, ID3D11DeviceContext, ID3D11Texture2D, D3D11_BOX,
+    D3D11_CREATE_DEVICE_BGRA_SUPPORT, D3D11_SDK_VERSION,
+};
+use winapi::um::d3dcommon::{D3D_DRIVER_TYPE_HARDWARE, D3D_DRIVER_TYPE_WARP};
+use winrt::windows::foundation::numerics::Vector2;
+use winrt::windows::foundation::Size;
+use winrt::windows::graphics::directx::{DirectXAlphaMode, DirectXPixelFormat};
+use winrt::windows::ui::composition::{
+    CompositionBrush, CompositionDrawingSurface, CompositionStretch, CompositionSurfaceBrush,
+    Compositor, ICompositionGraphicsDevice, ICompositionSurface, IVisual, SpriteVisual,
+};
+use winrt::ComInterface;
+
+use bitmap::Bitmap;
+use composition_interop::{create_graphics_device, DrawingSurfaceInterop};
+use image_brush::{ImageBrushOptions, Stretch};
+use nresult::{check_hresult, NError, NResult};
+
+/// A `CompositionGraphicsDevice` backed by a Direct3D 11 device, used to create drawing surfaces
+/// and upload pixels into them.
+pub struct CompositionGraphics {
+    pub device: ICompositionGraphicsDevice,
+    d3d_device: *mut ID3D11Device,
+    d3d_context: *mut ID3D11DeviceContext,
+}
+
+impl CompositionGraphics {
+    pub fn new(compositor: &Compositor) -> NResult<Self> {
+        let (d3d_device, d3d_context) = create_d3d_device()?;
+        unsafe {
+            match create_graphics_device(compositor, d3d_device) {
+                Ok(device) => Ok(CompositionGraphics {
+                    device,
+                    d3d_device,
+                    d3d_context,
+                }),
+                Err(err) => {
+                    (*d3d_context).Release();
+                    (*d3d_device).Release();
+                    Err(err)
+                }
+            }
+        }
+    }
+
+    /// The device context that uploads pixels to surfaces of this device.
+    #[inline]
+    pub(crate) fn d3d_context(&self) -> *mut ID3D11DeviceContext {
+        self.d3d_context
+    }
+
+    /// Creates a premultiplied BGRA drawing surface of the given size in pixels.
+    pub fn create_surface(&self, width: u32, height: u32) -> NResult<CompositionDrawingSurface> {
+        let size = Size {
+            Width: width as f32,
+            Height: height as f32,
+        };
+        Ok(self.device.create_drawing_surface(
+            size,
+            DirectXPixelFormat::B8G8R8A8UIntNormalized,
+            DirectXAlphaMode::Premultiplied,
+        )??)
+    }
+
+    /// Creates a drawing surface sized to the bitmap and uploads the bitmap into it.
+    pub fn create_surface_from_bitmap(
+        &self,
+        bitmap: &Bitmap,
+    ) -> NResult<CompositionDrawingSurface> {
+        let surface = self.create_surface(bitmap.width, bitmap.height)?;
+        self.upload(&surface, 0, 0, bitmap)?;
+        Ok(surface)
+    }
+
+    /// Copies the bitmap into the surface with its top-left corner at `(x, y)`.
+    pub fn upload(
+        &self,
+        surface: &CompositionDrawingSurface,
+        x: i32,
+        y: i32,
+        bitmap: &Bitmap,
+    ) -> NResult<()> {
+        if bitmap.width == 0 || bitmap.height == 0 {
+            return Ok(());
+        }
+        let mut interop = DrawingSurfaceInterop::new(surface)?;
+        let update_rect = RECT {
+            left: x,
+            top: y,
+            right: x + bitmap.width as i32,
+            bottom: y + bitmap.height as i32,
+        };
+        let session = interop.begin_draw::<ID3D11Texture2D>(Some(&update_rect))?;
+        let offset = session.offset();
+        unsafe {
+            // The update offset accounts for the surface living inside a larger atlas texture.
+            let dest = D3D11_BOX {
+                left: offset.x as u32,
+                top: offset.y as u32,
+                front: 0,
+                right: offset.x as u32 + bitmap.width,
+                bottom: offset.y as u32 + bitmap.height,
+                back: 1,
+            };
+            (*self.d3d_context).UpdateSubresource(
+                session.object() as *mut _,
+                0,
+                &dest,
+                bitmap.data.as_ptr() as *const _,
+                bitmap.stride() as u32,
+                0,
+            );
+        }
+        session.end()
+    }
+
+    /// Uploads the bitmap to a new surface and wraps it in a surface brush.
+    pub fn create_surface_brush(
+        &self,
+        compositor: &Compositor,
+        bitmap: &Bitmap,
+    ) -> NResult<CompositionSurfaceBrush> {
+        let surface = self.create_surface_from_bitmap(bitmap)?;
+        let surface = surface.query_interface::<ICompositionSurface>()?;
+        Ok(compositor.create_surface_brush_with_surface(&surface)??)
+    }
+
+    /// Uploads the bitmap to a new surface and wraps it in a surface brush that stretches and
+    /// aligns it according to `options`.
+    pub fn create_image_brush(
+        &self,
+        compositor: &Compositor,
+        bitmap: &Bitmap,
+        options: &ImageBrushOptions,
+    ) -> NResult<CompositionSurfaceBrush> {
+        let brush = self.create_surface_brush(compositor, bitmap)?;
+        brush.set_stretch(composition_stretch(options.stretch))?;
+        brush.set_horizontal_alignment_ratio(options.horizontal_alignment_ratio)?;
+        brush.set_vertical_alignment_ratio(options.vertical_alignment_ratio)?;
+        Ok(brush)
+    }
+
+    /// Creates a sprite visual the size of the bitmap that displays it.
+    pub fn create_bitmap_visual(
+        &self,
+        compositor: &Compositor,
+        bitmap: &Bitmap,
+    ) -> NResult<SpriteVisual> {
+        let brush = self.create_surface_brush(compositor, bitmap)?;
+        let visual = compositor.create_sprite_visual()??;
+        unsafe {
+            visual.set_brush(&transmute::<CompositionSurfaceBrush, CompositionBrush>(brush))?;
+        }
+        let ivisual = visual.query_interface::<IVisual>()?;
+        ivisual.set_size(Vector2 {
+            X: bitmap.width as f32,
+            Y: bitmap.height as f32,
+        })?;
+        Ok(visual)
+    }
+}
+
+impl Drop for CompositionGraphics {
+    fn drop(&mut self) {
+        unsafe {
+            (*self.d3d_context).Release();
+            (*self.d3d_device).Release();
+        }
+    }
+}
+
+fn composition_stretch(stretch: Stretch) -> CompositionStretch {
+    match stretch {
+        Stretch::None => CompositionStretch::None,
+        Stretch::Fill => CompositionStretch::Fill,
+        Stretch::Uniform => CompositionStretch::Uniform,
+        Stretch::UniformToFill => CompositionStretch::UniformToFill,
+    }
+}
+
+/// Creates a BGRA-capable Direct3D 11 device, falling back to WARP if no hardware device is
+/// available.
+fn create_d3d_device() -> NResult<(*mut ID3D11Device, *mut ID3D11DeviceContext)> {
+    let mut last_hr = E_FAIL;
+    for &driver_type in &[D3D_DRIVER_TYPE_HARDWARE, D3D_DRIVER_TYPE_WARP] {
+        let mut device = ptr::null_mut();
+        let mut context = ptr::null_mut();
+        let hr = unsafe {
+            D3D11CreateDevice(
+                ptr::null_mut(),
+                driver_type,
+                ptr::null_mut(),
+                D3D11_CREATE_DEVICE_BGRA_SUPPORT,
+                ptr::null(),
+                0,
+                D3D11_SDK_VERSION,
+                &mut device,
+                ptr::null_mut(),
+                &mut context,
+            )
+        };
+        if check_hresult(hr).is_ok() {
+            return Ok((device, context));
+        }
+        last_hr = hr;
+    }
+    Err(NError::Hr(last_hr))
+}
@@ -0,0 +1,189 @@
+use std::{mem, ptr};
+use winapi::shared::minwindef::{DWORD, LPARAM, UINT, USHORT, WPARAM};
+use winapi::shared::windef::HWND;
+use winapi::um::winuser::{
+    self, GetRawInputData, GetRegisteredRawInputDevices, RegisterRawInputDevices, HRAWINPUT,
+    RAWINPUTDEVICE, RAWINPUTHEADER,
+};
+
+use raw_input::{device_events, parse_raw_input};
+use window::{WinError, Window};
+use window_events::{DeviceEvent, DeviceId};
+use window_state::WindowState;
+
+/// The generic desktop controls usage page of the HID specification.
+const HID_USAGE_PAGE_GENERIC: USHORT = 0x01;
+
+bitflags! {
+    /// Kinds of devices whose raw input a window can receive.
+    pub struct RawInputDevices: u32 {
+        const MOUSE    = 1 << 0;
+        const KEYBOARD = 1 << 1;
+        const JOYSTICK = 1 << 2;
+        const GAMEPAD  = 1 << 3;
+    }
+}
+
+impl RawInputDevices {
+    /// The HID usages of the kinds of devices, in the generic desktop controls page.
+    fn usages(self) -> Vec<USHORT> {
+        [
+            (RawInputDevices::MOUSE, 0x02),
+            (RawInputDevices::JOYSTICK, 0x04),
+            (RawInputDevices::GAMEPAD, 0x05),
+            (RawInputDevices::KEYBOARD, 0x06),
+        ]
+        .iter()
+        .filter(|&&(kind, _)| self.contains(kind))
+        .map(|&(_, usage)| usage)
+        .collect()
+    }
+}
+
+/// Registers or removes devices in the raw input registrations of the process.
+unsafe fn register(usages: &[USHORT], flags: DWORD, target: HWND) -> Result<(), WinError> {
+    if usages.is_empty() {
+        return Ok(());
+    }
+    let devices: Vec<RAWINPUTDEVICE> = usages
+        .iter()
+        .map(|&usage| RAWINPUTDEVICE {
+            usUsagePage: HID_USAGE_PAGE_GENERIC,
+            usUsage: usage,
+            dwFlags: flags,
+            hwndTarget: target,
+        })
+        .collect();
+    let ok = RegisterRawInputDevices(
+        devices.as_ptr(),
+        devices.len() as UINT,
+        mem::size_of::<RAWINPUTDEVICE>() as UINT,
+    );
+    if ok == 0 {
+        return Err(WinError::from_last_error());
+    }
+    Ok(())
+}
+
+/// The devices of the process's raw input registrations that send their input to `window`.
+unsafe fn registered_usages(window: HWND) -> Vec<USHORT> {
+    let size = mem::size_of::<RAWINPUTDEVICE>() as UINT;
+    let mut count = 0;
+    GetRegisteredRawInputDevices(ptr::null_mut(), &mut count, size);
+    let mut devices = Vec::with_capacity(count as usize);
+    let read = GetRegisteredRawInputDevices(devices.as_mut_ptr(), &mut count, size);
+    if read == UINT::max_value() {
+        return Vec::new();
+    }
+    devices.set_len(read as usize);
+    devices
+        .iter()
+        .filter(|device| {
+            device.usUsagePage == HID_USAGE_PAGE_GENERIC && device.hwndTarget == window
+        })
+        .map(|device| device.usUsage)
+        .collect()
+}
+
+impl Window {
+    /// Starts delivering raw input from devices of the given kinds to the device event handler,
+    /// along with `Added` and `Removed` events as devices come and go. With `background`, input
+    /// also arrives while the window is not in the foreground.
+    ///
+    /// A process has a single registration for each kind of device, so registering a kind on one
+    /// window takes it away from any other.
+    pub fn register_raw_input(
+        &self,
+        devices: RawInputDevices,
+        background: bool,
+    ) -> Result<(), WinError> {
+        let mut flags = winuser::RIDEV_DEVNOTIFY;
+        if background {
+            flags |= winuser::RIDEV_INPUTSINK;
+        }
+        unsafe { register(&devices.usages(), flags, self.hwnd())? };
+        let state = self.state();
+        state.raw_input.set(state.raw_input.get() | devices);
+        Ok(())
+    }
+
+    /// Stops delivering raw input from devices of the given kinds.
+    pub fn unregister_raw_input(&self, devices: RawInputDevices) -> Result<(), WinError> {
+        let state = self.state();
+        unsafe { remove_registrations(self.hwnd(), state, devices) }
+    }
+
+    /// Sets the closure that receives the raw input events of the devices registered with
+    /// `register_raw_input`, replacing any previous one.
+    pub fn set_device_event_handler<F>(&self, handler: F)
+    where
+        F: FnMut(DeviceId, DeviceEvent) + 'static,
+    {
+        self.state().set_device_event_handler(Box::new(handler));
+    }
+}
+
+/// Removes the registrations of the given kinds, as long as they still send their input to
+/// `window`; another window may have taken them over since.
+pub(crate) unsafe fn remove_registrations(
+    window: HWND,
+    state: &WindowState,
+    devices: RawInputDevices,
+) -> Result<(), WinError> {
+    let ours = registered_usages(window);
+    let usages: Vec<USHORT> = devices
+        .usages()
+        .into_iter()
+        .filter(|usage| ours.contains(usage))
+        .collect();
+    register(&usages, winuser::RIDEV_REMOVE, ptr::null_mut())?;
+    state.raw_input.set(state.raw_input.get() - devices);
+    Ok(())
+}
+
+/// The id of the device behind a raw input handle. Input injected with `SendInput` has no device
+/// and is attributed to the virtual device.
+fn device_id(device: usize) -> DeviceId {
+    DeviceId(device as u64)
+}
+
+/// Handles `WM_INPUT`. The message still has to go to `DefWindowProcW` afterwards, which frees
+/// the input.
+pub(crate) unsafe fn handle_raw_input(state: &WindowState, lparam: LPARAM) {
+    let handle = lparam as HRAWINPUT;
+    let header_size = mem::size_of::<RAWINPUTHEADER>() as UINT;
+    let mut size = 0;
+    GetRawInputData(handle, winuser::RID_INPUT, ptr::null_mut(), &mut size, header_size);
+    if size == 0 {
+        return;
+    }
+    let mut data = vec![0u8; size as usize];
+    let read = GetRawInputData(
+        handle,
+        winuser::RID_INPUT,
+        data.as_mut_ptr() as *mut _,
+        &mut size,
+        header_size,
+    );
+    if read == UINT::max_value() {
+        return;
+    }
+    data.truncate(read as usize);
+    let input = match parse_raw_input(&data) {
+        Ok(input) => input,
+        Err(_) => return,
+    };
+    for event in device_events(&input) {
+        state.send_device_event(device_id(input.device), event);
+    }
+}
+
+/// Handles `WM_INPUT_DEVICE_CHANGE`.
+pub(crate) fn handle_device_change(state: &WindowState, wparam: WPARAM, lparam: LPARAM) {
+    let event = match wparam as DWORD {
+        winuser::GIDC_ARRIVAL => DeviceEvent::Added,
+        winuser::GIDC_REMOVAL => DeviceEvent::Removed,
+        _ => return,
+    };
+    state.send_device_event(device_id(lparam as usize), event);
+}
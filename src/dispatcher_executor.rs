@@ -0,0 +1,114 @@
+use std::cell::RefCell;
+use std::future::Future;
+use std::rc::Rc;
+use std::sync::Arc;
+use winapi::um::winuser;
+use winrt::windows::system::{DispatcherQueue, DispatcherQueueHandler, DispatcherQueuePriority};
+
+use executor::{JoinHandle, LocalExecutor, Priority, TaskId, WakeQueue};
+use nresult::NResult;
+use win32_composition::with_app;
+use window::{pump_event, Pumped};
+
+/// A `DispatcherQueue` that can be posted to from any thread. It runs the wakeups of the
+/// executor and the work of `QueueHandle`s.
+pub(crate) struct AgileDispatcherQueue {
+    pub queue: DispatcherQueue,
+}
+
+// `DispatcherQueue` is agile: `TryEnqueue` may be called from any thread, and the handler always
+// runs on the queue's thread.
+unsafe impl Send for AgileDispatcherQueue {}
+unsafe impl Sync for AgileDispatcherQueue {}
+
+pub(crate) fn dispatcher_priority(priority: Priority) -> DispatcherQueuePriority {
+    match priority {
+        Priority::Low => DispatcherQueuePriority::Low,
+        Priority::Normal => DispatcherQueuePriority::Normal,
+        Priority::High => DispatcherQueuePriority::High,
+    }
+}
+
+impl WakeQueue for AgileDispatcherQueue {
+    fn post(&self, task: TaskId, priority: Priority) -> bool {
+        let handler = DispatcherQueueHandler::new(move || {
+            run_task(task);
+            Ok(())
+        });
+        self.queue
+            .try_enqueue_with_priority(dispatcher_priority(priority), &handler)
+            .unwrap_or(false)
+    }
+}
+
+thread_local! {
+    /// The executor of this thread, created with the thread's dispatcher queue on first use.
+    static EXECUTOR: RefCell<Option<Rc<LocalExecutor>>> = RefCell::new(None);
+}
+
+fn run_task(task: TaskId) {
+    // The cell isn't borrowed while the task runs, so that the task can spawn others.
+    let executor = EXECUTOR.with(|executor| executor.borrow().clone());
+    if let Some(executor) = executor {
+        executor.run_task(task);
+    }
+}
+
+/// The dispatcher queue of the current thread. Threads without one, other than the threads of
+/// dedicated queues, get one from their `AppModel`.
+pub(crate) fn current_dispatcher_queue() -> NResult<DispatcherQueue> {
+    if let Some(queue) = DispatcherQueue::get_for_current_thread()? {
+        return Ok(queue);
+    }
+    let controller = with_app(|app| app.queue().map(|controller| controller.clone()))?;
+    Ok(controller.get_dispatcher_queue()??)
+}
+
+/// The executor of the current thread, creating the thread's dispatcher queue if it has none.
+fn executor() -> NResult<Rc<LocalExecutor>> {
+    if let Some(executor) = EXECUTOR.with(|executor| executor.borrow().clone()) {
+        return Ok(executor);
+    }
+    let queue = current_dispatcher_queue()?;
+    let executor = Rc::new(LocalExecutor::new(Arc::new(AgileDispatcherQueue { queue })));
+    EXECUTOR.with(|cell| *cell.borrow_mut() = Some(executor.clone()));
+    Ok(executor)
+}
+
+/// Runs `future` on the dispatcher queue of the current thread, polling it whenever it's woken.
+/// The future runs while the thread's events loop does.
+pub fn spawn_local<F>(future: F) -> NResult<JoinHandle<F::Output>>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    spawn_local_with_priority(Priority::Normal, future)
+}
+
+/// Like `spawn_local`, with the priority the future's wakeups are queued at. High priority work
+/// runs before input messages; low priority work only once the queue has nothing else to do.
+pub fn spawn_local_with_priority<F>(priority: Priority, future: F) -> NResult<JoinHandle<F::Output>>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    Ok(executor()?.spawn_local(priority, future))
+}
+
+/// Runs the events loop of the current thread until `future` completes, and returns its output.
+///
+/// Windows keep receiving events and spawned futures keep running meanwhile. Returns `None` if the
+/// events loop is asked to quit first; the quit message is posted again so that an enclosing
+/// `run_events_loop` ends too.
+pub fn block_on<F: Future>(future: F) -> NResult<Option<F::Output>> {
+    let executor = executor()?;
+    Ok(executor.block_on(future, || match pump_event() {
+        Pumped::Dispatched => true,
+        Pumped::Quit(code) => {
+            unsafe {
+                winuser::PostQuitMessage(code);
+            }
+            false
+        }
+    }))
+}
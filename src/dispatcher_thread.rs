@@ -0,0 +1,96 @@
+use std::marker::PhantomData;
+use std::sync::Arc;
+use winrt::windows::foundation::AsyncActionCompletedHandler;
+use winrt::windows::system::{DispatcherQueueHandler, IDispatcherQueueController};
+
+use dispatcher_executor::{current_dispatcher_queue, dispatcher_priority, AgileDispatcherQueue};
+use executor::Priority;
+use nresult::NResult;
+use win32_composition::create_dispatcher_queue_controller;
+use work_queue::{remote, LocalQueueHandle, QueueHandle, Remote, Work, WorkQueue};
+use DispatcherQueue::{DQTAT_COM_STA, DQTYPE_THREAD_DEDICATED};
+
+impl WorkQueue for AgileDispatcherQueue {
+    fn post(&self, priority: Priority, work: Work) -> bool {
+        let mut work = Some(work);
+        let handler = DispatcherQueueHandler::new(move || {
+            if let Some(work) = work.take() {
+                work();
+            }
+            Ok(())
+        });
+        self.queue
+            .try_enqueue_with_priority(dispatcher_priority(priority), &handler)
+            .unwrap_or(false)
+    }
+}
+
+/// A handle to the dispatcher queue of the current thread, for other threads to post to.
+pub fn current_queue() -> NResult<QueueHandle> {
+    let queue = current_dispatcher_queue()?;
+    Ok(QueueHandle::new(Arc::new(AgileDispatcherQueue { queue })))
+}
+
+/// A handle to the dispatcher queue of the current thread that posts closures that aren't `Send`.
+pub fn local_queue() -> NResult<LocalQueueHandle> {
+    let handle = current_queue()?;
+    // The queue belongs to this thread.
+    Ok(unsafe { LocalQueueHandle::new(handle) })
+}
+
+/// A dispatcher queue that runs on a thread of its own, in a single-threaded COM apartment.
+///
+/// Work reaches the thread through the `QueueHandle`s of the queue. The queue itself stays on the
+/// thread that created it: it can't be moved into work posted to the queue, where shutting the
+/// queue down would wait for itself.
+pub struct DedicatedQueue {
+    controller: Option<IDispatcherQueueController>,
+    handle: QueueHandle,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl DedicatedQueue {
+    /// Starts the thread and its queue.
+    pub fn new() -> NResult<Self> {
+        let controller =
+            create_dispatcher_queue_controller(DQTYPE_THREAD_DEDICATED, DQTAT_COM_STA)?;
+        let queue = controller.get_dispatcher_queue()??;
+        Ok(DedicatedQueue {
+            controller: Some(controller),
+            handle: QueueHandle::new(Arc::new(AgileDispatcherQueue { queue })),
+            _not_send: PhantomData,
+        })
+    }
+
+    /// A handle that posts work to the queue's thread.
+    #[inline]
+    pub fn handle(&self) -> QueueHandle {
+        self.handle.clone()
+    }
+
+    /// Shuts the queue down with `ShutdownQueueAsync`. New work is refused from now on, and
+    /// `Remote`s of refused work resolve to `Canceled`; work that is already queued still runs.
+    /// The returned `Remote` resolves once the queue is drained and its thread has exited.
+    pub fn shutdown(mut self) -> NResult<Remote<()>> {
+        let controller = self.controller.take().unwrap();
+        let action = controller.shutdown_queue_async()??;
+        let (completer, remote) = remote();
+        let mut completer = Some(completer);
+        action.set_completed(&AsyncActionCompletedHandler::new(move |_, _| {
+            if let Some(completer) = completer.take() {
+                completer.complete(());
+            }
+            Ok(())
+        }))?;
+        Ok(remote)
+    }
+}
+
+impl Drop for DedicatedQueue {
+    fn drop(&mut self) {
+        // Without `shutdown`, the queue is shut down without waiting for it.
+        if let Some(controller) = self.controller.take() {
+            let _ = controller.shutdown_queue_async();
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use std::cell::RefCell;
+use std::mem;
+use std::ptr;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use winapi::shared::minwindef::DWORD;
+use winapi::um::wingdi::DEVMODEW;
+use winapi::um::winuser;
+use winrt::windows::foundation::{TimeSpan, TypedEventHandler};
+use winrt::windows::system::DispatcherQueueTimer;
+use winrt::IInspectable;
+
+use dispatcher_executor::current_dispatcher_queue;
+use nresult::NResult;
+use timers::{
+    FrameCallbackId, FrameCallbacks, FrameTime, Interval, Sleep, TimerDriver, TimerId, Timers,
+    DEFAULT_FRAME_PERIOD,
+};
+
+/// Drives the `Timers` of a thread with a single one-shot `DispatcherQueueTimer`, restarted for
+/// the next deadline whenever it changes.
+pub struct DispatcherTimerDriver {
+    timer: DispatcherQueueTimer,
+    origin: Instant,
+}
+
+/// `TimeSpan` counts in 100 ns units.
+fn time_span(duration: Duration) -> TimeSpan {
+    TimeSpan {
+        Duration: duration.as_secs() as i64 * 10_000_000 + (duration.subsec_nanos() / 100) as i64,
+    }
+}
+
+impl TimerDriver for DispatcherTimerDriver {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+
+    fn arm(&self, deadline: Option<Duration>) {
+        // Failures leave the timers waiting for a later `arm`; there is nobody to report them to.
+        let _ = self.timer.stop();
+        if let Some(deadline) = deadline {
+            let delay = deadline
+                .checked_sub(self.now())
+                .unwrap_or(Duration::from_millis(0));
+            let _ = self.timer.set_interval(time_span(delay));
+            let _ = self.timer.start();
+        }
+    }
+}
+
+thread_local! {
+    /// The timers of this thread, created with its dispatcher queue on first use.
+    static TIMERS: RefCell<Option<Rc<Timers<DispatcherTimerDriver>>>> = RefCell::new(None);
+    static FRAMES: RefCell<Option<Rc<FrameCallbacks<DispatcherTimerDriver>>>> = RefCell::new(None);
+}
+
+fn fire() {
+    // The cell isn't borrowed while the callbacks run, so that they can use the timers.
+    let timers = TIMERS.with(|timers| timers.borrow().clone());
+    if let Some(timers) = timers {
+        timers.fire();
+    }
+}
+
+/// The timers of the current thread, creating the thread's dispatcher queue if it has none.
+pub fn timers() -> NResult<Rc<Timers<DispatcherTimerDriver>>> {
+    if let Some(timers) = TIMERS.with(|timers| timers.borrow().clone()) {
+        return Ok(timers);
+    }
+    let timer = current_dispatcher_queue()?.create_timer()??;
+    timer.set_is_repeating(false)?;
+    timer.add_tick(&TypedEventHandler::<DispatcherQueueTimer, IInspectable>::new(
+        |_sender, _args| {
+            fire();
+            Ok(())
+        },
+    ))?;
+    let timers = Rc::new(Timers::new(DispatcherTimerDriver {
+        timer,
+        origin: Instant::now(),
+    }));
+    TIMERS.with(|cell| *cell.borrow_mut() = Some(timers.clone()));
+    Ok(timers)
+}
+
+/// Runs `f` once on the current thread, `delay` from now.
+pub fn set_timeout<F>(delay: Duration, f: F) -> NResult<TimerId>
+where
+    F: FnOnce() + 'static,
+{
+    Ok(timers()?.set_timeout(delay, f))
+}
+
+/// Runs `f` on the current thread every `interval`, until the timer is cancelled.
+pub fn set_interval<F>(interval: Duration, f: F) -> NResult<TimerId>
+where
+    F: FnMut() + 'static,
+{
+    Ok(timers()?.set_interval(interval, f))
+}
+
+/// Stops a timer of the current thread. Returns `false` if it had already fired or been
+/// cancelled.
+pub fn cancel_timer(id: TimerId) -> bool {
+    match TIMERS.with(|timers| timers.borrow().clone()) {
+        Some(timers) => timers.cancel(id),
+        None => false,
+    }
+}
+
+/// A future that completes `delay` from now. Await it on the current thread.
+pub fn sleep(delay: Duration) -> NResult<Sleep<DispatcherTimerDriver>> {
+    Ok(Sleep::new(timers()?, delay))
+}
+
+/// Ticks every `period`, starting one period from now. Use it on the current thread.
+pub fn interval(period: Duration) -> NResult<Interval<DispatcherTimerDriver>> {
+    Ok(Interval::new(timers()?, period))
+}
+
+/// The refresh period of the primary display. Frame callbacks are timed by it, as there is no
+/// composition frame event to follow.
+fn frame_period() -> Duration {
+    unsafe {
+        let mut mode: DEVMODEW = mem::zeroed();
+        mode.dmSize = mem::size_of::<DEVMODEW>() as u16;
+        let found =
+            winuser::EnumDisplaySettingsW(ptr::null(), winuser::ENUM_CURRENT_SETTINGS, &mut mode);
+        // Frequencies of 0 and 1 stand for the hardware's default rate.
+        let frequency: DWORD = mode.dmDisplayFrequency;
+        if found == 0 || frequency <= 1 {
+            DEFAULT_FRAME_PERIOD
+        } else {
+            Duration::from_nanos(1_000_000_000 / frequency as u64)
+        }
+    }
+}
+
+fn frames() -> NResult<Rc<FrameCallbacks<DispatcherTimerDriver>>> {
+    if let Some(frames) = FRAMES.with(|frames| frames.borrow().clone()) {
+        return Ok(frames);
+    }
+    let frames = Rc::new(FrameCallbacks::new(timers()?, frame_period()));
+    FRAMES.with(|cell| *cell.borrow_mut() = Some(frames.clone()));
+    Ok(frames)
+}
+
+/// Runs `f` on the current thread once per frame of the primary display, for as long as it
+/// returns `true`.
+///
+/// This stands in for `CompositionTarget.Rendering`, which desktop composition doesn't have, and
+/// differs from it: the frames come from a `DispatcherQueueTimer` at the refresh period that the
+/// primary display had on first use, not from the compositor. They aren't aligned with vertical
+/// blanks or composition frames and drift against them, ticks are skipped while the thread is
+/// busy, and later changes of the refresh rate, or windows on displays with other rates, aren't
+/// followed.
+pub fn add_frame_callback<F>(f: F) -> NResult<FrameCallbackId>
+where
+    F: FnMut(FrameTime) -> bool + 'static,
+{
+    Ok(frames()?.add(f))
+}
+
+/// Stops a frame callback of the current thread. Returns `false` if it had already stopped.
+pub fn remove_frame_callback(id: FrameCallbackId) -> bool {
+    match FRAMES.with(|frames| frames.borrow().clone()) {
+        Some(frames) => frames.remove(id),
+        None => false,
+    }
+}
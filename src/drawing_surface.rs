@@ -0,0 +1,382 @@
+use std::mem::transmute;
+use winapi::shared::windef::RECT;
+use winapi::shared::winerror::E_INVALIDARG;
+use winapi::um::d3d11::{ID3D11DeviceContext, ID3D11Texture2D, D3D11_BOX};
+use winrt::windows::foundation::numerics::Vector2;
+use winrt::windows::foundation::Size;
+use winrt::windows::graphics::directx::{DirectXAlphaMode, DirectXPixelFormat};
+use winrt::windows::ui::composition::{
+    CompositionDrawingSurface, CompositionStretch, CompositionSurfaceBrush,
+    CompositionVirtualDrawingSurface, Compositor, ICompositionGraphicsDevice2,
+    ICompositionSurface, ICompositionSurfaceBrush2,
+};
+use winrt::ComInterface;
+
+use bitmap::{Bitmap, Rgba, BYTES_PER_PIXEL};
+use composition_interop::{DrawSession, DrawingSurfaceInterop};
+use composition_surface::CompositionGraphics;
+use nresult::{NError, NResult};
+use surface_model::{DirtyRegion, PixelRect, ShelfAtlas, SurfaceKind, SurfacePolicy};
+
+fn win32_rect(rect: &PixelRect) -> RECT {
+    RECT {
+        left: rect.x,
+        top: rect.y,
+        right: rect.right(),
+        bottom: rect.bottom(),
+    }
+}
+
+/// A composition surface that Rust code draws premultiplied BGRA pixels into.
+///
+/// The surface keeps track of what needs drawing: it starts out dirty, and resizing, scrolling
+/// and `invalidate` make more of it dirty. Drawing a rect with `begin_draw` cleans the dirty rects
+/// inside it once the drawing ends.
+pub struct DrawingSurface {
+    surface: CompositionDrawingSurface,
+    interop: DrawingSurfaceInterop,
+    d3d_context: *mut ID3D11DeviceContext,
+    kind: SurfaceKind,
+    width: u32,
+    height: u32,
+    dirty: DirtyRegion,
+}
+
+impl DrawingSurface {
+    /// Creates a surface of `width` by `height` pixels, using the default `SurfacePolicy` to
+    /// choose between a drawing and a virtual surface.
+    pub fn new(graphics: &CompositionGraphics, width: u32, height: u32) -> NResult<Self> {
+        DrawingSurface::with_policy(graphics, width, height, &SurfacePolicy::default())
+    }
+
+    /// Creates a surface of `width` by `height` pixels, virtual if `policy` says so. Sizes small
+    /// enough for an atlas get a drawing surface; `SurfaceAtlas` shares one between images.
+    pub fn with_policy(
+        graphics: &CompositionGraphics,
+        width: u32,
+        height: u32,
+        policy: &SurfacePolicy,
+    ) -> NResult<Self> {
+        let kind = match policy.kind(width, height) {
+            SurfaceKind::Virtual => SurfaceKind::Virtual,
+            _ => SurfaceKind::Drawing,
+        };
+        let surface = match kind {
+            SurfaceKind::Virtual => {
+                let device = graphics
+                    .device
+                    .query_interface::<ICompositionGraphicsDevice2>()?;
+                let surface = device.create_virtual_drawing_surface(
+                    Size {
+                        Width: width as f32,
+                        Height: height as f32,
+                    },
+                    DirectXPixelFormat::B8G8R8A8UIntNormalized,
+                    DirectXAlphaMode::Premultiplied,
+                )??;
+                unsafe {
+                    transmute::<CompositionVirtualDrawingSurface, CompositionDrawingSurface>(
+                        surface,
+                    )
+                }
+            }
+            _ => graphics.create_surface(width, height)?,
+        };
+        let interop = DrawingSurfaceInterop::new(&surface)?;
+        let d3d_context = graphics.d3d_context();
+        unsafe {
+            (*d3d_context).AddRef();
+        }
+        let mut dirty = DirtyRegion::default();
+        dirty.add(PixelRect::new(0, 0, width, height));
+        Ok(DrawingSurface {
+            surface,
+            interop,
+            d3d_context,
+            kind,
+            width,
+            height,
+            dirty,
+        })
+    }
+
+    #[inline]
+    pub fn surface(&self) -> &CompositionDrawingSurface {
+        &self.surface
+    }
+
+    /// Either `SurfaceKind::Drawing` or `SurfaceKind::Virtual`.
+    #[inline]
+    pub fn kind(&self) -> SurfaceKind {
+        self.kind
+    }
+
+    #[inline]
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// The whole surface.
+    #[inline]
+    pub fn bounds(&self) -> PixelRect {
+        PixelRect::new(0, 0, self.width, self.height)
+    }
+
+    /// The parts of the surface that need drawing.
+    #[inline]
+    pub fn dirty(&self) -> &DirtyRegion {
+        &self.dirty
+    }
+
+    /// Marks `rect` as needing drawing.
+    pub fn invalidate(&mut self, rect: PixelRect) {
+        if let Some(rect) = rect.intersection(&self.bounds()) {
+            self.dirty.add(rect);
+        }
+    }
+
+    pub fn invalidate_all(&mut self) {
+        let bounds = self.bounds();
+        self.dirty.add(bounds);
+    }
+
+    /// Starts drawing the part of `rect` inside the surface. Its pixels are undefined until the
+    /// guard writes them, so the guard has to cover every one of them before it ends.
+    pub fn begin_draw(&mut self, rect: PixelRect) -> NResult<DrawGuard> {
+        let rect = match rect.intersection(&self.bounds()) {
+            Some(rect) => rect,
+            None => return Err(NError::Hr(E_INVALIDARG)),
+        };
+        let session = self
+            .interop
+            .begin_draw::<ID3D11Texture2D>(Some(&win32_rect(&rect)))?;
+        Ok(DrawGuard {
+            session,
+            d3d_context: self.d3d_context,
+            rect,
+            dirty: &mut self.dirty,
+        })
+    }
+
+    /// Draws each dirty rect with `draw`, which must write all of the guard's rect, until the
+    /// surface is clean.
+    pub fn draw_dirty<F>(&mut self, mut draw: F) -> NResult<()>
+    where
+        F: FnMut(&mut DrawGuard) -> NResult<()>,
+    {
+        for rect in self.dirty.rects().to_vec() {
+            let mut guard = self.begin_draw(rect)?;
+            draw(&mut guard)?;
+            guard.end()?;
+        }
+        Ok(())
+    }
+
+    /// Resizes the surface to `width` by `height` pixels. This discards its contents, so it's all
+    /// dirty afterwards.
+    pub fn resize(&mut self, width: u32, height: u32) -> NResult<()> {
+        self.interop.resize(width as i32, height as i32)?;
+        self.width = width;
+        self.height = height;
+        self.dirty.clear();
+        self.invalidate_all();
+        Ok(())
+    }
+
+    /// Moves the pixels of `area`, or of the whole surface if it's `None`, by `(dx, dy)` without
+    /// redrawing them. Pixels that move out of the area are dropped, and the strips they uncover
+    /// become dirty.
+    pub fn scroll(&mut self, area: Option<PixelRect>, dx: i32, dy: i32) -> NResult<()> {
+        let bounds = self.bounds();
+        let area = match area.map_or(Some(bounds), |area| area.intersection(&bounds)) {
+            Some(area) => area,
+            None => return Ok(()),
+        };
+        let rect = win32_rect(&area);
+        self.interop.scroll(Some(&rect), Some(&rect), dx, dy)?;
+        self.dirty.scroll(&area, dx, dy);
+        Ok(())
+    }
+}
+
+impl Drop for DrawingSurface {
+    fn drop(&mut self) {
+        unsafe {
+            (*self.d3d_context).Release();
+        }
+    }
+}
+
+/// Drawing in progress on a rect of a `DrawingSurface`. Coordinates are in surface pixels.
+///
+/// Dropping the guard ends the drawing like `end` does, but leaves the rect dirty.
+pub struct DrawGuard<'a> {
+    session: DrawSession<'a, ID3D11Texture2D>,
+    d3d_context: *mut ID3D11DeviceContext,
+    rect: PixelRect,
+    dirty: &'a mut DirtyRegion,
+}
+
+impl<'a> DrawGuard<'a> {
+    /// The rect being drawn.
+    #[inline]
+    pub fn rect(&self) -> PixelRect {
+        self.rect
+    }
+
+    /// Copies `width` by `height` pixels of premultiplied BGRA, `stride` bytes apart, to `(x, y)`.
+    /// Pixels outside the guard's rect are skipped.
+    pub fn write(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+        bgra: &[u8],
+        stride: usize,
+    ) -> NResult<()> {
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+        let row = width as usize * BYTES_PER_PIXEL;
+        if stride < row || bgra.len() < stride * (height as usize - 1) + row {
+            return Err(NError::Hr(E_INVALIDARG));
+        }
+        let target = match PixelRect::new(x, y, width, height).intersection(&self.rect) {
+            Some(target) => target,
+            None => return Ok(()),
+        };
+        let first = (target.y - y) as usize * stride + (target.x - x) as usize * BYTES_PER_PIXEL;
+        // The session's offset is where the guard's rect starts in the texture, which may be an
+        // atlas shared with other surfaces.
+        let offset = self.session.offset();
+        let left = (offset.x + target.x - self.rect.x) as u32;
+        let top = (offset.y + target.y - self.rect.y) as u32;
+        let dest = D3D11_BOX {
+            left,
+            top,
+            front: 0,
+            right: left + target.width,
+            bottom: top + target.height,
+            back: 1,
+        };
+        unsafe {
+            (*self.d3d_context).UpdateSubresource(
+                self.session.object() as *mut _,
+                0,
+                &dest,
+                bgra[first..].as_ptr() as *const _,
+                stride as u32,
+                0,
+            );
+        }
+        Ok(())
+    }
+
+    /// Copies a bitmap to `(x, y)`.
+    pub fn write_bitmap(&mut self, x: i32, y: i32, bitmap: &Bitmap) -> NResult<()> {
+        self.write(
+            x,
+            y,
+            bitmap.width,
+            bitmap.height,
+            &bitmap.data,
+            bitmap.stride(),
+        )
+    }
+
+    /// Fills the guard's rect with `color`.
+    pub fn clear(&mut self, color: Rgba) -> NResult<()> {
+        let pixel = color.to_premultiplied_bgra();
+        let row = pixel
+            .iter()
+            .cloned()
+            .cycle()
+            .take(self.rect.width as usize * BYTES_PER_PIXEL)
+            .collect::<Vec<u8>>();
+        let mut data = Vec::with_capacity(row.len() * self.rect.height as usize);
+        for _ in 0..self.rect.height {
+            data.extend_from_slice(&row);
+        }
+        let rect = self.rect;
+        self.write(rect.x, rect.y, rect.width, rect.height, &data, row.len())
+    }
+
+    /// Ends the drawing and marks the dirty rects inside the guard's rect as clean.
+    pub fn end(self) -> NResult<()> {
+        let DrawGuard {
+            session,
+            rect,
+            dirty,
+            ..
+        } = self;
+        session.end()?;
+        dirty.remove_covered(&rect);
+        Ok(())
+    }
+}
+
+/// A shared drawing surface that holds many small images, in slots allocated by a `ShelfAtlas`.
+pub struct SurfaceAtlas {
+    surface: DrawingSurface,
+    atlas: ShelfAtlas,
+}
+
+impl SurfaceAtlas {
+    /// Creates an atlas of the size `policy` gives atlases.
+    pub fn new(graphics: &CompositionGraphics, policy: &SurfacePolicy) -> NResult<Self> {
+        let size = policy.atlas_size;
+        let surface = DrawingSurface::with_policy(graphics, size, size, policy)?;
+        Ok(SurfaceAtlas {
+            surface,
+            atlas: ShelfAtlas::new(size, size),
+        })
+    }
+
+    #[inline]
+    pub fn surface(&self) -> &DrawingSurface {
+        &self.surface
+    }
+
+    /// Copies `bitmap` into a free slot and returns the slot, or `None` if the atlas has no room
+    /// for it.
+    pub fn insert(&mut self, bitmap: &Bitmap) -> NResult<Option<PixelRect>> {
+        let slot = match self.atlas.allocate(bitmap.width, bitmap.height) {
+            Some(slot) => slot,
+            None => return Ok(None),
+        };
+        let mut guard = self.surface.begin_draw(slot)?;
+        guard.write_bitmap(slot.x, slot.y, bitmap)?;
+        guard.end()?;
+        Ok(Some(slot))
+    }
+
+    /// Frees a slot returned by `insert`. Its pixels stay until another image takes the slot.
+    pub fn remove(&mut self, slot: &PixelRect) -> bool {
+        self.atlas.free(slot)
+    }
+
+    /// A brush that paints the image in `slot` at its original size, from the top-left corner of
+    /// the area being painted. Size the visual to the slot so that the neighbors don't show.
+    pub fn create_slot_brush(
+        &self,
+        compositor: &Compositor,
+        slot: &PixelRect,
+    ) -> NResult<CompositionSurfaceBrush> {
+        let surface = self
+            .surface
+            .surface()
+            .query_interface::<ICompositionSurface>()?;
+        let brush = compositor.create_surface_brush_with_surface(&surface)??;
+        brush.set_stretch(CompositionStretch::None)?;
+        brush.set_horizontal_alignment_ratio(0.0)?;
+        brush.set_vertical_alignment_ratio(0.0)?;
+        brush
+            .query_interface::<ICompositionSurfaceBrush2>()?
+            .set_offset(Vector2 {
+                X: -slot.x as f32,
+                Y: -slot.y as f32,
+            })?;
+        Ok(brush)
+    }
+}
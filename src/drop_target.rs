@@ -0,0 +1,351 @@
+use std::cell::Cell;
+use std::ffi::OsString;
+use std::os::windows::ffi::OsStringExt;
+use std::path::PathBuf;
+use std::rc::{Rc, Weak};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{mem, panic, ptr, slice};
+use winapi::ctypes::c_void;
+use winapi::shared::guiddef::{IsEqualGUID, REFIID};
+use winapi::shared::minwindef::{DWORD, ULONG};
+use winapi::shared::ntdef::HRESULT;
+use winapi::shared::windef::{HWND, POINT, POINTL};
+use winapi::shared::winerror::{
+    E_NOINTERFACE, E_UNEXPECTED, RPC_E_CHANGED_MODE, SUCCEEDED, S_OK,
+};
+use winapi::shared::wtypes::DVASPECT_CONTENT;
+use winapi::um::objidl::{IDataObject, FORMATETC, STGMEDIUM, TYMED_HGLOBAL};
+use winapi::um::ole2;
+use winapi::um::oleidl::{IDropTarget, IDropTargetVtbl, DROPEFFECT_COPY, DROPEFFECT_NONE};
+use winapi::um::stringapiset::MultiByteToWideChar;
+use winapi::um::unknwnbase::{IUnknown, IUnknownVtbl};
+use winapi::um::winbase::{GlobalFree, GlobalLock, GlobalSize, GlobalUnlock};
+use winapi::um::winnls::CP_ACP;
+use winapi::um::winuser;
+use winapi::Interface;
+
+use drop_files::{parse_drop_files, FileNames};
+use window::{run_catch_panic, WinError};
+use window_events::{LogicalPosition, WindowEvent};
+use window_state::WindowState;
+
+thread_local! {
+    static OLE_INITIALIZED: Cell<bool> = Cell::new(false);
+}
+
+/// Initializes OLE on the current thread once. It stays initialized for the life of the thread.
+///
+/// Returns `false` if the thread's COM apartment is multithreaded, where OLE can't run.
+fn ensure_ole_initialized() -> Result<bool, WinError> {
+    OLE_INITIALIZED.with(|initialized| {
+        if initialized.get() {
+            return Ok(true);
+        }
+        let hr = unsafe { ole2::OleInitialize(ptr::null_mut()) };
+        if hr == RPC_E_CHANGED_MODE {
+            return Ok(false);
+        }
+        if !SUCCEEDED(hr) {
+            return Err(WinError::from_hresult(hr));
+        }
+        initialized.set(true);
+        Ok(true)
+    })
+}
+
+/// The effect of accepting a drag as a copy, given the effects its source allows.
+#[inline]
+fn copy_effect(allowed: DWORD) -> DWORD {
+    allowed & DROPEFFECT_COPY
+}
+
+/// An `IDropTarget` that turns file drags over a window into `HoveredFile`, `DroppedFile` and
+/// `HoveredFileCancelled` events.
+#[repr(C)]
+struct DropTarget {
+    // Must come first: COM callers see a pointer to this object as a pointer to the vtable.
+    vtable: *const IDropTargetVtbl,
+    ref_count: AtomicUsize,
+    window: HWND,
+    state: Weak<WindowState>,
+    /// Whether the current drag carries files, and so produced `HoveredFile` events.
+    hovered: Cell<bool>,
+}
+
+static DROP_TARGET_VTBL: IDropTargetVtbl = IDropTargetVtbl {
+    parent: IUnknownVtbl {
+        QueryInterface: query_interface,
+        AddRef: add_ref,
+        Release: release,
+    },
+    DragEnter: drag_enter,
+    DragOver: drag_over,
+    DragLeave: drag_leave,
+    Drop: drag_drop,
+};
+
+impl DropTarget {
+    fn send_event(&self, event: WindowEvent) {
+        if let Some(state) = self.state.upgrade() {
+            state.send_event(event);
+        }
+    }
+
+    /// Converts a drag position in screen coordinates to the window's client area.
+    unsafe fn client_position(&self, pt: *const POINTL) -> LogicalPosition {
+        let mut point = POINT {
+            x: (*pt).x,
+            y: (*pt).y,
+        };
+        winuser::ScreenToClient(self.window, &mut point);
+        let dpi_factor = self
+            .state
+            .upgrade()
+            .map_or(1.0, |state| state.hidpi_factor());
+        LogicalPosition::from_physical(point.x as f64, point.y as f64, dpi_factor)
+    }
+}
+
+/// Reads the files carried by a drag, if it carries any.
+unsafe fn dropped_paths(data: *const IDataObject) -> Option<Vec<PathBuf>> {
+    let format = FORMATETC {
+        cfFormat: winuser::CF_HDROP as u16,
+        ptd: ptr::null(),
+        dwAspect: DVASPECT_CONTENT,
+        lindex: -1,
+        tymed: TYMED_HGLOBAL,
+    };
+    let mut medium: STGMEDIUM = mem::zeroed();
+    if !SUCCEEDED((*data).GetData(&format, &mut medium)) {
+        return None;
+    }
+    // winapi declares the `STGMEDIUM` union as a pointer field, but it holds the `HGLOBAL`
+    // itself.
+    let global = medium.u as *mut c_void;
+    let memory = GlobalLock(global) as *const u8;
+    let paths = if memory.is_null() {
+        None
+    } else {
+        let bytes = slice::from_raw_parts(memory, GlobalSize(global));
+        let paths: Option<Vec<PathBuf>> = parse_drop_files(bytes).ok().map(|files| match files.names {
+            FileNames::Wide(names) => names
+                .iter()
+                .map(|name| PathBuf::from(OsString::from_wide(name)))
+                .collect(),
+            FileNames::Ansi(names) => names.iter().map(|name| ansi_to_path(name)).collect(),
+        });
+        GlobalUnlock(global);
+        paths
+    };
+    release_hglobal_medium(&mut medium);
+    paths.filter(|paths| !paths.is_empty())
+}
+
+/// Releases a `TYMED_HGLOBAL` medium the way `ReleaseStgMedium` does, which winapi doesn't
+/// declare: if the data object kept ownership it set `pUnkForRelease`, otherwise the global is
+/// ours to free.
+unsafe fn release_hglobal_medium(medium: &mut STGMEDIUM) {
+    if medium.pUnkForRelease.is_null() {
+        GlobalFree(medium.u as *mut c_void);
+    } else {
+        (*medium.pUnkForRelease).Release();
+    }
+}
+
+/// Converts a file name in the system code page.
+unsafe fn ansi_to_path(name: &[u8]) -> PathBuf {
+    let len = MultiByteToWideChar(
+        CP_ACP,
+        0,
+        name.as_ptr() as *const _,
+        name.len() as i32,
+        ptr::null_mut(),
+        0,
+    );
+    let mut wide = vec![0u16; len.max(0) as usize];
+    MultiByteToWideChar(
+        CP_ACP,
+        0,
+        name.as_ptr() as *const _,
+        name.len() as i32,
+        wide.as_mut_ptr(),
+        len,
+    );
+    PathBuf::from(OsString::from_wide(&wide))
+}
+
+unsafe extern "system" fn query_interface(
+    this: *mut IUnknown,
+    riid: REFIID,
+    object: *mut *mut c_void,
+) -> HRESULT {
+    let riid = &*riid;
+    if IsEqualGUID(riid, &IUnknown::uuidof()) || IsEqualGUID(riid, &IDropTarget::uuidof()) {
+        add_ref(this);
+        *object = this as *mut c_void;
+        S_OK
+    } else {
+        *object = ptr::null_mut();
+        E_NOINTERFACE
+    }
+}
+
+unsafe extern "system" fn add_ref(this: *mut IUnknown) -> ULONG {
+    let target = &*(this as *const DropTarget);
+    (target.ref_count.fetch_add(1, Ordering::Relaxed) + 1) as ULONG
+}
+
+unsafe extern "system" fn release(this: *mut IUnknown) -> ULONG {
+    let count = {
+        let target = &*(this as *const DropTarget);
+        target.ref_count.fetch_sub(1, Ordering::Release) - 1
+    };
+    if count == 0 {
+        mem::drop(Box::from_raw(this as *mut DropTarget));
+    }
+    count as ULONG
+}
+
+unsafe extern "system" fn drag_enter(
+    this: *mut IDropTarget,
+    data: *const IDataObject,
+    _key_state: DWORD,
+    pt: *const POINTL,
+    effect: *mut DWORD,
+) -> HRESULT {
+    run_catch_panic(
+        E_UNEXPECTED,
+        panic::AssertUnwindSafe(|| {
+            let target = &*(this as *const DropTarget);
+            // Only a source that allows copying can hand its files over.
+            let paths = match copy_effect(*effect) {
+                DROPEFFECT_NONE => None,
+                _ => dropped_paths(data),
+            };
+            match paths {
+                Some(paths) => {
+                    target.hovered.set(true);
+                    let position = target.client_position(pt);
+                    for path in paths {
+                        target.send_event(WindowEvent::HoveredFile { path, position });
+                    }
+                    *effect = DROPEFFECT_COPY;
+                }
+                None => {
+                    target.hovered.set(false);
+                    *effect = DROPEFFECT_NONE;
+                }
+            }
+            S_OK
+        }),
+    )
+}
+
+unsafe extern "system" fn drag_over(
+    this: *mut IDropTarget,
+    _key_state: DWORD,
+    _pt: *const POINTL,
+    effect: *mut DWORD,
+) -> HRESULT {
+    let target = &*(this as *const DropTarget);
+    // The allowed effects can change during the drag, e.g. with the modifier keys.
+    *effect = if target.hovered.get() {
+        copy_effect(*effect)
+    } else {
+        DROPEFFECT_NONE
+    };
+    S_OK
+}
+
+unsafe extern "system" fn drag_leave(this: *mut IDropTarget) -> HRESULT {
+    run_catch_panic(
+        E_UNEXPECTED,
+        panic::AssertUnwindSafe(|| {
+            let target = &*(this as *const DropTarget);
+            if target.hovered.replace(false) {
+                target.send_event(WindowEvent::HoveredFileCancelled);
+            }
+            S_OK
+        }),
+    )
+}
+
+unsafe extern "system" fn drag_drop(
+    this: *mut IDropTarget,
+    data: *const IDataObject,
+    _key_state: DWORD,
+    pt: *const POINTL,
+    effect: *mut DWORD,
+) -> HRESULT {
+    run_catch_panic(
+        E_UNEXPECTED,
+        panic::AssertUnwindSafe(|| {
+            let target = &*(this as *const DropTarget);
+            let hovered = target.hovered.replace(false);
+            let paths = match copy_effect(*effect) {
+                DROPEFFECT_NONE => None,
+                _ => dropped_paths(data),
+            };
+            match paths {
+                Some(paths) => {
+                    let position = target.client_position(pt);
+                    for path in paths {
+                        target.send_event(WindowEvent::DroppedFile { path, position });
+                    }
+                    *effect = DROPEFFECT_COPY;
+                }
+                None => {
+                    if hovered {
+                        target.send_event(WindowEvent::HoveredFileCancelled);
+                    }
+                    *effect = DROPEFFECT_NONE;
+                }
+            }
+            S_OK
+        }),
+    )
+}
+
+/// A drop target registered for a window. Dropping it revokes the registration, which has to
+/// happen while the `HWND` is still valid.
+pub struct DropTargetRegistration {
+    window: HWND,
+    target: *mut DropTarget,
+}
+
+impl DropTargetRegistration {
+    /// Registers a drop target that delivers its events to `state`.
+    ///
+    /// Returns `None` without registering anything on a thread whose COM apartment is
+    /// multithreaded, since drag and drop needs OLE.
+    pub unsafe fn register(
+        window: HWND,
+        state: &Rc<WindowState>,
+    ) -> Result<Option<DropTargetRegistration>, WinError> {
+        if !ensure_ole_initialized()? {
+            return Ok(None);
+        }
+        let target = Box::into_raw(Box::new(DropTarget {
+            vtable: &DROP_TARGET_VTBL,
+            ref_count: AtomicUsize::new(1),
+            window,
+            state: Rc::downgrade(state),
+            hovered: Cell::new(false),
+        }));
+        let hr = ole2::RegisterDragDrop(window, target as *mut IDropTarget);
+        if !SUCCEEDED(hr) {
+            release(target as *mut IUnknown);
+            return Err(WinError::from_hresult(hr));
+        }
+        Ok(Some(DropTargetRegistration { window, target }))
+    }
+}
+
+impl Drop for DropTargetRegistration {
+    fn drop(&mut self) {
+        unsafe {
+            ole2::RevokeDragDrop(self.window);
+            release(self.target as *mut IUnknown);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use std::rc::Rc;
+use std::sync::{Arc, Once};
+use std::{mem, ptr};
+use winapi::shared::basetsd::LONG_PTR;
+use winapi::shared::minwindef::{LPARAM, LRESULT, UINT, WPARAM};
+use winapi::shared::windef::HWND;
+use winapi::um::{libloaderapi, winuser};
+
+use user_events::{user_event_channel, EventLoopProxy, EventSink, LoopSignal, UserEventReceiver};
+use window::{run_catch_panic, run_events_loop, to_wide, WinError, Window};
+use window_events::Event;
+
+/// The class of the message-only windows that receive the wakeups of event loops.
+const LOOP_CLASS_NAME: &str = "rust-winui event loop";
+
+lazy_static! {
+    /// Posted to the window of an event loop when user events arrive.
+    static ref USER_EVENT_MESSAGE: UINT =
+        unsafe { winuser::RegisterWindowMessageW(to_wide("rust-winui user event").as_ptr()) };
+}
+
+static REGISTER_LOOP_CLASS: Once = Once::new();
+
+/// Drains the user events of a loop. Owned by the loop's window through `GWLP_USERDATA`.
+type Drain = Box<dyn Fn()>;
+
+/// Wakes a loop up by posting `USER_EVENT_MESSAGE` to its window.
+struct PostMessageSignal {
+    // Window handles may be used from any thread, but `HWND` is a pointer and isn't `Send`.
+    window: usize,
+}
+
+impl LoopSignal for PostMessageSignal {
+    fn signal(&self) -> bool {
+        unsafe { winuser::PostMessageW(self.window as HWND, *USER_EVENT_MESSAGE, 0, 0) != 0 }
+    }
+}
+
+unsafe extern "system" fn loop_callback(
+    window: HWND,
+    msg: UINT,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    run_catch_panic(-1, || {
+        let drain = winuser::GetWindowLongPtrW(window, winuser::GWLP_USERDATA) as *mut Drain;
+        if msg == *USER_EVENT_MESSAGE {
+            if !drain.is_null() {
+                (*drain)();
+            }
+            return 0;
+        }
+        if msg == winuser::WM_NCDESTROY && !drain.is_null() {
+            winuser::SetWindowLongPtrW(window, winuser::GWLP_USERDATA, 0);
+            drop(Box::from_raw(drain));
+        }
+        winuser::DefWindowProcW(window, msg, wparam, lparam)
+    })
+}
+
+fn register_loop_class() {
+    REGISTER_LOOP_CLASS.call_once(|| unsafe {
+        let class_name = to_wide(LOOP_CLASS_NAME);
+        let class = winuser::WNDCLASSEXW {
+            cbSize: mem::size_of::<winuser::WNDCLASSEXW>() as UINT,
+            style: 0,
+            lpfnWndProc: Some(loop_callback),
+            cbClsExtra: 0,
+            cbWndExtra: 0,
+            hInstance: libloaderapi::GetModuleHandleW(ptr::null()),
+            hCursor: ptr::null_mut(),
+            hbrBackground: ptr::null_mut(),
+            lpszMenuName: ptr::null(),
+            lpszClassName: class_name.as_ptr(),
+            hIcon: ptr::null_mut(),
+            hIconSm: ptr::null_mut(),
+        };
+        // A failure shows up when the window is created.
+        winuser::RegisterClassExW(&class);
+    });
+}
+
+/// Runs the events loop of the current thread, delivering the events of attached windows and the
+/// user events of its proxies to a single handler.
+///
+/// User events are posted to a message-only window owned by the loop, so they arrive wherever the
+/// thread is pumping messages, including in modal loops.
+pub struct EventLoop<T: 'static> {
+    window: HWND,
+    proxy: EventLoopProxy<T>,
+    receiver: Rc<UserEventReceiver<T>>,
+    sink: Rc<EventSink<Event<T>>>,
+}
+
+impl<T: 'static> EventLoop<T> {
+    pub fn new() -> Result<Self, WinError> {
+        register_loop_class();
+        let class_name = to_wide(LOOP_CLASS_NAME);
+        let window = unsafe {
+            winuser::CreateWindowExW(
+                0,
+                class_name.as_ptr(),
+                ptr::null(),
+                0,
+                0,
+                0,
+                0,
+                0,
+                winuser::HWND_MESSAGE,
+                ptr::null_mut(),
+                libloaderapi::GetModuleHandleW(ptr::null()),
+                ptr::null_mut(),
+            )
+        };
+        if window.is_null() {
+            return Err(WinError::from_last_error());
+        }
+
+        let (proxy, receiver) = user_event_channel(Arc::new(PostMessageSignal {
+            window: window as usize,
+        }));
+        let receiver = Rc::new(receiver);
+        let sink = Rc::new(EventSink::new());
+        let drain: Drain = {
+            let receiver = receiver.clone();
+            let sink = sink.clone();
+            Box::new(move || {
+                for event in receiver.drain() {
+                    sink.send(Event::UserEvent(event));
+                }
+            })
+        };
+        unsafe {
+            winuser::SetWindowLongPtrW(
+                window,
+                winuser::GWLP_USERDATA,
+                Box::into_raw(Box::new(drain)) as LONG_PTR,
+            );
+        }
+        Ok(EventLoop {
+            window,
+            proxy,
+            receiver,
+            sink,
+        })
+    }
+
+    /// A proxy that sends user events to this loop. Proxies can be cloned and, if `T` is `Send`,
+    /// used from other threads.
+    pub fn create_proxy(&self) -> EventLoopProxy<T> {
+        self.proxy.clone()
+    }
+
+    /// Delivers the events of `window` to the loop's handler as `Event::WindowEvent`, replacing
+    /// the window's own event handler.
+    pub fn attach(&self, window: &Window) {
+        let sink = self.sink.clone();
+        let window_id = window.id();
+        window.set_event_handler(move |event| sink.send(Event::WindowEvent { window_id, event }));
+    }
+
+    /// Runs the events loop until the thread quits, e.g. when its last window closes. Events that
+    /// arrived earlier are delivered first.
+    ///
+    /// Once the loop has exited, proxies start failing. The user events they sent before that are
+    /// still delivered, followed by `Event::LoopDestroyed`.
+    pub fn run<F>(self, handler: F)
+    where
+        F: FnMut(Event<T>) + 'static,
+    {
+        self.sink.set_handler(Box::new(handler));
+        run_events_loop();
+        for event in self.receiver.close() {
+            self.sink.send(Event::UserEvent(event));
+        }
+        self.sink.send(Event::LoopDestroyed);
+    }
+}
+
+impl<T: 'static> Drop for EventLoop<T> {
+    fn drop(&mut self) {
+        self.receiver.close();
+        unsafe {
+            winuser::DestroyWindow(self.window);
+        }
+    }
+}
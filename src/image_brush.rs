@@ -0,0 +1,19 @@
+use image::ImageError;
+use std::fs;
+use std::path::Path;
+
+use bitmap::Bitmap;
+use fsa_core::image_brush::decode_premultiplied;
+use nresult::NResult;
+
+pub use fsa_core::image_brush::{stretch_rect, ImageBrushOptions, Rect, Stretch};
+
+/// Decodes a PNG, JPEG or BMP image into a premultiplied BGRA bitmap.
+pub fn decode_image(bytes: &[u8]) -> NResult<Bitmap> {
+    Ok(decode_premultiplied(bytes)?)
+}
+
+/// Reads and decodes a PNG, JPEG or BMP file into a premultiplied BGRA bitmap.
+pub fn load_image<P: AsRef<Path>>(path: P) -> NResult<Bitmap> {
+    decode_image(&fs::read(path).map_err(ImageError::IoError)?)
+}
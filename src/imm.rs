@@ -0,0 +1,33 @@
+#![allow(non_camel_case_types, non_snake_case)]
+// The parts of the Input Method Manager API that winapi doesn't declare.
+use winapi::ctypes::c_void;
+use winapi::shared::minwindef::{BOOL, DWORD, LPARAM};
+use winapi::shared::ntdef::LONG;
+use winapi::shared::windef::{POINT, RECT};
+use winapi::um::imm::HIMC;
+use winapi::STRUCT;
+
+pub const GCS_COMPSTR: DWORD = 0x0008;
+pub const GCS_COMPATTR: DWORD = 0x0010;
+pub const GCS_CURSORPOS: DWORD = 0x0080;
+pub const GCS_RESULTSTR: DWORD = 0x0800;
+
+pub const ISC_SHOWUICOMPOSITIONWINDOW: LPARAM = 0x8000_0000;
+
+STRUCT! {struct CANDIDATEFORM {
+    dwIndex: DWORD,
+    dwStyle: DWORD,
+    ptCurrentPos: POINT,
+    rcArea: RECT,
+}}
+
+extern "system" {
+    #[link(name = "imm32")]
+    pub fn ImmGetCompositionStringW(
+        himc: HIMC,
+        dwIndex: DWORD,
+        lpBuf: *mut c_void,
+        dwBufLen: DWORD,
+    ) -> LONG;
+    pub fn ImmSetCandidateWindow(himc: HIMC, lpCandidate: *mut CANDIDATEFORM) -> BOOL;
+}
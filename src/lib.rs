@@ -0,0 +1,41 @@
+#![feature(try_trait)]
+
+extern crate fsa_core;
+extern crate winapi;
+extern crate winrt;
+#[macro_use]
+extern crate bitflags;
+#[macro_use]
+extern crate lazy_static;
+extern crate libc;
+extern crate image;
+
+// The platform-independent modules live in `fsa_core`, where they can be tested anywhere. They're
+// re-exported here, so the platform code names them like its own modules.
+pub use fsa_core::{
+    app, bitmap, clipboard_formats, cursor, drop_files, executor, gestures, icon, interop_vtables,
+    lifecycle, pointer_info, raw_input, scroll_model, surface_model, svg_path, text, text_input,
+    thread_affinity, timers, user_events, window_class, window_events, window_style, work_queue,
+};
+
+pub mod DispatcherQueue;
+pub mod clipboard;
+pub mod composition_interop;
+pub mod composition_surface;
+pub mod device_input;
+pub mod dispatcher_executor;
+pub mod dispatcher_thread;
+pub mod dispatcher_timer;
+pub mod drawing_surface;
+pub mod drop_target;
+pub mod event_loop;
+pub mod image_brush;
+pub mod imm;
+pub mod nresult;
+pub mod pointer_input;
+pub mod scroll_viewer;
+pub mod vector_shapes;
+pub mod win32_composition;
+pub mod window;
+pub mod window_state;
+pub mod windows_ui_composition_interop;
@@ -1,27 +1,31 @@
 #![feature(try_trait)]
 
-extern crate winapi;
+extern crate fsa_rust;
 extern crate winrt;
-#[macro_use]
-extern crate bitflags;
-extern crate libc;
-mod DispatcherQueue;
-mod nresult;
-mod win32_composition;
-mod window;
-mod windows_ui_composition_interop;
 
-use nresult::NResult;
+use fsa_rust::bitmap::Rgba;
+use fsa_rust::composition_surface::CompositionGraphics;
+use fsa_rust::nresult::NResult;
+use fsa_rust::text::{TextAlignment, TextFormat, TextLayout};
+use fsa_rust::window;
+use fsa_rust::window_events::WindowEvent;
+use std::cell::RefCell;
+use std::fs;
 use std::mem::transmute;
+use std::rc::Rc;
 use winrt::windows::foundation::numerics::{Vector2, Vector3};
 use winrt::windows::ui::composition::{
-    CompositionBrush, CompositionColorBrush, IVisual, SpriteVisual, Visual,
+    CompositionBrush, CompositionColorBrush, Compositor, IVisual, SpriteVisual, Visual,
 };
 use winrt::Guid;
 
 fn main() {
-    match window::Window::new(Default::default(), Default::default()) {
-        Ok(window) => match run(&window) {
+    // Both windows share the thread's compositor; the events loop ends when the last one closes.
+    let windows = (0..2)
+        .map(|_| window::Window::new(Default::default(), Default::default()))
+        .collect::<Result<Vec<_>, _>>();
+    match windows {
+        Ok(windows) => match run(windows) {
             Ok(()) => {
                 return;
             }
@@ -35,11 +39,42 @@ fn main() {
     }
 }
 
-fn run(window: &window::Window) -> NResult<()> {
-    window.show();
-    let comp_host = window.create_composition_host()?;
-    let comp = comp_host.compositor;
-    let children = comp_host.root_visual.get_children()??;
+fn run(windows: Vec<window::Window>) -> NResult<()> {
+    for window in &windows {
+        window.show();
+        window.create_composition_host()?;
+    }
+    let comp = windows[0].compositor()?;
+    let graphics = CompositionGraphics::new(&comp)?;
+    for window in &windows {
+        populate(window, &comp, &graphics)?;
+    }
+
+    // Closing a window drops it, which destroys it.
+    let windows = Rc::new(RefCell::new(
+        windows.into_iter().map(Some).collect::<Vec<_>>(),
+    ));
+    for (i, window) in windows.borrow().iter().enumerate() {
+        let windows = Rc::downgrade(&windows);
+        window.as_ref().unwrap().set_event_handler(move |event| {
+            if let WindowEvent::CloseRequested = event {
+                if let Some(windows) = windows.upgrade() {
+                    let window = windows.borrow_mut()[i].take();
+                    drop(window);
+                }
+            }
+        });
+    }
+    window::run_events_loop();
+    return Ok(());
+}
+
+fn populate(
+    window: &window::Window,
+    comp: &Compositor,
+    graphics: &CompositionGraphics,
+) -> NResult<()> {
+    let children = window.root_visual()?.get_children()??;
     for x in 0..5 {
         for y in 0..5 {
             let child_visual = comp.create_sprite_visual()??;
@@ -67,6 +102,22 @@ fn run(window: &window::Window) -> NResult<()> {
             }
         }
     }
-    window::run_events_loop();
+    if let Ok(font) = fs::read("C:\\Windows\\Fonts\\segoeui.ttf") {
+        if let Some(mut format) = TextFormat::from_font_bytes(font, 32.0) {
+            format.wrap_width = Some(700.0);
+            format.alignment = TextAlignment::Center;
+            let layout = TextLayout::new("Hello from Windows.UI.Composition", &format);
+            let bitmap = layout.rasterize(Rgba::new(0x20, 0x20, 0x20, 0xFF));
+            let label = graphics.create_bitmap_visual(comp, &bitmap)?;
+            label.query_interface::<IVisual>()?.set_offset(Vector3 {
+                X: 0.0,
+                Y: 750.0,
+                Z: 0.0,
+            })?;
+            unsafe {
+                children.insert_at_top(&transmute::<SpriteVisual, Visual>(label))?;
+            }
+        }
+    }
     return Ok(());
 }
@@ -1,10 +1,26 @@
+use image::ImageError;
 use std::option::NoneError;
+use winapi::shared::ntdef::HRESULT;
+use winapi::shared::winerror::SUCCEEDED;
 use winrt::Error;
 
+use app::{AppError, WindowId};
+use thread_affinity::WrongThread;
+
 #[derive(Debug)]
 pub enum NError {
     Rt(Error),
     Null(NoneError),
+    /// A failed `HRESULT` returned by a raw COM or Win32 call.
+    Hr(HRESULT),
+    /// A thread-affine object was used from another thread.
+    WrongThread(WrongThread),
+    /// Composition was attached to a window that isn't open.
+    UnknownWindow(WindowId),
+    /// Composition was attached to a window that already has it.
+    AlreadyAttached(WindowId),
+    /// An image couldn't be read or decoded.
+    Image(ImageError),
 }
 
 impl From<Error> for NError {
@@ -21,4 +37,38 @@ impl From<NoneError> for NError {
     }
 }
 
+impl From<WrongThread> for NError {
+    #[inline]
+    fn from(e: WrongThread) -> Self {
+        NError::WrongThread(e)
+    }
+}
+
+impl From<ImageError> for NError {
+    #[inline]
+    fn from(e: ImageError) -> Self {
+        NError::Image(e)
+    }
+}
+
+impl From<AppError<NError>> for NError {
+    fn from(e: AppError<NError>) -> Self {
+        match e {
+            AppError::UnknownWindow(id) => NError::UnknownWindow(id),
+            AppError::AlreadyAttached(id) => NError::AlreadyAttached(id),
+            AppError::Backend(e) => e,
+        }
+    }
+}
+
 pub type NResult<T> = std::result::Result<T, NError>;
+
+/// Converts the `HRESULT` of a raw COM call into an `NResult`.
+#[inline]
+pub fn check_hresult(hr: HRESULT) -> NResult<()> {
+    if SUCCEEDED(hr) {
+        Ok(())
+    } else {
+        Err(NError::Hr(hr))
+    }
+}
@@ -0,0 +1,100 @@
+use std::mem;
+use winapi::shared::minwindef::{DWORD, LOWORD, WPARAM};
+use winapi::shared::windef::{HWND, POINT};
+use winapi::um::winuser::{
+    self, GetPointerPenInfo, GetPointerTouchInfo, GetPointerType, POINTER_INFO,
+    POINTER_PEN_INFO, POINTER_TOUCH_INFO,
+};
+
+use pointer_info::{
+    pen_event, touch_event, touchpad_pressure_event, PenInfo, PointerInfo, TouchInfo,
+};
+use window_events::WindowEvent;
+use window_state::WindowState;
+
+/// Converts a point in screen coordinates to the client area of `window`.
+unsafe fn to_client(window: HWND, point: POINT) -> (i32, i32) {
+    let mut point = point;
+    winuser::ScreenToClient(window, &mut point);
+    (point.x, point.y)
+}
+
+unsafe fn pointer_info(window: HWND, info: &POINTER_INFO) -> PointerInfo {
+    PointerInfo {
+        id: info.pointerId,
+        flags: info.pointerFlags,
+        device: info.sourceDevice as usize,
+        position: to_client(window, info.ptPixelLocation),
+    }
+}
+
+unsafe fn touch_info(window: HWND, info: &POINTER_TOUCH_INFO) -> TouchInfo {
+    let rect = info.rcContact;
+    let (left, top) = to_client(window, POINT { x: rect.left, y: rect.top });
+    let (right, bottom) = to_client(window, POINT { x: rect.right, y: rect.bottom });
+    TouchInfo {
+        mask: info.touchMask,
+        contact: (left, top, right, bottom),
+        orientation: info.orientation,
+        pressure: info.pressure,
+    }
+}
+
+/// Handles `WM_POINTERDOWN`, `WM_POINTERUPDATE` and `WM_POINTERUP` for touch and pen pointers,
+/// and reports the pressure of touchpad pointers.
+///
+/// Returns `false` for other kinds of pointers and for touchpads, whose messages have to go to
+/// `DefWindowProcW` so that Windows turns them into mouse messages.
+pub(crate) unsafe fn handle_pointer(window: HWND, state: &WindowState, wparam: WPARAM) -> bool {
+    let id = LOWORD(wparam as DWORD) as u32;
+    let mut kind = 0;
+    if GetPointerType(id, &mut kind) == 0 {
+        return false;
+    }
+    let dpi_factor = state.hidpi_factor();
+    match kind {
+        winuser::PT_TOUCH => {
+            let mut info: POINTER_TOUCH_INFO = mem::zeroed();
+            if GetPointerTouchInfo(id, &mut info) == 0 {
+                return false;
+            }
+            let touch = touch_info(window, &info);
+            let pointer = pointer_info(window, &info.pointerInfo);
+            if let Some(touch) = touch_event(&pointer, &touch, dpi_factor) {
+                state.send_event(WindowEvent::Touch(touch));
+            }
+            true
+        }
+        winuser::PT_TOUCHPAD => {
+            let mut info: POINTER_TOUCH_INFO = mem::zeroed();
+            if GetPointerTouchInfo(id, &mut info) != 0 {
+                let touch = touch_info(window, &info);
+                let pointer = pointer_info(window, &info.pointerInfo);
+                if let Some(event) = touchpad_pressure_event(&pointer, &touch) {
+                    state.send_event(event);
+                }
+            }
+            false
+        }
+        winuser::PT_PEN => {
+            let mut info: POINTER_PEN_INFO = mem::zeroed();
+            if GetPointerPenInfo(id, &mut info) == 0 {
+                return false;
+            }
+            let pen = PenInfo {
+                flags: info.penFlags,
+                mask: info.penMask,
+                pressure: info.pressure,
+                rotation: info.rotation,
+                tilt_x: info.tiltX,
+                tilt_y: info.tiltY,
+            };
+            let pointer = pointer_info(window, &info.pointerInfo);
+            if let Some(pen) = pen_event(&pointer, &pen, dpi_factor) {
+                state.send_event(WindowEvent::Pen(pen));
+            }
+            true
+        }
+        _ => false,
+    }
+}
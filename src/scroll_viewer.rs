@@ -0,0 +1,518 @@
+use std::cell::RefCell;
+use std::mem::transmute;
+use std::rc::{Rc, Weak};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{mem, panic, ptr};
+use winapi::ctypes::c_void;
+use winapi::shared::guiddef::{IsEqualGUID, GUID, IID, REFIID};
+use winapi::shared::minwindef::ULONG;
+use winapi::shared::ntdef::HRESULT;
+use winapi::shared::winerror::{E_NOINTERFACE, E_UNEXPECTED, S_OK};
+use winapi::um::unknwnbase::{IUnknown, IUnknownVtbl};
+use winapi::winrt::hstring::HSTRING;
+use winapi::winrt::inspectable::{BaseTrust, IInspectable, IInspectableVtbl, TrustLevel};
+use winapi::Interface;
+use winrt::windows::foundation::numerics::{Vector2, Vector3};
+use winrt::windows::ui::composition::interactions::{
+    ICompositionInteractionSource, IInteractionTracker, IInteractionTrackerInertiaStateEnteredArgs,
+    IInteractionTrackerOwner, IInteractionTrackerValuesChangedArgs, InteractionSourceMode,
+    InteractionTracker, VisualInteractionSource,
+};
+use winrt::windows::ui::composition::{
+    CompositionAnimation, CompositionClip, CompositionObject, Compositor, ContainerVisual,
+    ExpressionAnimation, ICompositionAnimation, ICompositionObject, IVisual, InsetClip, Visual,
+};
+use winrt::windows::ui::input::PointerPoint;
+use winrt::{ComInterface, FastHString};
+
+use nresult::NResult;
+use scroll_model::{velocity_to_reach, ScrollCommand, ScrollConfig, ScrollModel};
+use window::run_catch_panic;
+use window_events::{ElementState, TouchPhase, WindowEvent};
+
+/// `IInteractionTrackerOwner`.
+const IID_IINTERACTION_TRACKER_OWNER: IID = GUID {
+    Data1: 0x2A8E_8CB1,
+    Data2: 0x1000,
+    Data3: 0x4416,
+    Data4: [0x83, 0x63, 0xCC, 0x27, 0xFB, 0x87, 0x73, 0x08],
+};
+
+/// Inertia that already comes to rest this close to a snap point is left alone, in pixels.
+const SNAP_TOLERANCE: f64 = 0.5;
+
+type Callback = unsafe extern "system" fn(*mut IInspectable, *mut c_void, *mut c_void) -> HRESULT;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct InteractionTrackerOwnerVtbl {
+    parent: IInspectableVtbl,
+    CustomAnimationStateEntered: Callback,
+    IdleStateEntered: Callback,
+    InertiaStateEntered: Callback,
+    InteractingStateEntered: Callback,
+    RequestIgnored: Callback,
+    ValuesChanged: Callback,
+}
+
+/// An `IInteractionTrackerOwner` that snaps inertia to the snap points of the scroll model and
+/// keeps the bounds of the tracker in step with its scale.
+///
+/// The tracker calls its owner on the thread of the compositor, which is the thread of the
+/// scroll viewer.
+#[repr(C)]
+struct TrackerOwner {
+    // Must come first: COM callers see a pointer to this object as a pointer to the vtable.
+    vtable: *const InteractionTrackerOwnerVtbl,
+    ref_count: AtomicUsize,
+    model: Weak<RefCell<ScrollModel>>,
+}
+
+static TRACKER_OWNER_VTBL: InteractionTrackerOwnerVtbl = InteractionTrackerOwnerVtbl {
+    parent: IInspectableVtbl {
+        parent: IUnknownVtbl {
+            QueryInterface: query_interface,
+            AddRef: add_ref,
+            Release: release,
+        },
+        GetIids: get_iids,
+        GetRuntimeClassName: get_runtime_class_name,
+        GetTrustLevel: get_trust_level,
+    },
+    CustomAnimationStateEntered: ignore,
+    IdleStateEntered: resting_state_entered,
+    InertiaStateEntered: inertia_state_entered,
+    InteractingStateEntered: resting_state_entered,
+    RequestIgnored: ignore,
+    ValuesChanged: values_changed,
+};
+
+fn to_vector3(value: (f64, f64)) -> Vector3 {
+    Vector3 {
+        X: value.0 as f32,
+        Y: value.1 as f32,
+        Z: 0.0,
+    }
+}
+
+fn from_vector3(value: Vector3) -> (f64, f64) {
+    (value.X as f64, value.Y as f64)
+}
+
+/// Wraps an interface pointer that the caller keeps its reference to.
+unsafe fn borrow_tracker(sender: *mut c_void) -> IInteractionTracker {
+    (*(sender as *mut IUnknown)).AddRef();
+    IInteractionTracker::wrap_com(sender as *mut _)
+}
+
+/// Brings the model up to date with a tracker that moved on its own, ending the model's inertia.
+fn sync_model(model: &mut ScrollModel, tracker: &IInteractionTracker) -> NResult<()> {
+    let position = from_vector3(tracker.get_position()?);
+    let scale = tracker.get_scale()? as f64;
+    model.sync(position, scale);
+    Ok(())
+}
+
+/// Runs a callback of the owner with its model, unless the scroll viewer is gone.
+unsafe fn with_model<F>(this: *mut IInspectable, f: F) -> HRESULT
+where
+    F: FnOnce(&mut ScrollModel) -> NResult<()>,
+{
+    run_catch_panic(
+        E_UNEXPECTED,
+        panic::AssertUnwindSafe(|| {
+            let owner = &*(this as *const TrackerOwner);
+            let model = match owner.model.upgrade() {
+                Some(model) => model,
+                None => return S_OK,
+            };
+            let mut model = match model.try_borrow_mut() {
+                Ok(model) => model,
+                Err(_) => return S_OK,
+            };
+            match f(&mut model) {
+                Ok(()) => S_OK,
+                Err(_) => E_UNEXPECTED,
+            }
+        }),
+    )
+}
+
+unsafe extern "system" fn ignore(
+    _this: *mut IInspectable,
+    _sender: *mut c_void,
+    _args: *mut c_void,
+) -> HRESULT {
+    S_OK
+}
+
+/// The tracker went idle, or the user took hold of it: whatever inertia the model expected is
+/// over.
+unsafe extern "system" fn resting_state_entered(
+    this: *mut IInspectable,
+    sender: *mut c_void,
+    _args: *mut c_void,
+) -> HRESULT {
+    with_model(this, |model| sync_model(model, &borrow_tracker(sender)))
+}
+
+/// Redirects inertia to the snap point closest to where it would come to rest.
+unsafe extern "system" fn inertia_state_entered(
+    this: *mut IInspectable,
+    sender: *mut c_void,
+    args: *mut c_void,
+) -> HRESULT {
+    with_model(this, |model| {
+        let tracker = borrow_tracker(sender);
+        (*(args as *mut IUnknown)).AddRef();
+        let args = IInteractionTrackerInertiaStateEnteredArgs::wrap_com(args as *mut _);
+        sync_model(model, &tracker)?;
+        let natural = from_vector3(args.get_natural_resting_position()?);
+        let target = model.snap(natural);
+        if (target.0 - natural.0).abs() > SNAP_TOLERANCE
+            || (target.1 - natural.1).abs() > SNAP_TOLERANCE
+        {
+            // Inertia comes to rest proportionally to its velocity, so the difference in velocity
+            // moves the resting position by the difference in position.
+            let correction = (
+                velocity_to_reach(natural.0, target.0),
+                velocity_to_reach(natural.1, target.1),
+            );
+            tracker.try_update_position_with_additional_velocity(to_vector3(correction))?;
+        }
+        model.animate_to(target);
+        Ok(())
+    })
+}
+
+/// Follows changes of scale, which change how far the content can scroll.
+unsafe extern "system" fn values_changed(
+    this: *mut IInspectable,
+    sender: *mut c_void,
+    args: *mut c_void,
+) -> HRESULT {
+    with_model(this, |model| {
+        (*(args as *mut IUnknown)).AddRef();
+        let args = IInteractionTrackerValuesChangedArgs::wrap_com(args as *mut _);
+        let scale = args.get_scale()? as f64;
+        if (scale - model.scale()).abs() > ::std::f64::EPSILON {
+            let tracker = borrow_tracker(sender);
+            sync_model(model, &tracker)?;
+            tracker.set_max_position(to_vector3(model.max_position()))?;
+        }
+        Ok(())
+    })
+}
+
+unsafe extern "system" fn query_interface(
+    this: *mut IUnknown,
+    riid: REFIID,
+    object: *mut *mut c_void,
+) -> HRESULT {
+    let riid = &*riid;
+    if IsEqualGUID(riid, &IUnknown::uuidof())
+        || IsEqualGUID(riid, &IInspectable::uuidof())
+        || IsEqualGUID(riid, &IID_IINTERACTION_TRACKER_OWNER)
+    {
+        add_ref(this);
+        *object = this as *mut c_void;
+        S_OK
+    } else {
+        *object = ptr::null_mut();
+        E_NOINTERFACE
+    }
+}
+
+unsafe extern "system" fn add_ref(this: *mut IUnknown) -> ULONG {
+    let owner = &*(this as *const TrackerOwner);
+    (owner.ref_count.fetch_add(1, Ordering::Relaxed) + 1) as ULONG
+}
+
+unsafe extern "system" fn release(this: *mut IUnknown) -> ULONG {
+    let count = {
+        let owner = &*(this as *const TrackerOwner);
+        owner.ref_count.fetch_sub(1, Ordering::Release) - 1
+    };
+    if count == 0 {
+        mem::drop(Box::from_raw(this as *mut TrackerOwner));
+    }
+    count as ULONG
+}
+
+unsafe extern "system" fn get_iids(
+    _this: *mut IInspectable,
+    count: *mut ULONG,
+    iids: *mut *mut IID,
+) -> HRESULT {
+    *count = 0;
+    *iids = ptr::null_mut();
+    S_OK
+}
+
+unsafe extern "system" fn get_runtime_class_name(
+    _this: *mut IInspectable,
+    name: *mut HSTRING,
+) -> HRESULT {
+    *name = ptr::null_mut();
+    S_OK
+}
+
+unsafe extern "system" fn get_trust_level(
+    _this: *mut IInspectable,
+    level: *mut TrustLevel,
+) -> HRESULT {
+    *level = BaseTrust;
+    S_OK
+}
+
+/// A container that scrolls and zooms its content with an `InteractionTracker`.
+///
+/// Touch input is handed to the compositor with `redirect_pointer`, which then pans, zooms and
+/// applies inertia on its own thread. Mouse wheel and keyboard scrolling arrive as window events
+/// and are passed in through `handle_window_event`; they glide with the same inertia. Inertia
+/// comes to rest on the snap points of the `ScrollConfig`, as in `ScrollModel`.
+pub struct ScrollViewer {
+    viewport: ContainerVisual,
+    content: ContainerVisual,
+    tracker: InteractionTracker,
+    source: VisualInteractionSource,
+    model: Rc<RefCell<ScrollModel>>,
+}
+
+impl ScrollViewer {
+    /// Creates a viewport of size `viewport` that shows content of size `content`. Children added
+    /// to `content()` scroll.
+    pub fn new(
+        compositor: &Compositor,
+        config: ScrollConfig,
+        viewport: (f64, f64),
+        content: (f64, f64),
+    ) -> NResult<Self> {
+        let model = Rc::new(RefCell::new(ScrollModel::new(config, viewport, content)));
+
+        let viewport_visual = compositor.create_container_visual()??;
+        let content_visual = compositor.create_container_visual()??;
+        let clip = compositor.create_inset_clip()??;
+        unsafe {
+            let ivisual = viewport_visual.query_interface::<IVisual>()?;
+            ivisual.set_clip(&transmute::<InsetClip, CompositionClip>(clip))?;
+            viewport_visual
+                .get_children()??
+                .insert_at_top(&transmute::<ContainerVisual, Visual>(content_visual.clone()))?;
+        }
+
+        let owner = Box::into_raw(Box::new(TrackerOwner {
+            vtable: &TRACKER_OWNER_VTBL,
+            ref_count: AtomicUsize::new(1),
+            model: Rc::downgrade(&model),
+        }));
+        // Takes over the reference of the new object.
+        let owner = unsafe { IInteractionTrackerOwner::wrap_com(owner as *mut _) };
+        let tracker = InteractionTracker::create_with_owner(compositor, &owner)??;
+
+        let source = unsafe {
+            VisualInteractionSource::create(&transmute::<ContainerVisual, Visual>(
+                viewport_visual.clone(),
+            ))??
+        };
+        source.set_position_x_source_mode(InteractionSourceMode::EnabledWithInertia)?;
+        source.set_position_y_source_mode(InteractionSourceMode::EnabledWithInertia)?;
+        tracker
+            .get_interaction_sources()??
+            .add(&source.query_interface::<ICompositionInteractionSource>()?)?;
+
+        // The content follows the tracker without a round trip through this thread.
+        let tracker_object =
+            unsafe { transmute::<InteractionTracker, CompositionObject>(tracker.clone()) };
+        let content_object = content_visual.query_interface::<ICompositionObject>()?;
+        for &(property, expression) in &[
+            ("Offset", "-tracker.Position"),
+            ("Scale", "Vector3(tracker.Scale, tracker.Scale, 1.0)"),
+        ] {
+            let animation = compositor
+                .create_expression_animation_with_expression(&FastHString::new(expression))??;
+            animation
+                .query_interface::<ICompositionAnimation>()?
+                .set_reference_parameter(&FastHString::new("tracker"), &tracker_object)?;
+            unsafe {
+                content_object.start_animation(
+                    &FastHString::new(property),
+                    &transmute::<ExpressionAnimation, CompositionAnimation>(animation),
+                )?;
+            }
+        }
+
+        let viewer = ScrollViewer {
+            viewport: viewport_visual,
+            content: content_visual,
+            tracker,
+            source,
+            model,
+        };
+        viewer.update_bounds()?;
+        Ok(viewer)
+    }
+
+    /// The visual to insert into the tree. It clips the content to the viewport.
+    #[inline]
+    pub fn visual(&self) -> &ContainerVisual {
+        &self.viewport
+    }
+
+    /// The visual that scrolls. Add the scrolled visuals to its children.
+    #[inline]
+    pub fn content(&self) -> &ContainerVisual {
+        &self.content
+    }
+
+    pub fn config(&self) -> ScrollConfig {
+        self.model.borrow().config().clone()
+    }
+
+    /// Applies the sizes and scale limits of the model to the visuals and the tracker.
+    fn update_bounds(&self) -> NResult<()> {
+        let model = self.model.borrow();
+        let config = model.config();
+        let (width, height) = model.viewport();
+        self.viewport.query_interface::<IVisual>()?.set_size(Vector2 {
+            X: width as f32,
+            Y: height as f32,
+        })?;
+        let (width, height) = model.content();
+        self.content.query_interface::<IVisual>()?.set_size(Vector2 {
+            X: width as f32,
+            Y: height as f32,
+        })?;
+        self.tracker.set_min_scale(config.min_scale as f32)?;
+        self.tracker.set_max_scale(config.max_scale as f32)?;
+        self.tracker.set_min_position(to_vector3((0.0, 0.0)))?;
+        self.tracker.set_max_position(to_vector3(model.max_position()))?;
+        self.source.set_scale_source_mode(if config.max_scale > config.min_scale {
+            InteractionSourceMode::EnabledWithInertia
+        } else {
+            InteractionSourceMode::Disabled
+        })?;
+        Ok(())
+    }
+
+    pub fn set_viewport_size(&self, viewport: (f64, f64)) -> NResult<()> {
+        self.model.borrow_mut().set_viewport(viewport);
+        self.update_bounds()
+    }
+
+    pub fn set_content_size(&self, content: (f64, f64)) -> NResult<()> {
+        self.model.borrow_mut().set_content(content);
+        self.update_bounds()
+    }
+
+    /// The current position of the viewport over the scaled content.
+    pub fn position(&self) -> NResult<(f64, f64)> {
+        Ok(from_vector3(self.tracker.get_position()?))
+    }
+
+    pub fn scale(&self) -> NResult<f64> {
+        Ok(self.tracker.get_scale()? as f64)
+    }
+
+    /// Jumps to a position, stopping inertia.
+    pub fn scroll_to(&self, position: (f64, f64)) -> NResult<()> {
+        let mut model = self.model.borrow_mut();
+        model.scroll_to(position);
+        self.tracker
+            .try_update_position(to_vector3(model.position()))?;
+        Ok(())
+    }
+
+    /// Glides to a position with the curve of inertia. If inertia is in progress, it is
+    /// redirected rather than restarted.
+    pub fn glide_to(&self, target: (f64, f64)) -> NResult<()> {
+        let mut model = self.model.borrow_mut();
+        if !model.is_inertial() {
+            sync_model(&mut model, &self.tracker)?;
+        }
+        let target = model.clamp(target);
+        let from = model.inertia_target().unwrap_or(model.position());
+        let velocity = (
+            velocity_to_reach(from.0, target.0),
+            velocity_to_reach(from.1, target.1),
+        );
+        self.tracker
+            .try_update_position_with_additional_velocity(to_vector3(velocity))?;
+        model.animate_to(target);
+        Ok(())
+    }
+
+    /// Zooms to `scale` around the point `center` of the viewport.
+    pub fn zoom_to(&self, scale: f64, center: (f64, f64)) -> NResult<()> {
+        self.tracker
+            .try_update_scale(scale as f32, to_vector3(center))?;
+        Ok(())
+    }
+
+    /// Hands a touch or pen pointer that went down on the viewport to the compositor, which
+    /// pans and zooms with it from then on. The pointer stops producing window events.
+    pub fn redirect_pointer(&self, pointer_id: u32) -> NResult<()> {
+        let point = PointerPoint::get_current_point(pointer_id)??;
+        self.source.try_redirect_for_manipulation(&point)?;
+        Ok(())
+    }
+
+    /// Scrolls for mouse wheel and keyboard events, and redirects touch and pen pointers as they
+    /// go down. Returns whether the event was used.
+    ///
+    /// Pointers are redirected wherever they go down; only pass events on to the viewer when
+    /// they happen over it.
+    pub fn handle_window_event(&self, event: &WindowEvent) -> NResult<bool> {
+        match *event {
+            WindowEvent::MouseWheel {
+                delta, modifiers, ..
+            } => {
+                let target = {
+                    let mut model = self.model.borrow_mut();
+                    if !model.is_inertial() {
+                        sync_model(&mut model, &self.tracker)?;
+                    }
+                    match model.wheel_target(delta, modifiers) {
+                        Some(target) => Ok(target),
+                        None => {
+                            let (width, height) = model.viewport();
+                            Err((model.wheel_zoom(delta), (width / 2.0, height / 2.0)))
+                        }
+                    }
+                };
+                match target {
+                    Ok(target) => self.glide_to(target)?,
+                    Err((scale, center)) => self.zoom_to(scale, center)?,
+                }
+                Ok(true)
+            }
+            WindowEvent::KeyboardInput { ref input, .. } if input.state == ElementState::Pressed => {
+                let command = input
+                    .virtual_keycode
+                    .and_then(|key| ScrollCommand::from_key(key, input.modifiers));
+                let command = match command {
+                    Some(command) => command,
+                    None => return Ok(false),
+                };
+                let target = {
+                    let mut model = self.model.borrow_mut();
+                    if !model.is_inertial() {
+                        sync_model(&mut model, &self.tracker)?;
+                    }
+                    model.command_target(command)
+                };
+                self.glide_to(target)?;
+                Ok(true)
+            }
+            WindowEvent::Touch(ref touch) if touch.phase == TouchPhase::Started => {
+                self.redirect_pointer(touch.id as u32)?;
+                Ok(true)
+            }
+            WindowEvent::Pen(ref pen) if pen.phase == TouchPhase::Started => {
+                self.redirect_pointer(pen.id as u32)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
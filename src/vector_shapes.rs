@@ -0,0 +1,624 @@
+use std::cell::RefCell;
+use std::mem::transmute;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{mem, ptr};
+use winapi::ctypes::c_void;
+use winapi::shared::guiddef::{IsEqualGUID, GUID, IID, REFIID};
+use winapi::shared::minwindef::ULONG;
+use winapi::shared::ntdef::HRESULT;
+use winapi::shared::winerror::{E_NOINTERFACE, E_POINTER, SUCCEEDED, S_OK};
+use winapi::um::d2d1::{
+    D2D1CreateFactory, ID2D1Factory, ID2D1Geometry, ID2D1GeometrySink, ID2D1PathGeometry,
+    D2D1_BEZIER_SEGMENT, D2D1_FACTORY_TYPE_MULTI_THREADED, D2D1_FIGURE_BEGIN_FILLED,
+    D2D1_FIGURE_END_CLOSED, D2D1_FIGURE_END_OPEN, D2D1_FILL_MODE_ALTERNATE, D2D1_FILL_MODE_WINDING,
+    D2D1_POINT_2F, D2D1_QUADRATIC_BEZIER_SEGMENT,
+};
+use winapi::um::unknwnbase::{IUnknown, IUnknownVtbl};
+use winapi::winrt::hstring::HSTRING;
+use winapi::winrt::inspectable::{BaseTrust, IInspectable, IInspectableVtbl, TrustLevel};
+use winapi::Interface;
+use winrt::windows::foundation::numerics::Vector2;
+use winrt::windows::graphics::IGeometrySource2D;
+use winrt::windows::ui::composition::{
+    CompositionBrush, CompositionColorBrush, CompositionEllipseGeometry, CompositionGeometry,
+    CompositionLineGeometry, CompositionPath, CompositionPathGeometry,
+    CompositionRectangleGeometry, CompositionRoundedRectangleGeometry, CompositionShape,
+    CompositionSpriteShape, CompositionStrokeCap, CompositionStrokeLineJoin, Compositor,
+    ICompositionShape, IVisual, ShapeVisual,
+};
+use winrt::windows::ui::Color;
+
+use bitmap::Rgba;
+use nresult::{check_hresult, NResult};
+use svg_path::{FillRule, PathData, PathError, PathSegment, Point};
+
+/// `IGeometrySource2D`.
+const IID_IGEOMETRY_SOURCE_2D: IID = GUID {
+    Data1: 0xCAFF_7902,
+    Data2: 0x670C,
+    Data3: 0x4181,
+    Data4: [0xA6, 0x24, 0xDA, 0x97, 0x72, 0x03, 0xB8, 0x45],
+};
+
+/// `IGeometrySource2DInterop`, from windows.graphics.interop.h.
+const IID_IGEOMETRY_SOURCE_2D_INTEROP: IID = GUID {
+    Data1: 0x0657_AF73,
+    Data2: 0x53FD,
+    Data3: 0x47CF,
+    Data4: [0x84, 0xFF, 0xC8, 0x49, 0x2D, 0x2A, 0x80, 0xA3],
+};
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct GeometrySource2DInteropVtbl {
+    parent: IUnknownVtbl,
+    GetGeometry: unsafe extern "system" fn(*mut IUnknown, *mut *mut ID2D1Geometry) -> HRESULT,
+    TryGetGeometryUsingFactory: unsafe extern "system" fn(
+        *mut IUnknown,
+        *mut ID2D1Factory,
+        *mut *mut ID2D1Geometry,
+    ) -> HRESULT,
+}
+
+/// An `IGeometrySource2D` that hands a Direct2D geometry to the compositor, which is how
+/// `CompositionPath` takes its shape.
+///
+/// The object implements two interfaces, so it has two vtable pointers: `IGeometrySource2D`
+/// pointers point at the first, `IGeometrySource2DInterop` pointers at the second.
+#[repr(C)]
+struct GeometrySource {
+    // Must come first: COM callers see a pointer to this object as a pointer to the vtable.
+    vtable: *const IInspectableVtbl,
+    interop_vtable: *const GeometrySource2DInteropVtbl,
+    ref_count: AtomicUsize,
+    geometry: *mut ID2D1Geometry,
+}
+
+impl Drop for GeometrySource {
+    fn drop(&mut self) {
+        unsafe {
+            (*self.geometry).Release();
+        }
+    }
+}
+
+static GEOMETRY_SOURCE_VTBL: IInspectableVtbl = IInspectableVtbl {
+    parent: IUnknownVtbl {
+        QueryInterface: query_interface,
+        AddRef: add_ref,
+        Release: release,
+    },
+    GetIids: get_iids,
+    GetRuntimeClassName: get_runtime_class_name,
+    GetTrustLevel: get_trust_level,
+};
+
+static GEOMETRY_SOURCE_INTEROP_VTBL: GeometrySource2DInteropVtbl = GeometrySource2DInteropVtbl {
+    parent: IUnknownVtbl {
+        QueryInterface: interop_query_interface,
+        AddRef: interop_add_ref,
+        Release: interop_release,
+    },
+    GetGeometry: get_geometry,
+    TryGetGeometryUsingFactory: try_get_geometry_using_factory,
+};
+
+/// The object behind an `IGeometrySource2DInterop` pointer.
+unsafe fn from_interop(this: *mut IUnknown) -> *mut IUnknown {
+    (this as *mut *const GeometrySource2DInteropVtbl).offset(-1) as *mut IUnknown
+}
+
+unsafe extern "system" fn query_interface(
+    this: *mut IUnknown,
+    riid: REFIID,
+    object: *mut *mut c_void,
+) -> HRESULT {
+    let riid = &*riid;
+    let source = &mut *(this as *mut GeometrySource);
+    if IsEqualGUID(riid, &IUnknown::uuidof())
+        || IsEqualGUID(riid, &IInspectable::uuidof())
+        || IsEqualGUID(riid, &IID_IGEOMETRY_SOURCE_2D)
+    {
+        add_ref(this);
+        *object = this as *mut c_void;
+        S_OK
+    } else if IsEqualGUID(riid, &IID_IGEOMETRY_SOURCE_2D_INTEROP) {
+        add_ref(this);
+        *object = &mut source.interop_vtable as *mut _ as *mut c_void;
+        S_OK
+    } else {
+        *object = ptr::null_mut();
+        E_NOINTERFACE
+    }
+}
+
+unsafe extern "system" fn add_ref(this: *mut IUnknown) -> ULONG {
+    let source = &*(this as *const GeometrySource);
+    (source.ref_count.fetch_add(1, Ordering::Relaxed) + 1) as ULONG
+}
+
+unsafe extern "system" fn release(this: *mut IUnknown) -> ULONG {
+    let count = {
+        let source = &*(this as *const GeometrySource);
+        source.ref_count.fetch_sub(1, Ordering::Release) - 1
+    };
+    if count == 0 {
+        mem::drop(Box::from_raw(this as *mut GeometrySource));
+    }
+    count as ULONG
+}
+
+unsafe extern "system" fn get_iids(
+    _this: *mut IInspectable,
+    count: *mut ULONG,
+    iids: *mut *mut IID,
+) -> HRESULT {
+    *count = 0;
+    *iids = ptr::null_mut();
+    S_OK
+}
+
+unsafe extern "system" fn get_runtime_class_name(
+    _this: *mut IInspectable,
+    name: *mut HSTRING,
+) -> HRESULT {
+    *name = ptr::null_mut();
+    S_OK
+}
+
+unsafe extern "system" fn get_trust_level(
+    _this: *mut IInspectable,
+    level: *mut TrustLevel,
+) -> HRESULT {
+    *level = BaseTrust;
+    S_OK
+}
+
+unsafe extern "system" fn interop_query_interface(
+    this: *mut IUnknown,
+    riid: REFIID,
+    object: *mut *mut c_void,
+) -> HRESULT {
+    query_interface(from_interop(this), riid, object)
+}
+
+unsafe extern "system" fn interop_add_ref(this: *mut IUnknown) -> ULONG {
+    add_ref(from_interop(this))
+}
+
+unsafe extern "system" fn interop_release(this: *mut IUnknown) -> ULONG {
+    release(from_interop(this))
+}
+
+unsafe extern "system" fn get_geometry(
+    this: *mut IUnknown,
+    value: *mut *mut ID2D1Geometry,
+) -> HRESULT {
+    if value.is_null() {
+        return E_POINTER;
+    }
+    let source = &*(from_interop(this) as *const GeometrySource);
+    (*source.geometry).AddRef();
+    *value = source.geometry;
+    S_OK
+}
+
+/// Only hands out the geometry to users of the factory that made it; other factories get none.
+unsafe extern "system" fn try_get_geometry_using_factory(
+    this: *mut IUnknown,
+    factory: *mut ID2D1Factory,
+    value: *mut *mut ID2D1Geometry,
+) -> HRESULT {
+    if value.is_null() {
+        return E_POINTER;
+    }
+    let source = &*(from_interop(this) as *const GeometrySource);
+    let mut own_factory: *mut ID2D1Factory = ptr::null_mut();
+    (*source.geometry).GetFactory(&mut own_factory);
+    if own_factory.is_null() {
+        *value = ptr::null_mut();
+        return S_OK;
+    }
+    (*own_factory).Release();
+    if own_factory == factory {
+        (*source.geometry).AddRef();
+        *value = source.geometry;
+    } else {
+        *value = ptr::null_mut();
+    }
+    S_OK
+}
+
+/// A Direct2D factory, released with the thread that created it.
+struct Factory(*mut ID2D1Factory);
+
+impl Drop for Factory {
+    fn drop(&mut self) {
+        unsafe {
+            (*self.0).Release();
+        }
+    }
+}
+
+thread_local! {
+    // Multithreaded, as the compositor may read the geometries it makes from its own threads.
+    static FACTORY: RefCell<Option<Factory>> = RefCell::new(None);
+}
+
+/// The Direct2D factory of this thread, created on first use.
+fn factory() -> NResult<*mut ID2D1Factory> {
+    FACTORY.with(|factory| {
+        let mut factory = factory.borrow_mut();
+        if let Some(ref factory) = *factory {
+            return Ok(factory.0);
+        }
+        let mut raw: *mut ID2D1Factory = ptr::null_mut();
+        unsafe {
+            check_hresult(D2D1CreateFactory(
+                D2D1_FACTORY_TYPE_MULTI_THREADED,
+                &ID2D1Factory::uuidof(),
+                ptr::null(),
+                &mut raw as *mut _ as *mut *mut c_void,
+            ))?;
+        }
+        *factory = Some(Factory(raw));
+        Ok(raw)
+    })
+}
+
+fn to_d2d_point(point: Point) -> D2D1_POINT_2F {
+    D2D1_POINT_2F {
+        x: point.x as f32,
+        y: point.y as f32,
+    }
+}
+
+/// Writes the figures of `path` to `sink`. Figures that aren't closed stay open, so they're
+/// filled but not stroked back to their start.
+unsafe fn stream_path(sink: &ID2D1GeometrySink, path: &PathData, fill_rule: FillRule) {
+    sink.SetFillMode(match fill_rule {
+        FillRule::NonZero => D2D1_FILL_MODE_WINDING,
+        FillRule::EvenOdd => D2D1_FILL_MODE_ALTERNATE,
+    });
+    let mut in_figure = false;
+    for segment in path.segments() {
+        match *segment {
+            PathSegment::MoveTo(point) => {
+                if in_figure {
+                    sink.EndFigure(D2D1_FIGURE_END_OPEN);
+                }
+                sink.BeginFigure(to_d2d_point(point), D2D1_FIGURE_BEGIN_FILLED);
+                in_figure = true;
+            }
+            PathSegment::LineTo(point) => sink.AddLine(to_d2d_point(point)),
+            PathSegment::QuadTo(control, point) => {
+                sink.AddQuadraticBezier(&D2D1_QUADRATIC_BEZIER_SEGMENT {
+                    point1: to_d2d_point(control),
+                    point2: to_d2d_point(point),
+                })
+            }
+            PathSegment::CubicTo(control1, control2, point) => {
+                sink.AddBezier(&D2D1_BEZIER_SEGMENT {
+                    point1: to_d2d_point(control1),
+                    point2: to_d2d_point(control2),
+                    point3: to_d2d_point(point),
+                })
+            }
+            PathSegment::Close => {
+                sink.EndFigure(D2D1_FIGURE_END_CLOSED);
+                in_figure = false;
+            }
+        }
+    }
+    if in_figure {
+        sink.EndFigure(D2D1_FIGURE_END_OPEN);
+    }
+}
+
+/// Builds a Direct2D path geometry from `path`. The caller owns the returned reference.
+fn create_d2d_geometry(path: &PathData, fill_rule: FillRule) -> NResult<*mut ID2D1Geometry> {
+    let factory = factory()?;
+    unsafe {
+        let mut geometry: *mut ID2D1PathGeometry = ptr::null_mut();
+        check_hresult((*factory).CreatePathGeometry(&mut geometry))?;
+        let mut sink: *mut ID2D1GeometrySink = ptr::null_mut();
+        let mut hr = (*geometry).Open(&mut sink);
+        if SUCCEEDED(hr) {
+            stream_path(&*sink, path, fill_rule);
+            hr = (*sink).Close();
+            (*sink).Release();
+        }
+        if let Err(error) = check_hresult(hr) {
+            (*geometry).Release();
+            return Err(error);
+        }
+        Ok(geometry as *mut ID2D1Geometry)
+    }
+}
+
+/// Creates a `CompositionPath` with the shape of `path`.
+pub fn create_path(path: &PathData, fill_rule: FillRule) -> NResult<CompositionPath> {
+    let source = Box::into_raw(Box::new(GeometrySource {
+        vtable: &GEOMETRY_SOURCE_VTBL,
+        interop_vtable: &GEOMETRY_SOURCE_INTEROP_VTBL,
+        ref_count: AtomicUsize::new(1),
+        geometry: create_d2d_geometry(path, fill_rule)?,
+    }));
+    // Takes over the reference of the new object.
+    let source = unsafe { IGeometrySource2D::wrap_com(source as *mut _) };
+    Ok(CompositionPath::create(&source)??)
+}
+
+/// The outline of a shape, in the coordinates of the shape.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShapeGeometry {
+    /// A rectangle from the origin.
+    Rectangle {
+        size: (f32, f32),
+    },
+    /// A rectangle from the origin with elliptical corners.
+    RoundedRectangle {
+        size: (f32, f32),
+        corner_radius: (f32, f32),
+    },
+    Ellipse {
+        center: (f32, f32),
+        radius: (f32, f32),
+    },
+    Line {
+        start: (f32, f32),
+        end: (f32, f32),
+    },
+    Path {
+        data: PathData,
+        fill_rule: FillRule,
+    },
+}
+
+impl ShapeGeometry {
+    /// A path from SVG path data, filled with the non-zero rule like SVG does by default.
+    pub fn svg_path(data: &str) -> Result<Self, PathError> {
+        Ok(ShapeGeometry::Path {
+            data: PathData::parse(data)?,
+            fill_rule: FillRule::NonZero,
+        })
+    }
+}
+
+/// The shape of the ends of a stroke or of its dashes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StrokeCap {
+    /// Ends exactly at the end point.
+    Flat,
+    /// A square half the thickness past the end point.
+    Square,
+    Round,
+    Triangle,
+}
+
+impl StrokeCap {
+    fn to_composition(self) -> CompositionStrokeCap {
+        match self {
+            StrokeCap::Flat => CompositionStrokeCap::Flat,
+            StrokeCap::Square => CompositionStrokeCap::Square,
+            StrokeCap::Round => CompositionStrokeCap::Round,
+            StrokeCap::Triangle => CompositionStrokeCap::Triangle,
+        }
+    }
+}
+
+/// The shape of the corners of a stroke.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LineJoin {
+    /// A sharp corner, beveled where it would reach past the miter limit.
+    Miter,
+    Bevel,
+    Round,
+    /// A sharp corner, cut off at the miter limit.
+    MiterOrBevel,
+}
+
+impl LineJoin {
+    fn to_composition(self) -> CompositionStrokeLineJoin {
+        match self {
+            LineJoin::Miter => CompositionStrokeLineJoin::Miter,
+            LineJoin::Bevel => CompositionStrokeLineJoin::Bevel,
+            LineJoin::Round => CompositionStrokeLineJoin::Round,
+            LineJoin::MiterOrBevel => CompositionStrokeLineJoin::MiterOrBevel,
+        }
+    }
+}
+
+/// How the outline of a shape is drawn.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stroke {
+    pub color: Rgba,
+    pub thickness: f32,
+    /// Alternating lengths of dashes and gaps, in multiples of the thickness. Empty for a solid
+    /// stroke.
+    pub dash_array: Vec<f32>,
+    /// How far into the dash array the stroke starts.
+    pub dash_offset: f32,
+    pub start_cap: StrokeCap,
+    pub end_cap: StrokeCap,
+    pub dash_cap: StrokeCap,
+    pub line_join: LineJoin,
+    /// How far sharp corners may reach past the stroke, in multiples of half the thickness.
+    pub miter_limit: f32,
+}
+
+impl Stroke {
+    /// A solid stroke with flat ends and mitered corners.
+    pub fn new(color: Rgba, thickness: f32) -> Self {
+        Stroke {
+            color,
+            thickness,
+            dash_array: Vec::new(),
+            dash_offset: 0.0,
+            start_cap: StrokeCap::Flat,
+            end_cap: StrokeCap::Flat,
+            dash_cap: StrokeCap::Square,
+            line_join: LineJoin::Miter,
+            miter_limit: 1.0,
+        }
+    }
+}
+
+/// How a shape is painted. Shapes without a fill or a stroke are invisible.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ShapeStyle {
+    pub fill: Option<Rgba>,
+    pub stroke: Option<Stroke>,
+}
+
+fn to_vector2(value: (f32, f32)) -> Vector2 {
+    Vector2 {
+        X: value.0,
+        Y: value.1,
+    }
+}
+
+fn create_color_brush(compositor: &Compositor, color: Rgba) -> NResult<CompositionBrush> {
+    let brush = compositor.create_color_brush_with_color(Color {
+        R: color.r,
+        G: color.g,
+        B: color.b,
+        A: color.a,
+    })??;
+    Ok(unsafe { transmute::<CompositionColorBrush, CompositionBrush>(brush) })
+}
+
+/// Creates the composition geometry of `geometry`.
+pub fn create_geometry(
+    compositor: &Compositor,
+    geometry: &ShapeGeometry,
+) -> NResult<CompositionGeometry> {
+    unsafe {
+        Ok(match *geometry {
+            ShapeGeometry::Rectangle { size } => {
+                let rectangle = compositor.create_rectangle_geometry()??;
+                rectangle.set_size(to_vector2(size))?;
+                transmute::<CompositionRectangleGeometry, CompositionGeometry>(rectangle)
+            }
+            ShapeGeometry::RoundedRectangle {
+                size,
+                corner_radius,
+            } => {
+                let rectangle = compositor.create_rounded_rectangle_geometry()??;
+                rectangle.set_size(to_vector2(size))?;
+                rectangle.set_corner_radius(to_vector2(corner_radius))?;
+                transmute::<CompositionRoundedRectangleGeometry, CompositionGeometry>(rectangle)
+            }
+            ShapeGeometry::Ellipse { center, radius } => {
+                let ellipse = compositor.create_ellipse_geometry()??;
+                ellipse.set_center(to_vector2(center))?;
+                ellipse.set_radius(to_vector2(radius))?;
+                transmute::<CompositionEllipseGeometry, CompositionGeometry>(ellipse)
+            }
+            ShapeGeometry::Line { start, end } => {
+                let line = compositor.create_line_geometry()??;
+                line.set_start(to_vector2(start))?;
+                line.set_end(to_vector2(end))?;
+                transmute::<CompositionLineGeometry, CompositionGeometry>(line)
+            }
+            ShapeGeometry::Path {
+                ref data,
+                fill_rule,
+            } => {
+                let path = create_path(data, fill_rule)?;
+                let geometry = compositor.create_path_geometry_with_path(&path)??;
+                transmute::<CompositionPathGeometry, CompositionGeometry>(geometry)
+            }
+        })
+    }
+}
+
+/// Creates a sprite shape that paints `geometry` with `style`.
+pub fn create_sprite_shape(
+    compositor: &Compositor,
+    geometry: &ShapeGeometry,
+    style: &ShapeStyle,
+) -> NResult<CompositionSpriteShape> {
+    let geometry = create_geometry(compositor, geometry)?;
+    let shape = compositor.create_sprite_shape_with_geometry(&geometry)??;
+    if let Some(fill) = style.fill {
+        shape.set_fill_brush(&create_color_brush(compositor, fill)?)?;
+    }
+    if let Some(ref stroke) = style.stroke {
+        shape.set_stroke_brush(&create_color_brush(compositor, stroke.color)?)?;
+        shape.set_stroke_thickness(stroke.thickness)?;
+        shape.set_stroke_start_cap(stroke.start_cap.to_composition())?;
+        shape.set_stroke_end_cap(stroke.end_cap.to_composition())?;
+        shape.set_stroke_dash_cap(stroke.dash_cap.to_composition())?;
+        shape.set_stroke_line_join(stroke.line_join.to_composition())?;
+        shape.set_stroke_miter_limit(stroke.miter_limit)?;
+        if !stroke.dash_array.is_empty() {
+            let dash_array = shape.get_stroke_dash_array()??;
+            for &length in &stroke.dash_array {
+                dash_array.append(length)?;
+            }
+            shape.set_stroke_dash_offset(stroke.dash_offset)?;
+        }
+    }
+    Ok(shape)
+}
+
+/// A `ShapeVisual` of vector shapes, painted in the order they're added.
+pub struct VectorVisual {
+    compositor: Compositor,
+    visual: ShapeVisual,
+}
+
+impl VectorVisual {
+    pub fn new(compositor: &Compositor, size: (f32, f32)) -> NResult<Self> {
+        let visual = compositor.create_shape_visual()??;
+        visual
+            .query_interface::<IVisual>()?
+            .set_size(to_vector2(size))?;
+        Ok(VectorVisual {
+            compositor: compositor.clone(),
+            visual,
+        })
+    }
+
+    /// The visual to insert into the tree. Shapes outside of its size are clipped.
+    #[inline]
+    pub fn visual(&self) -> &ShapeVisual {
+        &self.visual
+    }
+
+    pub fn set_size(&self, size: (f32, f32)) -> NResult<()> {
+        self.visual
+            .query_interface::<IVisual>()?
+            .set_size(to_vector2(size))?;
+        Ok(())
+    }
+
+    /// Adds a shape on top of the others, with its origin at `offset`. The returned shape can be
+    /// changed or animated later.
+    pub fn add(
+        &self,
+        geometry: &ShapeGeometry,
+        style: &ShapeStyle,
+        offset: (f32, f32),
+    ) -> NResult<CompositionSpriteShape> {
+        let shape = create_sprite_shape(&self.compositor, geometry, style)?;
+        shape
+            .query_interface::<ICompositionShape>()?
+            .set_offset(to_vector2(offset))?;
+        unsafe {
+            self.visual
+                .get_shapes()??
+                .append(&transmute::<CompositionSpriteShape, CompositionShape>(
+                    shape.clone(),
+                ))?;
+        }
+        Ok(shape)
+    }
+
+    /// Removes all shapes.
+    pub fn clear(&self) -> NResult<()> {
+        self.visual.get_shapes()??.clear()?;
+        Ok(())
+    }
+}
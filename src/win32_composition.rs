@@ -1,62 +1,400 @@
-use std::mem::{size_of, transmute};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::mem::{self, size_of, transmute};
 use std::ptr;
-use winapi::shared::minwindef::BOOL;
+use std::sync::Arc;
+use winapi::shared::minwindef::{BOOL, DWORD};
 use winapi::shared::windef::HWND;
+use winapi::shared::winerror::RPC_E_DISCONNECTED;
+use winapi::um::winbase::INFINITE;
+use winapi::um::winuser;
 use winrt::windows::foundation::numerics::{Vector2, Vector3};
-use winrt::windows::system::IDispatcherQueueController;
+use winrt::windows::system::{DispatcherQueue, IDispatcherQueueController};
 use winrt::windows::ui::composition::desktop::IDesktopWindowTarget;
 use winrt::windows::ui::composition::{
   Compositor, ContainerVisual, ICompositionTarget, IVisual, IVisual2, Visual,
 };
 use winrt::{ComInterface, RtDefaultConstructible};
 
-use nresult::NResult;
-use window::{Window, WindowExt};
+use app::{AppModel, CompositionBackend, ExitPolicy, WindowId};
+use dispatcher_executor::AgileDispatcherQueue;
+use dispatcher_thread::DedicatedQueue;
+use executor::Priority;
+use nresult::{check_hresult, NError, NResult};
+use thread_affinity::{current_thread_id, ThreadBound};
+use window::Window;
 use windows_ui_composition_interop::ICompositorDesktopInterop;
+use work_queue::{remote, Canceled, QueueHandle, Remote};
 use DispatcherQueue::{
-  CreateDispatcherQueueController, DispatcherQueueOptions, DQTAT_COM_ASTA, DQTYPE_THREAD_CURRENT,
+  CreateDispatcherQueueController, DispatcherQueueOptions, DISPATCHERQUEUE_THREAD_APARTMENTTYPE,
+  DISPATCHERQUEUE_THREAD_TYPE, DQTAT_COM_ASTA, DQTYPE_THREAD_CURRENT,
 };
 
+/// The thread that hosts the compositor of a thread's windows.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CompositionThread {
+  /// The thread of the windows, so that their event handlers can use composition directly.
+  Current,
+  /// A dispatcher thread of its own, so that composition work doesn't wait for the messages of
+  /// the windows. Composition is used through `Window::run_composition`.
+  Dedicated,
+}
+
+impl Default for CompositionThread {
+  #[inline]
+  fn default() -> Self {
+    CompositionThread::Current
+  }
+}
+
+/// The compositor of a thread's windows, and a queue that runs work on the compositor's thread.
+#[derive(Clone)]
+pub struct HostedCompositor {
+  compositor: ThreadBound<Compositor>,
+  queue: QueueHandle,
+}
+
+impl HostedCompositor {
+  /// The compositor, if the current thread is the compositor's.
+  pub fn get(&self) -> NResult<&Compositor> {
+    Ok(self.compositor.get()?)
+  }
+
+  /// The id of the compositor's thread.
+  #[inline]
+  pub fn thread(&self) -> DWORD {
+    self.compositor.owner()
+  }
+
+  /// Posts work to the compositor's thread.
+  #[inline]
+  pub fn queue(&self) -> &QueueHandle {
+    &self.queue
+  }
+}
+
+#[derive(Clone)]
 pub struct Win32CompositionHost {
-  pub compositor: Compositor,
-  pub root_visual: ContainerVisual,
-  // root_visual depends on the underlying composition target and
-  // dispatcher queue controller being kept alive, so we retain them here
+  compositor: HostedCompositor,
+  root_visual: ThreadBound<ContainerVisual>,
+  // root_visual depends on the underlying composition target being kept alive, so we retain it
+  // here. The dispatcher queue controller is shared by all windows of the thread and owned by
+  // the thread's `AppModel`.
   #[allow(dead_code)]
-  target: ICompositionTarget,
-  #[allow(dead_code)]
-  dispatcher_queue_controller: IDispatcherQueueController,
+  target: ThreadBound<ICompositionTarget>,
+}
+
+impl Win32CompositionHost {
+  /// The compositor, if the current thread is the compositor's.
+  #[inline]
+  pub fn compositor(&self) -> NResult<&Compositor> {
+    self.compositor.get()
+  }
+
+  /// The root visual of the window, if the current thread is the compositor's.
+  pub fn root_visual(&self) -> NResult<&ContainerVisual> {
+    Ok(self.root_visual.get()?)
+  }
+
+  /// The id of the compositor's thread.
+  #[inline]
+  pub fn thread(&self) -> DWORD {
+    self.compositor.thread()
+  }
+
+  /// Runs `f` with the compositor and the root visual on the compositor's thread, from its
+  /// dispatcher queue, and resolves to what it returns.
+  pub fn run<F, R>(&self, f: F) -> Remote<R>
+  where
+    F: FnOnce(&Compositor, &ContainerVisual) -> R + Send + 'static,
+    R: Send + 'static,
+  {
+    let host = self.clone();
+    self.compositor.queue.run(Priority::Normal, move || {
+      // The queue runs its work on the compositor's thread.
+      f(host.compositor().unwrap(), host.root_visual().unwrap())
+    })
+  }
+}
+
+/// Creates composition objects for the windows of the current thread.
+pub struct Win32Backend {
+  thread: CompositionThread,
+  dedicated: Option<DedicatedQueue>,
+}
+
+impl Win32Backend {
+  pub fn new() -> Self {
+    Win32Backend {
+      thread: CompositionThread::default(),
+      dedicated: None,
+    }
+  }
+
+  /// Where the compositor is, or will be, hosted.
+  #[inline]
+  pub fn composition_thread(&self) -> CompositionThread {
+    self.thread
+  }
+}
+
+impl CompositionBackend for Win32Backend {
+  type Queue = IDispatcherQueueController;
+  type Compositor = HostedCompositor;
+  type Target = Win32CompositionHost;
+  type Error = NError;
+
+  fn create_queue(&mut self) -> NResult<IDispatcherQueueController> {
+    init_dispatcher_queue()
+  }
+
+  fn create_compositor(&mut self) -> NResult<HostedCompositor> {
+    let (queue, compositor) = match self.thread {
+      CompositionThread::Current => {
+        let queue = DispatcherQueue::get_for_current_thread()??;
+        (
+          QueueHandle::new(Arc::new(AgileDispatcherQueue { queue })),
+          create_compositor()?,
+        )
+      }
+      CompositionThread::Dedicated => {
+        if self.dedicated.is_none() {
+          self.dedicated = Some(DedicatedQueue::new()?);
+        }
+        let queue = self.dedicated.as_ref().unwrap().handle();
+        let compositor = run_and_wait(&queue, create_compositor)?;
+        (queue, compositor)
+      }
+    };
+    Ok(HostedCompositor { compositor, queue })
+  }
+
+  fn create_target(
+    &mut self,
+    window: WindowId,
+    compositor: &HostedCompositor,
+  ) -> NResult<Win32CompositionHost> {
+    let hwnd = window.0 as HWND;
+    if compositor.thread() == current_thread_id() {
+      return create_host(hwnd, compositor);
+    }
+    let queue = compositor.queue.clone();
+    let compositor = compositor.clone();
+    // Window handles may be used from any thread, but `HWND` is a pointer and isn't `Send`.
+    let window = window.0;
+    run_and_wait(&queue, move || create_host(window as HWND, &compositor))
+  }
+
+  fn quit(&mut self) {
+    unsafe {
+      winuser::PostQuitMessage(0);
+    }
+  }
+}
+
+/// Creates a compositor on the current thread and binds it to the thread.
+fn create_compositor() -> NResult<ThreadBound<Compositor>> {
+  // Composition objects can be referenced and released from any thread.
+  Ok(unsafe { ThreadBound::new(Compositor::new(), "compositor") })
+}
+
+/// Creates the target and root visual of a window. Runs on the compositor's thread.
+fn create_host(hwnd: HWND, compositor: &HostedCompositor) -> NResult<Win32CompositionHost> {
+  let target = {
+    let compositor = compositor.get()?;
+    // Targets belong to the thread they are created on, which must be the compositor's.
+    ensure_on_thread(compositor, current_thread_id())?;
+    create_desktop_window_target(hwnd, compositor)?
+  };
+  let root_visual = create_composition_root(compositor.get()?, &target)?;
+  let target = target.query_interface::<ICompositionTarget>()?;
+  unsafe {
+    Ok(Win32CompositionHost {
+      compositor: compositor.clone(),
+      root_visual: ThreadBound::new(root_visual, "root visual"),
+      target: ThreadBound::new(target, "composition target"),
+    })
+  }
+}
+
+/// Runs `f` on the thread of `queue`, which isn't the current thread, and waits for what it
+/// returns.
+///
+/// While waiting, the messages sent to the current thread are handled, since creating a target
+/// on the compositor's thread may send messages to the window. Posted messages wait for the
+/// events loop.
+fn run_and_wait<F, T>(queue: &QueueHandle, f: F) -> NResult<T>
+where
+  F: FnOnce() -> NResult<T> + Send + 'static,
+  T: Send + 'static,
+{
+  let waiting = current_thread_id();
+  let (completer, remote) = remote();
+  let posted = queue.post(Priority::High, move || {
+    completer.complete(f());
+    // Wakes the waiting thread up.
+    unsafe {
+      winuser::PostThreadMessageW(waiting, winuser::WM_NULL, 0, 0);
+    }
+  });
+  if !posted {
+    return Err(NError::Hr(RPC_E_DISCONNECTED));
+  }
+  loop {
+    match remote.try_take() {
+      Some(Ok(result)) => return result,
+      Some(Err(Canceled)) => return Err(NError::Hr(RPC_E_DISCONNECTED)),
+      None => unsafe {
+        winuser::MsgWaitForMultipleObjectsEx(
+          0,
+          ptr::null(),
+          INFINITE,
+          winuser::QS_SENDMESSAGE | winuser::QS_POSTMESSAGE,
+          0,
+        );
+        let mut msg = mem::zeroed();
+        winuser::PeekMessageW(
+          &mut msg,
+          ptr::null_mut(),
+          0,
+          0,
+          winuser::PM_NOREMOVE | winuser::PM_QS_SENDMESSAGE,
+        );
+      },
+    }
+  }
+}
+
+/// An update of the application model that had to wait for the model to be released.
+type DeferredUpdate = Box<dyn FnOnce(&mut AppModel<Win32Backend>)>;
+
+thread_local! {
+  /// The windows of this thread and their shared dispatcher queue and compositor.
+  static APP: RefCell<AppModel<Win32Backend>> = RefCell::new(AppModel::new(Win32Backend::new()));
+  static DEFERRED: RefCell<VecDeque<DeferredUpdate>> = RefCell::new(VecDeque::new());
+}
+
+/// Runs `f` with the application model of the current thread.
+///
+/// The model is borrowed while `f` runs, so `f` must not handle messages that use it. The
+/// window procedure only touches it through `update_app`.
+pub fn with_app<F, R>(f: F) -> R
+where
+  F: FnOnce(&mut AppModel<Win32Backend>) -> R,
+{
+  let result = APP.with(|app| f(&mut app.borrow_mut()));
+  run_deferred_updates();
+  result
+}
+
+/// Runs `f` with the application model of the current thread, or once the model is released if
+/// it is in use further up the stack.
+///
+/// That happens when a window is created or destroyed by a message handled while `with_app`
+/// waits, e.g. in `run_and_wait`.
+pub(crate) fn update_app<F>(f: F)
+where
+  F: FnOnce(&mut AppModel<Win32Backend>) + 'static,
+{
+  let ran = APP.with(|app| match app.try_borrow_mut() {
+    Ok(mut app) => {
+      f(&mut app);
+      true
+    }
+    Err(_) => {
+      DEFERRED.with(|deferred| deferred.borrow_mut().push_back(Box::new(f)));
+      false
+    }
+  });
+  if ran {
+    run_deferred_updates();
+  }
+}
+
+fn run_deferred_updates() {
+  loop {
+    let update = DEFERRED.with(|deferred| deferred.borrow_mut().pop_front());
+    match update {
+      Some(update) => APP.with(|app| update(&mut app.borrow_mut())),
+      None => break,
+    }
+  }
+}
+
+/// Chooses whether closing the last window of the current thread ends its events loop.
+pub fn set_exit_policy(policy: ExitPolicy) {
+  with_app(|app| app.set_exit_policy(policy));
+}
+
+/// Chooses the thread that hosts the compositor of the current thread's windows. Once one of them
+/// uses composition, the choice is fixed; this returns `false` if it differs from `thread`.
+pub fn set_composition_thread(thread: CompositionThread) -> bool {
+  with_app(|app| {
+    if app.compositor().is_none() {
+      app.backend_mut().thread = thread;
+    }
+    app.backend().composition_thread() == thread
+  })
 }
 
 impl Window {
-  pub fn create_composition_host(&self) -> NResult<Win32CompositionHost> {
-    let queue: IDispatcherQueueController = init_dispatcher_queue();
-    let comp = winrt::windows::ui::composition::Compositor::new();
-    let target = create_desktop_window_target(&self, &comp)?;
-    let comp_root = create_composition_root(&comp, &target)?;
-    let composition_target = target.query_interface::<ICompositionTarget>()?;
-    return Ok(Win32CompositionHost {
-      compositor: comp,
-      root_visual: comp_root,
-      target: composition_target,
-      dispatcher_queue_controller: queue,
-    });
-  }
-}
-
-pub fn init_dispatcher_queue() -> IDispatcherQueueController {
+  /// Hooks the window up to the compositor of its thread, creating the compositor on first use.
+  /// The window's target is released when the window is destroyed.
+  pub fn create_composition_host(&self) -> NResult<()> {
+    let id = self.id();
+    with_app(|app| match app.attach_composition(id) {
+      Ok(_) => Ok(()),
+      Err(err) => Err(err.into()),
+    })
+  }
+
+  /// The compositor shared by the windows of this thread, once one of them has a composition
+  /// host. Fails with `NError::WrongThread` if the compositor is hosted on a dedicated thread.
+  pub fn compositor(&self) -> NResult<Compositor> {
+    with_app(|app| Ok(app.compositor()?.get()?.clone()))
+  }
+
+  /// The root visual of the window's composition host. Fails with `NError::WrongThread` if the
+  /// compositor is hosted on a dedicated thread.
+  pub fn root_visual(&self) -> NResult<ContainerVisual> {
+    let id = self.id();
+    with_app(|app| Ok(app.target(id)?.root_visual()?.clone()))
+  }
+
+  /// Runs `f` with the compositor and the window's root visual on the thread that hosts
+  /// composition, wherever that is, and resolves to what it returns.
+  pub fn run_composition<F, R>(&self, f: F) -> NResult<Remote<R>>
+  where
+    F: FnOnce(&Compositor, &ContainerVisual) -> R + Send + 'static,
+    R: Send + 'static,
+  {
+    let id = self.id();
+    with_app(|app| Ok(app.target(id)?.run(f)))
+  }
+}
+
+/// Creates a dispatcher queue for the current thread. This fails if the thread already has one.
+pub fn init_dispatcher_queue() -> NResult<IDispatcherQueueController> {
+  create_dispatcher_queue_controller(DQTYPE_THREAD_CURRENT, DQTAT_COM_ASTA)
+}
+
+/// Creates a dispatcher queue, either for the current thread or on a new thread of its own.
+pub fn create_dispatcher_queue_controller(
+  thread_type: DISPATCHERQUEUE_THREAD_TYPE,
+  apartment_type: DISPATCHERQUEUE_THREAD_APARTMENTTYPE,
+) -> NResult<IDispatcherQueueController> {
   let options = DispatcherQueueOptions {
     dwSize: size_of::<DispatcherQueueOptions>() as u32,
-    threadType: DQTYPE_THREAD_CURRENT,
-    apartmentType: DQTAT_COM_ASTA,
+    threadType: thread_type,
+    apartmentType: apartment_type,
   };
   unsafe {
     let mut p_controller: *mut <IDispatcherQueueController as ComInterface>::TAbi = ptr::null_mut();
-    CreateDispatcherQueueController(
+    check_hresult(CreateDispatcherQueueController(
       options,
       (&mut p_controller) as *mut *mut <IDispatcherQueueController as ComInterface>::TAbi,
-    );
-    return IDispatcherQueueController::wrap_com(p_controller);
+    ))?;
+    return Ok(IDispatcherQueueController::wrap_com(p_controller));
   }
 }
 
@@ -81,19 +419,25 @@ pub fn create_composition_root(
   }
 }
 
+/// Associates the compositor with the thread `thread_id`, or checks that it already is. Fails with
+/// `RPC_E_WRONG_THREAD` if the compositor belongs to another thread.
+pub fn ensure_on_thread(compositor: &Compositor, thread_id: DWORD) -> NResult<()> {
+  let mut interop = compositor.query_interface::<ICompositorDesktopInterop>()?;
+  unsafe { check_hresult(interop.EnsureOnThread(thread_id)) }
+}
+
 pub fn create_desktop_window_target(
-  window: &Window,
+  hwnd: HWND,
   compositor: &Compositor,
 ) -> NResult<IDesktopWindowTarget> {
-  let hwnd = window.get_hwnd() as HWND;
   let mut interop = compositor.query_interface::<ICompositorDesktopInterop>()?;
   unsafe {
     let mut ret: *mut <IDesktopWindowTarget as ComInterface>::TAbi = ptr::null_mut();
-    interop.CreateDesktopWindowTarget(
+    check_hresult(interop.CreateDesktopWindowTarget(
       hwnd,
       true as BOOL,
       (&mut ret) as *mut *mut _ as *mut IDesktopWindowTarget,
-    );
+    ))?;
     return Ok(IDesktopWindowTarget::wrap_com(ret));
   }
 }
@@ -4,65 +4,58 @@
 // winit is licensed under Apache License 2.0 which can be found in this project as "LICENSE_winit"
 
 use libc;
+use std::cell::{Cell, RefCell};
 use std::ffi::OsStr;
 use std::os::windows::ffi::OsStrExt;
-use std::{
-    assert_eq, debug_assert_eq, f64, format, io, isize, mem, panic, ptr, u16, u32, u8, usize,
-};
+use std::rc::Rc;
+use std::sync::Mutex;
+use std::{debug_assert_eq, f64, format, io, mem, panic, ptr, u16, u32, u8, usize};
 use winapi::ctypes::{c_int, wchar_t};
-use winapi::shared::minwindef::{BYTE, DWORD, LPARAM, LRESULT, UINT, WPARAM};
-use winapi::shared::ntdef::{LANG_NEUTRAL, LPCWSTR, MAKELANGID, SUBLANG_DEFAULT};
-use winapi::shared::windef::{HICON, HWND};
+use winapi::shared::basetsd::LONG_PTR;
+use winapi::shared::minwindef::{
+    BYTE, DWORD, FALSE, HIWORD, LOWORD, LPARAM, LPVOID, LRESULT, MAKELONG, TRUE, UINT, WORD,
+    WPARAM,
+};
+use winapi::shared::ntdef::{HRESULT, LANG_NEUTRAL, LONG, LPCWSTR, MAKELANGID, SUBLANG_DEFAULT};
+use winapi::shared::windef::{HBRUSH, HCURSOR, HICON, HWND, POINT, RECT};
+use winapi::shared::windowsx::{GET_X_LPARAM, GET_Y_LPARAM};
+use winapi::shared::winerror::{RPC_E_CHANGED_MODE, SUCCEEDED};
+use winapi::shared::wtypesbase::CLSCTX_INPROC_SERVER;
+use winapi::um::combaseapi::{CoCreateInstance, CoInitializeEx};
 use winapi::um::errhandlingapi::GetLastError;
+use winapi::um::imm::{
+    ImmGetContext, ImmReleaseContext, ImmSetCompositionWindow, CFS_CANDIDATEPOS, CFS_POINT,
+    COMPOSITIONFORM, HIMC,
+};
 use winapi::um::libloaderapi;
+use winapi::um::objbase::COINIT_APARTMENTTHREADED;
 use winapi::um::winbase::{
     lstrlenW, FormatMessageW, LocalFree, FORMAT_MESSAGE_ALLOCATE_BUFFER,
     FORMAT_MESSAGE_FROM_SYSTEM, FORMAT_MESSAGE_IGNORE_INSERTS,
 };
+use winapi::um::shobjidl_core::{CLSID_TaskbarList, ITaskbarList3};
+use winapi::um::wingdi;
 use winapi::um::winuser;
+use winapi::Interface;
 
-/// A size represented in logical pixels.
-///
-/// The size is stored as floats, so please be careful. Casting floats to integers truncates the fractional part,
-/// which can cause noticable issues. To help with that, an `Into<(u32, u32)>` implementation is provided which
-/// does the rounding for you.
-#[derive(Debug, Copy, Clone, PartialEq)]
-#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-pub struct LogicalSize {
-    pub width: f64,
-    pub height: f64,
-}
-
-impl LogicalSize {
-    #[inline]
-    pub fn new(width: f64, height: f64) -> Self {
-        LogicalSize { width, height }
-    }
-}
-
-impl From<(u32, u32)> for LogicalSize {
-    #[inline]
-    fn from((width, height): (u32, u32)) -> Self {
-        Self::new(width as f64, height as f64)
-    }
-}
-
-#[repr(C)]
-#[derive(Debug)]
-pub(crate) struct Pixel {
-    pub(crate) r: u8,
-    pub(crate) g: u8,
-    pub(crate) b: u8,
-    pub(crate) a: u8,
-}
-
-impl Pixel {
-    fn to_bgra(&mut self) {
-        mem::swap(&mut self.r, &mut self.b);
-    }
-}
-
-pub(crate) const PIXEL_SIZE: usize = mem::size_of::<Pixel>();
+use app::WindowId;
+use cursor::{cursor_action, CursorAction, CursorIcon, CustomCursor};
+use device_input;
+use drop_target::DropTargetRegistration;
+use imm::{
+    ImmGetCompositionStringW, ImmSetCandidateWindow, CANDIDATEFORM, GCS_COMPATTR, GCS_COMPSTR,
+    GCS_CURSORPOS, GCS_RESULTSTR, ISC_SHOWUICOMPOSITIONWINDOW,
+};
+use icon::{rgba_to_bgra_and_mask, Icon, IconAllocator, IconImage, IconKind, WindowIcons};
+use lifecycle::{Lifecycle, LifecycleEvent};
+use pointer_input;
+use text_input::{preedit_range, Ime};
+use win32_composition::{self, CompositionThread};
+use window_class::{ClassKey, ClassRegistrar, ClassRegistry};
+pub use window_events::LogicalSize;
+use window_events::{LogicalPosition, WindowEvent};
+use window_state::{WindowState, BASE_DPI};
+use window_style::{StyleError, WindowFlags};
 
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct WinError(Option<String>);
@@ -71,6 +64,12 @@ impl WinError {
     pub fn from_last_error() -> Self {
         WinError(unsafe { get_last_error() })
     }
+
+    pub fn from_hresult(hr: HRESULT) -> Self {
+        let message = unsafe { format_error_message(hr as DWORD) }
+            .unwrap_or_else(|| format!("HRESULT 0x{:08X}", hr as u32));
+        WinError(Some(message))
+    }
 }
 
 pub fn wchar_to_string(wchar: &[wchar_t]) -> String {
@@ -80,43 +79,38 @@ pub fn wchar_to_string(wchar: &[wchar_t]) -> String {
 pub unsafe fn get_last_error() -> Option<String> {
     let err = GetLastError();
     if err != 0 {
-        let buf_addr: LPCWSTR = {
-            let mut buf_addr: LPCWSTR = mem::uninitialized();
-            FormatMessageW(
-                FORMAT_MESSAGE_ALLOCATE_BUFFER
-                    | FORMAT_MESSAGE_FROM_SYSTEM
-                    | FORMAT_MESSAGE_IGNORE_INSERTS,
-                ptr::null(),
-                err,
-                MAKELANGID(LANG_NEUTRAL, SUBLANG_DEFAULT) as DWORD,
-                // This is a pointer to a pointer
-                &mut buf_addr as *mut LPCWSTR as *mut _,
-                0,
-                ptr::null_mut(),
-            );
-            buf_addr
-        };
-        if !buf_addr.is_null() {
-            let buf_len = lstrlenW(buf_addr) as usize;
-            let buf_slice = std::slice::from_raw_parts(buf_addr, buf_len);
-            let string = wchar_to_string(buf_slice);
-            LocalFree(buf_addr as *mut _);
-            return Some(string);
-        }
+        format_error_message(err)
+    } else {
+        None
     }
-    None
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-/// An icon used for the window titlebar, taskbar, etc.
-///
-/// Enabling the `icon_loading` feature provides you with several convenience methods for creating
-/// an `Icon` from any format supported by the [image](https://github.com/PistonDevelopers/image)
-/// crate.
-pub struct Icon {
-    pub(crate) rgba: Vec<u8>,
-    pub(crate) width: u32,
-    pub(crate) height: u32,
+/// Looks up the system message for a Win32 error code or `HRESULT`.
+pub unsafe fn format_error_message(code: DWORD) -> Option<String> {
+    let buf_addr: LPCWSTR = {
+        let mut buf_addr: LPCWSTR = mem::uninitialized();
+        FormatMessageW(
+            FORMAT_MESSAGE_ALLOCATE_BUFFER
+                | FORMAT_MESSAGE_FROM_SYSTEM
+                | FORMAT_MESSAGE_IGNORE_INSERTS,
+            ptr::null(),
+            code,
+            MAKELANGID(LANG_NEUTRAL, SUBLANG_DEFAULT) as DWORD,
+            // This is a pointer to a pointer
+            &mut buf_addr as *mut LPCWSTR as *mut _,
+            0,
+            ptr::null_mut(),
+        );
+        buf_addr
+    };
+    if !buf_addr.is_null() {
+        let buf_len = lstrlenW(buf_addr) as usize;
+        let buf_slice = std::slice::from_raw_parts(buf_addr, buf_len);
+        let string = wchar_to_string(buf_slice);
+        LocalFree(buf_addr as *mut _);
+        return Some(string);
+    }
+    None
 }
 
 #[derive(Clone, Debug)]
@@ -124,6 +118,33 @@ pub struct WinIcon {
     pub handle: HICON,
 }
 
+/// Creates icon handles with `CreateIcon` and releases them with `DestroyIcon`.
+pub struct Win32IconAllocator;
+
+impl IconAllocator for Win32IconAllocator {
+    type Handle = HICON;
+    type Error = WinError;
+
+    fn create(&mut self, image: &IconImage) -> Result<HICON, WinError> {
+        WinIcon::from_image(image).map(|icon| icon.handle)
+    }
+
+    fn destroy(&mut self, handle: HICON) {
+        unsafe {
+            winuser::DestroyIcon(handle);
+        }
+    }
+}
+
+/// The size Windows displays an icon of the given kind at for a window at `dpi`.
+pub fn icon_size(kind: IconKind, dpi: u32) -> u32 {
+    let metric = match kind {
+        IconKind::Small | IconKind::Overlay => winuser::SM_CXSMICON,
+        IconKind::Big => winuser::SM_CXICON,
+    };
+    unsafe { winuser::GetSystemMetricsForDpi(metric, dpi) as u32 }
+}
+
 /// A simple non-owning wrapper around a window.
 #[doc(hidden)]
 #[derive(Clone)]
@@ -199,17 +220,154 @@ impl Default for WindowAttributes {
     }
 }
 
-#[derive(Clone, Default)]
+#[derive(Clone)]
 pub struct PlatformSpecificWindowBuilderAttributes {
-    pub parent: Option<HWND>,
+    /// How the window relates to other windows.
+    ///
+    /// The default is `WindowKind::TopLevel`.
+    pub kind: WindowKind,
+    /// Whether the window is a tool window, with a small caption and no taskbar button. Child
+    /// windows can't be tool windows.
+    ///
+    /// The default is `false`.
+    pub tool_window: bool,
+    /// Whether files can be dragged onto the window, producing `HoveredFile`, `DroppedFile` and
+    /// `HoveredFileCancelled` events. This initializes OLE on the thread. OLE can't run on a thread
+    /// whose COM apartment is multithreaded, so there the window doesn't accept files.
+    ///
+    /// The default is `true`.
+    pub drag_and_drop: bool,
     pub taskbar_icon: Option<Icon>,
     pub no_redirection_bitmap: bool,
+    pub class: WindowClassAttributes,
+    /// The thread that hosts the compositor of the window's thread. The windows of a thread share
+    /// their compositor, so this has to agree with the windows that already use composition.
+    ///
+    /// The default is `None`, which keeps the thread's choice: `CompositionThread::Current`
+    /// unless a window or `set_composition_thread` chose otherwise.
+    pub composition_thread: Option<CompositionThread>,
+}
+
+impl Default for PlatformSpecificWindowBuilderAttributes {
+    fn default() -> Self {
+        PlatformSpecificWindowBuilderAttributes {
+            kind: WindowKind::default(),
+            tool_window: false,
+            drag_and_drop: true,
+            taskbar_icon: None,
+            no_redirection_bitmap: false,
+            class: WindowClassAttributes::default(),
+            composition_thread: None,
+        }
+    }
+}
+
+/// How a window relates to other windows.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WindowKind {
+    /// An independent top-level window with its own taskbar button.
+    TopLevel,
+    /// A top-level window owned by another window. It always stays above its owner, is hidden
+    /// while the owner is minimized, is destroyed along with it and has no taskbar button.
+    Owned(HWND),
+    /// A `WS_POPUP` window owned by `parent`, for menus, tooltips and the like. `position` is in
+    /// logical pixels relative to the parent's client area.
+    ///
+    /// Popups have no frame, whatever `WindowAttributes::decorations` says. They don't get a
+    /// default size either, so `WindowAttributes::dimensions` must be set.
+    Popup {
+        parent: HWND,
+        position: LogicalPosition,
+    },
+    /// A `WS_CHILD` window inside the client area of its parent.
+    Child(HWND),
+}
+
+impl Default for WindowKind {
+    #[inline]
+    fn default() -> Self {
+        WindowKind::TopLevel
+    }
+}
+
+impl WindowKind {
+    /// The window passed to `CreateWindowExW` as the parent or owner.
+    pub fn parent(&self) -> Option<HWND> {
+        match *self {
+            WindowKind::TopLevel => None,
+            WindowKind::Owned(owner) => Some(owner),
+            WindowKind::Popup { parent, .. } => Some(parent),
+            WindowKind::Child(parent) => Some(parent),
+        }
+    }
+
+    /// The flags that make a window of this kind.
+    pub fn flags(&self) -> WindowFlags {
+        match *self {
+            WindowKind::TopLevel => WindowFlags::ON_TASKBAR,
+            // Owned windows never get a taskbar button unless forced to with `WS_EX_APPWINDOW`.
+            WindowKind::Owned(_) => WindowFlags::empty(),
+            WindowKind::Popup { .. } => WindowFlags::POPUP,
+            WindowKind::Child(_) => WindowFlags::CHILD,
+        }
+    }
+}
+
+/// The window class a window is created with.
+///
+/// Windows created with equal attributes share a single class, which is unregistered when the
+/// last of them is destroyed.
+#[derive(Clone, Debug)]
+pub struct WindowClassAttributes {
+    /// The name to register the class under. If this is `None`, a unique name is generated.
+    ///
+    /// The default is `None`.
+    pub name: Option<String>,
+    /// The class styles (`CS_*`).
+    ///
+    /// The default is `CS_HREDRAW | CS_VREDRAW`.
+    pub style: UINT,
+    /// The class icons. These are only used until an icon is set on the window itself, and must
+    /// stay valid for as long as the class is registered.
+    ///
+    /// The default is null.
+    pub icon: HICON,
+    pub small_icon: HICON,
+    /// The class cursor. This must be null for `Window::set_cursor_icon` and the other per-window
+    /// cursor settings to work properly.
+    ///
+    /// The default is null.
+    pub cursor: HCURSOR,
+    /// The brush used to erase the background.
+    ///
+    /// The default is null.
+    pub background: HBRUSH,
+}
+
+impl Default for WindowClassAttributes {
+    #[inline]
+    fn default() -> Self {
+        WindowClassAttributes {
+            name: None,
+            style: winuser::CS_HREDRAW | winuser::CS_VREDRAW,
+            icon: ptr::null_mut(),
+            small_icon: ptr::null_mut(),
+            cursor: ptr::null_mut(),
+            background: ptr::null_mut(),
+        }
+    }
 }
 
 /// The Win32 implementation of the main `Window` object.
 pub struct Window {
     /// Main handle for the window.
     window: WindowWrapper,
+    /// The name of the window class, released when the window is destroyed.
+    class_name: String,
+    /// The icon handles set on the window with `WM_SETICON` and on its taskbar button.
+    icons: RefCell<WindowIcons<Win32IconAllocator>>,
+    /// State shared with the window procedure.
+    state: Rc<WindowState>,
 }
 
 impl Window {
@@ -219,12 +377,266 @@ impl Window {
         self.window.0
     }
 
+    /// Identifies the window among the windows of the application.
+    #[inline]
+    pub fn id(&self) -> WindowId {
+        WindowId(self.hwnd() as usize)
+    }
+
     #[inline]
     pub fn show(&self) {
         unsafe {
             winuser::ShowWindow(self.window.0, winuser::SW_SHOW);
         }
     }
+
+    /// Sets the text of the title bar.
+    pub fn set_title(&self, title: &str) {
+        let title = to_wide(title);
+        unsafe {
+            winuser::SetWindowTextW(self.window.0, title.as_ptr());
+        }
+    }
+
+    /// Sets the icon shown in the title bar (`ICON_SMALL`), or removes it.
+    pub fn set_window_icon(&self, icon: Option<&Icon>) -> Result<(), WinError> {
+        self.set_icon(IconKind::Small, icon)
+    }
+
+    /// Sets the icon shown in the taskbar and the Alt+Tab switcher (`ICON_BIG`), or removes it.
+    pub fn set_taskbar_icon(&self, icon: Option<&Icon>) -> Result<(), WinError> {
+        self.set_icon(IconKind::Big, icon)
+    }
+
+    fn set_icon(&self, kind: IconKind, icon: Option<&Icon>) -> Result<(), WinError> {
+        let hwnd = self.window.0;
+        let image = icon.map(|icon| icon.best_image(icon_size(kind, self.dpi())));
+        self.icons.borrow_mut().set(kind, image, |handle| {
+            unsafe {
+                send_icon_message(hwnd, kind, handle.unwrap_or(ptr::null_mut()));
+            }
+            Ok(())
+        })
+    }
+
+    /// Draws `icon` as a badge over the window's taskbar button, or removes the badge.
+    ///
+    /// `description` is read by accessibility tools in place of the badge.
+    pub fn set_overlay_icon(
+        &self,
+        icon: Option<&Icon>,
+        description: &str,
+    ) -> Result<(), WinError> {
+        let hwnd = self.window.0;
+        let image = icon.map(|icon| icon.best_image(icon_size(IconKind::Overlay, self.dpi())));
+        let description = to_wide(description);
+        let taskbar = TaskbarList::new()?;
+        self.icons
+            .borrow_mut()
+            .set(IconKind::Overlay, image, |handle| {
+                taskbar.set_overlay_icon(
+                    hwnd,
+                    handle.unwrap_or(ptr::null_mut()),
+                    description.as_ptr(),
+                )
+            })
+    }
+
+    /// The DPI of the monitor the window is on.
+    #[inline]
+    pub fn dpi(&self) -> u32 {
+        self.state.dpi.get()
+    }
+
+    /// The ratio between physical and logical pixels.
+    #[inline]
+    pub fn hidpi_factor(&self) -> f64 {
+        self.state.hidpi_factor()
+    }
+
+    /// Sets the cursor shown over the client area, replacing any custom cursor.
+    pub fn set_cursor_icon(&self, icon: CursorIcon) {
+        let mut cursor = self.state.cursor.get();
+        cursor.icon = icon;
+        cursor.custom = false;
+        self.state.cursor.set(cursor);
+        self.state.custom_cursor.borrow_mut().take();
+        unsafe { refresh_cursor(self.window.0) };
+    }
+
+    /// Shows a cursor made from an image over the client area.
+    pub fn set_custom_cursor(&self, cursor: &CustomCursor) -> Result<(), WinError> {
+        let handle = WinCursor::from_custom(cursor)?;
+        *self.state.custom_cursor.borrow_mut() = Some(handle);
+        let mut state = self.state.cursor.get();
+        state.custom = true;
+        self.state.cursor.set(state);
+        unsafe { refresh_cursor(self.window.0) };
+        Ok(())
+    }
+
+    /// Hides or shows the cursor while it is over the client area.
+    pub fn set_cursor_visible(&self, visible: bool) {
+        let mut cursor = self.state.cursor.get();
+        cursor.visible = visible;
+        self.state.cursor.set(cursor);
+        unsafe { refresh_cursor(self.window.0) };
+    }
+
+    /// Confines the cursor to the client area while the window is active.
+    pub fn set_cursor_grab(&self, grab: bool) -> Result<(), WinError> {
+        let mut cursor = self.state.cursor.get();
+        cursor.grabbed = grab;
+        self.state.cursor.set(cursor);
+        unsafe { update_cursor_clip(self.window.0, &self.state) }
+    }
+
+    /// Moves the composition and candidate windows of input method editors to `position`, in
+    /// the client area. This should be the caret of the text being edited.
+    pub fn set_ime_position(&self, position: LogicalPosition) {
+        self.state.ime_position.set(Some(position));
+        unsafe { apply_ime_position(self.window.0, &self.state) };
+    }
+
+    /// Sets whether the application draws IME compositions itself.
+    ///
+    /// When allowed, the window receives `WindowEvent::Ime` events and the IME doesn't draw its
+    /// composition window. Otherwise, which is the default, the IME handles the composition on its
+    /// own and the committed text arrives as `ReceivedCharacter` events. The composition window is
+    /// shown or hidden from the next time the window is activated.
+    pub fn set_ime_allowed(&self, allowed: bool) {
+        self.state.ime_allowed.set(allowed);
+    }
+
+    /// Sets the closure that receives the events of this window, replacing any previous one.
+    pub fn set_event_handler<F>(&self, handler: F)
+    where
+        F: FnMut(WindowEvent) + 'static,
+    {
+        self.state.set_event_handler(Box::new(handler));
+    }
+
+    /// Whether the native window still exists. It is destroyed when the `Window` is dropped, but
+    /// can also go away earlier, e.g. when the user closes it.
+    #[inline]
+    pub fn is_alive(&self) -> bool {
+        self.state.lifecycle().is_attached()
+    }
+
+    #[inline]
+    pub(crate) fn state(&self) -> &WindowState {
+        &self.state
+    }
+}
+
+impl Drop for Window {
+    fn drop(&mut self) {
+        let hwnd = self.window.0;
+        let alive = self.is_alive();
+        // Detach the icons before the handles are destroyed, so the window never refers to a
+        // destroyed icon.
+        self.icons.borrow_mut().clear(|kind| unsafe {
+            if !alive {
+                return;
+            }
+            match kind {
+                IconKind::Small | IconKind::Big => send_icon_message(hwnd, kind, ptr::null_mut()),
+                IconKind::Overlay => {
+                    if let Ok(taskbar) = TaskbarList::new() {
+                        let _ = taskbar.set_overlay_icon(hwnd, ptr::null_mut(), ptr::null());
+                    }
+                }
+            }
+        });
+        if alive {
+            unsafe {
+                winuser::DestroyWindow(hwnd);
+            }
+        }
+        release_window_class(&self.class_name);
+    }
+}
+
+unsafe fn send_icon_message(hwnd: HWND, kind: IconKind, icon: HICON) {
+    let which = match kind {
+        IconKind::Small => winuser::ICON_SMALL,
+        IconKind::Big => winuser::ICON_BIG,
+        IconKind::Overlay => unreachable!("overlay icons are set through ITaskbarList3"),
+    };
+    winuser::SendMessageW(hwnd, winuser::WM_SETICON, which as WPARAM, icon as LPARAM);
+}
+
+thread_local! {
+    static COM_INITIALIZED: Cell<bool> = Cell::new(false);
+}
+
+/// Initializes COM on the current thread once, as a single-threaded apartment. It stays
+/// initialized for the life of the thread.
+///
+/// A thread the application already initialized as a multithreaded apartment is left as it is;
+/// COM objects can be created there too.
+fn ensure_com_initialized() -> Result<(), WinError> {
+    COM_INITIALIZED.with(|initialized| {
+        if initialized.get() {
+            return Ok(());
+        }
+        let hr = unsafe { CoInitializeEx(ptr::null_mut(), COINIT_APARTMENTTHREADED) };
+        if !SUCCEEDED(hr) && hr != RPC_E_CHANGED_MODE {
+            return Err(WinError::from_hresult(hr));
+        }
+        initialized.set(true);
+        Ok(())
+    })
+}
+
+/// An owned `ITaskbarList3`, used to decorate a window's taskbar button.
+struct TaskbarList(*mut ITaskbarList3);
+
+impl TaskbarList {
+    fn new() -> Result<Self, WinError> {
+        ensure_com_initialized()?;
+        unsafe {
+            let mut taskbar: *mut ITaskbarList3 = ptr::null_mut();
+            let hr = CoCreateInstance(
+                &CLSID_TaskbarList,
+                ptr::null_mut(),
+                CLSCTX_INPROC_SERVER,
+                &ITaskbarList3::uuidof(),
+                &mut taskbar as *mut *mut ITaskbarList3 as *mut _,
+            );
+            if !SUCCEEDED(hr) {
+                return Err(WinError::from_hresult(hr));
+            }
+            let taskbar = TaskbarList(taskbar);
+            let hr = (*taskbar.0).HrInit();
+            if !SUCCEEDED(hr) {
+                return Err(WinError::from_hresult(hr));
+            }
+            Ok(taskbar)
+        }
+    }
+
+    fn set_overlay_icon(
+        &self,
+        hwnd: HWND,
+        icon: HICON,
+        description: LPCWSTR,
+    ) -> Result<(), WinError> {
+        let hr = unsafe { (*self.0).SetOverlayIcon(hwnd, icon, description) };
+        if SUCCEEDED(hr) {
+            Ok(())
+        } else {
+            Err(WinError::from_hresult(hr))
+        }
+    }
+}
+
+impl Drop for TaskbarList {
+    fn drop(&mut self) {
+        unsafe {
+            (*self.0).Release();
+        }
+    }
 }
 
 /// Additional methods on `Window` that are specific to Windows.
@@ -243,31 +655,17 @@ impl WindowExt for Window {
 }
 
 impl WinIcon {
-    pub fn from_icon(icon: Icon) -> Result<Self, WinError> {
-        Self::from_rgba(icon.rgba, icon.width, icon.height)
-    }
-
-    pub fn from_rgba(mut rgba: Vec<u8>, width: u32, height: u32) -> Result<Self, WinError> {
-        assert_eq!(rgba.len() % PIXEL_SIZE, 0);
-        let pixel_count = rgba.len() / PIXEL_SIZE;
-        assert_eq!(pixel_count, (width * height) as usize);
-        let mut and_mask = Vec::with_capacity(pixel_count);
-        let pixels = rgba.as_mut_ptr() as *mut Pixel; // how not to write idiomatic Rust
-        for pixel_index in 0..pixel_count {
-            let pixel = unsafe { &mut *pixels.offset(pixel_index as isize) };
-            and_mask.push(pixel.a.wrapping_sub(std::u8::MAX)); // invert alpha channel
-            pixel.to_bgra();
-        }
-        assert_eq!(and_mask.len(), pixel_count);
+    pub fn from_image(image: &IconImage) -> Result<Self, WinError> {
+        let (bgra, and_mask) = rgba_to_bgra_and_mask(&image.rgba, image.width, image.height);
         let handle = unsafe {
             winuser::CreateIcon(
                 ptr::null_mut(),
-                width as c_int,
-                height as c_int,
+                image.width as c_int,
+                image.height as c_int,
                 1,
-                (PIXEL_SIZE * 8) as BYTE,
+                32,
                 and_mask.as_ptr() as *const BYTE,
-                rgba.as_ptr() as *const BYTE,
+                bgra.as_ptr() as *const BYTE,
             ) as HICON
         };
         if !handle.is_null() {
@@ -278,87 +676,210 @@ impl WinIcon {
     }
 }
 
-bitflags! {
-    pub struct WindowFlags: u32 {
-        const RESIZABLE      = 1 << 0;
-        const DECORATIONS    = 1 << 1;
-        const VISIBLE        = 1 << 2;
-        const ON_TASKBAR     = 1 << 3;
-        const ALWAYS_ON_TOP  = 1 << 4;
-        const NO_BACK_BUFFER = 1 << 5;
-        const TRANSPARENT    = 1 << 6;
-        const CHILD          = 1 << 7;
-        const MAXIMIZED      = 1 << 8;
+/// An owned cursor handle, destroyed on drop.
+pub struct WinCursor {
+    pub handle: HCURSOR,
+}
 
-        /// Marker flag for fullscreen. Should always match `WindowState::fullscreen`, but is
-        /// included here to make masking easier.
-        const MARKER_FULLSCREEN = 1 << 9;
+impl WinCursor {
+    pub fn from_custom(cursor: &CustomCursor) -> Result<Self, WinError> {
+        let image = cursor.image();
+        let (hotspot_x, hotspot_y) = cursor.hotspot();
+        let (bgra, and_mask) = rgba_to_bgra_and_mask(&image.rgba, image.width, image.height);
+        unsafe {
+            // `CreateCursor` only makes monochrome cursors, so build a 32 bpp one from bitmaps.
+            let color = wingdi::CreateBitmap(
+                image.width as c_int,
+                image.height as c_int,
+                1,
+                32,
+                bgra.as_ptr() as *const _,
+            );
+            let mask = wingdi::CreateBitmap(
+                image.width as c_int,
+                image.height as c_int,
+                1,
+                1,
+                and_mask.as_ptr() as *const _,
+            );
+            let handle = if !color.is_null() && !mask.is_null() {
+                let mut info = winuser::ICONINFO {
+                    fIcon: FALSE,
+                    xHotspot: hotspot_x,
+                    yHotspot: hotspot_y,
+                    hbmMask: mask,
+                    hbmColor: color,
+                };
+                winuser::CreateIconIndirect(&mut info) as HCURSOR
+            } else {
+                ptr::null_mut()
+            };
+            // The cursor keeps copies of the bitmaps.
+            let error = WinError::from_last_error();
+            if !color.is_null() {
+                wingdi::DeleteObject(color as _);
+            }
+            if !mask.is_null() {
+                wingdi::DeleteObject(mask as _);
+            }
+            if !handle.is_null() {
+                Ok(WinCursor { handle })
+            } else {
+                Err(error)
+            }
+        }
+    }
+}
 
-        /// The `WM_SIZE` event contains some parameters that can effect the state of `WindowFlags`.
-        /// In most cases, it's okay to let those parameters change the state. However, when we're
-        /// running the `WindowFlags::apply_diff` function, we *don't* want those parameters to
-        /// effect our stored state, because the purpose of `apply_diff` is to update the actual
-        /// window's state to match our stored state. This controls whether to accept those changes.
-        const MARKER_RETAIN_STATE_ON_SIZE = 1 << 10;
+impl Drop for WinCursor {
+    fn drop(&mut self) {
+        unsafe {
+            winuser::DestroyCursor(self.handle);
+        }
+    }
+}
 
-        const FULLSCREEN_AND_MASK = !(
-            WindowFlags::DECORATIONS.bits |
-            WindowFlags::RESIZABLE.bits |
-            WindowFlags::MAXIMIZED.bits
-        );
-        const NO_DECORATIONS_AND_MASK = !WindowFlags::RESIZABLE.bits;
-        const INVISIBLE_AND_MASK = !WindowFlags::MAXIMIZED.bits;
+/// Makes Windows ask the window for its cursor again if the cursor is over it, so that changes to
+/// the cursor state show up without waiting for the mouse to move.
+unsafe fn refresh_cursor(window: HWND) {
+    let mut point = mem::zeroed();
+    if winuser::GetCursorPos(&mut point) == 0 || winuser::WindowFromPoint(point) != window {
+        return;
     }
+    let hit_test = winuser::SendMessageW(
+        window,
+        winuser::WM_NCHITTEST,
+        0,
+        MAKELONG(point.x as WORD, point.y as WORD) as LPARAM,
+    );
+    winuser::SendMessageW(
+        window,
+        winuser::WM_SETCURSOR,
+        window as WPARAM,
+        MAKELONG(hit_test as WORD, winuser::WM_MOUSEMOVE as WORD) as LPARAM,
+    );
 }
 
-impl WindowFlags {
-    pub fn to_window_styles(self) -> (DWORD, DWORD) {
-        use winapi::um::winuser::*;
+/// Applies the cursor grab: while the window is active and grabs the cursor, the cursor is
+/// clipped to the client area. Called whenever the grab, the focus or the client area changes.
+unsafe fn update_cursor_clip(window: HWND, state: &WindowState) -> Result<(), WinError> {
+    if winuser::GetActiveWindow() != window {
+        return Ok(());
+    }
+    let result = if state.cursor.get().grabbed {
+        let mut rect: RECT = mem::zeroed();
+        winuser::GetClientRect(window, &mut rect);
+        // Converts both corners to screen coordinates.
+        winuser::MapWindowPoints(window, ptr::null_mut(), &mut rect as *mut RECT as *mut POINT, 2);
+        winuser::ClipCursor(&rect)
+    } else {
+        winuser::ClipCursor(ptr::null())
+    };
+    if result != 0 {
+        Ok(())
+    } else {
+        Err(WinError::from_last_error())
+    }
+}
 
-        let (mut style, mut style_ex) = (0, 0);
+/// Places the IME windows at the position set with `Window::set_ime_position`, if any.
+unsafe fn apply_ime_position(window: HWND, state: &WindowState) {
+    let position = match state.ime_position.get() {
+        Some(position) => position,
+        None => return,
+    };
+    let himc = ImmGetContext(window);
+    if himc.is_null() {
+        return;
+    }
+    let dpi_factor = state.hidpi_factor();
+    let point = POINT {
+        x: (position.x * dpi_factor).round() as LONG,
+        y: (position.y * dpi_factor).round() as LONG,
+    };
+    let mut composition = COMPOSITIONFORM {
+        dwStyle: CFS_POINT,
+        ptCurrentPos: point,
+        rcArea: mem::zeroed(),
+    };
+    ImmSetCompositionWindow(himc, &mut composition);
+    let mut candidate = CANDIDATEFORM {
+        dwIndex: 0,
+        dwStyle: CFS_CANDIDATEPOS,
+        ptCurrentPos: point,
+        rcArea: mem::zeroed(),
+    };
+    ImmSetCandidateWindow(himc, &mut candidate);
+    ImmReleaseContext(window, himc);
+}
 
-        if self.contains(WindowFlags::RESIZABLE) {
-            style |= WS_SIZEBOX | WS_MAXIMIZEBOX;
-        }
-        if self.contains(WindowFlags::DECORATIONS) {
-            style |= WS_CAPTION | WS_MINIMIZEBOX | WS_BORDER;
-            style_ex = WS_EX_WINDOWEDGE;
-        }
-        if self.contains(WindowFlags::VISIBLE) {
-            style |= WS_VISIBLE;
-        }
-        if self.contains(WindowFlags::ON_TASKBAR) {
-            style_ex |= WS_EX_APPWINDOW;
-        }
-        if self.contains(WindowFlags::ALWAYS_ON_TOP) {
-            style_ex |= WS_EX_TOPMOST;
-        }
-        if self.contains(WindowFlags::NO_BACK_BUFFER) {
-            style_ex |= WS_EX_NOREDIRECTIONBITMAP;
-        }
-        if self.contains(WindowFlags::TRANSPARENT) {
-            // Is this necessary? The docs say that WS_EX_LAYERED requires a windows class without
-            // CS_OWNDC, and Winit windows have that flag set.
-            style_ex |= WS_EX_LAYERED;
-        }
-        if self.contains(WindowFlags::CHILD) {
-            style |= WS_CHILD; // This is incompatible with WS_POPUP if that gets added eventually.
-        }
-        if self.contains(WindowFlags::MAXIMIZED) {
-            style |= WS_MAXIMIZE;
-        }
+/// Reads a composition string (`GCS_COMPSTR` or `GCS_RESULTSTR`).
+unsafe fn composition_string(himc: HIMC, index: DWORD) -> String {
+    let bytes = composition_data(himc, index);
+    let units: Vec<u16> = bytes
+        .chunks(2)
+        .filter(|unit| unit.len() == 2)
+        .map(|unit| unit[0] as u16 | (unit[1] as u16) << 8)
+        .collect();
+    String::from_utf16_lossy(&units)
+}
 
-        style |= WS_CLIPSIBLINGS | WS_CLIPCHILDREN | WS_SYSMENU;
-        style_ex |= WS_EX_ACCEPTFILES;
+/// Reads the raw bytes of a part of the composition.
+unsafe fn composition_data(himc: HIMC, index: DWORD) -> Vec<u8> {
+    let len = ImmGetCompositionStringW(himc, index, ptr::null_mut(), 0);
+    if len <= 0 {
+        return Vec::new();
+    }
+    let mut data = vec![0u8; len as usize];
+    let len = ImmGetCompositionStringW(himc, index, data.as_mut_ptr() as *mut _, len as DWORD);
+    data.truncate(len.max(0) as usize);
+    data
+}
 
-        (style, style_ex)
+/// Turns a `WM_IME_COMPOSITION` into preedit and commit events.
+unsafe fn handle_ime_composition(window: HWND, state: &WindowState, flags: DWORD) {
+    let himc = ImmGetContext(window);
+    if himc.is_null() {
+        return;
+    }
+    if flags & GCS_RESULTSTR != 0 {
+        let text = composition_string(himc, GCS_RESULTSTR);
+        state.send_event(WindowEvent::Ime(Ime::Preedit(String::new(), None)));
+        state.send_event(WindowEvent::Ime(Ime::Commit(text)));
+    }
+    if flags & GCS_COMPSTR != 0 {
+        let text = composition_string(himc, GCS_COMPSTR);
+        let attributes = if flags & GCS_COMPATTR != 0 {
+            composition_data(himc, GCS_COMPATTR)
+        } else {
+            Vec::new()
+        };
+        let cursor = if flags & GCS_CURSORPOS != 0 {
+            let cursor = ImmGetCompositionStringW(himc, GCS_CURSORPOS, ptr::null_mut(), 0);
+            if cursor >= 0 {
+                Some(cursor as usize)
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+        let range = preedit_range(&text, &attributes, cursor);
+        state.send_event(WindowEvent::Ime(Ime::Preedit(text, range)));
     }
+    ImmReleaseContext(window, himc);
 }
 
 /// Error that can happen while creating a window or a headless renderer.
 #[derive(Debug, Clone)]
 pub enum CreationError {
     OsError(String),
+    /// The requested attributes translate to window styles that can't be combined.
+    InvalidStyle(StyleError),
+    /// The windows of the thread already host composition on another thread.
+    CompositionThread(CompositionThread),
+    /// A `WindowKind::Popup` was requested without `WindowAttributes::dimensions`.
+    PopupWithoutDimensions,
 }
 
 impl Window {
@@ -366,149 +887,248 @@ impl Window {
         w_attr: WindowAttributes,
         pl_attr: PlatformSpecificWindowBuilderAttributes,
     ) -> Result<Window, CreationError> {
-        // registering the window class
-
-        let window_icon = {
-            let icon = w_attr
-                .window_icon
-                // .take()
-                .map(WinIcon::from_icon);
-            if icon.is_some() {
-                Some(icon.unwrap().map_err(|err| {
-                    CreationError::OsError(format!("Failed to create `ICON_SMALL`: {:?}", err))
-                })?)
-            } else {
-                None
-            }
+        let popup_size = match pl_attr.kind {
+            WindowKind::Popup { .. } => match w_attr.dimensions {
+                Some(size) => Some(size),
+                None => return Err(CreationError::PopupWithoutDimensions),
+            },
+            _ => None,
         };
-        let taskbar_icon = {
-            let icon = pl_attr
-                .taskbar_icon
-                // .take()
-                .map(WinIcon::from_icon);
-            if icon.is_some() {
-                Some(icon.unwrap().map_err(|err| {
-                    CreationError::OsError(format!("Failed to create `ICON_BIG`: {:?}", err))
-                })?)
-            } else {
-                None
+        if let Some(thread) = pl_attr.composition_thread {
+            if !win32_composition::set_composition_thread(thread) {
+                return Err(CreationError::CompositionThread(thread));
             }
-        };
+        }
+
+        // registering the window class
         unsafe {
-            let class_name = register_window_class(&window_icon, &taskbar_icon);
-            let mut window_flags = WindowFlags::empty();
+            let mut window_flags = pl_attr.kind.flags();
             window_flags.set(WindowFlags::DECORATIONS, w_attr.decorations);
             window_flags.set(WindowFlags::ALWAYS_ON_TOP, w_attr.always_on_top);
             window_flags.set(WindowFlags::NO_BACK_BUFFER, pl_attr.no_redirection_bitmap);
             window_flags.set(WindowFlags::TRANSPARENT, w_attr.transparent);
             // WindowFlags::VISIBLE and MAXIMIZED are set down below after the window has been configured.
             window_flags.set(WindowFlags::RESIZABLE, w_attr.resizable);
-            window_flags.set(WindowFlags::CHILD, pl_attr.parent.is_some());
-            window_flags.set(WindowFlags::ON_TASKBAR, true);
+            if pl_attr.tool_window {
+                window_flags.remove(WindowFlags::ON_TASKBAR);
+                window_flags.insert(WindowFlags::TOOL_WINDOW);
+            }
+            if popup_size.is_some() {
+                window_flags.remove(WindowFlags::DECORATIONS);
+            }
+            let (style, ex_style) = window_flags
+                .to_window_styles()
+                .map_err(CreationError::InvalidStyle)?;
+            let (x, y, width, height) = match (pl_attr.kind, popup_size) {
+                (WindowKind::Popup { parent, position }, Some(size)) => {
+                    popup_rect(parent, position, size)
+                }
+                _ => (
+                    winuser::CW_USEDEFAULT,
+                    winuser::CW_USEDEFAULT,
+                    winuser::CW_USEDEFAULT,
+                    winuser::CW_USEDEFAULT,
+                ),
+            };
 
-            let title = OsStr::new(&w_attr.title)
-                .encode_wide()
-                .chain(Some(0).into_iter())
-                .collect::<Vec<_>>();
+            let class_name = acquire_window_class(&pl_attr.class)?;
+
+            let title = to_wide(&w_attr.title);
+
+            // The window procedure takes over this reference in `WM_NCCREATE`.
+            let state = Rc::new(WindowState::new(window_flags));
+            let state_ptr = Rc::into_raw(state.clone());
 
             // creating the real window this time, by using the functions in `extra_functions`
             let real_window = {
-                let (style, ex_style) = window_flags.to_window_styles();
                 let handle = winuser::CreateWindowExW(
                     ex_style,
-                    class_name.as_ptr(),
+                    to_wide(&class_name).as_ptr(),
                     title.as_ptr() as LPCWSTR,
                     style,
-                    winuser::CW_USEDEFAULT,
-                    winuser::CW_USEDEFAULT,
-                    winuser::CW_USEDEFAULT,
-                    winuser::CW_USEDEFAULT,
-                    pl_attr.parent.unwrap_or(ptr::null_mut()),
+                    x,
+                    y,
+                    width,
+                    height,
+                    pl_attr.kind.parent().unwrap_or(ptr::null_mut()),
                     ptr::null_mut(),
                     libloaderapi::GetModuleHandleW(ptr::null()),
-                    ptr::null_mut(),
+                    state_ptr as LPVOID,
                 );
 
                 if handle.is_null() {
+                    if state.lifecycle() == Lifecycle::Unattached {
+                        // `WM_NCCREATE` never ran, so the reference was never handed over.
+                        drop(Rc::from_raw(state_ptr));
+                    }
+                    state.transition(LifecycleEvent::CreateFailed);
+                    release_window_class(&class_name);
                     return Err(CreationError::OsError(format!(
                         "CreateWindowEx function failed: {}",
                         format!("{}", io::Error::last_os_error())
                     )));
                 }
 
+                state.transition(LifecycleEvent::CreateReturned);
+                win32_composition::update_app(move |app| {
+                    app.window_opened(WindowId(handle as usize));
+                });
                 WindowWrapper(handle)
             };
 
-            Ok(Window {
+            let window = Window {
                 window: real_window,
-            })
+                class_name,
+                icons: RefCell::new(WindowIcons::new(Win32IconAllocator)),
+                state,
+            };
+            window
+                .set_window_icon(w_attr.window_icon.as_ref())
+                .map_err(|err| {
+                    CreationError::OsError(format!("Failed to create `ICON_SMALL`: {:?}", err))
+                })?;
+            window
+                .set_taskbar_icon(pl_attr.taskbar_icon.as_ref())
+                .map_err(|err| {
+                    CreationError::OsError(format!("Failed to create `ICON_BIG`: {:?}", err))
+                })?;
+            if pl_attr.drag_and_drop {
+                let registration = DropTargetRegistration::register(window.hwnd(), &window.state)
+                    .map_err(|err| {
+                        CreationError::OsError(format!(
+                            "Failed to register the drop target: {:?}",
+                            err
+                        ))
+                    })?;
+                *window.state.drop_target.borrow_mut() = registration;
+            }
+            Ok(window)
         }
     }
 }
 
-// init instance
-// message loop?
+/// Converts a popup's position relative to the client area of its parent, and its logical size,
+/// to a rect in physical screen coordinates, using the DPI of the parent.
+unsafe fn popup_rect(
+    parent: HWND,
+    position: LogicalPosition,
+    size: LogicalSize,
+) -> (c_int, c_int, c_int, c_int) {
+    let dpi_factor = winuser::GetDpiForWindow(parent) as f64 / BASE_DPI as f64;
+    let mut origin = POINT {
+        x: (position.x * dpi_factor).round() as LONG,
+        y: (position.y * dpi_factor).round() as LONG,
+    };
+    winuser::ClientToScreen(parent, &mut origin);
+    let width = (size.width * dpi_factor).round() as c_int;
+    let height = (size.height * dpi_factor).round() as c_int;
+    (origin.x, origin.y, width, height)
+}
 
-unsafe fn register_window_class(
-    window_icon: &Option<WinIcon>,
-    taskbar_icon: &Option<WinIcon>,
-) -> Vec<u16> {
-    let class_name: Vec<_> = std::ffi::OsStr::new("Window Class")
-        .encode_wide()
-        .chain(Some(0).into_iter())
-        .collect();
+lazy_static! {
+    /// The window classes registered by this process. Classes are process-wide, so this is shared
+    /// between threads.
+    static ref CLASS_REGISTRY: Mutex<ClassRegistry<Win32ClassRegistrar>> =
+        Mutex::new(ClassRegistry::new(Win32ClassRegistrar));
+}
+
+/// Registers window classes with `RegisterClassExW`, using `callback` as the window procedure.
+pub struct Win32ClassRegistrar;
+
+impl ClassRegistrar for Win32ClassRegistrar {
+    type Error = WinError;
 
-    let h_icon = taskbar_icon
-        .as_ref()
-        .map(|icon| icon.handle)
-        .unwrap_or(ptr::null_mut());
-
-    let h_icon_small = window_icon
-        .as_ref()
-        .map(|icon| icon.handle)
-        .unwrap_or(ptr::null_mut());
-
-    let class = winuser::WNDCLASSEXW {
-        cbSize: mem::size_of::<winuser::WNDCLASSEXW>() as UINT,
-        style: winuser::CS_HREDRAW | winuser::CS_VREDRAW,
-        lpfnWndProc: Some(callback),
-        cbClsExtra: 0,
-        cbWndExtra: 0,
-        hInstance: libloaderapi::GetModuleHandleW(ptr::null()),
-        hCursor: ptr::null_mut(), // must be null in order for cursor state to work properly
-        hbrBackground: ptr::null_mut(),
-        lpszMenuName: ptr::null(),
-        lpszClassName: class_name.as_ptr(),
-        hIcon: h_icon,
-        hIconSm: h_icon_small,
+    fn register(&mut self, name: &str, key: &ClassKey) -> Result<(), WinError> {
+        let class_name = to_wide(name);
+        let class = winuser::WNDCLASSEXW {
+            cbSize: mem::size_of::<winuser::WNDCLASSEXW>() as UINT,
+            style: key.style,
+            lpfnWndProc: Some(callback),
+            cbClsExtra: 0,
+            cbWndExtra: 0,
+            hInstance: unsafe { libloaderapi::GetModuleHandleW(ptr::null()) },
+            hCursor: key.cursor as HCURSOR,
+            hbrBackground: key.background as HBRUSH,
+            lpszMenuName: ptr::null(),
+            lpszClassName: class_name.as_ptr(),
+            hIcon: key.icon as HICON,
+            hIconSm: key.small_icon as HICON,
+        };
+        if unsafe { winuser::RegisterClassExW(&class) } == 0 {
+            Err(WinError::from_last_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn unregister(&mut self, name: &str) {
+        let class_name = to_wide(name);
+        unsafe {
+            winuser::UnregisterClassW(
+                class_name.as_ptr(),
+                libloaderapi::GetModuleHandleW(ptr::null()),
+            );
+        }
+    }
+}
+
+fn acquire_window_class(attr: &WindowClassAttributes) -> Result<String, CreationError> {
+    let key = ClassKey {
+        style: attr.style,
+        icon: attr.icon as usize,
+        small_icon: attr.small_icon as usize,
+        cursor: attr.cursor as usize,
+        background: attr.background as usize,
     };
+    CLASS_REGISTRY
+        .lock()
+        .unwrap()
+        .acquire(key, attr.name.as_ref().map(|name| name.as_str()))
+        .map_err(|err| CreationError::OsError(format!("{}", err)))
+}
 
-    // We ignore errors because registering the same window class twice would trigger
-    //  an error, and because errors here are detected during CreateWindowEx anyway.
-    // Also since there is no weird element in the struct, there is no reason for this
-    //  call to fail.
-    winuser::RegisterClassExW(&class);
-    class_name
+fn release_window_class(name: &str) {
+    CLASS_REGISTRY.lock().unwrap().release(name);
 }
 
-pub fn run_events_loop() {
-    unsafe {
-        winuser::IsGUIThread(1);
+pub(crate) fn to_wide(s: &str) -> Vec<u16> {
+    OsStr::new(s)
+        .encode_wide()
+        .chain(Some(0).into_iter())
+        .collect()
+}
 
+/// What `pump_event` did.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Pumped {
+    Dispatched,
+    /// `WM_QUIT` was received, with this exit code.
+    Quit(i32),
+}
+
+/// Waits for the next message of the thread and dispatches it. The work of the thread's
+/// dispatcher queue arrives as messages too.
+pub(crate) fn pump_event() -> Pumped {
+    unsafe {
         let mut msg = mem::uninitialized();
+        if winuser::GetMessageW(&mut msg, ptr::null_mut(), 0, 0) == 0 {
+            // Only happens if the message is `WM_QUIT`.
+            debug_assert_eq!(msg.message, winuser::WM_QUIT);
+            return Pumped::Quit(msg.wParam as i32);
+        }
 
-        loop {
-            if winuser::GetMessageW(&mut msg, ptr::null_mut(), 0, 0) == 0 {
-                // Only happens if the message is `WM_QUIT`.
-                debug_assert_eq!(msg.message, winuser::WM_QUIT);
-                break;
-            }
+        // Calls `callback` below.
+        winuser::TranslateMessage(&msg);
+        winuser::DispatchMessageW(&msg);
+        Pumped::Dispatched
+    }
+}
 
-            // Calls `callback` below.
-            winuser::TranslateMessage(&msg);
-            winuser::DispatchMessageW(&msg);
-        }
+pub fn run_events_loop() {
+    unsafe {
+        winuser::IsGUIThread(1);
     }
+    while pump_event() == Pumped::Dispatched {}
 }
 
 pub unsafe fn run_catch_panic<F, R>(error: R, f: F) -> R
@@ -544,7 +1164,218 @@ pub unsafe extern "system" fn callback(
 }
 
 unsafe fn callback_inner(window: HWND, msg: UINT, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    if msg == winuser::WM_NCCREATE {
+        let create_struct = &*(lparam as *const winuser::CREATESTRUCTW);
+        let state = create_struct.lpCreateParams as *const WindowState;
+        if !state.is_null() {
+            (*state).transition(LifecycleEvent::Attach);
+            (*state).dpi.set(winuser::GetDpiForWindow(window));
+            winuser::SetWindowLongPtrW(window, winuser::GWLP_USERDATA, state as LONG_PTR);
+        }
+        return winuser::DefWindowProcW(window, msg, wparam, lparam);
+    }
+
+    let state_ptr =
+        winuser::GetWindowLongPtrW(window, winuser::GWLP_USERDATA) as *const WindowState;
+    if state_ptr.is_null() {
+        return winuser::DefWindowProcW(window, msg, wparam, lparam);
+    }
+    // Hold a reference of our own until the message returns. A handler may drop its `Window`, and
+    // the `WM_NCDESTROY` that sends must not free the state under the frames still using it.
+    let state = {
+        let window_reference = Rc::from_raw(state_ptr);
+        let state = window_reference.clone();
+        mem::forget(window_reference);
+        state
+    };
+    let state = &*state;
+
     match msg {
+        winuser::WM_NCDESTROY => {
+            winuser::SetWindowLongPtrW(window, winuser::GWLP_USERDATA, 0);
+            state.transition(LifecycleEvent::Detach);
+            state.tear_down();
+            // Releases the window's composition target, and ends the events loop if this was the
+            // last window of the thread.
+            let id = WindowId(window as usize);
+            win32_composition::update_app(move |app| {
+                app.window_closed(id);
+            });
+            // Give up the window procedure's reference. The state lives on until this message
+            // returns, through the reference taken above.
+            drop(Rc::from_raw(state_ptr));
+            winuser::DefWindowProcW(window, msg, wparam, lparam)
+        }
+
+        // The window is only destroyed when the handler drops its `Window`.
+        winuser::WM_CLOSE => {
+            state.send_event(WindowEvent::CloseRequested);
+            0
+        }
+
+        winuser::WM_DESTROY => {
+            state.transition(LifecycleEvent::Destroy);
+            state.drop_target.borrow_mut().take();
+            let raw_input = state.raw_input.get();
+            if !raw_input.is_empty() {
+                let _ = device_input::remove_registrations(window, state, raw_input);
+            }
+            state.send_event(WindowEvent::Destroyed);
+            0
+        }
+
+        winuser::WM_SIZE => {
+            let w = LOWORD(lparam as DWORD) as u32;
+            let h = HIWORD(lparam as DWORD) as u32;
+            let dpi_factor = state.hidpi_factor();
+            let size = LogicalSize::new(w as f64 / dpi_factor, h as f64 / dpi_factor);
+            let _ = update_cursor_clip(window, state);
+            state.send_event(WindowEvent::Resized(size));
+            0
+        }
+
+        winuser::WM_MOVE => {
+            let x = GET_X_LPARAM(lparam) as f64;
+            let y = GET_Y_LPARAM(lparam) as f64;
+            let position = LogicalPosition::from_physical(x, y, state.hidpi_factor());
+            let _ = update_cursor_clip(window, state);
+            state.send_event(WindowEvent::Moved(position));
+            0
+        }
+
+        winuser::WM_SETFOCUS => {
+            let _ = update_cursor_clip(window, state);
+            state.send_event(WindowEvent::Focused(true));
+            0
+        }
+
+        winuser::WM_KILLFOCUS => {
+            let mut utf16 = state.utf16.get();
+            utf16.reset();
+            state.utf16.set(utf16);
+            if state.cursor.get().grabbed {
+                winuser::ClipCursor(ptr::null());
+            }
+            state.send_event(WindowEvent::Focused(false));
+            0
+        }
+
+        winuser::WM_CHAR => {
+            let mut utf16 = state.utf16.get();
+            let chars = utf16.push(wparam as u16);
+            state.utf16.set(utf16);
+            for c in chars {
+                state.send_event(WindowEvent::ReceivedCharacter(c));
+            }
+            0
+        }
+
+        winuser::WM_IME_SETCONTEXT => {
+            // The application draws the preedit text, so the IME shouldn't show its composition
+            // window. The candidate window stays.
+            let lparam = if state.ime_allowed.get() {
+                lparam & !ISC_SHOWUICOMPOSITIONWINDOW
+            } else {
+                lparam
+            };
+            winuser::DefWindowProcW(window, msg, wparam, lparam)
+        }
+
+        winuser::WM_IME_STARTCOMPOSITION => {
+            // The IME forgets the positions of its windows between compositions.
+            apply_ime_position(window, state);
+            if state.ime_allowed.get() {
+                state.send_event(WindowEvent::Ime(Ime::Start));
+                // Not calling `DefWindowProcW` keeps the IME from drawing its own composition
+                // window.
+                0
+            } else {
+                winuser::DefWindowProcW(window, msg, wparam, lparam)
+            }
+        }
+
+        winuser::WM_IME_COMPOSITION => {
+            if state.ime_allowed.get() {
+                handle_ime_composition(window, state, lparam as DWORD);
+                // Not calling `DefWindowProcW` keeps the committed text from also arriving as
+                // `WM_IME_CHAR` and `WM_CHAR`.
+                0
+            } else {
+                winuser::DefWindowProcW(window, msg, wparam, lparam)
+            }
+        }
+
+        winuser::WM_IME_ENDCOMPOSITION => {
+            if state.ime_allowed.get() {
+                state.send_event(WindowEvent::Ime(Ime::End));
+                0
+            } else {
+                winuser::DefWindowProcW(window, msg, wparam, lparam)
+            }
+        }
+
+        winuser::WM_POINTERDOWN | winuser::WM_POINTERUPDATE | winuser::WM_POINTERUP => {
+            if pointer_input::handle_pointer(window, state, wparam) {
+                0
+            } else {
+                winuser::DefWindowProcW(window, msg, wparam, lparam)
+            }
+        }
+
+        winuser::WM_INPUT => {
+            device_input::handle_raw_input(state, lparam);
+            winuser::DefWindowProcW(window, msg, wparam, lparam)
+        }
+
+        winuser::WM_INPUT_DEVICE_CHANGE => {
+            device_input::handle_device_change(state, wparam, lparam);
+            0
+        }
+
+        winuser::WM_SETCURSOR => {
+            // Child windows pass the message on to their parent first; `wparam` is the window
+            // that actually contains the cursor.
+            let hit_test = LOWORD(lparam as DWORD) as i16;
+            let over_window = wparam as HWND == window;
+            match cursor_action(hit_test, over_window, state.cursor.get()) {
+                CursorAction::Default => winuser::DefWindowProcW(window, msg, wparam, lparam),
+                CursorAction::Hide => {
+                    winuser::SetCursor(ptr::null_mut());
+                    TRUE as LRESULT
+                }
+                CursorAction::System(cursor) => {
+                    let id = winuser::MAKEINTRESOURCEW(cursor.resource_id());
+                    winuser::SetCursor(winuser::LoadCursorW(ptr::null_mut(), id));
+                    TRUE as LRESULT
+                }
+                CursorAction::Custom => {
+                    if let Some(ref cursor) = *state.custom_cursor.borrow() {
+                        winuser::SetCursor(cursor.handle);
+                    }
+                    TRUE as LRESULT
+                }
+            }
+        }
+
+        winuser::WM_DPICHANGED => {
+            let dpi = LOWORD(wparam as DWORD) as u32;
+            state.dpi.set(dpi);
+            state.send_event(WindowEvent::HiDpiFactorChanged(state.hidpi_factor()));
+            // Windows suggests a rect that keeps the window the same logical size on the new
+            // monitor.
+            let rect = &*(lparam as *const RECT);
+            winuser::SetWindowPos(
+                window,
+                ptr::null_mut(),
+                rect.left,
+                rect.top,
+                rect.right - rect.left,
+                rect.bottom - rect.top,
+                winuser::SWP_NOZORDER | winuser::SWP_NOACTIVATE,
+            );
+            0
+        }
+
         _ => winuser::DefWindowProcW(window, msg, wparam, lparam),
     }
 }
@@ -0,0 +1,200 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use cursor::CursorState;
+use device_input::RawInputDevices;
+use drop_target::DropTargetRegistration;
+use lifecycle::{Lifecycle, LifecycleEvent};
+use text_input::Utf16Assembler;
+use window::WinCursor;
+use window_style::WindowFlags;
+use window_events::{DeviceEvent, DeviceId, LogicalPosition, WindowEvent};
+
+/// The DPI Windows treats as a scale factor of 1.
+pub const BASE_DPI: u32 = 96;
+
+/// Receives the events of a single window.
+pub type EventHandler = Box<dyn FnMut(WindowEvent)>;
+
+/// Receives the raw input events of the devices registered on a single window.
+pub type DeviceEventHandler = Box<dyn FnMut(DeviceId, DeviceEvent)>;
+
+/// An event waiting for its handler.
+enum PendingEvent {
+    Window(WindowEvent),
+    Device(DeviceId, DeviceEvent),
+}
+
+/// State shared between a `Window` and its window procedure.
+///
+/// The state is reference counted: the `Window` holds one reference, and the `HWND` holds another
+/// in `GWLP_USERDATA` from `WM_NCCREATE` until `WM_NCDESTROY`. This way neither side can outlive
+/// the state, whichever of them goes away first.
+pub struct WindowState {
+    lifecycle: Cell<Lifecycle>,
+    event_handler: RefCell<Option<EventHandler>>,
+    device_event_handler: RefCell<Option<DeviceEventHandler>>,
+    pending_events: RefCell<VecDeque<PendingEvent>>,
+    dispatching: Cell<bool>,
+    pub flags: Cell<WindowFlags>,
+    pub dpi: Cell<u32>,
+    pub cursor: Cell<CursorState>,
+    /// The custom cursor, shown while `cursor.custom` is set.
+    pub custom_cursor: RefCell<Option<WinCursor>>,
+    /// Joins the surrogate pairs that `WM_CHAR` delivers in two messages.
+    pub utf16: Cell<Utf16Assembler>,
+    /// Where input method editors place their windows, in client coordinates.
+    pub ime_position: Cell<Option<LogicalPosition>>,
+    /// Whether the application draws IME compositions itself, set with `Window::set_ime_allowed`.
+    pub ime_allowed: Cell<bool>,
+    /// The kinds of devices whose raw input is registered on this window.
+    pub raw_input: Cell<RawInputDevices>,
+    /// The window's drag-and-drop registration, revoked on `WM_DESTROY`.
+    pub drop_target: RefCell<Option<DropTargetRegistration>>,
+}
+
+impl WindowState {
+    pub fn new(flags: WindowFlags) -> Self {
+        WindowState {
+            lifecycle: Cell::new(Lifecycle::Unattached),
+            event_handler: RefCell::new(None),
+            device_event_handler: RefCell::new(None),
+            pending_events: RefCell::new(VecDeque::new()),
+            dispatching: Cell::new(false),
+            flags: Cell::new(flags),
+            dpi: Cell::new(BASE_DPI),
+            cursor: Cell::new(CursorState::default()),
+            custom_cursor: RefCell::new(None),
+            utf16: Cell::new(Utf16Assembler::new()),
+            ime_position: Cell::new(None),
+            ime_allowed: Cell::new(false),
+            raw_input: Cell::new(RawInputDevices::empty()),
+            drop_target: RefCell::new(None),
+        }
+    }
+
+    #[inline]
+    pub fn lifecycle(&self) -> Lifecycle {
+        self.lifecycle.get()
+    }
+
+    /// Advances the lifecycle. Impossible transitions are bugs in the window procedure; they are
+    /// reported in debug builds and otherwise leave the lifecycle unchanged.
+    pub fn transition(&self, event: LifecycleEvent) {
+        match self.lifecycle.get().transition(event) {
+            Ok(next) => self.lifecycle.set(next),
+            Err(err) => debug_assert!(false, "invalid window lifecycle transition: {:?}", err),
+        }
+    }
+
+    /// The ratio between physical and logical pixels.
+    #[inline]
+    pub fn hidpi_factor(&self) -> f64 {
+        self.dpi.get() as f64 / BASE_DPI as f64
+    }
+
+    pub fn set_event_handler(&self, handler: EventHandler) {
+        *self.event_handler.borrow_mut() = Some(handler);
+    }
+
+    pub fn set_device_event_handler(&self, handler: DeviceEventHandler) {
+        *self.device_event_handler.borrow_mut() = Some(handler);
+    }
+
+    /// Delivers an event to the event handler.
+    ///
+    /// Handlers often do things that make Windows send the window more messages synchronously. The
+    /// events produced by those messages are queued and delivered in order once the handler
+    /// returns, so the handler never runs reentrantly.
+    pub fn send_event(&self, event: WindowEvent) {
+        self.dispatch(PendingEvent::Window(event));
+    }
+
+    /// Delivers an event to the device event handler, queued behind the window events like
+    /// `send_event`.
+    pub fn send_device_event(&self, device_id: DeviceId, event: DeviceEvent) {
+        self.dispatch(PendingEvent::Device(device_id, event));
+    }
+
+    fn dispatch(&self, event: PendingEvent) {
+        self.pending_events.borrow_mut().push_back(event);
+        if self.dispatching.get() {
+            return;
+        }
+        self.dispatching.set(true);
+        // Resets `dispatching` even if a handler panics, so the window isn't silenced for good.
+        let _dispatching = ResetOnDrop(&self.dispatching);
+        loop {
+            let event = match self.pending_events.borrow_mut().pop_front() {
+                Some(event) => event,
+                None => break,
+            };
+            match event {
+                PendingEvent::Window(event) => {
+                    self.call_handler(&self.event_handler, |handler| handler(event))
+                }
+                PendingEvent::Device(device_id, event) => self
+                    .call_handler(&self.device_event_handler, |handler| {
+                        handler(device_id, event)
+                    }),
+            }
+        }
+    }
+
+    /// Runs the handler in `slot`, if there is one. The handler is taken out of its slot while it
+    /// runs, so that it can replace itself.
+    fn call_handler<H, F>(&self, slot: &RefCell<Option<H>>, call: F)
+    where
+        F: FnOnce(&mut H),
+    {
+        let handler = slot.borrow_mut().take();
+        if let Some(handler) = handler {
+            let mut restore = RestoreHandler {
+                slot,
+                lifecycle: &self.lifecycle,
+                handler: Some(handler),
+            };
+            call(restore.handler.as_mut().unwrap());
+        }
+    }
+
+    /// Releases everything that refers to the window. Called once the `HWND` is gone.
+    pub fn tear_down(&self) {
+        self.drop_target.borrow_mut().take();
+        self.custom_cursor.borrow_mut().take();
+        self.event_handler.borrow_mut().take();
+        self.device_event_handler.borrow_mut().take();
+        self.pending_events.borrow_mut().clear();
+    }
+}
+
+/// Clears a flag when dropped.
+struct ResetOnDrop<'a>(&'a Cell<bool>);
+
+impl<'a> Drop for ResetOnDrop<'a> {
+    fn drop(&mut self) {
+        self.0.set(false);
+    }
+}
+
+/// Puts a handler back into its slot once it has run, even if it panicked.
+struct RestoreHandler<'a, H: 'a> {
+    slot: &'a RefCell<Option<H>>,
+    lifecycle: &'a Cell<Lifecycle>,
+    handler: Option<H>,
+}
+
+impl<'a, H> Drop for RestoreHandler<'a, H> {
+    fn drop(&mut self) {
+        // A handler that destroyed its window saw the state torn down while it ran; bringing it
+        // back would keep whatever it holds alive until the `Window` is dropped.
+        if self.lifecycle.get() == Lifecycle::Destroyed {
+            return;
+        }
+        // Keep a handler that was installed while this one was running.
+        let mut slot = self.slot.borrow_mut();
+        if slot.is_none() {
+            *slot = self.handler.take();
+        }
+    }
+}
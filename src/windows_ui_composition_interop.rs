@@ -1,10 +1,17 @@
 #![allow(non_snake_case, non_upper_case_globals)]
+use winapi::ctypes::c_void;
+use winapi::shared::guiddef::REFIID;
+use std::mem::size_of;
 use winapi::shared::minwindef::{BOOL, DWORD};
-use winapi::shared::ntdef::HRESULT;
-use winapi::shared::windef::HWND;
+use winapi::shared::ntdef::{HANDLE, HRESULT};
+use winapi::shared::windef::{HWND, POINT, RECT, SIZE};
 use winrt::windows::ui::composition::desktop::IDesktopWindowTarget;
-use winrt::IUnknown;
+use winrt::windows::ui::composition::{ICompositionGraphicsDevice, ICompositionSurface};
+use winrt::{ComInterface, IUnknown};
 
+use interop_vtables as vtables;
+
+// {29E691FA-4567-4DCA-B319-D0F207EB6807}, from windows.ui.composition.interop.h.
 winrt::DEFINE_IID!(
     IID_ICompositorDesktopInterop,
     0x29e691fa,
@@ -31,3 +38,118 @@ winrt::COM_INTERFACE! {interface ICompositorDesktopInterop(ICompositorDesktopInt
         threadId: DWORD
     ) -> HRESULT
 }}
+
+// {25297D5C-3AD4-4C9C-B5CF-E36A38512330}
+winrt::DEFINE_IID!(
+    IID_ICompositorInterop,
+    0x25297d5c,
+    0x3ad4,
+    0x4c9c,
+    0xb5,
+    0xcf,
+    0xe3,
+    0x6a,
+    0x38,
+    0x51,
+    0x23,
+    0x30
+);
+winrt::COM_INTERFACE! {interface ICompositorInterop(ICompositorInteropVtbl): IUnknown [IID_ICompositorInterop] {
+    fn CreateCompositionSurfaceForHandle(
+        &mut self,
+        swapChain: HANDLE,
+        result: *mut *mut <ICompositionSurface as ComInterface>::TAbi
+    ) -> HRESULT,
+    fn CreateCompositionSurfaceForSwapChain(
+        &mut self,
+        swapChain: *mut IUnknown,
+        result: *mut *mut <ICompositionSurface as ComInterface>::TAbi
+    ) -> HRESULT,
+    fn CreateGraphicsDevice(
+        &mut self,
+        renderingDevice: *mut IUnknown,
+        result: *mut *mut <ICompositionGraphicsDevice as ComInterface>::TAbi
+    ) -> HRESULT
+}}
+
+// {FD04E6E3-FE0C-4C3C-AB19-A07601A576EE}
+winrt::DEFINE_IID!(
+    IID_ICompositionDrawingSurfaceInterop,
+    0xfd04e6e3,
+    0xfe0c,
+    0x4c3c,
+    0xab,
+    0x19,
+    0xa0,
+    0x76,
+    0x01,
+    0xa5,
+    0x76,
+    0xee
+);
+winrt::COM_INTERFACE! {interface ICompositionDrawingSurfaceInterop(ICompositionDrawingSurfaceInteropVtbl): IUnknown [IID_ICompositionDrawingSurfaceInterop] {
+    fn BeginDraw(
+        &mut self,
+        updateRect: *const RECT,
+        iid: REFIID,
+        updateObject: *mut *mut c_void,
+        updateOffset: *mut POINT
+    ) -> HRESULT,
+    fn EndDraw(&mut self) -> HRESULT,
+    fn Resize(
+        &mut self,
+        sizePixels: SIZE
+    ) -> HRESULT,
+    fn Scroll(
+        &mut self,
+        scrollRect: *const RECT,
+        clipRect: *const RECT,
+        offsetX: i32,
+        offsetY: i32
+    ) -> HRESULT,
+    fn ResumeDraw(&mut self) -> HRESULT,
+    fn SuspendDraw(&mut self) -> HRESULT
+}}
+
+// {A116FF71-F8BF-4C8A-9C98-70779A32A9C8}
+winrt::DEFINE_IID!(
+    IID_ICompositionGraphicsDeviceInterop,
+    0xa116ff71,
+    0xf8bf,
+    0x4c8a,
+    0x9c,
+    0x98,
+    0x70,
+    0x77,
+    0x9a,
+    0x32,
+    0xa9,
+    0xc8
+);
+winrt::COM_INTERFACE! {interface ICompositionGraphicsDeviceInterop(ICompositionGraphicsDeviceInteropVtbl): IUnknown [IID_ICompositionGraphicsDeviceInterop] {
+    fn GetRenderingDevice(
+        &mut self,
+        value: *mut *mut IUnknown
+    ) -> HRESULT,
+    fn SetRenderingDevice(
+        &mut self,
+        value: *mut IUnknown
+    ) -> HRESULT
+}}
+
+// The slot of each method is tested by the plain declarations of `interop_vtables`. These
+// constants only compile while the declarations above are the same size as those.
+#[allow(dead_code)]
+const COMPOSITOR_DESKTOP_INTEROP_SLOTS: [(); size_of::<vtables::ICompositorDesktopInteropVtbl>()] =
+    [(); size_of::<ICompositorDesktopInteropVtbl>()];
+#[allow(dead_code)]
+const COMPOSITOR_INTEROP_SLOTS: [(); size_of::<vtables::ICompositorInteropVtbl>()] =
+    [(); size_of::<ICompositorInteropVtbl>()];
+#[allow(dead_code)]
+const COMPOSITION_DRAWING_SURFACE_INTEROP_SLOTS: [(); size_of::<
+    vtables::ICompositionDrawingSurfaceInteropVtbl,
+>()] = [(); size_of::<ICompositionDrawingSurfaceInteropVtbl>()];
+#[allow(dead_code)]
+const COMPOSITION_GRAPHICS_DEVICE_INTEROP_SLOTS: [(); size_of::<
+    vtables::ICompositionGraphicsDeviceInteropVtbl,
+>()] = [(); size_of::<ICompositionGraphicsDeviceInteropVtbl>()];